@@ -31,8 +31,18 @@ fn main() {
         }
     }
 
+    // CRASHTEST=1 builds in crashat(), for "make crashtest".
+    // Naming what to watch stops cargo watching the whole
+    // package, so name c/ as well.
+    println!("cargo:rerun-if-changed=c");
+    println!("cargo:rerun-if-env-changed=CRASHTEST");
+    if std::env::var("CRASHTEST").map_or(false, |v| v == "1") {
+        build.define("CRASHTEST", None);
+        println!("cargo:rustc-cfg=crashtest");
+    }
+
     let c_files = [
-        "console", "exec", "file", "fs", "kalloc", "log", "proc", "sysfile", "sysproc",
+        "console", "disk", "exec", "fs", "kalloc", "log", "proc", "sysfile", "sysproc",
     ];
     for file in c_files {
         build
@@ -6,9 +6,14 @@ struct buf {
   uint refcnt;
   struct buf *prev; // LRU cache list
   struct buf *next;
+  struct buf *hnext; // hash chain
   struct buf *qnext; // disk queue
-  uchar data[BSIZE];
+  struct buf *rnext; // next block of a multi-block read
+  uint size;         // block size of dev, in bytes
+  uchar *data;       // a page
 };
 #define B_VALID 0x2  // buffer has been read from disk
 #define B_DIRTY 0x4  // buffer needs to be written to disk
+#define B_LATER 0x8  // dirty, and written back by the cache, not the log
+#define B_AHEAD 0x10 // being read ahead; the disk releases it
 
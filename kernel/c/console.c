@@ -14,6 +14,7 @@
 #include "mmu.h"
 #include "proc.h"
 #include "x86.h"
+#include "fcntl.h"
 
 static void consputc(int);
 
@@ -251,7 +252,7 @@ void consoleintr(int (*getc)(void))
   }
 }
 
-int consoleread(struct inode *ip, char *dst, int n)
+int consoleread(struct inode *ip, char *dst, uint off, int n, int flags)
 {
   uint target;
   int c;
@@ -263,12 +264,14 @@ int consoleread(struct inode *ip, char *dst, int n)
   {
     while (input.r == input.w)
     {
-      if (myproc()->killed)
+      if (myproc()->killed || ((flags & O_NONBLOCK) && n == (int)target))
       {
         release(&cons.lock);
         ilock(ip);
         return -1;
       }
+      if (flags & O_NONBLOCK)
+        goto done;
       sleep(&input.r, &cons.lock);
     }
     c = input.buf[input.r++ % INPUT_BUF];
@@ -287,13 +290,14 @@ int consoleread(struct inode *ip, char *dst, int n)
     if (c == '\n')
       break;
   }
+done:
   release(&cons.lock);
   ilock(ip);
 
   return (int)target - n;
 }
 
-int consolewrite(struct inode *ip, char *buf, int n)
+int consolewrite(struct inode *ip, char *buf, uint off, int n, int flags)
 {
   int i;
 
@@ -307,12 +311,23 @@ int consolewrite(struct inode *ip, char *buf, int n)
   return n;
 }
 
+int consoleioctl(struct inode *ip, int req, char *arg)
+{
+  if (req != FIONREAD)
+    return -1;
+  acquire(&cons.lock);
+  *(uint *)arg = input.w - input.r;
+  release(&cons.lock);
+  return 0;
+}
+
+static struct devsw consdevsw = {consoleread, consolewrite, consoleioctl};
+
 void consoleinit(void)
 {
   initlock(&cons.lock, "console");
 
-  devsw[CONSOLE].write = consolewrite;
-  devsw[CONSOLE].read = consoleread;
+  devregister("console", CONSOLE, 1, &consdevsw);
   cons.locking = 1;
 
   ioapicenable(IRQ_KBD, 0);
@@ -1,10 +1,13 @@
 struct buf;
 struct context;
+struct devsw;
+struct fdtable;
 struct file;
 struct inode;
 struct pipe;
 struct proc;
 struct rtcdate;
+struct timeval;
 struct spinlock;
 struct sleeplock;
 struct stat;
@@ -12,8 +15,15 @@ struct superblock;
 
 // bio.c
 struct buf*     bread(uint, uint);
+void            breadrun(uint, uint, int, struct buf**);
 void            brelse(struct buf*);
+int             bsetsize(uint, uint);
+uint            bgetsize(uint);
 void            bwrite(struct buf*);
+void            bdwrite(struct buf*);
+void            breadahead(uint, uint, int);
+void            bflush(int);
+void            bflusher(void);
 
 // console.c
 void            consoleinit(void);
@@ -21,10 +31,32 @@ void            cprintf(const char*, ...);
 void            consoleintr(int(*)(void));
 void            panic(const char*) __attribute__((noreturn));
 
+// dev.rs
+int             devregister(char*, int, int, struct devsw*);
+int             devread(struct inode*, char*, uint, int, int);
+int             devwrite(struct inode*, char*, uint, int, int);
+int             devioctl(struct inode*, int, char*);
+
+// disk.c
+void            diskinit(void);
+
 // exec.c
 int             exec(char*, char**);
 
-// file.c
+// fdtable.rs
+void            fdtabinit(struct fdtable*);
+int             fdtabcopy(struct fdtable*, struct fdtable*);
+void            fdtabclose(struct fdtable*);
+void            fdtabcloseexec(struct fdtable*);
+struct file*    fdlookup(int fd);
+int             fdalloc(struct file*, int minfd);
+int             fdreserve(int fd);
+int             fdinstall(int fd, struct file*);
+struct file*    fdremove(int fd);
+int             fdgetflags(int fd);
+int             fdsetflags(int fd, int flags);
+
+// file.rs
 struct file*    filealloc(void);
 void            fileclose(struct file*);
 struct file*    filedup(struct file*);
@@ -32,29 +64,39 @@ void            fileinit(void);
 int             fileread(struct file*, char*, int n);
 int             filestat(struct file*, struct stat*);
 int             filewrite(struct file*, char*, int n);
+int             filepread(struct file*, char*, int n, uint off);
+int             filepwrite(struct file*, char*, int n, uint off);
+int             fileseek(struct file*, int offset, int whence);
 
 // fs.c
 void            readsb(int dev, struct superblock *sb);
-int             dirlink(struct inode*, char*, uint);
+uint            dirhash(char*, uint);
+int             isancestor(struct inode*, struct inode*);
+int             dirread(struct inode*, uint*, char*, int);
 struct inode*   dirlookup(struct inode*, char*, uint*);
 struct inode*   ialloc(uint, short);
 struct inode*   idup(struct inode*);
+struct inode*   iget(uint, uint);
 void            iinit(int dev);
 void            ilock(struct inode*);
 void            iput(struct inode*);
+int             iref(uint, uint);
+int             irefs(uint);
+int             idevrefs(short, short);
 void            iunlock(struct inode*);
 void            iunlockput(struct inode*);
-void            iupdate(struct inode*);
 int             namecmp(const char*, const char*);
 struct inode*   namei(char*);
+struct inode*   lnamei(char*);
 struct inode*   nameiparent(char*, char*);
-int             readi(struct inode*, char*, uint, uint);
+struct inode*   linkparent(char*, char*);
 void            stati(struct inode*, struct stat*);
-int             writei(struct inode*, char*, uint, uint);
 
 // ide.rs
 void            ideintr(void);
 void            iderw(struct buf*);
+int             idepresent(int);
+uint            idesectors(int);
 
 // ioapic.rs
 void            ioapicenable(uint irq, uint cpu);
@@ -76,12 +118,20 @@ void            initlog(int dev);
 void            log_write(struct buf*);
 void            begin_op(void);
 void            end_op(void);
+void            logflush(void);
+#ifdef CRASHTEST
+int             crashat(int, int, int);
+#endif
 
 // pipe.c
 int             pipealloc(struct file**, struct file**);
 void            pipeclose(struct pipe*, int);
-int             piperead(struct pipe*, char*, int);
-int             pipewrite(struct pipe*, char*, int);
+int             piperead(struct pipe*, char*, int, int);
+int             pipewrite(struct pipe*, char*, int, int);
+
+// rtc.rs
+void            gettime(struct timeval*);
+uint            unixtime(void);
 
 //PAGEBREAK: 16
 // proc.c
@@ -89,6 +139,7 @@ void            exit(void);
 int             fork(void);
 int             growproc(int);
 int             kill(int);
+void            kproc(char*, void (*)(void));
 struct cpu*     mycpu(void);
 struct proc*    myproc(void);
 void            pinit(void);
@@ -139,6 +190,29 @@ void            syscall(void);
 // uart.rs
 void            uartputc(char);
 
+// vfs.rs
+int             dirlink(struct inode*, char*, uint);
+int             dirremove(struct inode*, uint);
+int             dirset(struct inode*, uint, uint);
+uint            direntat(struct inode*, uint, uint*, char*);
+int             itruncate(struct inode*, uint, int);
+int             ifallocate(struct inode*, uint, uint);
+void            iupdate(struct inode*);
+int             readi(struct inode*, char*, uint, uint);
+int             writei(struct inode*, char*, uint, uint);
+void            mountroot(int);
+int             vfsmount(char*, int, char*, struct inode*);
+int             vfsumount(struct inode*);
+int             vfsmounted(struct inode*);
+struct inode*   vfsenter(struct inode*);
+struct inode*   vfsleave(struct inode*);
+uint            vfsroot(uint);
+uint            vfsblksize(uint);
+uint            vfsialloc(uint, short);
+void            vfsload(struct inode*);
+void            vfsfree(struct inode*);
+uint            vfslookup(struct inode*, char*, uint*);
+
 // vm.c
 pde_t*          setupkvm(void);
 int             allocuvm(pde_t*, uint, uint);
@@ -0,0 +1,108 @@
+// Raw disk devices. Disk device minor n reads and writes IDE
+// disk n as a flat array of bytes, through the buffer cache,
+// so it sees the same blocks the file system does. Writes to
+// the root disk are logged like any other block write; the
+// caller must be in a transaction, as filewrite() is.
+// Disk n shows up as /dev/diskn. fsck uses these to check
+// and repair file systems.
+
+#include "types.h"
+#include "defs.h"
+#include "param.h"
+#include "fs.h"
+#include "spinlock.h"
+#include "sleeplock.h"
+#include "file.h"
+#include "buf.h"
+#include "fcntl.h"
+
+#define min(a, b) ((a) < (b) ? (a) : (b))
+
+// Bytes on disk dev. Offsets are 32 bits, so only the first
+// 4 GB of a bigger disk show.
+static uint
+disksize(int dev)
+{
+  uint n;
+
+  n = idesectors(dev);
+  if(n > 0xffffffff / MINBSIZE)
+    n = 0xffffffff / MINBSIZE;
+  return n * MINBSIZE;
+}
+
+// Copy n bytes between p and disk dev at offset off, in the
+// direction write says. Stops at the end of the disk; returns
+// the number of bytes copied.
+static int
+diskrw(int dev, char *p, uint off, int n, int write)
+{
+  struct buf *bp;
+  uint bs, m, tot;
+
+  if(dev < 0 || dev >= NDISK || !idepresent(dev) || n < 0)
+    return -1;
+  if(off >= disksize(dev))
+    return 0;
+  if(n > disksize(dev) - off)
+    n = disksize(dev) - off;
+
+  bs = bgetsize(dev);
+  for(tot = 0; tot < n; tot += m, off += m, p += m){
+    bp = bread(dev, off/bs);
+    if(bp->size != bs){
+      // A mount changed the block size; go again.
+      brelse(bp);
+      bs = bgetsize(dev);
+      m = 0;
+      continue;
+    }
+    m = min(n - tot, bs - off%bs);
+    if(write){
+      memmove(bp->data + off%bs, p, m);
+      if(dev == ROOTDEV)
+        log_write(bp);
+      else
+        bwrite(bp);
+    } else
+      memmove(p, bp->data + off%bs, m);
+    brelse(bp);
+  }
+  return n;
+}
+
+int
+diskread(struct inode *ip, char *dst, uint off, int n, int flags)
+{
+  return diskrw(ip->minor, dst, off, n, 0);
+}
+
+int
+diskwrite(struct inode *ip, char *src, uint off, int n, int flags)
+{
+  return diskrw(ip->minor, src, off, n, 1);
+}
+
+// DIOCGSIZE: the size of the disk in bytes.
+int
+diskioctl(struct inode *ip, int req, char *arg)
+{
+  if(req != DIOCGSIZE || !idepresent(ip->minor))
+    return -1;
+  *(uint*)arg = disksize(ip->minor);
+  return 0;
+}
+
+static struct devsw disksw = { diskread, diskwrite, diskioctl };
+static char *names[NDISK] = { "disk0", "disk1", "disk2", "disk3" };
+
+// Register a device for each disk there is.
+void
+diskinit(void)
+{
+  int i;
+
+  for(i = 0; i < NDISK; i++)
+    if(idepresent(i))
+      devregister(names[i], DISK, i, &disksw);
+}
@@ -101,6 +101,9 @@ exec(char *path, char **argv)
   curproc->tf->esp = sp;
   switchuvm(curproc);
   freevm(oldpgdir);
+
+  // Close descriptors marked close-on-exec.
+  fdtabcloseexec(&curproc->fdt);
   return 0;
 
  bad:
@@ -2,3 +2,28 @@
 #define O_WRONLY 0x001
 #define O_RDWR 0x002
 #define O_CREATE 0x200
+#define O_TRUNC 0x400     // truncate a regular file to length 0
+#define O_APPEND 0x800    // every write goes to the end of the file
+#define O_EXCL 0x1000     // with O_CREATE, fail if the file exists
+#define O_CLOEXEC 0x2000  // close the descriptor on exec
+#define O_NONBLOCK 0x4000 // reads and writes fail instead of sleeping
+#define O_NOFOLLOW 0x8000 // fail if the last path element is a symbolic link
+
+// File descriptor flags
+#define FD_CLOEXEC 1
+
+// fcntl() commands
+#define F_DUPFD 0  // duplicate onto the lowest free descriptor >= arg
+#define F_GETFD 1  // get descriptor flags
+#define F_SETFD 2  // set descriptor flags
+#define F_GETFL 3  // get access mode and file status flags
+#define F_SETFL 4  // set O_APPEND and O_NONBLOCK
+
+// ioctl() requests; arg points to a uint
+#define FIONREAD 1  // get the bytes waiting to be read from the console
+#define DIOCGSIZE 2 // get the size of a disk device in bytes
+
+// lseek() whence values
+#define SEEK_SET 0  // offset is absolute
+#define SEEK_CUR 1  // offset is relative to the current offset
+#define SEEK_END 2  // offset is relative to the end of the file
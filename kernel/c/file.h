@@ -6,6 +6,7 @@ struct file {
   struct pipe *pipe;
   struct inode *ip;
   uint off;
+  uint flags; // O_APPEND, O_NONBLOCK
 };
 
 
@@ -16,22 +17,28 @@ struct inode {
   int ref;            // Reference count
   struct sleeplock lock; // protects everything below here
   int valid;          // inode has been read from disk?
+  uint seqoff;        // offset just past the last read, for read-ahead
 
   short type;         // copy of disk inode
   short major;
   short minor;
   short nlink;
   uint size;
-  uint addrs[NDIRECT+1];
+  uint atime;         // kept in memory by readi until the next iupdate
+  uint mtime;
+  uint ctime;
+  uint addrs[NADDRS];
 };
 
-// table mapping major device number to
-// device functions
+// device functions for a major device number,
+// registered with devregister()
 struct devsw {
-  int (*read)(struct inode*, char*, int);
-  int (*write)(struct inode*, char*, int);
+  int (*read)(struct inode*, char*, uint, int, int);
+  int (*write)(struct inode*, char*, uint, int, int);
+  int (*ioctl)(struct inode*, int, char*);
 };
 
-extern struct devsw devsw[];
-
 #define CONSOLE 1
+#define DISK 2
+#define UART 3
+#define MEM 4
@@ -8,6 +8,10 @@
 // This file contains the low-level file system manipulation
 // routines.  The (higher-level) system call implementations
 // are in sysfile.c.
+//
+// The inode cache and path names serve every mounted file
+// system; the rest is the xv6 file system, which vfs.rs calls
+// through the xv6*() functions (see vfs.rs).
 
 #include "types.h"
 #include "defs.h"
@@ -22,19 +26,25 @@
 #include "file.h"
 
 #define min(a, b) ((a) < (b) ? (a) : (b))
-static void itrunc(struct inode*);
-// there should be one superblock per disk device, but we run with
-// only one device
-struct superblock sb; 
+// The super block of each disk holding a mounted xv6 file system.
+static struct superblock sbs[NDISK];
+#define SB(dev) (sbs[dev])
 
-// Read the super block.
+// Read the super block, SBOFF bytes into the disk.
+// Works whatever block size the buffer cache uses for dev.
 void
 readsb(int dev, struct superblock *sb)
 {
   struct buf *bp;
+  uint bsize;
 
-  bp = bread(dev, 1);
-  memmove(sb, bp->data, sizeof(*sb));
+  bp = bread(dev, 0);
+  bsize = bp->size;
+  if(SBOFF >= bsize){
+    brelse(bp);
+    bp = bread(dev, SBOFF / bsize);
+  }
+  memmove(sb, bp->data + SBOFF % bsize, sizeof(*sb));
   brelse(bp);
 }
 
@@ -45,7 +55,7 @@ bzero(int dev, int bno)
   struct buf *bp;
 
   bp = bread(dev, bno);
-  memset(bp->data, 0, BSIZE);
+  memset(bp->data, 0, SB(dev).bsize);
   log_write(bp);
   brelse(bp);
 }
@@ -60,9 +70,9 @@ balloc(uint dev)
   struct buf *bp;
 
   bp = 0;
-  for(b = 0; b < sb.size; b += BPB){
-    bp = bread(dev, BBLOCK(b, sb));
-    for(bi = 0; bi < BPB && b + bi < sb.size; bi++){
+  for(b = 0; b < SB(dev).size; b += BPB(SB(dev))){
+    bp = bread(dev, BBLOCK(b, SB(dev)));
+    for(bi = 0; bi < BPB(SB(dev)) && b + bi < SB(dev).size; bi++){
       m = 1 << (bi % 8);
       if((bp->data[bi/8] & m) == 0){  // Is block free?
         bp->data[bi/8] |= m;  // Mark block in use.
@@ -77,6 +87,73 @@ balloc(uint dev)
   panic("balloc: out of blocks");
 }
 
+// Allocate disk block b if it is free.
+// Returns 1 on success, 0 if b is in use.
+static int
+ballocat(uint dev, uint b)
+{
+  struct buf *bp;
+  int bi, m;
+
+  if(b >= SB(dev).size)
+    return 0;
+  bp = bread(dev, BBLOCK(b, SB(dev)));
+  bi = b % BPB(SB(dev));
+  m = 1 << (bi % 8);
+  if(bp->data[bi/8] & m){
+    brelse(bp);
+    return 0;
+  }
+  bp->data[bi/8] |= m;
+  log_write(bp);
+  brelse(bp);
+  bzero(dev, b);
+  return 1;
+}
+
+// Allocate a zeroed disk block at the start of the first run of
+// want free blocks at or after goal, so that a file can keep
+// growing into the blocks after it. If there is no such run,
+// settle for the first free block at or after goal, then for
+// any free block.
+static uint
+ballocrun(uint dev, uint goal, uint want)
+{
+  uint b, bi, start, len, first;
+  struct buf *bp;
+
+  if(goal >= SB(dev).size)
+    goal = 0;
+  first = 0;  // block 0 is the boot block, never free
+  start = len = 0;
+  for(b = goal - goal % BPB(SB(dev)); b < SB(dev).size; b += BPB(SB(dev))){
+    bp = bread(dev, BBLOCK(b, SB(dev)));
+    for(bi = b < goal ? goal - b : 0; bi < BPB(SB(dev)) && b + bi < SB(dev).size; bi++){
+      if(bp->data[bi/8] & (1 << (bi % 8))){
+        len = 0;
+        continue;
+      }
+      if(len++ == 0)
+        start = b + bi;
+      if(first == 0)
+        first = start;
+      if(len == want){
+        brelse(bp);
+        goto found;
+      }
+    }
+    brelse(bp);
+  }
+  if(first == 0)
+    return balloc(dev);
+  start = first;
+
+found:
+  if(!ballocat(dev, start))
+    panic("ballocrun");
+  return start;
+}
+
 // Free a disk block.
 static void
 bfree(int dev, uint b)
@@ -84,8 +161,8 @@ bfree(int dev, uint b)
   struct buf *bp;
   int bi, m;
 
-  bp = bread(dev, BBLOCK(b, sb));
-  bi = b % BPB;
+  bp = bread(dev, BBLOCK(b, SB(dev)));
+  bi = b % BPB(SB(dev));
   m = 1 << (bi % 8);
   if((bp->data[bi/8] & m) == 0)
     panic("freeing free block");
@@ -102,7 +179,7 @@ bfree(int dev, uint b)
 // list of blocks holding the file's content.
 //
 // The inodes are laid out sequentially on disk at
-// sb.startinode. Each inode has a number, indicating its
+// sb.inodestart. Each inode has a number, indicating its
 // position on the disk.
 //
 // The kernel keeps a cache of in-use inodes in memory
@@ -168,6 +245,10 @@ struct {
   struct inode inode[NINODE];
 } icache;
 
+// Renames take this lock first, so that only one at a time
+// can move directories around the tree.
+struct sleeplock renamelock;
+
 void
 iinit(int dev)
 {
@@ -177,59 +258,100 @@ iinit(int dev)
   for(i = 0; i < NINODE; i++) {
     initsleeplock(&icache.inode[i].lock, "inode");
   }
+  initsleeplock(&renamelock, "rename");
 
-  readsb(dev, &sb);
-  cprintf("sb: size %d nblocks %d ninodes %d nlog %d logstart %d\
- inodestart %d bmap start %d\n", sb.size, sb.nblocks,
-          sb.ninodes, sb.nlog, sb.logstart, sb.inodestart,
-          sb.bmapstart);
+  mountroot(dev);
 }
 
-static struct inode* iget(uint dev, uint inum);
+// Get ready to use the xv6 file system on disk dev.
+// Returns -1 if its super block makes no sense.
+int
+xv6mount(int dev)
+{
+  struct superblock *sb;
+
+  sb = &SB(dev);
+  readsb(dev, sb);
+  if(sb->bsize < MINBSIZE || sb->bsize > MAXBSIZE || (sb->bsize & (sb->bsize-1)))
+    return -1;
+  if(sb->features & ~(FS_EXTENTS|FS_LONGNAMES))
+    return -1;
+  if(sb->ninodes == 0 || IBLOCK(sb->ninodes - 1, *sb) >= sb->size ||
+     BBLOCK(sb->size - 1, *sb) >= sb->size)
+    return -1;
+  if(sb->size > idesectors(dev) / (sb->bsize / MINBSIZE))
+    return -1;  // bigger than the disk
+  if(bsetsize(dev, sb->bsize) < 0)
+    return -1;
+  cprintf("sb: size %d nblocks %d ninodes %d nlog %d logstart %d\
+ inodestart %d bmap start %d features %x bsize %d\n", sb->size,
+          sb->nblocks, sb->ninodes, sb->nlog, sb->logstart, sb->inodestart,
+          sb->bmapstart, sb->features, sb->bsize);
+  return 0;
+}
 
 //PAGEBREAK!
 // Allocate an inode on device dev.
 // Mark it as allocated by  giving it type type.
-// Returns an unlocked but allocated and referenced inode.
-struct inode*
-ialloc(uint dev, short type)
+// Returns its inode number.
+uint
+xv6ialloc(uint dev, short type)
 {
   int inum;
   struct buf *bp;
   struct dinode *dip;
 
-  for(inum = 1; inum < sb.ninodes; inum++){
-    bp = bread(dev, IBLOCK(inum, sb));
-    dip = (struct dinode*)bp->data + inum%IPB;
+  for(inum = 1; inum < SB(dev).ninodes; inum++){
+    bp = bread(dev, IBLOCK(inum, SB(dev)));
+    dip = (struct dinode*)bp->data + inum%IPB(SB(dev));
     if(dip->type == 0){  // a free inode
       memset(dip, 0, sizeof(*dip));
       dip->type = type;
+      dip->atime = dip->mtime = dip->ctime = unixtime();
       log_write(bp);   // mark it allocated on the disk
       brelse(bp);
-      return iget(dev, inum);
+      return inum;
     }
     brelse(bp);
   }
   panic("ialloc: no inodes");
 }
 
-// Copy a modified in-memory inode to disk.
+// Allocate an inode of type type on the file system of dev.
+// Returns an unlocked but allocated and referenced inode,
+// or 0 if the file system has no room for one.
+struct inode*
+ialloc(uint dev, short type)
+{
+  uint inum;
+
+  if((inum = vfsialloc(dev, type)) == 0)
+    return 0;
+  return iget(dev, inum);
+}
+
+// Copy a modified in-memory inode to disk, stamping its
+// change time.
 // Must be called after every change to an ip->xxx field
 // that lives on disk, since i-node cache is write-through.
 // Caller must hold ip->lock.
 void
-iupdate(struct inode *ip)
+xv6iupdate(struct inode *ip)
 {
   struct buf *bp;
   struct dinode *dip;
 
-  bp = bread(ip->dev, IBLOCK(ip->inum, sb));
-  dip = (struct dinode*)bp->data + ip->inum%IPB;
+  bp = bread(ip->dev, IBLOCK(ip->inum, SB(ip->dev)));
+  dip = (struct dinode*)bp->data + ip->inum%IPB(SB(ip->dev));
   dip->type = ip->type;
   dip->major = ip->major;
   dip->minor = ip->minor;
   dip->nlink = ip->nlink;
   dip->size = ip->size;
+  ip->ctime = unixtime();
+  dip->atime = ip->atime;
+  dip->mtime = ip->mtime;
+  dip->ctime = ip->ctime;
   memmove(dip->addrs, ip->addrs, sizeof(ip->addrs));
   log_write(bp);
   brelse(bp);
@@ -238,7 +360,7 @@ iupdate(struct inode *ip)
 // Find the inode with number inum on device dev
 // and return the in-memory copy. Does not lock
 // the inode and does not read it from disk.
-static struct inode*
+struct inode*
 iget(uint dev, uint inum)
 {
   struct inode *ip, *empty;
@@ -266,6 +388,7 @@ iget(uint dev, uint inum)
   ip->inum = inum;
   ip->ref = 1;
   ip->valid = 0;
+  ip->seqoff = 0;
   release(&icache.lock);
 
   return ip;
@@ -282,29 +405,90 @@ idup(struct inode *ip)
   return ip;
 }
 
-// Lock the given inode.
-// Reads the inode from disk if necessary.
+// Number of references to the cached inodes of device dev.
+int
+irefs(uint dev)
+{
+  struct inode *ip;
+  int n;
+
+  acquire(&icache.lock);
+  n = 0;
+  for(ip = &icache.inode[0]; ip < &icache.inode[NINODE]; ip++)
+    if(ip->dev == dev)
+      n += ip->ref;
+  release(&icache.lock);
+  return n;
+}
+
+// References to inode inum of dev in the cache.
+int
+iref(uint dev, uint inum)
+{
+  struct inode *ip;
+  int n;
+
+  acquire(&icache.lock);
+  n = 0;
+  for(ip = &icache.inode[0]; ip < &icache.inode[NINODE]; ip++)
+    if(ip->dev == dev && ip->inum == inum)
+      n += ip->ref;
+  release(&icache.lock);
+  return n;
+}
+
+// References to cached device inodes for device major, minor,
+// such as an open raw disk.
+int
+idevrefs(short major, short minor)
+{
+  struct inode *ip;
+  int n;
+
+  acquire(&icache.lock);
+  n = 0;
+  for(ip = &icache.inode[0]; ip < &icache.inode[NINODE]; ip++)
+    if(ip->ref > 0 && ip->valid && ip->type == T_DEV &&
+       ip->major == major && ip->minor == minor)
+      n += ip->ref;
+  release(&icache.lock);
+  return n;
+}
+
+// Copy ip's dinode from the disk.
+// Caller must hold ip->lock.
 void
-ilock(struct inode *ip)
+xv6iload(struct inode *ip)
 {
   struct buf *bp;
   struct dinode *dip;
 
+  bp = bread(ip->dev, IBLOCK(ip->inum, SB(ip->dev)));
+  dip = (struct dinode*)bp->data + ip->inum%IPB(SB(ip->dev));
+  ip->type = dip->type;
+  ip->major = dip->major;
+  ip->minor = dip->minor;
+  ip->nlink = dip->nlink;
+  ip->size = dip->size;
+  ip->atime = dip->atime;
+  ip->mtime = dip->mtime;
+  ip->ctime = dip->ctime;
+  memmove(ip->addrs, dip->addrs, sizeof(ip->addrs));
+  brelse(bp);
+}
+
+// Lock the given inode.
+// Reads the inode from its file system if necessary.
+void
+ilock(struct inode *ip)
+{
   if(ip == 0 || ip->ref < 1)
     panic("ilock");
 
   acquiresleep(&ip->lock);
 
   if(ip->valid == 0){
-    bp = bread(ip->dev, IBLOCK(ip->inum, sb));
-    dip = (struct dinode*)bp->data + ip->inum%IPB;
-    ip->type = dip->type;
-    ip->major = dip->major;
-    ip->minor = dip->minor;
-    ip->nlink = dip->nlink;
-    ip->size = dip->size;
-    memmove(ip->addrs, dip->addrs, sizeof(ip->addrs));
-    brelse(bp);
+    vfsload(ip);
     ip->valid = 1;
     if(ip->type == 0)
       panic("ilock: no type");
@@ -325,7 +509,7 @@ iunlock(struct inode *ip)
 // If that was the last reference, the inode cache entry can
 // be recycled.
 // If that was the last reference and the inode has no links
-// to it, free the inode (and its content) on disk.
+// to it, its file system frees the inode (and its content).
 // All calls to iput() must be inside a transaction in
 // case it has to free the inode.
 void
@@ -337,10 +521,8 @@ iput(struct inode *ip)
     int r = ip->ref;
     release(&icache.lock);
     if(r == 1){
-      // inode has no links and no other references: truncate and free.
-      itrunc(ip);
-      ip->type = 0;
-      iupdate(ip);
+      // inode has no links and no other references: free it.
+      vfsfree(ip);
       ip->valid = 0;
     }
   }
@@ -365,73 +547,411 @@ iunlockput(struct inode *ip)
 // The content (data) associated with each inode is stored
 // in blocks on the disk. The first NDIRECT block numbers
 // are listed in ip->addrs[].  The next NINDIRECT blocks are
-// listed in block ip->addrs[NDIRECT].
+// listed in block ip->addrs[NDIRECT], the NDINDIRECT after
+// those in the blocks listed in ip->addrs[NDIRECT+1], and the
+// NTINDIRECT after those one level further down again, from
+// ip->addrs[NDIRECT+2].
+
+// With FS_EXTENTS, ip->addrs[] holds extents instead: runs of
+// consecutive file blocks stored in consecutive disk blocks.
+
+// Copy ip's extents into ext, which has room for MAXEXTENT.
+// Returns how many there are.
+static int
+eload(struct inode *ip, struct extent *ext)
+{
+  struct buf *bp;
+  int n;
+
+  memset(ext, 0, MAXEXTENT*sizeof(struct extent));
+  memmove(ext, ip->addrs, NEXTENT*sizeof(struct extent));
+  if(ip->addrs[EXTBLK]){
+    bp = bread(ip->dev, ip->addrs[EXTBLK]);
+    memmove(ext+NEXTENT, bp->data, NXEXTENT*sizeof(struct extent));
+    brelse(bp);
+  }
+  for(n = 0; n < MAXEXTENT && ext[n].len; n++)
+    ;
+  return n;
+}
+
+// Store the n extents in ext back into ip, allocating the
+// overflow block if they no longer fit in the inode.
+// Caller must iupdate(ip).
+static void
+esave(struct inode *ip, struct extent *ext, int n)
+{
+  struct buf *bp;
+
+  memmove(ip->addrs, ext, NEXTENT*sizeof(struct extent));
+  if(n <= NEXTENT && ip->addrs[EXTBLK] == 0)
+    return;
+  if(ip->addrs[EXTBLK] == 0)
+    ip->addrs[EXTBLK] = balloc(ip->dev);
+  bp = bread(ip->dev, ip->addrs[EXTBLK]);
+  memmove(bp->data, ext+NEXTENT, NXEXTENT*sizeof(struct extent));
+  log_write(bp);
+  brelse(bp);
+}
+
+// Extent version of bmap, below. If block bn is mapped, also
+// sets *run to the number of mapped blocks from bn on.
+// Returns 0 when allocating if ip has no extents left.
+static uint
+ebmap(struct inode *ip, uint bn, uint alloc, uint *run)
+{
+  struct extent ext[MAXEXTENT], *e;
+  uint addr, goal;
+  int i, n;
+
+  n = eload(ip, ext);
+  for(i = 0; i < n && ext[i].off <= bn; i++){
+    e = &ext[i];
+    if(bn < e->off + e->len){
+      if(run)
+        *run = e->off + e->len - bn;
+      return e->start + (bn - e->off);
+    }
+  }
+  if(!alloc)
+    return 0;
+
+  // ext[i] is the first extent after bn. Grow the one before
+  // if bn follows it in the file and its next disk block is free.
+  goal = 0;
+  if(i > 0){
+    e = &ext[i-1];
+    goal = e->start + (bn - e->off);
+    if(e->off + e->len == bn && ballocat(ip->dev, goal)){
+      e->len++;
+      esave(ip, ext, n);
+      return goal;
+    }
+  }
+
+  if(n == MAXEXTENT)
+    return 0;
+  addr = ballocrun(ip->dev, goal, alloc);
+  memmove(ext+i+1, ext+i, (n-i)*sizeof(struct extent));
+  ext[i].off = bn;
+  ext[i].start = addr;
+  ext[i].len = 1;
+  esave(ip, ext, n+1);
+  return addr;
+}
+
+// Free every block of an extent-mapped inode.
+static void
+etrunc(struct inode *ip)
+{
+  struct extent ext[MAXEXTENT];
+  uint b;
+  int i, n;
+
+  n = eload(ip, ext);
+  for(i = 0; i < n; i++){
+    for(b = 0; b < ext[i].len; b++)
+      bfree(ip->dev, ext[i].start + b);
+  }
+  if(ip->addrs[EXTBLK])
+    bfree(ip->dev, ip->addrs[EXTBLK]);
+  memset(ip->addrs, 0, sizeof(ip->addrs));
+}
+
+// Extent version of bunmap, below.
+static uint
+eunmap(struct inode *ip, uint bn, int budget)
+{
+  struct extent ext[MAXEXTENT], *e;
+  uint end;
+  int n;
+
+  n = eload(ip, ext);
+  end = bn;
+  budget -= 2;  // the overflow block, written or freed
+  while(n > 0){
+    e = &ext[n-1];
+    if(e->off + e->len <= bn)
+      break;
+    if(budget-- < 1){
+      end = e->off + e->len;
+      break;
+    }
+    e->len--;
+    bfree(ip->dev, e->start + e->len);
+    if(e->len == 0){
+      memset(e, 0, sizeof(*e));
+      n--;
+    }
+  }
+  if(n <= NEXTENT && ip->addrs[EXTBLK]){
+    bfree(ip->dev, ip->addrs[EXTBLK]);
+    ip->addrs[EXTBLK] = 0;
+  }
+  esave(ip, ext, n);
+  return end;
+}
 
 // Return the disk block address of the nth block in inode ip.
-// If there is no such block, bmap allocates one.
+// If there is no such block, bmap allocates one when alloc is set
+// and returns 0 (a hole) otherwise. A nonzero alloc is the number
+// of blocks the caller is about to write, as a hint for where to
+// put them; bmap returns 0 if it cannot allocate.
 static uint
-bmap(struct inode *ip, uint bn)
+bmap(struct inode *ip, uint bn, uint alloc)
 {
-  uint addr, *a;
+  uint addr, *a, i, n, per, nind;
   struct buf *bp;
 
+  if(SB(ip->dev).features & FS_EXTENTS)
+    return ebmap(ip, bn, alloc, 0);
+
   if(bn < NDIRECT){
-    if((addr = ip->addrs[bn]) == 0)
+    if((addr = ip->addrs[bn]) == 0 && alloc)
       ip->addrs[bn] = addr = balloc(ip->dev);
     return addr;
   }
   bn -= NDIRECT;
 
-  if(bn < NINDIRECT){
-    // Load indirect block, allocating if necessary.
-    if((addr = ip->addrs[NDIRECT]) == 0)
-      ip->addrs[NDIRECT] = addr = balloc(ip->dev);
+  // Find the indirect tree holding bn: tree i is i+1 levels
+  // deep and maps n blocks.
+  nind = NINDIRECT(SB(ip->dev).bsize);
+  for(i = 0, n = nind; bn >= n; i++, n *= nind){
+    if(i == NADDRS - NDIRECT - 1)
+      panic("bmap: out of range");
+    bn -= n;
+  }
+
+  if((addr = ip->addrs[NDIRECT+i]) == 0){
+    if(!alloc)
+      return 0;
+    ip->addrs[NDIRECT+i] = addr = balloc(ip->dev);
+  }
+
+  // Walk down one indirect block per level, allocating if
+  // necessary; each entry at this level covers per blocks.
+  for(per = n / nind; ; per /= nind){
     bp = bread(ip->dev, addr);
     a = (uint*)bp->data;
-    if((addr = a[bn]) == 0){
-      a[bn] = addr = balloc(ip->dev);
+    if((addr = a[bn / per]) == 0){
+      if(!alloc){
+        brelse(bp);
+        return 0;
+      }
+      a[bn / per] = addr = balloc(ip->dev);
       log_write(bp);
     }
     brelse(bp);
-    return addr;
+    if(per == 1)
+      return addr;
+    bn %= per;
   }
+}
 
-  panic("bmap: out of range");
+// Free block addr and, if it is an indirect block with
+// depth levels below it, every block it maps.
+static void
+bfreetree(uint dev, uint addr, int depth)
+{
+  struct buf *bp;
+  uint *a;
+  int j;
+
+  if(depth > 0){
+    bp = bread(dev, addr);
+    a = (uint*)bp->data;
+    for(j = 0; j < NINDIRECT(SB(dev).bsize); j++){
+      if(a[j])
+        bfreetree(dev, a[j], depth-1);
+    }
+    brelse(bp);
+  }
+  bfree(dev, addr);
+}
+
+// Free the blocks from bn on in the tree at *ap, which maps the
+// n blocks from base on (n is 1 for a data block), last first,
+// spending at most *budget log writes. Clears *ap if the tree
+// is left empty. Returns the block past the last one still
+// mapped, which is bn once everything from bn on is free.
+static uint
+btrunctree(uint dev, uint *ap, uint base, uint n, uint bn, int *budget)
+{
+  struct buf *bp;
+  uint *a, per, end;
+  int j;
+
+  if(*ap == 0 || base + n <= bn)
+    return bn;
+  if(n == 1){
+    if(*budget < 1)
+      return base + 1;
+    bfree(dev, *ap);
+    *ap = 0;
+    (*budget)--;
+    return bn;
+  }
+
+  // An indirect block is written, and may be freed too.
+  if(*budget < 2)
+    return base + n;
+  *budget -= 2;
+  per = n / NINDIRECT(SB(dev).bsize);
+  bp = bread(dev, *ap);
+  a = (uint*)bp->data;
+  end = bn;
+  for(j = NINDIRECT(SB(dev).bsize) - 1; j >= 0 && end == bn; j--)
+    end = btrunctree(dev, &a[j], base + j*per, per, bn, budget);
+  log_write(bp);
+  brelse(bp);
+  if(end == bn && bn <= base){
+    bfree(dev, *ap);
+    *ap = 0;
+  }
+  return end;
 }
 
 // Truncate inode (discard contents).
-// Only called when the inode has no links
-// to it (no directory entries referring to it)
-// and has no in-memory reference to it (is
-// not an open file or current directory).
+// Called when the inode has no links to it (no directory
+// entries referring to it) and has no in-memory reference
+// to it (is not an open file or current directory).
+// Caller must hold ip->lock.
 static void
 itrunc(struct inode *ip)
 {
-  int i, j;
-  struct buf *bp;
-  uint *a;
+  int i;
 
-  for(i = 0; i < NDIRECT; i++){
+  if(SB(ip->dev).features & FS_EXTENTS)
+    etrunc(ip);
+  for(i = 0; i < NADDRS; i++){
     if(ip->addrs[i]){
-      bfree(ip->dev, ip->addrs[i]);
+      bfreetree(ip->dev, ip->addrs[i], i < NDIRECT ? 0 : i - NDIRECT + 1);
       ip->addrs[i] = 0;
     }
   }
 
-  if(ip->addrs[NDIRECT]){
-    bp = bread(ip->dev, ip->addrs[NDIRECT]);
-    a = (uint*)bp->data;
-    for(j = 0; j < NINDIRECT; j++){
-      if(a[j])
-        bfree(ip->dev, a[j]);
+  ip->size = 0;
+  xv6iupdate(ip);
+}
+
+// Free ip, which has no links and no references left, and
+// its content.
+// Caller must hold ip->lock.
+void
+xv6ifree(struct inode *ip)
+{
+  itrunc(ip);
+  ip->type = 0;
+  xv6iupdate(ip);
+}
+
+// Free ip's blocks from block bn on, last first, spending at
+// most budget log writes. Returns the block past the last one
+// still mapped, which is bn once everything from bn on is free.
+static uint
+bunmap(struct inode *ip, uint bn, int budget)
+{
+  uint base, n, end, nind;
+  int i;
+
+  if(SB(ip->dev).features & FS_EXTENTS)
+    return eunmap(ip, bn, budget);
+
+  // The last indirect tree maps n blocks from base on.
+  nind = NINDIRECT(SB(ip->dev).bsize);
+  base = NDIRECT;
+  n = nind;
+  for(i = NDIRECT; i < NADDRS - 1; i++){
+    base += n;
+    n *= nind;
+  }
+  for(i = NADDRS - 1; i >= NDIRECT; i--, n /= nind, base -= n){
+    if((end = btrunctree(ip->dev, &ip->addrs[i], base, n, bn, &budget)) != bn)
+      return end;
+  }
+  for(i = NDIRECT - 1; i >= 0; i--){
+    if((end = btrunctree(ip->dev, &ip->addrs[i], i, 1, bn, &budget)) != bn)
+      return end;
+  }
+  return bn;
+}
+
+// Set the size of ip to size bytes. Growing leaves a hole.
+// Shrinking frees the blocks past the new end, last first,
+// but no more than a transaction of budget blocks can hold:
+// returns 0 if the caller must start another transaction and
+// call again, 1 when done, or -1 if size is too large. Each
+// call leaves ip->size covering the blocks still mapped, so a
+// crash in between leaves a consistent, partly truncated file.
+// Caller must hold ip->lock.
+int
+xv6itruncate(struct inode *ip, uint size, int budget)
+{
+  uint bs, bn, end, addr;
+  struct buf *bp;
+
+  bs = SB(ip->dev).bsize;
+  if(size > 0 && (size - 1)/bs >= MAXFILE(bs))
+    return -1;
+  if(size != ip->size)
+    ip->mtime = unixtime();
+
+  if(size > ip->size)
+    end = bn = 0;
+  else {
+    bn = (size + bs - 1) / bs;
+    end = bunmap(ip, bn, budget - 2);  // the inode and the tail block
+    if(end < (ip->size + bs - 1) / bs)
+      ip->size = end * bs;
+  }
+
+  if(end == bn){
+    // Zero the rest of the last block, which a later
+    // write past the end would otherwise expose.
+    if(size < ip->size && size % bs && (addr = bmap(ip, size / bs, 0))){
+      bp = bread(ip->dev, addr);
+      memset(bp->data + size % bs, 0, bs - size % bs);
+      log_write(bp);
+      brelse(bp);
     }
-    brelse(bp);
-    bfree(ip->dev, ip->addrs[NDIRECT]);
-    ip->addrs[NDIRECT] = 0;
+    ip->size = size;
   }
+  xv6iupdate(ip);
+  return end == bn;
+}
 
-  ip->size = 0;
-  iupdate(ip);
+// Allocate the blocks of ip holding the n bytes at off, so
+// that writing them later cannot run out of space, and grow
+// ip->size to cover them. Returns the number of bytes
+// covered, which is less than n if ip ran out of extents.
+// Caller must hold ip->lock.
+int
+xv6ifallocate(struct inode *ip, uint off, uint n)
+{
+  uint bn, last, end, bs;
+
+  bs = SB(ip->dev).bsize;
+  if(off + n < off)
+    return -1;
+  if(n == 0)
+    return 0;
+  last = (off + n - 1) / bs;
+  if(last >= MAXFILE(bs))
+    return -1;
+
+  for(bn = off / bs; bn <= last; bn++){
+    if(bmap(ip, bn, last - bn + 1) == 0)
+      break;
+  }
+  end = bn > last ? off + n : bn * bs;
+  if(end <= off)
+    return -1;
+  if(end > ip->size){
+    ip->size = end;
+    ip->mtime = unixtime();
+  }
+  xv6iupdate(ip);
+  return end - off;
 }
 
 // Copy stat information from inode.
@@ -444,70 +964,136 @@ stati(struct inode *ip, struct stat *st)
   st->type = ip->type;
   st->nlink = ip->nlink;
   st->size = ip->size;
+  st->blksize = vfsblksize(ip->dev);
+  st->atime = ip->atime;
+  st->mtime = ip->mtime;
+  st->ctime = ip->ctime;
+}
+
+// Start reading the blocks of ip from off on into the buffer
+// cache, at most MAXRUN and not past the end of the file, with
+// one disk request for each stretch of consecutive blocks.
+static void
+readahead(struct inode *ip, uint off)
+{
+  uint bn, end, addr, first, n, bs;
+
+  bs = SB(ip->dev).bsize;
+  bn = (off + bs - 1) / bs;
+  end = min(bn + MAXRUN, (ip->size + bs - 1) / bs);
+  first = n = 0;
+  for(; bn < end; bn++){
+    addr = bmap(ip, bn, 0);
+    if(n > 0 && addr == first + n){
+      n++;
+      continue;
+    }
+    if(n > 0)
+      breadahead(ip->dev, first, n);
+    first = addr;
+    n = addr != 0;
+  }
+  if(n > 0)
+    breadahead(ip->dev, first, n);
 }
 
 //PAGEBREAK!
 // Read data from inode.
+// Blocks never written (holes) read as zeroes.
+// The new access time is only written to disk along with
+// the next change to the inode.
 // Caller must hold ip->lock.
 int
-readi(struct inode *ip, char *dst, uint off, uint n)
+xv6readi(struct inode *ip, char *dst, uint off, uint n)
 {
-  uint tot, m;
-  struct buf *bp;
-
-  if(ip->type == T_DEV){
-    if(ip->major < 0 || ip->major >= NDEV || !devsw[ip->major].read)
-      return -1;
-    return devsw[ip->major].read(ip, dst, n);
-  }
+  uint tot, m, m1, addr, run, i, bs, start;
+  struct buf *bp[MAXRUN];
 
-  if(off > ip->size || off + n < off)
+  if(off + n < off)
     return -1;
+  if(off >= ip->size)
+    return 0;
   if(off + n > ip->size)
     n = ip->size - off;
+  ip->atime = unixtime();
 
+  bs = SB(ip->dev).bsize;
+  start = off;
   for(tot=0; tot<n; tot+=m, off+=m, dst+=m){
-    bp = bread(ip->dev, bmap(ip, off/BSIZE));
-    m = min(n - tot, BSIZE - off%BSIZE);
-    memmove(dst, bp->data + off%BSIZE, m);
-    brelse(bp);
+    run = 1;
+    if(SB(ip->dev).features & FS_EXTENTS)
+      addr = ebmap(ip, off/bs, 0, &run);
+    else
+      addr = bmap(ip, off/bs, 0);
+    if(addr == 0){
+      m = min(n - tot, bs - off%bs);
+      memset(dst, 0, m);
+      continue;
+    }
+
+    // Read as much of the rest as lies in consecutive
+    // blocks with a single disk request.
+    run = min(run, (off%bs + n - tot + bs - 1) / bs);
+    run = min(run, MAXRUN);
+    breadrun(ip->dev, addr, run, bp);
+    for(i = 0, m = 0; i < run; i++, m += m1){
+      m1 = min(n - tot - m, bs - (off + m)%bs);
+      memmove(dst + m, bp[i]->data + (off + m)%bs, m1);
+      brelse(bp[i]);
+    }
   }
+
+  // A read that starts where the last one stopped is likely
+  // part of a sequential scan: start reading what comes next.
+  if(start == ip->seqoff)
+    readahead(ip, off);
+  ip->seqoff = off;
   return n;
 }
 
 // PAGEBREAK!
 // Write data to inode.
+// Writing past the end of the file leaves a hole between
+// the old end and off; no blocks are allocated for it.
+// Stops early if the inode runs out of extents;
+// returns the number of bytes written, or -1 if none were.
 // Caller must hold ip->lock.
 int
-writei(struct inode *ip, char *src, uint off, uint n)
+xv6writei(struct inode *ip, char *src, uint off, uint n)
 {
-  uint tot, m;
+  uint tot, m, addr, bs;
   struct buf *bp;
 
-  if(ip->type == T_DEV){
-    if(ip->major < 0 || ip->major >= NDEV || !devsw[ip->major].write)
-      return -1;
-    return devsw[ip->major].write(ip, src, n);
-  }
-
-  if(off > ip->size || off + n < off)
+  bs = SB(ip->dev).bsize;
+  if(off + n < off)
     return -1;
-  if(off + n > MAXFILE*BSIZE)
+  if(n > 0 && (off + n - 1)/bs >= MAXFILE(bs))
     return -1;
 
   for(tot=0; tot<n; tot+=m, off+=m, src+=m){
-    bp = bread(ip->dev, bmap(ip, off/BSIZE));
-    m = min(n - tot, BSIZE - off%BSIZE);
-    memmove(bp->data + off%BSIZE, src, m);
+    addr = bmap(ip, off/bs, (off%bs + n - tot + bs - 1) / bs);
+    if(addr == 0)
+      break;
+    bp = bread(ip->dev, addr);
+    m = min(n - tot, bs - off%bs);
+    memmove(bp->data + off%bs, src, m);
     log_write(bp);
     brelse(bp);
   }
 
-  if(n > 0 && off > ip->size){
+  if(off > ip->size)
     ip->size = off;
-    iupdate(ip);
+
+  // Write the inode back even if the size didn't change,
+  // since bmap() may have filled a hole or added an extent,
+  // and the modification time has.
+  if(tot > 0){
+    ip->mtime = unixtime();
+    xv6iupdate(ip);
   }
-  return n;
+  if(tot == 0 && n > 0)
+    return -1;
+  return tot;
 }
 
 //PAGEBREAK!
@@ -516,54 +1102,404 @@ writei(struct inode *ip, char *src, uint off, uint n)
 int
 namecmp(const char *s, const char *t)
 {
-  return strncmp(s, t, DIRSIZ);
+  return strncmp(s, t, NAMEMAX);
+}
+
+// With FS_LONGNAMES, directories are read and written a block
+// at a time rather than with readi and writei, since entries
+// move between blocks when a hash bucket splits. Names hash
+// with dirhash(), the ondisk crate's (see fs.rs), so buckets
+// are where mkfs and fsck put them.
+#define RECEND(bp) ((bp)->size - DIRTAIL)
+#define REC(bp, o) ((struct dirrec*)((bp)->data + (o)))
+#define TAIL(bp) (*(uint*)((bp)->data + RECEND(bp)))
+
+// Deepest index that fits in a block.
+static uint
+dirmaxdepth(uint bs)
+{
+  uint d;
+
+  for(d = 0; sizeof(struct dirindex) + (4 << (d+1)) <= bs; d++)
+    ;
+  return d;
+}
+
+// Return a locked buf holding file block bn of directory dp.
+static struct buf*
+dirblock(struct inode *dp, uint bn)
+{
+  uint addr;
+
+  if((addr = bmap(dp, bn, 0)) == 0)
+    panic("dirblock");
+  return bread(dp->dev, addr);
+}
+
+// Offset of the record after the one at o.
+static uint
+recnext(struct buf *bp, uint o)
+{
+  struct dirrec *r;
+
+  r = REC(bp, o);
+  if(r->reclen < DIRRECLEN(r->namelen) || r->reclen % 4 ||
+     o + r->reclen > RECEND(bp))
+    panic("dirrec");
+  return o + r->reclen;
+}
+
+// Offset of the live record for name in bp, or -1.
+static int
+recfind(struct buf *bp, char *name, uint len)
+{
+  uint o;
+  struct dirrec *r;
+
+  for(o = 0; o < RECEND(bp); o = recnext(bp, o)){
+    r = REC(bp, o);
+    if(r->inum && r->namelen == len && memcmp(r+1, name, len) == 0)
+      return o;
+  }
+  return -1;
+}
+
+// Add an entry to bp, in a free record or in the slack
+// at the end of a live one. Returns -1 if there is no room.
+static int
+recadd(struct buf *bp, char *name, uint len, uint inum)
+{
+  uint o, used;
+  struct dirrec *r;
+
+  for(o = 0; o < RECEND(bp); o = recnext(bp, o)){
+    r = REC(bp, o);
+    used = r->inum ? DIRRECLEN(r->namelen) : 0;
+    if(r->reclen - used < DIRRECLEN(len))
+      continue;
+    if(used){
+      REC(bp, o + used)->reclen = r->reclen - used;
+      r->reclen = used;
+      r = REC(bp, o + used);
+    }
+    r->inum = inum;
+    r->namelen = len;
+    r->pad = 0;
+    memmove(r+1, name, len);
+    log_write(bp);
+    return 0;
+  }
+  return -1;
+}
+
+// Remove the record at off from bp, giving its space to
+// the record before it.
+static void
+recdel(struct buf *bp, uint off)
+{
+  uint o, prev;
+
+  prev = 0;
+  for(o = 0; o != off; o = recnext(bp, o)){
+    if(o > off)
+      panic("recdel");
+    prev = o;
+  }
+  if(off == 0)
+    REC(bp, 0)->inum = 0;
+  else
+    REC(bp, prev)->reclen += REC(bp, off)->reclen;
+  log_write(bp);
+}
+
+// Add an empty block to the end of dp.
+// Returns its file block number, or -1 if out of blocks.
+static int
+dirgrow(struct inode *dp)
+{
+  uint bn;
+  struct buf *bp;
+  struct dirrec *r;
+
+  bn = dp->size / SB(dp->dev).bsize;
+  if(bn >= MAXFILE(SB(dp->dev).bsize) || bmap(dp, bn, 1) == 0)
+    return -1;
+  bp = dirblock(dp, bn);
+  memset(bp->data, 0, SB(dp->dev).bsize);
+  r = REC(bp, 0);
+  r->reclen = RECEND(bp);
+  log_write(bp);
+  brelse(bp);
+  dp->size += SB(dp->dev).bsize;
+  xv6iupdate(dp);
+  return bn;
+}
+
+// First block of the bucket for hash h in dp's index,
+// or 0 if dp has none.
+static uint
+dirslot(struct inode *dp, uint h)
+{
+  uint bn;
+  struct buf *bp;
+  struct dirindex *x;
+
+  if(dp->size <= SB(dp->dev).bsize)
+    return 0;
+  bp = dirblock(dp, 0);
+  x = (struct dirindex*)bp->data;
+  bn = 0;
+  if(x->magic == DIRINDEXMAGIC){
+    if(x->depth > dirmaxdepth(SB(dp->dev).bsize))
+      panic("dirindex");
+    bn = ((uint*)(x+1))[h & ((1 << x->depth) - 1)];
+    if(bn == 0 || bn >= dp->size / SB(dp->dev).bsize)
+      panic("dirindex");
+  }
+  brelse(bp);
+  return bn;
+}
+
+// Turn dp's single full block into an index pointing
+// at a copy of it. Returns -1 if out of blocks.
+static int
+dirindexify(struct inode *dp)
+{
+  struct buf *bp0, *bp1;
+  struct dirindex *x;
+
+  if(dirgrow(dp) != 1)
+    return -1;
+  bp0 = dirblock(dp, 0);
+  bp1 = dirblock(dp, 1);
+  memmove(bp1->data, bp0->data, SB(dp->dev).bsize);
+  TAIL(bp1) = 0;
+  memset(bp0->data, 0, SB(dp->dev).bsize);
+  x = (struct dirindex*)bp0->data;
+  x->magic = DIRINDEXMAGIC;
+  x->depth = 0;
+  ((uint*)(x+1))[0] = 1;
+  log_write(bp0);
+  log_write(bp1);
+  brelse(bp0);
+  brelse(bp1);
+  return 0;
+}
+
+// Split bucket bn of dp's index, moving the entries whose hash
+// has the next distinguishing bit set into a new block. The
+// index doubles if only one slot points at bn.
+// Returns -1 if the index is as deep as it goes or out of blocks.
+static int
+dirsplit(struct inode *dp, uint bn)
+{
+  uint i, n, cnt, bit, o, next;
+  int nbn;
+  struct buf *bp0, *bp, *nbp;
+  struct dirindex *x;
+  struct dirrec *r;
+  uint *slot;
+
+  bp0 = dirblock(dp, 0);
+  x = (struct dirindex*)bp0->data;
+  slot = (uint*)(x+1);
+  n = 1 << x->depth;
+  for(cnt = 0, i = 0; i < n; i++)
+    if(slot[i] == bn)
+      cnt++;
+  if((cnt == 1 && x->depth == dirmaxdepth(SB(dp->dev).bsize)) ||
+     (nbn = dirgrow(dp)) < 0){
+    brelse(bp0);
+    return -1;
+  }
+  if(cnt == 1){
+    memmove(slot + n, slot, n * sizeof(uint));
+    x->depth++;
+    n *= 2;
+    cnt = 2;
+  }
+
+  // The cnt slots pointing at bn agree in their low bits up
+  // to bit; those with bit set move to the new block.
+  for(bit = n; cnt > 1; cnt /= 2)
+    bit /= 2;
+  for(i = 0; i < n; i++)
+    if(slot[i] == bn && (i & bit))
+      slot[i] = nbn;
+  log_write(bp0);
+  brelse(bp0);
+
+  bp = dirblock(dp, bn);
+  nbp = dirblock(dp, nbn);
+  for(o = 0; o < RECEND(bp); o = next){
+    next = recnext(bp, o);
+    r = REC(bp, o);
+    if(r->inum && (dirhash((char*)(r+1), r->namelen) & bit)){
+      if(recadd(nbp, (char*)(r+1), r->namelen, r->inum) < 0)
+        panic("dirsplit");
+      recdel(bp, o);
+    }
+  }
+  brelse(bp);
+  brelse(nbp);
+  return 0;
+}
+
+// Find name in dp, scanning its bucket if it has an index and
+// every block if not. Returns the inum, or 0 if absent.
+static uint
+dirfind(struct inode *dp, char *name, uint *poff)
+{
+  uint len, nb, bn, b, i, next, inum;
+  int o;
+  struct buf *bp;
+
+  len = strlen(name);
+  nb = dp->size / SB(dp->dev).bsize;
+  bn = dirslot(dp, dirhash(name, len));
+  for(i = 0; i < nb; i++){
+    b = bn ? bn : i;
+    bp = dirblock(dp, b);
+    if((o = recfind(bp, name, len)) >= 0){
+      inum = REC(bp, o)->inum;
+      brelse(bp);
+      if(poff)
+        *poff = b*SB(dp->dev).bsize + o;
+      return inum;
+    }
+    next = TAIL(bp);
+    brelse(bp);
+    if(bn){
+      if(next == 0)
+        break;
+      if(next >= nb)
+        panic("dirlookup chain");
+      bn = next;
+    }
+  }
+  return 0;
+}
+
+// Add (name, inum) to dp, which holds no entry for name.
+// Returns -1 if out of blocks.
+static int
+diradd(struct inode *dp, char *name, uint inum)
+{
+  uint len, h, nb, bn, b, i, split;
+  int last;
+  struct buf *bp;
+
+  len = strlen(name);
+  h = dirhash(name, len);
+  nb = dp->size / SB(dp->dev).bsize;
+  if(dirslot(dp, h) == 0){
+    for(i = 0; i < nb; i++){
+      bp = dirblock(dp, i);
+      if(recadd(bp, name, len, inum) == 0){
+        brelse(bp);
+        return 0;
+      }
+      brelse(bp);
+    }
+    // Index a directory as it outgrows its first block.
+    if(nb != 1){
+      if((last = dirgrow(dp)) < 0)
+        return -1;
+      bp = dirblock(dp, last);
+      recadd(bp, name, len, inum);
+      brelse(bp);
+      return 0;
+    }
+    if(dirindexify(dp) < 0)
+      return -1;
+  }
+
+  // Try each block of the bucket; split a full bucket once,
+  // or chain another block to it if it won't split.
+  for(split = 0;; split++){
+    bn = dirslot(dp, h);
+    nb = dp->size / SB(dp->dev).bsize;
+    for(i = 0, b = bn; i < nb; i++){
+      bp = dirblock(dp, b);
+      if(recadd(bp, name, len, inum) == 0){
+        brelse(bp);
+        return 0;
+      }
+      if(TAIL(bp) == 0)
+        break;
+      b = TAIL(bp);
+      brelse(bp);
+    }
+    if(i == nb)
+      panic("dirlink chain");
+    if(i == 0 && split == 0){
+      brelse(bp);
+      if(dirsplit(dp, bn) == 0)
+        continue;
+      bp = dirblock(dp, b);
+    }
+    if((last = dirgrow(dp)) < 0){
+      brelse(bp);
+      return -1;
+    }
+    TAIL(bp) = last;
+    log_write(bp);
+    brelse(bp);
+    bp = dirblock(dp, last);
+    recadd(bp, name, len, inum);
+    brelse(bp);
+    return 0;
+  }
 }
 
 // Look for a directory entry in a directory.
-// If found, set *poff to byte offset of entry.
-struct inode*
-dirlookup(struct inode *dp, char *name, uint *poff)
+// If found, set *poff to byte offset of entry
+// and return its inode number; otherwise return 0.
+uint
+xv6dirlookup(struct inode *dp, char *name, uint *poff)
 {
-  uint off, inum;
+  uint off;
   struct dirent de;
 
-  if(dp->type != T_DIR)
-    panic("dirlookup not DIR");
+  if(SB(dp->dev).features & FS_LONGNAMES)
+    return dirfind(dp, name, poff);
 
   for(off = 0; off < dp->size; off += sizeof(de)){
-    if(readi(dp, (char*)&de, off, sizeof(de)) != sizeof(de))
+    if(xv6readi(dp, (char*)&de, off, sizeof(de)) != sizeof(de))
       panic("dirlookup read");
     if(de.inum == 0)
       continue;
-    if(namecmp(name, de.name) == 0){
+    if(strncmp(name, de.name, DIRSIZ) == 0){
       // entry matches path element
       if(poff)
         *poff = off;
-      inum = de.inum;
-      return iget(dp->dev, inum);
+      return de.inum;
     }
   }
 
   return 0;
 }
 
-// Write a new directory entry (name, inum) into the directory dp.
+// Write a new directory entry (name, inum) into the directory dp,
+// which does not hold name yet. Returns -1 if dp cannot grow to
+// hold it.
 int
-dirlink(struct inode *dp, char *name, uint inum)
+xv6dirlink(struct inode *dp, char *name, uint inum)
 {
   int off;
   struct dirent de;
-  struct inode *ip;
 
-  // Check that name is not present.
-  if((ip = dirlookup(dp, name, 0)) != 0){
-    iput(ip);
-    return -1;
+  if(SB(dp->dev).features & FS_LONGNAMES){
+    if(diradd(dp, name, inum) < 0)
+      return -1;
+    dp->mtime = unixtime();
+    xv6iupdate(dp);
+    return 0;
   }
 
   // Look for an empty dirent.
   for(off = 0; off < dp->size; off += sizeof(de)){
-    if(readi(dp, (char*)&de, off, sizeof(de)) != sizeof(de))
+    if(xv6readi(dp, (char*)&de, off, sizeof(de)) != sizeof(de))
       panic("dirlink read");
     if(de.inum == 0)
       break;
@@ -571,12 +1507,201 @@ dirlink(struct inode *dp, char *name, uint inum)
 
   strncpy(de.name, name, DIRSIZ);
   de.inum = inum;
-  if(writei(dp, (char*)&de, off, sizeof(de)) != sizeof(de))
-    panic("dirlink");
+  // A dirent never straddles a block, so a short write wrote
+  // nothing.
+  if(xv6writei(dp, (char*)&de, off, sizeof(de)) != sizeof(de))
+    return -1;
 
   return 0;
 }
 
+// Remove the entry at byte offset off, found by dirlookup,
+// from directory dp.
+void
+xv6dirremove(struct inode *dp, uint off)
+{
+  struct dirent de;
+  struct buf *bp;
+
+  if(SB(dp->dev).features & FS_LONGNAMES){
+    bp = dirblock(dp, off / SB(dp->dev).bsize);
+    recdel(bp, off % SB(dp->dev).bsize);
+    brelse(bp);
+    dp->mtime = unixtime();
+    xv6iupdate(dp);
+    return;
+  }
+  memset(&de, 0, sizeof(de));
+  if(xv6writei(dp, (char*)&de, off, sizeof(de)) != sizeof(de))
+    panic("dirremove");
+}
+
+// Point the entry at byte offset off in directory dp at inum.
+void
+xv6dirset(struct inode *dp, uint off, uint inum)
+{
+  struct dirent de;
+  struct buf *bp;
+
+  if(SB(dp->dev).features & FS_LONGNAMES){
+    bp = dirblock(dp, off / SB(dp->dev).bsize);
+    REC(bp, off % SB(dp->dev).bsize)->inum = inum;
+    log_write(bp);
+    brelse(bp);
+    dp->mtime = unixtime();
+    xv6iupdate(dp);
+    return;
+  }
+  if(xv6readi(dp, (char*)&de, off, sizeof(de)) != sizeof(de))
+    panic("dirset");
+  de.inum = inum;
+  if(xv6writei(dp, (char*)&de, off, sizeof(de)) != sizeof(de))
+    panic("dirset");
+}
+
+// Find the first live entry of directory dp at or after byte
+// offset off, copying its name, which has room for NAMEMAX+1
+// bytes, and setting *inum. Returns the offset just past it.
+// At the end of dp, *inum is 0.
+uint
+xv6direntat(struct inode *dp, uint off, uint *inum, char *name)
+{
+  uint bn, o, len;
+  struct dirent de;
+  struct buf *bp;
+  struct dirrec *r;
+
+  *inum = 0;
+  if(!(SB(dp->dev).features & FS_LONGNAMES)){
+    for(off -= off % sizeof(de); off < dp->size; off += sizeof(de)){
+      if(xv6readi(dp, (char*)&de, off, sizeof(de)) != sizeof(de))
+        panic("direntat read");
+      if(de.inum == 0)
+        continue;
+      for(len = 0; len < DIRSIZ && de.name[len]; len++)
+        ;
+      memmove(name, de.name, len);
+      name[len] = 0;
+      *inum = de.inum;
+      return off + sizeof(de);
+    }
+    return dp->size;
+  }
+
+  for(; off < dp->size; off = (bn+1) * SB(dp->dev).bsize){
+    bn = off / SB(dp->dev).bsize;
+    if(bn == 0 && dirslot(dp, 0) != 0)
+      continue;
+    bp = dirblock(dp, bn);
+    for(o = 0; o < RECEND(bp); o = recnext(bp, o)){
+      r = REC(bp, o);
+      if(o < off % SB(dp->dev).bsize || r->inum == 0)
+        continue;
+      memmove(name, r+1, r->namelen);
+      name[r->namelen] = 0;
+      *inum = r->inum;
+      off = bn*SB(dp->dev).bsize + recnext(bp, o);
+      brelse(bp);
+      if(off % SB(dp->dev).bsize == SB(dp->dev).bsize - DIRTAIL)
+        off += DIRTAIL;
+      return off;
+    }
+    brelse(bp);
+  }
+  return dp->size;
+}
+
+// Look for a directory entry in a directory, on any file system.
+// If found, set *poff to byte offset of entry.
+struct inode*
+dirlookup(struct inode *dp, char *name, uint *poff)
+{
+  uint inum;
+
+  if(dp->type != T_DIR)
+    panic("dirlookup not DIR");
+  if((inum = vfslookup(dp, name, poff)) == 0)
+    return 0;
+  return iget(dp->dev, inum);
+}
+
+// Fill dst, which has room for n bytes, with getdents records for
+// the entries of directory dp starting at byte offset *poff, and
+// advance *poff past them. dp must not be locked, and the caller
+// must be inside a transaction, since the entry's inode may be
+// unlinked while we hold the last reference to it.
+// Returns the number of bytes filled; -1 if dp is not a
+// directory or the next record does not fit.
+int
+dirread(struct inode *dp, uint *poff, char *dst, int n)
+{
+  int tot, len, reclen;
+  uint inum, next;
+  char name[NAMEMAX+1];
+  struct dentry *d;
+  struct inode *ip;
+
+  for(tot = 0; ; *poff = next){
+    ilock(dp);
+    if(dp->type != T_DIR){
+      iunlock(dp);
+      return -1;
+    }
+    next = direntat(dp, *poff, &inum, name);
+    if(inum == 0){
+      *poff = next;
+      iunlock(dp);
+      break;
+    }
+    len = strlen(name);
+    reclen = DENTRYLEN(len);
+    if(tot + reclen > n){
+      iunlock(dp);
+      if(tot == 0)
+        return -1;
+      break;
+    }
+    // Take a reference while the entry is known to be good,
+    // but lock the inode only after releasing dp: ".." is
+    // dp's parent, and parents are locked before children.
+    ip = iget(dp->dev, inum);
+    iunlock(dp);
+
+    ilock(ip);
+    d = (struct dentry*)(dst + tot);
+    d->ino = inum;
+    d->reclen = reclen;
+    d->type = ip->type;
+    memmove(d->name, name, len + 1);
+    iunlockput(ip);
+    tot += reclen;
+  }
+  return tot;
+}
+
+// Is directory a the same as dp or one of its ancestors?
+// Follows ".." up from dp to the root of its file system, locking
+// each directory in turn, so the caller must hold renamelock and
+// no inode locks.
+int
+isancestor(struct inode *a, struct inode *dp)
+{
+  struct inode *ip, *next;
+
+  ip = idup(dp);
+  while(ip != a){
+    ilock(ip);
+    if(ip->inum == vfsroot(ip->dev) || (next = dirlookup(ip, "..", 0)) == 0){
+      iunlockput(ip);
+      return 0;
+    }
+    iunlockput(ip);
+    ip = next;
+  }
+  iput(ip);
+  return 1;
+}
+
 //PAGEBREAK!
 // Paths
 
@@ -606,47 +1731,107 @@ skipelem(char *path, char *name)
   while(*path != '/' && *path != 0)
     path++;
   len = path - s;
-  if(len >= DIRSIZ)
-    memmove(name, s, DIRSIZ);
-  else {
-    memmove(name, s, len);
-    name[len] = 0;
-  }
+  if(len > NAMEMAX)
+    len = NAMEMAX;
+  memmove(name, s, len);
+  name[len] = 0;
   while(*path == '/')
     path++;
   return path;
 }
 
+// Replace the symbolic link ip in a path with its target: put the
+// target, a slash and the rest of the path in buf, which has room
+// for MAXPATH bytes and may already hold rest.
+// Returns the new path, or 0 if it does not fit.
+// Caller must hold ip->lock.
+static char*
+linkpath(struct inode *ip, char *rest, char *buf)
+{
+  uint n, len;
+
+  n = ip->size;
+  len = strlen(rest);
+  if(n == 0 || n + 1 + len >= MAXPATH)
+    return 0;
+  memmove(buf + n + 1, rest, len + 1);
+  buf[n] = '/';
+  if(readi(ip, buf, 0, n) != n)
+    return 0;
+  return buf;
+}
+
 // Look up and return the inode for a path name.
 // If parent != 0, return the inode for the parent and copy the final
-// path element into name, which must have room for DIRSIZ bytes.
+// path element into name, which must have room for NAMEMAX+1 bytes.
+// Symbolic links met along the way are followed, up to MAXSYMLINKS
+// of them; one in the final element only if follow is set, and
+// then the parent returned is that of the link's target.
 // Must be called inside a transaction since it calls iput().
 static struct inode*
-namex(char *path, int nameiparent, char *name)
+namex(char *path, int nameiparent, int follow, char *name)
 {
   struct inode *ip, *next;
+  char buf[MAXPATH];
+  int nlinks, last;
 
   if(*path == '/')
     ip = iget(ROOTDEV, ROOTINO);
   else
     ip = idup(myproc()->cwd);
 
+  nlinks = 0;
   while((path = skipelem(path, name)) != 0){
+    // ".." of the root of a mounted file system is looked
+    // up in the directory it is mounted on.
+    if(namecmp(name, "..") == 0)
+      ip = vfsleave(ip);
     ilock(ip);
     if(ip->type != T_DIR){
       iunlockput(ip);
       return 0;
     }
-    if(nameiparent && *path == '\0'){
+    last = *path == '\0';
+    if(nameiparent && last && !follow){
       // Stop one level early.
       iunlock(ip);
       return ip;
     }
     if((next = dirlookup(ip, name, 0)) == 0){
+      if(nameiparent && last){
+        // No link to follow; ip is the parent.
+        iunlock(ip);
+        return ip;
+      }
       iunlockput(ip);
       return 0;
     }
-    iunlockput(ip);
+    iunlock(ip);
+
+    // Step onto the root of a file system mounted on next.
+    next = vfsenter(next);
+    ilock(next);
+    if(next->type == T_SYMLINK && (!last || follow)){
+      // Go on from the directory holding the link, or
+      // from the root if the target is absolute.
+      if(++nlinks > MAXSYMLINKS || (path = linkpath(next, path, buf)) == 0){
+        iunlockput(next);
+        iput(ip);
+        return 0;
+      }
+      iunlockput(next);
+      if(*path == '/'){
+        iput(ip);
+        ip = iget(ROOTDEV, ROOTINO);
+      }
+      continue;
+    }
+    if(nameiparent && last){
+      iunlockput(next);
+      return ip;
+    }
+    iunlock(next);
+    iput(ip);
     ip = next;
   }
   if(nameiparent){
@@ -659,12 +1844,29 @@ namex(char *path, int nameiparent, char *name)
 struct inode*
 namei(char *path)
 {
-  char name[DIRSIZ];
-  return namex(path, 0, name);
+  char name[NAMEMAX+1];
+  return namex(path, 0, 1, name);
+}
+
+// Like namei, but a symbolic link as the last path
+// element is returned rather than followed.
+struct inode*
+lnamei(char *path)
+{
+  char name[NAMEMAX+1];
+  return namex(path, 0, 0, name);
 }
 
 struct inode*
 nameiparent(char *path, char *name)
 {
-  return namex(path, 1, name);
+  return namex(path, 1, 0, name);
+}
+
+// Like nameiparent, but a symbolic link as the last path
+// element is followed, and the parent of its target returned.
+struct inode*
+linkparent(char *path, char *name)
+{
+  return namex(path, 1, 1, name);
 }
@@ -5,6 +5,8 @@
 #include "sleeplock.h"
 #include "fs.h"
 #include "buf.h"
+#include "x86.h"
+#include "crash.h"
 
 // Simple logging that allows concurrent FS system calls.
 //
@@ -20,49 +22,116 @@
 // But if it thinks the log is close to running out, it
 // sleeps until the last outstanding end_op() commits.
 //
+// end_op() does not wait for the disk: operations collect in
+// the log until it is nearly full, until logflush() asks for
+// a commit (sync, and fsync on the root disk, do), or until
+// the logflush kernel process finds them COMMITTICKS old. A
+// crash loses whole operations that were not yet committed,
+// never part of one.
+//
 // The log is a physical re-do log containing disk blocks.
 // The on-disk log format:
-//   header block, containing block #s for block A, B, C, ...
+//   header blocks, containing block #s for block A, B, C, ...
 //   block A
 //   block B
 //   block C
 //   ...
-// Log appends are synchronous.
+// Log appends are synchronous. Each commit gets the next
+// sequence number, and checksums over the header and the
+// logged blocks let recovery refuse a torn transaction (see
+// fs.h).
+//
+// To test recovery, a kernel built with CRASHTEST has crashat()
+// to arm a crash point: commit() then halts the machine at that point of a chosen commit,
+// leaving the disk as a power failure there would.
 
-// Contents of the header block, used for both the on-disk header block
-// and to keep track in memory of logged block# before commit.
+// Contents of the header blocks: a struct loghead and its
+// struct logblocks. Used for both the on-disk header and to keep
+// track in memory of logged block# before commit.
 struct logheader {
-  int n;
-  int block[LOGSIZE];
+  uint magic;
+  uint seq;
+  uint n;
+  uint sum;
+  struct logblock block[LOGSIZE];
 };
 
+// Bytes of the header when it describes n blocks.
+#define HEADSIZE(n) (sizeof(struct loghead) + (n)*sizeof(struct logblock))
+
 struct log {
   struct spinlock lock;
-  int start;
-  int size;
+  int start;       // first header block
+  int nhead;       // number of header blocks
+  int size;        // number of blocks the log can hold
   int outstanding; // how many FS sys calls are executing.
   int committing;  // in commit(), please wait.
+  int flush;       // logflush() wants a commit; begin_op() waits.
+  uint commits;    // number of commits finished
   int dev;
   struct logheader lh;
 };
 struct log log;
 
+#ifdef CRASHTEST
+// The armed crash point: halt at point during commit number
+// commit, once nblock blocks are installed for CRASH_INSTALL.
+static struct {
+  int point;
+  uint commit;
+  int nblock;
+} crash;
+
+static void crashcheck(int point, int nblock, char *where);
+#else
+#define crashcheck(point, nblock, where)
+#endif
+
+extern uint TICKS;
+extern struct spinlock TICKSLOCK;
+
+static uint crctab[256];
+
 static void recover_from_log(void);
 static void commit();
+static void logflusher(void);
+
+// CRC-32 of the n bytes at p, continuing from crc.
+static uint
+crc32(uint crc, uchar *p, uint n)
+{
+  crc = ~crc;
+  while(n-- > 0)
+    crc = crctab[(crc ^ *p++) & 0xff] ^ (crc >> 8);
+  return ~crc;
+}
 
 void
 initlog(int dev)
 {
-  if (sizeof(struct logheader) >= BSIZE)
-    panic("initlog: too big logheader");
-
   struct superblock sb;
+  uint c;
+  int i, k;
+
+  for(i = 0; i < 256; i++){
+    c = i;
+    for(k = 0; k < 8; k++)
+      c = c & 1 ? 0xedb88320 ^ (c >> 1) : c >> 1;
+    crctab[i] = c;
+  }
+
   initlock(&log.lock, "log");
   readsb(dev, &sb);
   log.start = sb.logstart;
-  log.size = sb.nlog;
+  log.nhead = LOGHDRBLOCKS(sb.nlog, sb.bsize);
+  log.size = sb.nlog - log.nhead;
+  // mkfs makes logs of MAXOPBLOCKS to LOGSIZE blocks, all the
+  // in-memory header and buffer cache hold.
+  if(log.size < MAXOPBLOCKS || log.size > LOGSIZE)
+    panic("initlog: bad log size");
   log.dev = dev;
   recover_from_log();
+  kproc("logflush", logflusher);
 }
 
 // Copy committed blocks from log to their home location
@@ -72,27 +141,72 @@ install_trans(void)
   int tail;
 
   for (tail = 0; tail < log.lh.n; tail++) {
-    struct buf *lbuf = bread(log.dev, log.start+tail+1); // read log block
-    struct buf *dbuf = bread(log.dev, log.lh.block[tail]); // read dst
-    memmove(dbuf->data, lbuf->data, BSIZE);  // copy block to dst
+    struct buf *lbuf = bread(log.dev, log.start+log.nhead+tail); // read log block
+    struct buf *dbuf = bread(log.dev, log.lh.block[tail].blockno); // read dst
+    memmove(dbuf->data, lbuf->data, lbuf->size);  // copy block to dst
     bwrite(dbuf);  // write dst to disk
     brelse(lbuf);
     brelse(dbuf);
+    crashcheck(CRASH_INSTALL, tail + 1, "during install_trans");
   }
 }
 
-// Read the log header from disk into the in-memory log header
+// Copy the first n bytes of the in-memory log header to or
+// from the header blocks.
 static void
-read_head(void)
+rwhead(uint n, int write)
 {
-  struct buf *buf = bread(log.dev, log.start);
-  struct logheader *lh = (struct logheader *) (buf->data);
+  struct buf *buf;
+  uint off, m;
   int i;
-  log.lh.n = lh->n;
+
+  for (i = 0, off = 0; off < n; i++, off += m) {
+    buf = bread(log.dev, log.start+i);
+    m = n - off < buf->size ? n - off : buf->size;
+    if (write) {
+      memmove(buf->data, (char*)&log.lh + off, m);
+      bwrite(buf);
+    } else
+      memmove((char*)&log.lh + off, buf->data, m);
+    brelse(buf);
+  }
+}
+
+// Read the log header from disk into the in-memory log header.
+// Returns 1 if the log holds a whole committed transaction,
+// checking the header and every logged block against their
+// checksums.
+static int
+read_head(void)
+{
+  struct buf *buf;
+  uint sum;
+  int i, ok;
+
+  rwhead(HEADSIZE(0), 0);
+  if (log.lh.magic != LOGMAGIC)
+    return 0;  // never written
+  if (log.lh.n > log.size) {
+    cprintf("log: transaction %d too big, not recovered\n", log.lh.seq);
+    return 0;
+  }
+  rwhead(HEADSIZE(log.lh.n), 0);
+  sum = log.lh.sum;
+  log.lh.sum = 0;
+  if (crc32(0, (uchar*)&log.lh, HEADSIZE(log.lh.n)) != sum) {
+    cprintf("log: torn header, transaction %d not recovered\n", log.lh.seq);
+    return 0;
+  }
   for (i = 0; i < log.lh.n; i++) {
-    log.lh.block[i] = lh->block[i];
+    buf = bread(log.dev, log.start+log.nhead+i);
+    ok = crc32(log.lh.seq, buf->data, buf->size) == log.lh.block[i].sum;
+    brelse(buf);
+    if (!ok) {
+      cprintf("log: corrupt block, transaction %d not recovered\n", log.lh.seq);
+      return 0;
+    }
   }
-  brelse(buf);
+  return 1;
 }
 
 // Write in-memory log header to disk.
@@ -101,22 +215,17 @@ read_head(void)
 static void
 write_head(void)
 {
-  struct buf *buf = bread(log.dev, log.start);
-  struct logheader *hb = (struct logheader *) (buf->data);
-  int i;
-  hb->n = log.lh.n;
-  for (i = 0; i < log.lh.n; i++) {
-    hb->block[i] = log.lh.block[i];
-  }
-  bwrite(buf);
-  brelse(buf);
+  log.lh.magic = LOGMAGIC;
+  log.lh.sum = 0;
+  log.lh.sum = crc32(0, (uchar*)&log.lh, HEADSIZE(log.lh.n));
+  rwhead(HEADSIZE(log.lh.n), 1);
 }
 
 static void
 recover_from_log(void)
 {
-  read_head();
-  install_trans(); // if committed, copy from log to disk
+  if (read_head())
+    install_trans(); // if committed, copy from log to disk
   log.lh.n = 0;
   write_head(); // clear the log
 }
@@ -127,9 +236,9 @@ begin_op(void)
 {
   acquire(&log.lock);
   while(1){
-    if(log.committing){
+    if(log.committing || log.flush){
       sleep(&log, &log.lock);
-    } else if(log.lh.n + (log.outstanding+1)*MAXOPBLOCKS > LOGSIZE){
+    } else if(log.lh.n + (log.outstanding+1)*MAXOPBLOCKS > log.size){
       // this op might exhaust log space; wait for commit.
       sleep(&log, &log.lock);
     } else {
@@ -140,20 +249,36 @@ begin_op(void)
   }
 }
 
+// Commit with log.lock held and no operation outstanding.
+static void
+commitlocked(void)
+{
+  log.committing = 1;
+  log.flush = 0;
+  // call commit w/o holding locks, since not allowed
+  // to sleep with locks.
+  release(&log.lock);
+  commit();
+  acquire(&log.lock);
+  log.committing = 0;
+  log.commits++;
+  wakeup(&log);
+}
+
 // called at the end of each FS system call.
-// commits if this was the last outstanding operation.
+// commits if this was the last outstanding operation
+// and the log has no room for another, or logflush()
+// is waiting.
 void
 end_op(void)
 {
-  int do_commit = 0;
-
   acquire(&log.lock);
   log.outstanding -= 1;
   if(log.committing)
     panic("log.committing");
-  if(log.outstanding == 0){
-    do_commit = 1;
-    log.committing = 1;
+  if(log.outstanding == 0 &&
+     (log.flush || log.lh.n + MAXOPBLOCKS > log.size)){
+    commitlocked();
   } else {
     // begin_op() may be waiting for log space,
     // and decrementing log.outstanding has decreased
@@ -161,15 +286,49 @@ end_op(void)
     wakeup(&log);
   }
   release(&log.lock);
+}
 
-  if(do_commit){
-    // call commit w/o holding locks, since not allowed
-    // to sleep with locks.
-    commit();
-    acquire(&log.lock);
-    log.committing = 0;
-    wakeup(&log);
+// Commit every operation that has finished, and wait
+// until they are on disk.
+void
+logflush(void)
+{
+  uint target;
+
+  acquire(&log.lock);
+  if(log.committing){
+    // The commit under way holds every finished operation.
+    target = log.commits + 1;
+  } else if(log.lh.n == 0){
     release(&log.lock);
+    return;
+  } else {
+    target = log.commits + 1;
+    if(log.outstanding == 0)
+      commitlocked();
+    else
+      log.flush = 1;  // the last end_op() commits
+  }
+  while(log.commits < target)
+    sleep(&log, &log.lock);
+  release(&log.lock);
+}
+
+// The logflush kernel process: commits the log every
+// COMMITTICKS, so operations reach the disk before long
+// even if nothing fills the log or calls logflush().
+static void
+logflusher(void)
+{
+  uint ticks0;
+
+  for(;;){
+    acquire(&TICKSLOCK);
+    ticks0 = TICKS;
+    while(TICKS - ticks0 < COMMITTICKS)
+      sleep(&TICKS, &TICKSLOCK);
+    release(&TICKSLOCK);
+    logflush();
   }
 }
 
@@ -180,9 +339,10 @@ write_log(void)
   int tail;
 
   for (tail = 0; tail < log.lh.n; tail++) {
-    struct buf *to = bread(log.dev, log.start+tail+1); // log block
-    struct buf *from = bread(log.dev, log.lh.block[tail]); // cache block
-    memmove(to->data, from->data, BSIZE);
+    struct buf *to = bread(log.dev, log.start+log.nhead+tail); // log block
+    struct buf *from = bread(log.dev, log.lh.block[tail].blockno); // cache block
+    memmove(to->data, from->data, from->size);
+    log.lh.block[tail].sum = crc32(log.lh.seq, to->data, to->size);
     bwrite(to);  // write the log
     brelse(from);
     brelse(to);
@@ -193,14 +353,68 @@ static void
 commit()
 {
   if (log.lh.n > 0) {
+    log.lh.seq++;
     write_log();     // Write modified blocks from cache to log
+    crashcheck(CRASH_LOGGED, 0, "after write_log");
     write_head();    // Write header to disk -- the real commit
+    crashcheck(CRASH_COMMITTED, 0, "after write_head");
     install_trans(); // Now install writes to home locations
     log.lh.n = 0;
     write_head();    // Erase the transaction from the log
   }
 }
 
+#ifdef CRASHTEST
+// Stop the machine dead, as a power failure would: no disk
+// write follows. QEMU powers off; elsewhere the CPU spins, and
+// the other CPUs find the log busy.
+static void
+crashhalt(char *where)
+{
+  cprintf("crash: halted %s\n", where);
+  cli();
+  outw(0x604, 0x2000);  // QEMU's ACPI power-off
+  for(;;)
+    hlt();
+}
+
+// Halt if crashat() armed point for the commit under way,
+// with nblock blocks installed.
+static void
+crashcheck(int point, int nblock, char *where)
+{
+  if (crash.point == point && crash.nblock == nblock &&
+      log.commits + 1 == crash.commit)
+    crashhalt(where);
+}
+
+// Arm crash point point for the n'th commit from now, and
+// for CRASH_INSTALL after it installs nblock blocks. CRASH_NOW
+// halts at once, but not in the middle of a commit.
+int
+crashat(int point, int n, int nblock)
+{
+  if (point < CRASH_NONE || point > CRASH_NOW)
+    return -1;
+  if ((point == CRASH_LOGGED || point == CRASH_COMMITTED) && n < 1)
+    return -1;
+  if (point == CRASH_INSTALL && (n < 1 || nblock < 1))
+    return -1;
+
+  acquire(&log.lock);
+  if (point == CRASH_NOW) {
+    while (log.committing)
+      sleep(&log, &log.lock);
+    crashhalt("between commits");
+  }
+  crash.point = point;
+  crash.commit = log.commits + n;
+  crash.nblock = point == CRASH_INSTALL ? nblock : 0;
+  release(&log.lock);
+  return 0;
+}
+#endif
+
 // Caller has modified b->data and is done with the buffer.
 // Record the block number and pin in the cache with B_DIRTY.
 // commit()/write_log() will do the disk write.
@@ -210,22 +424,26 @@ commit()
 //   modify bp->data[]
 //   log_write(bp)
 //   brelse(bp)
+// Only the root disk has a log, so other xv6 disks mount
+// read-only (fs.rs) and never get here.
 void
 log_write(struct buf *b)
 {
   int i;
 
-  if (log.lh.n >= LOGSIZE || log.lh.n >= log.size - 1)
+  if (b->dev != log.dev)
+    panic("log_write: no log on this disk");
+  if (log.lh.n >= log.size)
     panic("too big a transaction");
   if (log.outstanding < 1)
     panic("log_write outside of trans");
 
   acquire(&log.lock);
   for (i = 0; i < log.lh.n; i++) {
-    if (log.lh.block[i] == b->blockno)   // log absorbtion
+    if (log.lh.block[i].blockno == b->blockno)   // log absorbtion
       break;
   }
-  log.lh.block[i] = b->blockno;
+  log.lh.block[i].blockno = b->blockno;
   if (i == log.lh.n)
     log.lh.n++;
   b->flags |= B_DIRTY; // prevent eviction
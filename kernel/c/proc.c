@@ -6,6 +6,7 @@
 #include "x86.h"
 #include "proc.h"
 #include "spinlock.h"
+#include "syscall.h"
 
 struct {
   struct spinlock lock;
@@ -36,6 +37,7 @@ allocproc(void)
 {
   struct proc *p;
   char *sp;
+  int i;
 
   acquire(&ptable.lock);
 
@@ -50,6 +52,13 @@ found:
   p->state = EMBRYO;
   p->pid = nextpid++;
 
+  // New processes may make every system call until restrict() says otherwise.
+  for(i = 0; i < NSYSMASK; i++)
+    p->sysallow[i] = ~0;
+  p->sysmode = RESTRICT_EPERM;
+
+  fdtabinit(&p->fdt);
+
   release(&ptable.lock);
 
   // Allocate kernel stack.
@@ -115,6 +124,25 @@ userinit(void)
   release(&ptable.lock);
 }
 
+// Start a kernel process that runs fn, which never returns.
+// It has no user memory and no parent.
+void
+kproc(char *name, void (*fn)(void))
+{
+  struct proc *p;
+
+  if((p = allocproc()) == 0 || (p->pgdir = setupkvm()) == 0)
+    panic("kproc");
+
+  // forkret returns to fn instead of trapret.
+  *(uint*)(p->context + 1) = (uint)fn;
+  safestrcpy(p->name, name, sizeof(p->name));
+
+  acquire(&ptable.lock);
+  p->state = RUNNABLE;
+  release(&ptable.lock);
+}
+
 // Create a new process copying p as the parent.
 // Sets up stack to return as if from system call.
 // Caller must set state of returned proc to RUNNABLE.
@@ -144,11 +172,20 @@ fork(void)
   // Clear %eax so that fork returns 0 in the child.
   np->tf->eax = 0;
 
-  for(i = 0; i < NOFILE; i++)
-    if(curproc->ofile[i])
-      np->ofile[i] = filedup(curproc->ofile[i]);
+  if(fdtabcopy(&np->fdt, &curproc->fdt) < 0){
+    freevm(np->pgdir);
+    kfree(np->kstack);
+    np->kstack = 0;
+    np->state = UNUSED;
+    return -1;
+  }
   np->cwd = idup(curproc->cwd);
 
+  // The child inherits the syscall allowlist; exec leaves it alone too.
+  for(i = 0; i < NSYSMASK; i++)
+    np->sysallow[i] = curproc->sysallow[i];
+  np->sysmode = curproc->sysmode;
+
   safestrcpy(np->name, curproc->name, sizeof(curproc->name));
 
   pid = np->pid;
@@ -170,18 +207,12 @@ exit(void)
 {
   struct proc *curproc = myproc();
   struct proc *p;
-  int fd;
 
   if(curproc == initproc)
     panic("init exiting");
 
   // Close all open files.
-  for(fd = 0; fd < NOFILE; fd++){
-    if(curproc->ofile[fd]){
-      fileclose(curproc->ofile[fd]);
-      curproc->ofile[fd] = 0;
-    }
-  }
+  fdtabclose(&curproc->fdt);
 
   begin_op();
   iput(curproc->cwd);
@@ -322,6 +353,7 @@ forkret(void)
     first = 0;
     iinit(ROOTDEV);
     initlog(ROOTDEV);
+    kproc("bflush", bflusher);
   }
 
   // Return to "caller", actually trapret (see allocproc).
@@ -34,6 +34,16 @@ struct context {
 
 enum procstate { UNUSED, EMBRYO, SLEEPING, RUNNABLE, RUNNING, ZOMBIE };
 
+// Per-process file descriptor table, managed by fdtable.rs.
+// Holds NOFILE descriptors until a process needs more, then
+// moves to a page of NOFILEMAX.
+struct fdtable {
+  uint nfd;                    // Number of usable descriptors
+  struct fdpage *big;          // Descriptors once past NOFILE, or 0
+  struct file *ofile[NOFILE];  // Open files while big is 0
+  char flags[NOFILE];          // FD_CLOEXEC per descriptor while big is 0
+};
+
 // Per-process state
 struct proc {
   uint sz;                     // Size of process memory (bytes)
@@ -46,9 +56,11 @@ struct proc {
   struct context *context;     // swtch() here to run process
   void *chan;                  // If non-zero, sleeping on chan
   int killed;                  // If non-zero, have been killed
-  struct file *ofile[NOFILE];  // Open files
+  struct fdtable fdt;          // Open files
   struct inode *cwd;           // Current directory
   char name[16];               // Process name (debugging)
+  uint sysallow[NSYSMASK];     // Allowed syscalls, bit n = syscall n
+  int sysmode;                 // RESTRICT_EPERM or RESTRICT_KILL
 };
 
 // Process memory is laid out contiguously, low addresses first:
@@ -20,3 +20,31 @@
 #define SYS_link   19
 #define SYS_mkdir  20
 #define SYS_close  21
+#define SYS_restrict 22
+#define SYS_lseek  23
+#define SYS_pread  24
+#define SYS_pwrite 25
+#define SYS_dup2   26
+#define SYS_dup3   27
+#define SYS_fcntl  28
+#define SYS_getdents 29
+#define SYS_symlink 30
+#define SYS_readlink 31
+#define SYS_lstat  32
+#define SYS_time   33
+#define SYS_gettimeofday 34
+#define SYS_utime  35
+#define SYS_rename 36
+#define SYS_truncate 37
+#define SYS_ftruncate 38
+#define SYS_fallocate 39
+#define SYS_fsync  40
+#define SYS_sync   41
+#define SYS_crashat 42
+#define SYS_mount  43
+#define SYS_umount 44
+#define SYS_ioctl  45
+
+// restrict() modes: what happens when a filtered call is made
+#define RESTRICT_EPERM 0  // the call fails with -1
+#define RESTRICT_KILL  1  // the process is killed
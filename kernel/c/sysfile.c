@@ -15,6 +15,9 @@
 #include "sleeplock.h"
 #include "file.h"
 #include "fcntl.h"
+#include "date.h"
+
+extern struct sleeplock renamelock;
 
 // Fetch the nth word-sized system call argument as a file descriptor
 // and return both the descriptor and the corresponding struct file.
@@ -26,7 +29,7 @@ argfd(int n, int *pfd, struct file **pf)
 
   if(argint(n, &fd) < 0)
     return -1;
-  if(fd < 0 || fd >= NOFILE || (f=myproc()->ofile[fd]) == 0)
+  if((f=fdlookup(fd)) == 0)
     return -1;
   if(pfd)
     *pfd = fd;
@@ -35,23 +38,6 @@ argfd(int n, int *pfd, struct file **pf)
   return 0;
 }
 
-// Allocate a file descriptor for the given file.
-// Takes over file reference from caller on success.
-static int
-fdalloc(struct file *f)
-{
-  int fd;
-  struct proc *curproc = myproc();
-
-  for(fd = 0; fd < NOFILE; fd++){
-    if(curproc->ofile[fd] == 0){
-      curproc->ofile[fd] = f;
-      return fd;
-    }
-  }
-  return -1;
-}
-
 int
 sys_dup(void)
 {
@@ -60,12 +46,94 @@ sys_dup(void)
 
   if(argfd(0, 0, &f) < 0)
     return -1;
-  if((fd=fdalloc(f)) < 0)
+  if((fd=fdalloc(f, 0)) < 0)
     return -1;
   filedup(f);
   return fd;
 }
 
+// Make newfd refer to f, closing whatever newfd had open.
+static int
+dupto(struct file *f, int newfd, int fdflags)
+{
+  struct file *old;
+
+  if(newfd < 0 || newfd >= NOFILEMAX)
+    return -1;
+  // Grow the table first: once the old file is gone,
+  // installing f must not fail.
+  if(fdreserve(newfd) < 0)
+    return -1;
+  old = fdremove(newfd);
+  if(fdinstall(newfd, filedup(f)) < 0)
+    panic("dupto");
+  fdsetflags(newfd, fdflags);
+  if(old)
+    fileclose(old);
+  return newfd;
+}
+
+int
+sys_dup2(void)
+{
+  struct file *f;
+  int oldfd, newfd;
+
+  if(argfd(0, &oldfd, &f) < 0 || argint(1, &newfd) < 0)
+    return -1;
+  if(newfd == oldfd)
+    return newfd;
+  return dupto(f, newfd, 0);
+}
+
+int
+sys_dup3(void)
+{
+  struct file *f;
+  int oldfd, newfd, flags;
+
+  if(argfd(0, &oldfd, &f) < 0 || argint(1, &newfd) < 0 || argint(2, &flags) < 0)
+    return -1;
+  if(newfd == oldfd || (flags & ~O_CLOEXEC))
+    return -1;
+  return dupto(f, newfd, (flags & O_CLOEXEC) ? FD_CLOEXEC : 0);
+}
+
+int
+sys_fcntl(void)
+{
+  struct file *f;
+  int fd, cmd, arg, mode;
+
+  if(argfd(0, &fd, &f) < 0 || argint(1, &cmd) < 0 || argint(2, &arg) < 0)
+    return -1;
+
+  switch(cmd){
+  case F_DUPFD:
+    if((fd = fdalloc(f, arg)) < 0)
+      return -1;
+    filedup(f);
+    return fd;
+  case F_GETFD:
+    return fdgetflags(fd);
+  case F_SETFD:
+    return fdsetflags(fd, arg & FD_CLOEXEC);
+  case F_GETFL:
+    if(f->readable && f->writable)
+      mode = O_RDWR;
+    else if(f->writable)
+      mode = O_WRONLY;
+    else
+      mode = O_RDONLY;
+    return mode | f->flags;
+  case F_SETFL:
+    // The access mode is fixed at open.
+    f->flags = arg & (O_APPEND|O_NONBLOCK);
+    return 0;
+  }
+  return -1;
+}
+
 int
 sys_read(void)
 {
@@ -90,6 +158,82 @@ sys_write(void)
   return filewrite(f, p, n);
 }
 
+int
+sys_pread(void)
+{
+  struct file *f;
+  int n, off;
+  char *p;
+
+  if(argfd(0, 0, &f) < 0 || argint(2, &n) < 0 || argptr(1, &p, n) < 0 || argint(3, &off) < 0)
+    return -1;
+  if(off < 0)
+    return -1;
+  return filepread(f, p, n, off);
+}
+
+int
+sys_pwrite(void)
+{
+  struct file *f;
+  int n, off;
+  char *p;
+
+  if(argfd(0, 0, &f) < 0 || argint(2, &n) < 0 || argptr(1, &p, n) < 0 || argint(3, &off) < 0)
+    return -1;
+  if(off < 0)
+    return -1;
+  return filepwrite(f, p, n, off);
+}
+
+// Carry out the request req on the device open as fd, with
+// arg pointing to a uint that the request reads or fills in.
+int
+sys_ioctl(void)
+{
+  struct file *f;
+  char *arg;
+  int req, r;
+
+  if(argfd(0, 0, &f) < 0 || argint(1, &req) < 0 ||
+     argptr(2, &arg, sizeof(uint)) < 0)
+    return -1;
+  if(f->type != FD_INODE)
+    return -1;
+  ilock(f->ip);
+  r = f->ip->type == T_DEV ? devioctl(f->ip, req, arg) : -1;
+  iunlock(f->ip);
+  return r;
+}
+
+int
+sys_lseek(void)
+{
+  struct file *f;
+  int offset, whence;
+
+  if(argfd(0, 0, &f) < 0 || argint(1, &offset) < 0 || argint(2, &whence) < 0)
+    return -1;
+  return fileseek(f, offset, whence);
+}
+
+int
+sys_getdents(void)
+{
+  struct file *f;
+  char *p;
+  int n, r;
+
+  if(argfd(0, 0, &f) < 0 || argint(2, &n) < 0 || argptr(1, &p, n) < 0)
+    return -1;
+  if(f->type != FD_INODE || !f->readable)
+    return -1;
+  begin_op();
+  r = dirread(f->ip, &f->off, p, n);
+  end_op();
+  return r;
+}
+
 int
 sys_close(void)
 {
@@ -98,11 +242,54 @@ sys_close(void)
 
   if(argfd(0, &fd, &f) < 0)
     return -1;
-  myproc()->ofile[fd] = 0;
+  fdremove(fd);
   fileclose(f);
   return 0;
 }
 
+// Wait until f's file, and everything else written to its
+// disk before the call, is on that disk. Other disks are left
+// to the flusher processes; sync() flushes them all.
+int
+sys_fsync(void)
+{
+  struct file *f;
+  uint dev;
+
+  if(argfd(0, 0, &f) < 0)
+    return -1;
+  if(f->type != FD_INODE)
+    return 0;
+  dev = f->ip->dev;
+  if(dev == ROOTDEV)  // only the root disk has a log
+    logflush();
+  if(dev < NDISK)
+    bflush(dev);
+  return 0;
+}
+
+// Wait until everything written before the call, on every
+// disk, is on disk.
+int
+sys_sync(void)
+{
+  logflush();
+  bflush(-1);
+  return 0;
+}
+
+#ifdef CRASHTEST
+int
+sys_crashat(void)
+{
+  int point, n, nblock;
+
+  if(argint(0, &point) < 0 || argint(1, &n) < 0 || argint(2, &nblock) < 0)
+    return -1;
+  return crashat(point, n, nblock);
+}
+#endif
+
 int
 sys_fstat(void)
 {
@@ -114,11 +301,35 @@ sys_fstat(void)
   return filestat(f, st);
 }
 
+// Like fstat of path, but a symbolic link is described
+// itself rather than followed.
+int
+sys_lstat(void)
+{
+  char *path;
+  struct stat *st;
+  struct inode *ip;
+
+  if(argstr(0, &path) < 0 || argptr(1, (void*)&st, sizeof(*st)) < 0)
+    return -1;
+
+  begin_op();
+  if((ip = lnamei(path)) == 0){
+    end_op();
+    return -1;
+  }
+  ilock(ip);
+  stati(ip, st);
+  iunlockput(ip);
+  end_op();
+  return 0;
+}
+
 // Create the path new as a link to the same inode as old.
 int
 sys_link(void)
 {
-  char name[DIRSIZ], *new, *old;
+  char name[NAMEMAX+1], *new, *old;
   struct inode *dp, *ip;
 
   if(argstr(0, &old) < 0 || argstr(1, &new) < 0)
@@ -168,35 +379,29 @@ bad:
 static int
 isdirempty(struct inode *dp)
 {
-  int off;
-  struct dirent de;
+  uint off, inum;
+  char name[NAMEMAX+1];
 
-  for(off=2*sizeof(de); off<dp->size; off+=sizeof(de)){
-    if(readi(dp, (char*)&de, off, sizeof(de)) != sizeof(de))
-      panic("isdirempty: readi");
-    if(de.inum != 0)
+  for(off = 0; ; ){
+    off = direntat(dp, off, &inum, name);
+    if(inum == 0)
+      return 1;
+    if(namecmp(name, ".") != 0 && namecmp(name, "..") != 0)
       return 0;
   }
-  return 1;
 }
 
 //PAGEBREAK!
-int
-sys_unlink(void)
+// Remove the name path. Caller must be inside a transaction.
+static int
+unlinkpath(char *path)
 {
   struct inode *ip, *dp;
-  struct dirent de;
-  char name[DIRSIZ], *path;
+  char name[NAMEMAX+1];
   uint off;
 
-  if(argstr(0, &path) < 0)
-    return -1;
-
-  begin_op();
-  if((dp = nameiparent(path, name)) == 0){
-    end_op();
+  if((dp = nameiparent(path, name)) == 0)
     return -1;
-  }
 
   ilock(dp);
 
@@ -210,14 +415,15 @@ sys_unlink(void)
 
   if(ip->nlink < 1)
     panic("unlink: nlink < 1");
-  if(ip->type == T_DIR && !isdirempty(ip)){
+  if(ip->type == T_DIR && (!isdirempty(ip) || vfsmounted(ip))){
     iunlockput(ip);
     goto bad;
   }
 
-  memset(&de, 0, sizeof(de));
-  if(writei(dp, (char*)&de, off, sizeof(de)) != sizeof(de))
-    panic("unlink: writei");
+  if(dirremove(dp, off) < 0){
+    iunlockput(ip);
+    goto bad;
+  }
   if(ip->type == T_DIR){
     dp->nlink--;
     iupdate(dp);
@@ -228,37 +434,199 @@ sys_unlink(void)
   iupdate(ip);
   iunlockput(ip);
 
-  end_op();
-
   return 0;
 
 bad:
   iunlockput(dp);
-  end_op();
   return -1;
 }
 
+int
+sys_unlink(void)
+{
+  char *path;
+  int r;
+
+  if(argstr(0, &path) < 0)
+    return -1;
+
+  begin_op();
+  r = unlinkpath(path);
+  end_op();
+  return r;
+}
+
+// Give old the name new in a single transaction, replacing
+// whatever new named before. A directory may only replace an
+// empty directory, and may not move into its own subtree.
+int
+sys_rename(void)
+{
+  struct inode *odp, *ndp, *ip, *tp, *dp;
+  char oname[NAMEMAX+1], nname[NAMEMAX+1], *old, *new;
+  uint ooff, noff, off;
+  int isdir, tdir, r;
+
+  if(argstr(0, &old) < 0 || argstr(1, &new) < 0)
+    return -1;
+
+  begin_op();
+  acquiresleep(&renamelock);
+  ip = tp = 0;
+  r = -1;
+  odp = nameiparent(old, oname);
+  ndp = nameiparent(new, nname);
+  if(odp == 0 || ndp == 0 || odp->dev != ndp->dev)
+    goto out;
+  if(namecmp(oname, ".") == 0 || namecmp(oname, "..") == 0 ||
+     namecmp(nname, ".") == 0 || namecmp(nname, "..") == 0)
+    goto out;
+
+  // Find both inodes while no directory is locked, since
+  // isancestor() locks its way up the tree. Only renames
+  // move directories, and we hold renamelock.
+  ilock(odp);
+  ip = dirlookup(odp, oname, 0);
+  iunlock(odp);
+  ilock(ndp);
+  tp = dirlookup(ndp, nname, 0);
+  iunlock(ndp);
+  if(ip == 0)
+    goto out;
+  if(ip == tp){
+    r = 0;
+    goto out;
+  }
+  // Nothing may be mounted on a directory that moves or goes.
+  if(vfsmounted(ip) || (tp && vfsmounted(tp)))
+    goto out;
+  ilock(ip);
+  isdir = ip->type == T_DIR;
+  iunlock(ip);
+  if(isdir && isancestor(ip, ndp))
+    goto out;
+  if(tp){
+    // A directory above odp is not empty, and locking it
+    // after odp would invert the usual order.
+    ilock(tp);
+    tdir = tp->type == T_DIR;
+    iunlock(tp);
+    if(tdir && isancestor(tp, odp))
+      goto out;
+  }
+
+  // Lock the parents, an ancestor before its descendant.
+  if(odp == ndp)
+    ilock(odp);
+  else if(isancestor(ndp, odp)){
+    ilock(ndp);
+    ilock(odp);
+  } else {
+    ilock(odp);
+    ilock(ndp);
+  }
+
+  // Give up if either name changed while they were unlocked.
+  dp = dirlookup(odp, oname, &ooff);
+  if(dp)
+    iput(dp);
+  if(dp != ip)
+    goto unlock;
+  dp = dirlookup(ndp, nname, &noff);
+  if(dp)
+    iput(dp);
+  if(dp != tp)
+    goto unlock;
+
+  if(tp){
+    ilock(tp);
+    if(tp->type == T_DIR ? !isdir || !isdirempty(tp) : isdir){
+      iunlock(tp);
+      goto unlock;
+    }
+  }
+  ilock(ip);
+
+  // Point new at ip, then remove old.
+  if(tp ? dirset(ndp, noff, ip->inum) < 0 : dirlink(ndp, nname, ip->inum) < 0){
+    iunlock(ip);
+    if(tp)
+      iunlock(tp);
+    goto unlock;
+  }
+  dirremove(odp, ooff);
+
+  // Aim a directory's ".." at ndp.
+  if(isdir && odp != ndp){
+    if((dp = dirlookup(ip, "..", &off)) == 0)
+      panic("rename: ..");
+    iput(dp);
+    dirset(ip, off, ndp->inum);
+    odp->nlink--;
+    ndp->nlink++;
+    iupdate(odp);
+    iupdate(ndp);
+  }
+  iupdate(ip);
+  iunlock(ip);
+
+  if(tp){
+    if(tp->type == T_DIR){
+      ndp->nlink--;  // for tp's ".."
+      iupdate(ndp);
+    }
+    tp->nlink--;
+    iupdate(tp);
+    iunlock(tp);
+  }
+  r = 0;
+
+unlock:
+  iunlock(odp);
+  if(ndp != odp)
+    iunlock(ndp);
+out:
+  if(tp)
+    iput(tp);
+  if(ip)
+    iput(ip);
+  if(odp)
+    iput(odp);
+  if(ndp)
+    iput(ndp);
+  releasesleep(&renamelock);
+  end_op();
+  return r;
+}
+
+// Create the file path and return it locked.  If path already
+// exists and both it and the request are plain files, return the
+// existing file instead, unless excl is set.
 static struct inode*
-create(char *path, short type, short major, short minor)
+create(char *path, short type, short major, short minor, int excl, int follow)
 {
   struct inode *ip, *dp;
-  char name[DIRSIZ];
+  char name[NAMEMAX+1];
 
-  if((dp = nameiparent(path, name)) == 0)
+  // With follow, a symbolic link as the last element leads to
+  // where the file is, or is to be created.
+  if((dp = follow ? linkparent(path, name) : nameiparent(path, name)) == 0)
     return 0;
   ilock(dp);
 
   if((ip = dirlookup(dp, name, 0)) != 0){
     iunlockput(dp);
     ilock(ip);
-    if(type == T_FILE && ip->type == T_FILE)
+    if(type == T_FILE && ip->type == T_FILE && !excl)
       return ip;
     iunlockput(ip);
     return 0;
   }
 
-  if((ip = ialloc(dp->dev, type)) == 0)
-    panic("create: ialloc");
+  if((ip = ialloc(dp->dev, type)) == 0){
+    iunlockput(dp);
+    return 0;
+  }
 
   ilock(ip);
   ip->major = major;
@@ -267,26 +635,53 @@ create(char *path, short type, short major, short minor)
   iupdate(ip);
 
   if(type == T_DIR){  // Create . and .. entries.
-    dp->nlink++;  // for ".."
-    iupdate(dp);
     // No ip->nlink++ for ".": avoid cyclic ref count.
     if(dirlink(ip, ".", ip->inum) < 0 || dirlink(ip, "..", dp->inum) < 0)
-      panic("create dots");
+      goto bad;
   }
 
   if(dirlink(dp, name, ip->inum) < 0)
-    panic("create: dirlink");
+    goto bad;
 
+  if(type == T_DIR){
+    dp->nlink++;  // for ".."
+    iupdate(dp);
+  }
   iunlockput(dp);
 
   return ip;
+
+bad:
+  // The directories can run out of room for the entries.
+  ip->nlink = 0;
+  iunlockput(ip);
+  iunlockput(dp);
+  return 0;
+}
+
+// Set the size of the file ip, taking as many transactions
+// as freeing its blocks needs. The caller holds a reference
+// to ip, but not its lock, and is not in a transaction.
+static int
+truncto(struct inode *ip, uint size)
+{
+  int r;
+
+  do {
+    begin_op();
+    ilock(ip);
+    r = ip->type == T_FILE ? itruncate(ip, size, MAXOPBLOCKS) : -1;
+    iunlock(ip);
+    end_op();
+  } while(r == 0);
+  return r < 0 ? -1 : 0;
 }
 
 int
 sys_open(void)
 {
   char *path;
-  int fd, omode;
+  int fd, omode, trunc;
   struct file *f;
   struct inode *ip;
 
@@ -296,25 +691,32 @@ sys_open(void)
   begin_op();
 
   if(omode & O_CREATE){
-    ip = create(path, T_FILE, 0, 0);
+    // The file may be reached, or created, through a symbolic
+    // link, unless O_EXCL or O_NOFOLLOW says otherwise.
+    ip = create(path, T_FILE, 0, 0, omode & O_EXCL,
+                !(omode & (O_EXCL|O_NOFOLLOW)));
     if(ip == 0){
       end_op();
       return -1;
     }
   } else {
-    if((ip = namei(path)) == 0){
+    if((ip = (omode & O_NOFOLLOW) ? lnamei(path) : namei(path)) == 0){
       end_op();
       return -1;
     }
     ilock(ip);
-    if(ip->type == T_DIR && omode != O_RDONLY){
+    if(ip->type == T_SYMLINK ||
+       (ip->type == T_DIR && (omode & (O_WRONLY|O_RDWR)))){
       iunlockput(ip);
       end_op();
       return -1;
     }
   }
 
-  if((f = filealloc()) == 0 || (fd = fdalloc(f)) < 0){
+  trunc = (omode & O_TRUNC) && (omode & (O_WRONLY|O_RDWR)) &&
+          ip->type == T_FILE && ip->size > 0;
+
+  if((f = filealloc()) == 0 || (fd = fdalloc(f, 0)) < 0){
     if(f)
       fileclose(f);
     iunlockput(ip);
@@ -324,11 +726,18 @@ sys_open(void)
   iunlock(ip);
   end_op();
 
+  // A large file may take more than one transaction to free.
+  if(trunc)
+    truncto(ip, 0);
+
   f->type = FD_INODE;
   f->ip = ip;
   f->off = 0;
   f->readable = !(omode & O_WRONLY);
   f->writable = (omode & O_WRONLY) || (omode & O_RDWR);
+  f->flags = omode & (O_APPEND|O_NONBLOCK);
+  if(omode & O_CLOEXEC)
+    fdsetflags(fd, FD_CLOEXEC);
   return fd;
 }
 
@@ -339,7 +748,7 @@ sys_mkdir(void)
   struct inode *ip;
 
   begin_op();
-  if(argstr(0, &path) < 0 || (ip = create(path, T_DIR, 0, 0)) == 0){
+  if(argstr(0, &path) < 0 || (ip = create(path, T_DIR, 0, 0, 0, 0)) == 0){
     end_op();
     return -1;
   }
@@ -359,15 +768,169 @@ sys_mknod(void)
   if((argstr(0, &path)) < 0 ||
      argint(1, &major) < 0 ||
      argint(2, &minor) < 0 ||
-     (ip = create(path, T_DEV, major, minor)) == 0){
+     (ip = create(path, T_DEV, major, minor, 0, 0)) == 0){
+    end_op();
+    return -1;
+  }
+  iunlockput(ip);
+  end_op();
+  return 0;
+}
+
+// Create path as a symbolic link holding target.
+int
+sys_symlink(void)
+{
+  char *target, *path;
+  struct inode *ip;
+  int n;
+
+  if(argstr(0, &target) < 0 || argstr(1, &path) < 0)
+    return -1;
+  n = strlen(target);
+  if(n == 0 || n >= MAXPATH)
+    return -1;
+
+  begin_op();
+  if((ip = create(path, T_SYMLINK, 0, 0, 1, 0)) == 0){
+    end_op();
+    return -1;
+  }
+  if(writei(ip, target, 0, n) != n){
+    // No room for the target: take the link out again.
+    iunlockput(ip);
+    unlinkpath(path);
+    end_op();
+    return -1;
+  }
+  iunlockput(ip);
+  end_op();
+  return 0;
+}
+
+// Copy the target of the symbolic link path into p, without
+// a terminating null. Returns the number of bytes copied.
+int
+sys_readlink(void)
+{
+  char *path, *p;
+  struct inode *ip;
+  int n, r;
+
+  if(argstr(0, &path) < 0 || argint(2, &n) < 0 || argptr(1, &p, n) < 0)
+    return -1;
+
+  begin_op();
+  if((ip = lnamei(path)) == 0){
+    end_op();
+    return -1;
+  }
+  ilock(ip);
+  r = -1;
+  if(ip->type == T_SYMLINK)
+    r = readi(ip, p, 0, n);
+  iunlockput(ip);
+  end_op();
+  return r;
+}
+
+// Set the access and modification times of path to those in
+// times, or to the current time if times is 0.
+int
+sys_utime(void)
+{
+  char *path;
+  struct utimbuf *times;
+  struct inode *ip;
+
+  if(argstr(0, &path) < 0 || argint(1, (int*)&times) < 0)
+    return -1;
+  if(times && argptr(1, (void*)&times, sizeof(*times)) < 0)
+    return -1;
+
+  begin_op();
+  if((ip = namei(path)) == 0){
     end_op();
     return -1;
   }
+  ilock(ip);
+  if(times){
+    ip->atime = times->atime;
+    ip->mtime = times->mtime;
+  } else
+    ip->atime = ip->mtime = unixtime();
+  iupdate(ip);
   iunlockput(ip);
   end_op();
   return 0;
 }
 
+int
+sys_truncate(void)
+{
+  char *path;
+  struct inode *ip;
+  int size, r;
+
+  if(argstr(0, &path) < 0 || argint(1, &size) < 0 || size < 0)
+    return -1;
+
+  begin_op();
+  ip = namei(path);
+  end_op();
+  if(ip == 0)
+    return -1;
+  r = truncto(ip, size);
+  begin_op();
+  iput(ip);
+  end_op();
+  return r;
+}
+
+int
+sys_ftruncate(void)
+{
+  struct file *f;
+  int size;
+
+  if(argfd(0, 0, &f) < 0 || argint(1, &size) < 0 || size < 0)
+    return -1;
+  if(f->type != FD_INODE || !f->writable)
+    return -1;
+  return truncto(f->ip, size);
+}
+
+// Allocate the blocks holding the n bytes at off in a file,
+// growing it if need be, a few blocks per transaction as
+// filewrite() does.
+int
+sys_fallocate(void)
+{
+  struct file *f;
+  int off, n, n1, r;
+  int max = (MAXOPBLOCKS-1-5-2-1) * MINBSIZE;
+
+  if(argfd(0, 0, &f) < 0 || argint(1, &off) < 0 || argint(2, &n) < 0)
+    return -1;
+  if(off < 0 || n < 0 || f->type != FD_INODE || !f->writable)
+    return -1;
+
+  r = 0;
+  while(n > 0){
+    n1 = n < max ? n : max;
+    begin_op();
+    ilock(f->ip);
+    r = f->ip->type == T_FILE ? ifallocate(f->ip, off, n1) : -1;
+    iunlock(f->ip);
+    end_op();
+    if(r != n1)
+      break;
+    off += n1;
+    n -= n1;
+  }
+  return n > 0 ? -1 : 0;
+}
+
 int
 sys_chdir(void)
 {
@@ -431,9 +994,9 @@ sys_pipe(void)
   if(pipealloc(&rf, &wf) < 0)
     return -1;
   fd0 = -1;
-  if((fd0 = fdalloc(rf)) < 0 || (fd1 = fdalloc(wf)) < 0){
+  if((fd0 = fdalloc(rf, 0)) < 0 || (fd1 = fdalloc(wf, 0)) < 0){
     if(fd0 >= 0)
-      myproc()->ofile[fd0] = 0;
+      fdremove(fd0);
     fileclose(rf);
     fileclose(wf);
     return -1;
@@ -442,3 +1005,71 @@ sys_pipe(void)
   fd[1] = fd1;
   return 0;
 }
+
+// Mount the file system of type type on the directory dir,
+// with the mount options opts, which may be null. A file
+// system that lives on a disk is on the disk device src;
+// for one that does not, src is only a name.
+int
+sys_mount(void)
+{
+  char *src, *dir, *type, *opts;
+  struct inode *ip;
+  int disk, r, p;
+
+  if(argstr(0, &src) < 0 || argstr(1, &dir) < 0 || argstr(2, &type) < 0 ||
+     argint(3, &p) < 0)
+    return -1;
+  opts = "";
+  if(p != 0 && argstr(3, &opts) < 0)
+    return -1;
+
+  begin_op();
+  disk = -1;
+  if((ip = namei(src)) != 0){
+    ilock(ip);
+    if(ip->type == T_DEV && ip->major == DISK)
+      disk = ip->minor;
+    iunlockput(ip);
+  }
+  // Not while the raw disk is open: mounting changes the size
+  // of its blocks in the cache under the reader.
+  if(disk >= 0 && idevrefs(DISK, disk) > 0){
+    end_op();
+    return -1;
+  }
+  if((ip = namei(dir)) == 0){
+    end_op();
+    return -1;
+  }
+  ilock(ip);
+  r = ip->type == T_DIR ? 0 : -1;
+  iunlock(ip);
+  if(r == 0)
+    r = vfsmount(type, disk, opts, ip);
+  iput(ip);
+  end_op();
+  return r;
+}
+
+// Unmount the file system mounted on dir, which nothing
+// may be using.
+int
+sys_umount(void)
+{
+  char *dir;
+  struct inode *ip;
+  int r;
+
+  if(argstr(0, &dir) < 0)
+    return -1;
+
+  begin_op();
+  if((ip = namei(dir)) == 0){
+    end_op();
+    return -1;
+  }
+  r = vfsumount(ip);
+  end_op();
+  return r;
+}
@@ -7,6 +7,7 @@
 #include "mmu.h"
 #include "proc.h"
 #include "spinlock.h"
+#include "syscall.h"
 
 int
 sys_fork(void)
@@ -93,3 +94,45 @@ sys_uptime(void)
   release(&TICKSLOCK);
   return xticks;
 }
+
+// Return the current time in seconds since 1970.
+int
+sys_time(void)
+{
+  return unixtime();
+}
+
+// Fill in the current time, to the microsecond.
+int
+sys_gettimeofday(void)
+{
+  struct timeval *tv;
+
+  if(argptr(0, (void*)&tv, sizeof(*tv)) < 0)
+    return -1;
+  gettime(tv);
+  return 0;
+}
+
+// Narrow the system call allowlist of the current process to the
+// calls whose bits are set in allow[NSYSMASK].  Bits can only be
+// cleared, never set again, and the mode can only be tightened
+// from RESTRICT_EPERM to RESTRICT_KILL.  The allowlist is inherited
+// by fork and kept across exec.
+int
+sys_restrict(void)
+{
+  uint *allow;
+  int i, mode;
+  struct proc *curproc = myproc();
+
+  if(argptr(0, (void*)&allow, NSYSMASK*sizeof(allow[0])) < 0 || argint(1, &mode) < 0)
+    return -1;
+  if(mode != RESTRICT_EPERM && mode != RESTRICT_KILL)
+    return -1;
+  for(i = 0; i < NSYSMASK; i++)
+    curproc->sysallow[i] &= allow[i];
+  if(mode == RESTRICT_KILL)
+    curproc->sysmode = RESTRICT_KILL;
+  return 0;
+}
@@ -1,6 +1,23 @@
-use arrayvec::ArrayVec;
+use crate::{
+    fs::{MAXBSIZE, MINBSIZE},
+    ide::IDE,
+    kalloc::kalloc,
+    memlayout::PHYSTOP,
+    mmu::PGSIZE,
+    param::{BUFFRAC, LOGSIZE, MAXOPBLOCKS, MAXRUN, NDISK, WRITEBACKTICKS},
+    proc::{sleep, wakeup},
+    sleeplock::SleepLockC,
+    spinlock::SpinLockC,
+    trap::{TICKS, TICKSLOCK},
+};
 
-use crate::{fs::BSIZE, ide::IDE, param::NBUF, sleeplock::SleepLockC, spinlock::SpinLockC};
+// Buffers in the cache, each with a page for its data: a
+// fraction of physical memory, and at least a full log and more.
+const NBUF: usize = PHYSTOP / PGSIZE / BUFFRAC;
+const NBUCKET: usize = NBUF / 2; // hash chains for looking blocks up
+
+const _: () = assert!(NBUF >= LOGSIZE + MAXOPBLOCKS * 2);
+const _: () = assert!(MAXBSIZE <= PGSIZE);
 
 #[repr(C)]
 pub struct Buffer {
@@ -9,30 +26,43 @@ pub struct Buffer {
     pub blockno: usize,
     pub lock: SleepLockC,
     refcnt: u32,
-    prev: *mut Self,
+    prev: *mut Self, // LRU cache list
     next: *mut Self,
+    hnext: *mut Self, // hash chain
     pub qnext: *mut Self,
-    pub data: [u8; BSIZE],
+    pub rnext: *mut Self,
+    pub size: usize, // block size of dev, in bytes
+    data: *mut u8,   // a page
 }
 
 impl Buffer {
     pub const VALID: i32 = 0x2; // buffer has been read from disk
     pub const DIRTY: i32 = 0x4; // buffer needs to be written to disk
+    pub const LATER: i32 = 0x8; // dirty, and written back by the cache, not the log
+    pub const AHEAD: i32 = 0x10; // being read ahead; the disk releases it
 
-    pub const fn new(dev: usize, blockno: usize) -> Self {
+    pub const fn new() -> Self {
         Self {
             flags: 0,
-            dev,
-            blockno,
+            dev: 0,
+            blockno: 0,
             lock: SleepLockC::new(),
-            refcnt: 1,
+            refcnt: 0,
             prev: core::ptr::null_mut(),
             next: core::ptr::null_mut(),
+            hnext: core::ptr::null_mut(),
             qnext: core::ptr::null_mut(),
-            data: [0; BSIZE],
+            rnext: core::ptr::null_mut(),
+            size: MINBSIZE,
+            data: core::ptr::null_mut(),
         }
     }
 
+    // The block's contents, the first size bytes of the page.
+    pub fn data(&mut self) -> &mut [u8; MAXBSIZE] {
+        unsafe { &mut *(self.data as *mut [u8; MAXBSIZE]) }
+    }
+
     // Write b's contents to disk.  Must be locked.
     pub fn write(&mut self) {
         if !self.lock.is_locked() {
@@ -46,89 +76,265 @@ impl Buffer {
         }
     }
 
+    // Leave b's contents for the cache to write to disk, within
+    // WRITEBACKTICKS or when sync asks. Must be locked.
+    pub fn write_later(&mut self) {
+        if !self.lock.is_locked() {
+            panic!("Buffer::write_later");
+        }
+
+        self.flags |= Buffer::VALID | Buffer::DIRTY | Buffer::LATER;
+    }
+
     pub fn release(&mut self, cache: &mut BufferCache) {
         if !self.lock.is_locked() {
             panic!("Buffer::release");
         }
 
+        // Unlock before dropping refcnt, so that a buffer with
+        // refcnt 0 is never locked (see victim()).
         self.lock.release();
 
         cache.release_buffer(self);
     }
+
+    // Let go of a buffer that the disk has filled for
+    // read_ahead(). Called from the disk interrupt, where no
+    // process holds it, with idelock held: idelock comes before
+    // the cache lock, so the cache never calls into the disk
+    // driver while holding its lock. Unlocks before dropping
+    // refcnt, as release() does.
+    pub fn release_ahead(&mut self) {
+        self.flags &= !Buffer::AHEAD;
+        self.lock.release();
+        unsafe {
+            BUFFER_CACHE.release_buffer(self);
+        }
+    }
 }
 
 // Buffer cache.
 //
-// The buffer cache is a linked list of buf structures holding
+// The buffer cache is an array of buf structures holding
 // cached copies of disk block contents.  Caching disk blocks
 // in memory reduces the number of disk reads and also provides
 // a synchronization point for disk blocks used by multiple processes.
 //
 // Interface:
 // * To get a buffer for a particular disk block, call bread.
-// * After changing buffer data, call bwrite to write it to disk.
+// * After changing buffer data, call bwrite to write it to disk,
+//     or bdwrite to have the cache write it back later.
 // * When done with the buffer, call brelse.
 // * Do not use the buffer after calling brelse.
 // * Only one process at a time can use a buffer,
 //     so do not keep them longer than necessary.
 //
-// The implementation uses two state flags internally:
+// The implementation uses these state flags internally:
 // * B_VALID: the buffer data has been read from the disk.
 // * B_DIRTY: the buffer data has been modified
 //     and needs to be written to disk.
+// * B_LATER: the cache writes the buffer back, in the bflush
+//     kernel process or in bflush(); B_DIRTY alone means the
+//     log will.
+// * B_AHEAD: the disk is reading the buffer for breadahead(),
+//     and releases it when done.
+//
+// Blocks are found through a hash table. A buffer nothing
+// refers to goes to the front of the LRU list, and a block not
+// in the cache takes the buffer at its back that holds no
+// dirty data.
 
 pub struct BufferCache {
     lock: SpinLockC,
-    buffers: ArrayVec<Buffer, NBUF>,
+    buffers: [Buffer; NBUF],
+    lru: Buffer,                  // head of the LRU list; lru.next is most recent
+    hash: [*mut Buffer; NBUCKET], // chains through hnext
+    starved: bool,                // is a process waiting for a free buffer?
+    block_size: [usize; NDISK],   // block size of each disk, in bytes
+}
+
+fn bucket(dev: usize, blockno: usize) -> usize {
+    (blockno ^ dev << 24) % NBUCKET
 }
 
 impl BufferCache {
     pub const fn new() -> Self {
+        const UNUSED: Buffer = Buffer::new();
         Self {
             lock: SpinLockC::new(),
-            buffers: ArrayVec::new_const(),
+            buffers: [UNUSED; NBUF],
+            lru: Buffer::new(),
+            hash: [core::ptr::null_mut(); NBUCKET],
+            starved: false,
+            block_size: [MINBSIZE; NDISK],
+        }
+    }
+
+    // Give each buffer a page, and put them all on the LRU
+    // list. Called once, after kinit2().
+    pub fn init(&mut self) {
+        let head: *mut Buffer = &mut self.lru;
+        self.lru.prev = head;
+        self.lru.next = head;
+        for i in 0..NBUF {
+            let buf: *mut Buffer = &mut self.buffers[i];
+            unsafe {
+                (*buf).data = match kalloc() {
+                    Some(page) => page as *mut u8,
+                    None => panic!("BufferCache::init"),
+                };
+                self.touch(buf);
+            }
+        }
+    }
+
+    // Block size of dev, in bytes.
+    pub fn block_size(&self, dev: usize) -> usize {
+        self.block_size[dev]
+    }
+
+    // Disks are read in MINBSIZE blocks until the file system on
+    // them says otherwise. Cached blocks of dev are written back
+    // and dropped. Returns false, changing nothing, if one is in
+    // use, as by a process reading the raw disk.
+    pub fn set_block_size(&mut self, dev: usize, size: usize) -> bool {
+        if dev >= NDISK || size < MINBSIZE || size > MAXBSIZE || size % MINBSIZE != 0 {
+            panic!("BufferCache::set_block_size");
+        }
+
+        self.flush(Some(dev), true);
+        self.lock.acquire();
+        let busy = self
+            .buffers
+            .iter()
+            .any(|buf| buf.dev == dev && (buf.refcnt != 0 || buf.flags & Buffer::DIRTY != 0));
+        if !busy {
+            for buf in self.buffers.iter_mut().filter(|buf| buf.dev == dev) {
+                buf.flags = 0;
+            }
+            self.block_size[dev] = size;
         }
+        self.lock.release();
+        !busy
     }
 
-    pub fn push(&mut self, dev: usize, blockno: usize) -> usize {
-        let mut buf = Buffer::new(dev, blockno);
-        buf.lock.acquire();
+    // The buffer for block blockno of dev, if it is cached.
+    // Buffers that are neither in use nor valid hold nothing.
+    // Caller holds lock.
+    fn lookup(&self, dev: usize, blockno: usize) -> Option<*mut Buffer> {
+        let mut buf = self.hash[bucket(dev, blockno)];
+        while !buf.is_null() {
+            let b = unsafe { &*buf };
+            if b.dev == dev
+                && b.blockno == blockno
+                && (b.refcnt != 0 || b.flags & (Buffer::VALID | Buffer::DIRTY) != 0)
+            {
+                return Some(buf);
+            }
+            buf = b.hnext;
+        }
+        None
+    }
 
-        if self.buffers.try_push(buf).is_err() {
-            panic!("BufferCache::get: no buffer");
+    // The least recently used buffer that is not in use.
+    // Even if refcnt==0, B_DIRTY indicates a buffer is in use
+    // because log.c has modified it but not yet committed it,
+    // or it has not been written back yet. A buffer with
+    // refcnt==0 is never locked either, since release() and
+    // release_ahead() unlock it first. Caller holds lock.
+    fn victim(&mut self) -> Option<*mut Buffer> {
+        let head: *mut Buffer = &mut self.lru;
+        let mut buf = self.lru.prev;
+        while buf != head {
+            let b = unsafe { &*buf };
+            if b.refcnt == 0 && b.flags & Buffer::DIRTY == 0 {
+                return Some(buf);
+            }
+            buf = b.prev;
         }
+        None
+    }
+
+    // Make buf, which is not in use, hold block blockno of dev,
+    // moving it to that block's hash chain. Caller holds lock.
+    unsafe fn claim(&mut self, buf: *mut Buffer, dev: usize, blockno: usize) {
+        let mut pp = &mut self.hash[bucket((*buf).dev, (*buf).blockno)] as *mut *mut Buffer;
+        while !(*pp).is_null() && *pp != buf {
+            pp = &mut (**pp).hnext;
+        }
+        if *pp == buf {
+            *pp = (*buf).hnext;
+        }
+        let chain = &mut self.hash[bucket(dev, blockno)];
+        (*buf).hnext = *chain;
+        *chain = buf;
 
-        self.buffers.len() - 1
+        (*buf).dev = dev;
+        (*buf).blockno = blockno;
+        (*buf).size = self.block_size[dev];
+        (*buf).flags = 0;
+        (*buf).refcnt = 1;
+    }
+
+    // Move buf to the front of the LRU list. Caller holds lock.
+    unsafe fn touch(&mut self, buf: *mut Buffer) {
+        if !(*buf).next.is_null() {
+            (*(*buf).next).prev = (*buf).prev;
+            (*(*buf).prev).next = (*buf).next;
+        }
+        let head: *mut Buffer = &mut self.lru;
+        (*buf).next = (*head).next;
+        (*buf).prev = head;
+        (*(*head).next).prev = buf;
+        (*head).next = buf;
     }
 
     // Look through buffer cache for block on device dev.
     // If not found, allocate a buffer.
     // In either case, return locked buffer.
     pub fn get(&mut self, dev: usize, blockno: usize) -> &mut Buffer {
-        self.lock.acquire();
+        if dev >= NDISK {
+            panic!("BufferCache::get: bad dev");
+        }
 
-        self.buffers
-            .retain(|buf| buf.refcnt != 0 || buf.flags & Buffer::DIRTY != 0);
+        self.lock.acquire();
+        loop {
+            // Is the block already cached?
+            if let Some(buf) = self.lookup(dev, blockno) {
+                let buf = unsafe { &mut *buf };
+                buf.refcnt += 1;
+                self.lock.release();
+                buf.lock.acquire();
+                return buf;
+            }
 
-        let at = self
-            .buffers
-            .iter_mut()
-            .position(|buf| buf.dev == dev && buf.blockno == blockno);
+            // Not cached; recycle the least recently used buffer.
+            if let Some(buf) = self.victim() {
+                let buf = unsafe {
+                    self.claim(buf, dev, blockno);
+                    &mut *buf
+                };
+                self.lock.release();
+                buf.lock.acquire();
+                return buf;
+            }
 
-        // Is the block already cached?
-        if let Some(at) = at {
-            let buf = &mut self.buffers[at];
-            buf.refcnt += 1;
-            self.lock.release();
-            buf.lock.acquire();
-            buf
-        } else {
-            // Not cached; recycle an unused buffer.
-            // Even if refcnt==0, B_DIRTY indicates a buffer is in use
-            // because log.c has modified it but not yet committed it.
-            let at = self.push(dev, blockno);
-            self.lock.release();
-            &mut self.buffers[at]
+            // Every buffer is in use or dirty. Write back those
+            // the cache may, or else wait for one to be released.
+            let later = self
+                .buffers
+                .iter()
+                .any(|buf| buf.refcnt == 0 && buf.flags & Buffer::LATER != 0);
+            if later {
+                self.lock.release();
+                self.flush(None, false);
+                self.lock.acquire();
+            } else {
+                self.starved = true;
+                unsafe {
+                    sleep(self as *const Self as *const _, &self.lock);
+                }
+            }
         }
     }
 
@@ -143,17 +349,111 @@ impl BufferCache {
         buf
     }
 
+    // Fill out with locked bufs holding the n blocks starting at
+    // blockno, reading each stretch of uncached blocks from the
+    // disk in a single request.
+    pub fn read_run(&mut self, dev: usize, blockno: usize, out: &mut [*mut Buffer]) {
+        for (i, buf) in out.iter_mut().enumerate() {
+            *buf = self.get(dev, blockno + i);
+        }
+
+        let valid = |buf: *mut Buffer| unsafe { (*buf).flags & Buffer::VALID != 0 };
+        let mut i = 0;
+        while i < out.len() {
+            if valid(out[i]) {
+                i += 1;
+                continue;
+            }
+            let mut j = i + 1;
+            while j < out.len() && !valid(out[j]) {
+                j += 1;
+            }
+            unsafe {
+                IDE.as_mut().unwrap().read_run(&out[i..j]);
+            }
+            i = j;
+        }
+    }
+
+    // Start reading the n blocks starting at blockno into the
+    // cache, each stretch of uncached blocks with a single disk
+    // request, without waiting for the disk. Never waits for a
+    // buffer either: blocks with none to spare are skipped.
+    pub fn read_ahead(&mut self, dev: usize, blockno: usize, n: usize) {
+        let mut run: [*mut Buffer; MAXRUN] = [core::ptr::null_mut(); MAXRUN];
+        let mut len = 0;
+        for b in blockno..blockno + n.min(MAXRUN) {
+            self.lock.acquire();
+            let buf = match self.lookup(dev, b) {
+                Some(_) => None,
+                None => self.victim(),
+            };
+            if let Some(buf) = buf {
+                unsafe {
+                    // Nothing refers to buf, so its lock is free
+                    // (see victim()) and taking it here, under
+                    // the cache lock, does not sleep.
+                    assert!((*buf).refcnt == 0 && (*buf).lock.is_free());
+                    self.claim(buf, dev, b);
+                    (*buf).lock.acquire();
+                    (*buf).flags |= Buffer::AHEAD;
+                }
+                run[len] = buf;
+                len += 1;
+            }
+            // Only without the cache lock: the disk interrupt
+            // takes it under idelock (release_ahead()).
+            self.lock.release();
+
+            if buf.is_none() && len > 0 {
+                unsafe { IDE.as_mut().unwrap().read_ahead(&run[..len]) };
+                len = 0;
+            }
+        }
+        if len > 0 {
+            unsafe { IDE.as_mut().unwrap().read_ahead(&run[..len]) };
+        }
+    }
+
+    // Write back the buffers the cache writes, of dev or of
+    // every disk. With wait, waits for those in use and for
+    // those being written; otherwise skips them.
+    pub fn flush(&mut self, dev: Option<usize>, wait: bool) {
+        for i in 0..NBUF {
+            self.lock.acquire();
+            let buf = &mut self.buffers[i];
+            let later = buf.flags & Buffer::LATER != 0
+                && dev.map_or(true, |dev| buf.dev == dev)
+                && (wait || buf.refcnt == 0);
+            if later {
+                buf.refcnt += 1;
+            }
+            let buf: *mut Buffer = buf;
+            self.lock.release();
+
+            if later {
+                let buf = unsafe { &mut *buf };
+                buf.lock.acquire();
+                if buf.flags & Buffer::LATER != 0 {
+                    buf.write();
+                }
+                buf.release(self);
+            }
+        }
+    }
+
     fn release_buffer(&mut self, buf: &mut Buffer) {
         self.lock.acquire();
         buf.refcnt -= 1;
-        if buf.refcnt == 0 && buf.flags & Buffer::DIRTY == 0 {
-            let index = self
-                .buffers
-                .iter()
-                .position(|v| v.dev == buf.dev && v.blockno == buf.blockno)
-                .unwrap();
-
-            self.buffers.swap_remove(index);
+        if buf.refcnt == 0 {
+            // no one is waiting for it.
+            unsafe {
+                self.touch(buf);
+                if self.starved {
+                    self.starved = false;
+                    wakeup(self as *const Self as *const _);
+                }
+            }
         }
         self.lock.release();
     }
@@ -169,6 +469,38 @@ mod _binding {
         unsafe { BUFFER_CACHE.read(dev as usize, blockno as usize) }
     }
 
+    #[no_mangle]
+    extern "C" fn breadrun(dev: u32, blockno: u32, n: i32, bp: *mut *mut Buffer) {
+        if n < 1 || n as usize > MAXRUN {
+            panic!("breadrun");
+        }
+        unsafe {
+            let out = core::slice::from_raw_parts_mut(bp, n as usize);
+            BUFFER_CACHE.read_run(dev as usize, blockno as usize, out);
+        }
+    }
+
+    #[no_mangle]
+    extern "C" fn breadahead(dev: u32, blockno: u32, n: i32) {
+        if n < 1 || n as usize > MAXRUN {
+            panic!("breadahead");
+        }
+        unsafe { BUFFER_CACHE.read_ahead(dev as usize, blockno as usize, n as usize) }
+    }
+
+    #[no_mangle]
+    extern "C" fn bsetsize(dev: u32, size: u32) -> i32 {
+        match unsafe { BUFFER_CACHE.set_block_size(dev as usize, size as usize) } {
+            true => 0,
+            false => -1,
+        }
+    }
+
+    #[no_mangle]
+    extern "C" fn bgetsize(dev: u32) -> u32 {
+        unsafe { BUFFER_CACHE.block_size(dev as usize) as u32 }
+    }
+
     #[no_mangle]
     extern "C" fn bwrite(b: *mut Buffer) {
         unsafe {
@@ -176,10 +508,43 @@ mod _binding {
         }
     }
 
+    #[no_mangle]
+    extern "C" fn bdwrite(b: *mut Buffer) {
+        unsafe {
+            (*b).write_later();
+        }
+    }
+
     #[no_mangle]
     extern "C" fn brelse(b: *mut Buffer) {
         unsafe {
             (*b).release(&mut BUFFER_CACHE);
         }
     }
+
+    // Write back everything the cache writes for disk dev, or for
+    // every disk if dev < 0, and wait until it is on disk.
+    #[no_mangle]
+    extern "C" fn bflush(dev: i32) {
+        let dev = if dev < 0 { None } else { Some(dev as usize) };
+        unsafe { BUFFER_CACHE.flush(dev, true) }
+    }
+
+    // The bflush kernel process: writes back what the cache
+    // writes every WRITEBACKTICKS, skipping buffers in use,
+    // which the next round gets.
+    #[no_mangle]
+    extern "C" fn bflusher() {
+        loop {
+            unsafe {
+                TICKSLOCK.acquire();
+                let ticks0 = TICKS;
+                while TICKS - ticks0 < WRITEBACKTICKS as u32 {
+                    sleep(&TICKS as *const _ as *const _, &TICKSLOCK);
+                }
+                TICKSLOCK.release();
+                BUFFER_CACHE.flush(None, false);
+            }
+        }
+    }
 }
@@ -0,0 +1,247 @@
+// Devices.
+//
+// A driver registers each of its devices with a name, a major
+// and minor number, and the functions that read, write and
+// control devices of that major number. The functions live in a
+// DevSw, which is struct devsw to the drivers written in C.
+// devread(), devwrite() and devioctl() find the functions for a
+// device inode by its major number, and devfs lists the devices
+// by name, as the files of /dev.
+//
+// This file also has the memory devices: null, which is empty,
+// zero, which reads as zeroes, and random.
+
+use arrayvec::ArrayVec;
+
+use crate::{
+    file::INode,
+    fs::NAMEMAX,
+    param::{NDEV, NDEVICE},
+    rtc::unixtime,
+    spinlock::SpinLockC,
+    x86::rdtsc,
+};
+
+// Major numbers, as in file.h.
+pub const UART: u16 = 3;
+pub const MEM: u16 = 4;
+
+pub type ReadFn =
+    unsafe extern "C" fn(ip: *mut INode, dst: *mut u8, off: u32, n: i32, flags: i32) -> i32;
+pub type WriteFn =
+    unsafe extern "C" fn(ip: *mut INode, src: *const u8, off: u32, n: i32, flags: i32) -> i32;
+pub type IoctlFn = unsafe extern "C" fn(ip: *mut INode, req: i32, arg: *mut u8) -> i32;
+
+// Functions for the devices of one major number; any of them
+// may be missing. They are called with the device inode locked.
+#[repr(C)]
+pub struct DevSw {
+    pub read: Option<ReadFn>,
+    pub write: Option<WriteFn>,
+    pub ioctl: Option<IoctlFn>,
+}
+
+#[derive(Clone, Copy)]
+pub struct Device {
+    pub name: &'static [u8],
+    pub major: u16,
+    pub minor: u16,
+}
+
+struct DeviceTable {
+    lock: SpinLockC,
+    devsw: [Option<&'static DevSw>; NDEV],
+    devices: ArrayVec<Device, NDEVICE>,
+}
+
+static mut DEVICES: DeviceTable = DeviceTable {
+    lock: SpinLockC::new(),
+    devsw: [None; NDEV],
+    devices: ArrayVec::new_const(),
+};
+
+// Register the device name, with number (major, minor), whose
+// major number has the functions sw. Devices of one major
+// number must share their functions. Returns false if name or
+// (major, minor) is taken, or the table is full.
+pub fn register(name: &'static [u8], major: u16, minor: u16, sw: &'static DevSw) -> bool {
+    let table = unsafe { &mut DEVICES };
+    if name.is_empty() || name.len() > NAMEMAX || major as usize >= NDEV {
+        return false;
+    }
+    table.lock.acquire();
+    let ok = !table.devices.is_full()
+        && table.devsw[major as usize].map_or(true, |other| core::ptr::eq(other, sw))
+        && table
+            .devices
+            .iter()
+            .all(|d| d.name != name && (d.major, d.minor) != (major, minor));
+    if ok {
+        table.devsw[major as usize] = Some(sw);
+        table.devices.push(Device { name, major, minor });
+    }
+    table.lock.release();
+    ok
+}
+
+// Registered device i, numbered in the order they came.
+pub fn device(i: usize) -> Option<Device> {
+    let table = unsafe { &mut DEVICES };
+    table.lock.acquire();
+    let d = table.devices.get(i).copied();
+    table.lock.release();
+    d
+}
+
+// Number of registered devices.
+pub fn ndevices() -> usize {
+    let table = unsafe { &mut DEVICES };
+    table.lock.acquire();
+    let n = table.devices.len();
+    table.lock.release();
+    n
+}
+
+fn devsw(ip: *const INode) -> Option<&'static DevSw> {
+    let major = unsafe { (*ip).major } as usize;
+    let table = unsafe { &mut DEVICES };
+    table.lock.acquire();
+    let sw = table.devsw.get(major).copied().flatten();
+    table.lock.release();
+    sw
+}
+
+// Memory devices.
+
+const NULL: u16 = 0;
+const ZERO: u16 = 1;
+const RANDOM: u16 = 2;
+
+// State of a xorshift generator, stirred with the cycle counter
+// on every read. Good enough for games, not for keys.
+static mut SEED: u64 = 0;
+
+fn random() -> u64 {
+    unsafe {
+        let mut x = SEED ^ rdtsc();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        SEED = x;
+        x
+    }
+}
+
+unsafe extern "C" fn memread(ip: *mut INode, dst: *mut u8, _off: u32, n: i32, _flags: i32) -> i32 {
+    if n < 0 {
+        return -1;
+    }
+    let dst = core::slice::from_raw_parts_mut(dst, n as usize);
+    match (*ip).minor {
+        NULL => 0,
+        ZERO => {
+            dst.fill(0);
+            n
+        }
+        RANDOM => {
+            for chunk in dst.chunks_mut(8) {
+                let r = random().to_ne_bytes();
+                chunk.copy_from_slice(&r[..chunk.len()]);
+            }
+            n
+        }
+        _ => -1,
+    }
+}
+
+// Writes to any of them vanish.
+unsafe extern "C" fn memwrite(
+    ip: *mut INode,
+    _src: *const u8,
+    _off: u32,
+    n: i32,
+    _flags: i32,
+) -> i32 {
+    match (*ip).minor {
+        NULL | ZERO | RANDOM => n,
+        _ => -1,
+    }
+}
+
+static MEMSW: DevSw = DevSw {
+    read: Some(memread),
+    write: Some(memwrite),
+    ioctl: None,
+};
+
+pub fn meminit() {
+    unsafe { SEED = (unixtime() as u64) << 32 ^ rdtsc() | 1 };
+    register(b"null", MEM, NULL, &MEMSW);
+    register(b"zero", MEM, ZERO, &MEMSW);
+    register(b"random", MEM, RANDOM, &MEMSW);
+}
+
+mod binding {
+    use super::*;
+
+    // Register a device for a driver in C; name must stay put.
+    #[no_mangle]
+    unsafe extern "C" fn devregister(
+        name: *const u8,
+        major: i32,
+        minor: i32,
+        sw: *const DevSw,
+    ) -> i32 {
+        let mut n = 0;
+        while *name.add(n) != 0 {
+            n += 1;
+        }
+        let name = core::slice::from_raw_parts(name, n);
+        register(name, major as u16, minor as u16, &*sw) as i32 - 1
+    }
+
+    // Read from the device behind inode ip, at byte offset off
+    // for devices that have offsets. flags carries the O_NONBLOCK
+    // bit of the open file, if any.
+    // Caller must hold ip->lock.
+    #[no_mangle]
+    unsafe extern "C" fn devread(
+        ip: *mut INode,
+        dst: *mut u8,
+        off: u32,
+        n: i32,
+        flags: i32,
+    ) -> i32 {
+        match devsw(ip).and_then(|sw| sw.read) {
+            Some(read) => read(ip, dst, off, n, flags),
+            None => -1,
+        }
+    }
+
+    // Write to the device behind inode ip.
+    // Caller must hold ip->lock.
+    #[no_mangle]
+    unsafe extern "C" fn devwrite(
+        ip: *mut INode,
+        src: *const u8,
+        off: u32,
+        n: i32,
+        flags: i32,
+    ) -> i32 {
+        match devsw(ip).and_then(|sw| sw.write) {
+            Some(write) => write(ip, src, off, n, flags),
+            None => -1,
+        }
+    }
+
+    // Carry out the device-specific request req on the device
+    // behind inode ip; arg points to a uint in the kernel.
+    // Caller must hold ip->lock.
+    #[no_mangle]
+    unsafe extern "C" fn devioctl(ip: *mut INode, req: i32, arg: *mut u8) -> i32 {
+        match devsw(ip).and_then(|sw| sw.ioctl) {
+            Some(ioctl) => ioctl(ip, req, arg),
+            None => -1,
+        }
+    }
+}
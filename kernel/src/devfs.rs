@@ -0,0 +1,138 @@
+// devfs: the registered devices, as a directory of device files.
+//
+// There is nothing to store: the root directory lists the
+// device table, and device i is inode i+2, with the device's
+// major and minor number. Devices come and go only through
+// the device table, so devfs refuses to create, link, rename
+// or remove anything.
+
+use crate::{
+    dev::{device, ndevices},
+    file::INode,
+    param::{NDISK, NMOUNT},
+    rtc::unixtime,
+    stat::{T_DEV, T_DIR},
+    vfs::{FileSystem, INodeOperations},
+};
+
+const ROOT: u32 = 1; // inode number of the root directory
+
+pub struct DevFs {
+    time: core::cell::Cell<u32>, // when it was mounted
+}
+
+pub static mut DEVFS: DevFs = DevFs {
+    time: core::cell::Cell::new(0),
+};
+
+// The entry at directory offset off, as (inum, name). Offsets
+// count entries: ".", "..", then the devices.
+fn entry_at(off: u32) -> Option<(u32, &'static [u8])> {
+    match off {
+        0 => Some((ROOT, b".")),
+        1 => Some((ROOT, b"..")),
+        _ => device(off as usize - 2).map(|d| (off, d.name)),
+    }
+}
+
+impl FileSystem for DevFs {
+    fn mount(&self, opts: &[u8]) -> bool {
+        self.time.set(unixtime());
+        opts.is_empty()
+    }
+
+    // Numbered after the disks and the tmpfs file systems.
+    fn dev(&self) -> u32 {
+        (NDISK + NMOUNT) as u32
+    }
+
+    fn root(&self) -> u32 {
+        ROOT
+    }
+
+    fn block_size(&self) -> u32 {
+        512
+    }
+
+    fn alloc(&self, _ty: i16) -> u32 {
+        0
+    }
+
+    fn load(&self, ip: &mut INode) {
+        ip.nlink = 1;
+        ip.size = 0;
+        ip.atime = self.time.get();
+        ip.mtime = self.time.get();
+        ip.ctime = self.time.get();
+        if ip.inum == ROOT {
+            ip.ty = T_DIR;
+            ip.size = ndevices() as u32 + 2;
+            return;
+        }
+        let d = device(ip.inum as usize - 2).expect("devfs: no device");
+        ip.ty = T_DEV;
+        ip.major = d.major;
+        ip.minor = d.minor;
+    }
+
+    fn update(&self, _ip: &mut INode) {}
+
+    fn free(&self, _ip: &mut INode) {}
+}
+
+impl INodeOperations for DevFs {
+    fn read(&self, _ip: &mut INode, _dst: &mut [u8], _off: u32) -> i32 {
+        -1
+    }
+
+    fn write(&self, _ip: &mut INode, _src: &[u8], _off: u32) -> i32 {
+        -1
+    }
+
+    fn truncate(&self, _ip: &mut INode, _size: u32, _budget: i32) -> i32 {
+        -1
+    }
+
+    fn allocate(&self, _ip: &mut INode, _off: u32, _n: u32) -> i32 {
+        -1
+    }
+
+    fn lookup(&self, _dp: &mut INode, name: &[u8], off: &mut u32) -> u32 {
+        let mut o = 0;
+        while let Some((inum, n)) = entry_at(o) {
+            if n == name {
+                *off = o;
+                return inum;
+            }
+            o += 1;
+        }
+        0
+    }
+
+    fn link(&self, _dp: &mut INode, _name: &[u8], _inum: u32) -> i32 {
+        -1
+    }
+
+    fn remove(&self, _dp: &mut INode, _off: u32) -> i32 {
+        -1
+    }
+
+    fn set(&self, _dp: &mut INode, _off: u32, _inum: u32) -> i32 {
+        -1
+    }
+
+    fn entry(&self, dp: &mut INode, off: u32, inum: &mut u32, name: &mut [u8]) -> u32 {
+        // Devices registered since dp was loaded show up too.
+        dp.size = ndevices() as u32 + 2;
+        *inum = 0;
+        match entry_at(off) {
+            Some((i, n)) => {
+                name[..n.len()].copy_from_slice(n);
+                name[n.len()] = 0;
+                *inum = i;
+                off + 1
+            }
+            None => dp.size,
+        }
+    }
+}
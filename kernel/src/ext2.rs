@@ -0,0 +1,505 @@
+// ext2: disks made by Linux's mke2fs, read only.
+//
+// An ext2 file system is a superblock 1024 bytes in, then block
+// groups, each with a bitmap of its blocks, one of its inodes,
+// and a table of the inodes themselves; the group descriptors
+// just after the superblock say where each group's table is.
+// An inode has 12 direct block numbers, then singly, doubly
+// and triply indirect ones, much as xv6's do, and 0 is a hole.
+// A directory is a list of entries of varying length, none of
+// which crosses a block.
+//
+// The inode numbers are ext2's own, with the root at 2. Nothing
+// is written: the operations that would change the disk fail,
+// update() reads the inode back, which undoes any change made
+// to it in memory, and access times are left alone. Devices,
+// FIFOs and sockets are left out of directories, since their
+// numbers mean other things to xv6. A file system with features
+// that move things around, like extents or 64-bit block
+// numbers, or with a journal to recover, does not mount; ext3
+// with a clean journal mounts as ext2.
+
+use core::cell::UnsafeCell;
+
+use crate::{
+    buf::BUFFER_CACHE,
+    file::INode,
+    fs::{MAXBSIZE, MINBSIZE, NAMEMAX},
+    ide::IDE,
+    param::NDISK,
+    stat::{T_DIR, T_FILE, T_SYMLINK},
+    vfs::{FileSystem, INodeOperations},
+};
+
+const SBSECTOR: u32 = 1024 / MINBSIZE as u32; // superblock, read before the block size is known
+const MAGIC: u16 = 0xef53;
+const ROOTINO: u32 = 2;
+const NDIRECT: usize = 12;
+const NBLOCK: usize = NDIRECT + 3; // block numbers in an inode
+const GDSIZE: u32 = 32; // bytes per group descriptor
+
+// Incompatible features it reads: file types in directory
+// entries, and groups whose tables are packed together.
+const INCOMPAT_FILETYPE: u32 = 0x0002;
+const INCOMPAT_FLEX_BG: u32 = 0x0200;
+const INCOMPAT: u32 = INCOMPAT_FILETYPE | INCOMPAT_FLEX_BG;
+
+// Inode modes.
+const S_IFMT: u16 = 0xf000;
+const S_IFDIR: u16 = 0x4000;
+const S_IFREG: u16 = 0x8000;
+const S_IFLNK: u16 = 0xa000;
+
+// File types in directory entries.
+const FT_UNKNOWN: u8 = 0;
+const FT_REG_FILE: u8 = 1;
+const FT_DIR: u8 = 2;
+const FT_SYMLINK: u8 = 7;
+
+// xv6's type for an inode of mode, if it has one.
+fn kind(mode: u16) -> Option<u16> {
+    match mode & S_IFMT {
+        S_IFDIR => Some(T_DIR),
+        S_IFREG => Some(T_FILE),
+        S_IFLNK => Some(T_SYMLINK),
+        _ => None,
+    }
+}
+
+fn u32_at(d: &[u8], i: usize) -> u32 {
+    u32::from_le_bytes([d[i], d[i + 1], d[i + 2], d[i + 3]])
+}
+
+fn u16_at(d: &[u8], i: usize) -> u16 {
+    u16::from_le_bytes([d[i], d[i + 1]])
+}
+
+// The parts of an on-disk inode that are used.
+#[derive(Clone, Copy)]
+struct DInode {
+    mode: u16,
+    nlink: u16,
+    size: u32,
+    atime: u32,
+    ctime: u32,
+    mtime: u32,
+    sectors: u32, // 512-byte sectors held
+    acl: u32,     // block of extended attributes, or 0
+    block: [u32; NBLOCK],
+}
+
+impl DInode {
+    fn new(d: &[u8]) -> Self {
+        let mut block = [0; NBLOCK];
+        for (i, b) in block.iter_mut().enumerate() {
+            *b = u32_at(d, 40 + 4 * i);
+        }
+        DInode {
+            mode: u16_at(d, 0),
+            size: u32_at(d, 4),
+            atime: u32_at(d, 8),
+            ctime: u32_at(d, 12),
+            mtime: u32_at(d, 16),
+            nlink: u16_at(d, 26),
+            sectors: u32_at(d, 28),
+            acl: u32_at(d, 104),
+            block,
+        }
+    }
+}
+
+struct State {
+    dev: u32,
+    bsize: u32,     // bytes per block
+    nblocks: u32,   // blocks in the file system
+    ninodes: u32,   // inodes in the file system
+    ipg: u32,       // inodes per group
+    isize: u32,     // bytes per inode
+    gdt: u32,       // first block of the group descriptors
+    filetype: bool, // do directory entries have file types?
+}
+
+pub struct Ext2 {
+    dev: u32,
+    state: UnsafeCell<State>,
+}
+
+// One for each disk.
+pub static mut EXT2FS: [Ext2; NDISK] = {
+    let mut fs = [const { Ext2::new() }; NDISK];
+    let mut i = 0;
+    while i < NDISK {
+        fs[i].dev = i as u32;
+        i += 1;
+    }
+    fs
+};
+
+impl State {
+    // Run f on block b.
+    fn block<R>(&self, b: u32, f: impl FnOnce(&[u8]) -> R) -> R {
+        unsafe {
+            let buf = BUFFER_CACHE.read(self.dev as usize, b as usize);
+            let r = f(&buf.data()[..self.bsize as usize]);
+            buf.release(&mut BUFFER_CACHE);
+            r
+        }
+    }
+
+    fn valid(&self, b: u32) -> bool {
+        b != 0 && b < self.nblocks
+    }
+
+    // The 32-bit word at byte o of block b, or 0 if there is no
+    // such block.
+    fn get(&self, b: u32, o: u32) -> u32 {
+        if !self.valid(b) {
+            return 0;
+        }
+        self.block(b, |d| u32_at(d, o as usize))
+    }
+
+    fn dinode(&self, inum: u32) -> Option<DInode> {
+        if inum == 0 || inum > self.ninodes {
+            return None;
+        }
+        let (group, i) = ((inum - 1) / self.ipg, (inum - 1) % self.ipg);
+        let at = group * GDSIZE;
+        let table = self.get(self.gdt + at / self.bsize, at % self.bsize + 8);
+        if !self.valid(table) {
+            return None;
+        }
+        let at = i * self.isize;
+        let b = table + at / self.bsize;
+        if !self.valid(b) {
+            return None;
+        }
+        let o = (at % self.bsize) as usize;
+        Some(self.block(b, |d| DInode::new(&d[o..])))
+    }
+
+    // Disk block holding block n of the inode d, or 0 for a
+    // hole.
+    fn bmap(&self, d: &DInode, n: u32) -> u32 {
+        let per = self.bsize / 4;
+        let mut b = 0;
+        if (n as usize) < NDIRECT {
+            b = d.block[n as usize];
+        } else {
+            let mut n = n - NDIRECT as u32;
+            let mut span = per;
+            for level in 0..3 {
+                if n < span {
+                    b = d.block[NDIRECT + level];
+                    for _ in 0..=level {
+                        span /= per;
+                        b = self.get(b, n / span * 4);
+                        n %= span;
+                    }
+                    break;
+                }
+                n -= span;
+                span = span.saturating_mul(per);
+            }
+        }
+        if self.valid(b) {
+            b
+        } else {
+            0
+        }
+    }
+
+    // Is d a symbolic link short enough to be kept in place of
+    // its block numbers?
+    fn fast(&self, d: &DInode) -> bool {
+        let acl = if d.acl != 0 { self.bsize / 512 } else { 0 };
+        d.sectors == acl
+    }
+
+    // The first entry of the directory d, of size bytes, at or
+    // after off, whose name passes f. Returns its offset, the
+    // offset just past it, its inode number and file type.
+    fn scan(
+        &self,
+        d: &DInode,
+        size: u32,
+        mut off: u32,
+        mut f: impl FnMut(&[u8]) -> bool,
+    ) -> Option<(u32, u32, u32, u8)> {
+        let bs = self.bsize;
+        while off < size {
+            let base = off - off % bs;
+            let b = self.bmap(d, off / bs);
+            if b == 0 {
+                off = base + bs;
+                continue;
+            }
+            let found = self.block(b, |data| {
+                let mut o = (off % bs) as usize;
+                while o + 8 <= bs as usize {
+                    let inum = u32_at(data, o);
+                    let reclen = u16_at(data, o + 4) as usize;
+                    let namelen = data[o + 6] as usize;
+                    if reclen < 8 || o + reclen > bs as usize || 8 + namelen > reclen {
+                        // A bad entry ends the block.
+                        break;
+                    }
+                    if inum != 0 && f(&data[o + 8..o + 8 + namelen]) {
+                        return Some((o as u32, (o + reclen) as u32, inum, data[o + 7]));
+                    }
+                    o += reclen;
+                }
+                None
+            });
+            if let Some((o, next, inum, ft)) = found {
+                return Some((base + o, base + next, inum, ft));
+            }
+            off = base + bs;
+        }
+        None
+    }
+
+    // Does a directory show the entry for inum, of file type ft?
+    fn shown(&self, inum: u32, ft: u8) -> bool {
+        if inum > self.ninodes {
+            return false;
+        }
+        if self.filetype && ft != FT_UNKNOWN {
+            return ft == FT_REG_FILE || ft == FT_DIR || ft == FT_SYMLINK;
+        }
+        self.dinode(inum).and_then(|d| kind(d.mode)).is_some()
+    }
+
+    // Read the superblock, with the disk in MINBSIZE blocks.
+    fn mount(&mut self) -> bool {
+        self.bsize = MINBSIZE as u32;
+        let sb = self.block(SBSECTOR, |d| {
+            let mut sb = [0; MINBSIZE];
+            sb.copy_from_slice(d);
+            sb
+        });
+        let word = |i| u32_at(&sb, i);
+        let half = |i| u16_at(&sb, i);
+        if half(56) != MAGIC || word(24) > 2 {
+            return false;
+        }
+        let (rev, incompat) = (word(76), word(96));
+        self.bsize = 1024 << word(24);
+        self.ninodes = word(0);
+        self.nblocks = word(4);
+        self.ipg = word(40);
+        self.isize = if rev == 0 { 128 } else { half(88) as u32 };
+        self.gdt = word(20) + 1;
+        self.filetype = rev != 0 && incompat & INCOMPAT_FILETYPE != 0;
+
+        let bpg = word(32);
+        (rev == 0 || incompat & !INCOMPAT == 0)
+            && self.bsize as usize <= MAXBSIZE
+            && self.nblocks as usize
+                <= unsafe { IDE.as_ref().unwrap().sectors(self.dev as usize) }
+                    / (self.bsize as usize / MINBSIZE)
+            && bpg != 0
+            && self.ipg != 0
+            && self.ninodes / self.ipg <= (self.nblocks + bpg - 1) / bpg
+            && self.isize >= 128
+            && self.isize <= self.bsize
+            && self.isize.is_power_of_two()
+    }
+}
+
+impl Ext2 {
+    const fn new() -> Self {
+        Self {
+            dev: 0,
+            state: UnsafeCell::new(State {
+                dev: 0,
+                bsize: 0,
+                nblocks: 0,
+                ninodes: 0,
+                ipg: 0,
+                isize: 0,
+                gdt: 0,
+                filetype: false,
+            }),
+        }
+    }
+
+    // Nothing changes the state once mounted, so there is no
+    // lock.
+    fn state(&self) -> &State {
+        unsafe { &*self.state.get() }
+    }
+}
+
+impl FileSystem for Ext2 {
+    fn mount(&self, opts: &[u8]) -> bool {
+        if !opts.is_empty() {
+            return false;
+        }
+        if !unsafe { BUFFER_CACHE.set_block_size(self.dev as usize, MINBSIZE) } {
+            return false;
+        }
+        let st = unsafe { &mut *self.state.get() };
+        st.dev = self.dev;
+        if !st.mount() {
+            return false;
+        }
+        if !unsafe { BUFFER_CACHE.set_block_size(self.dev as usize, st.bsize as usize) } {
+            return false;
+        }
+        st.dinode(ROOTINO)
+            .map_or(false, |d| d.mode & S_IFMT == S_IFDIR)
+    }
+
+    fn dev(&self) -> u32 {
+        self.dev
+    }
+
+    fn root(&self) -> u32 {
+        ROOTINO
+    }
+
+    fn block_size(&self) -> u32 {
+        self.state().bsize
+    }
+
+    fn alloc(&self, _ty: i16) -> u32 {
+        0
+    }
+
+    fn load(&self, ip: &mut INode) {
+        let d = self.state().dinode(ip.inum);
+        let d = match d {
+            Some(d) if kind(d.mode).is_some() => d,
+            _ => {
+                ip.ty = 0;
+                return;
+            }
+        };
+        ip.ty = kind(d.mode).unwrap();
+        ip.major = 0;
+        ip.minor = 0;
+        ip.nlink = d.nlink;
+        ip.size = d.size;
+        ip.atime = d.atime;
+        ip.mtime = d.mtime;
+        ip.ctime = d.ctime;
+    }
+
+    fn update(&self, ip: &mut INode) {
+        self.load(ip)
+    }
+
+    fn free(&self, _ip: &mut INode) {}
+}
+
+impl INodeOperations for Ext2 {
+    fn read(&self, ip: &mut INode, dst: &mut [u8], off: u32) -> i32 {
+        if off.checked_add(dst.len() as u32).is_none() {
+            return -1;
+        }
+        if off >= ip.size {
+            return 0;
+        }
+        let n = dst.len().min((ip.size - off) as usize);
+        let st = self.state();
+        let Some(d) = st.dinode(ip.inum) else {
+            return -1;
+        };
+
+        if ip.ty == T_SYMLINK && st.fast(&d) {
+            // The target is where the block numbers would be.
+            let mut target = [0; NBLOCK * 4];
+            for (t, b) in target.chunks_mut(4).zip(d.block.iter()) {
+                t.copy_from_slice(&b.to_le_bytes());
+            }
+            if ip.size as usize > target.len() {
+                return -1;
+            }
+            dst[..n].copy_from_slice(&target[off as usize..off as usize + n]);
+            return n as i32;
+        }
+
+        let bs = st.bsize;
+        let mut tot = 0;
+        while tot < n {
+            let o = off + tot as u32;
+            let m = (n - tot).min((bs - o % bs) as usize);
+            let dst = &mut dst[tot..tot + m];
+            match st.bmap(&d, o / bs) {
+                0 => dst.fill(0),
+                b => st.block(b, |data| {
+                    dst.copy_from_slice(&data[(o % bs) as usize..(o % bs) as usize + m])
+                }),
+            }
+            tot += m;
+        }
+        n as i32
+    }
+
+    fn write(&self, _ip: &mut INode, _src: &[u8], _off: u32) -> i32 {
+        -1
+    }
+
+    fn truncate(&self, _ip: &mut INode, _size: u32, _budget: i32) -> i32 {
+        -1
+    }
+
+    fn allocate(&self, _ip: &mut INode, _off: u32, _n: u32) -> i32 {
+        -1
+    }
+
+    fn lookup(&self, dp: &mut INode, name: &[u8], off: &mut u32) -> u32 {
+        let st = self.state();
+        let d = match st.dinode(dp.inum) {
+            Some(d) if dp.ty == T_DIR => d,
+            _ => return 0,
+        };
+        let mut o = 0;
+        while let Some((at, next, inum, ft)) = st.scan(&d, dp.size, o, |n| n == name) {
+            if st.shown(inum, ft) {
+                *off = at;
+                return inum;
+            }
+            o = next;
+        }
+        0
+    }
+
+    fn link(&self, _dp: &mut INode, _name: &[u8], _inum: u32) -> i32 {
+        -1
+    }
+
+    fn remove(&self, _dp: &mut INode, _off: u32) -> i32 {
+        -1
+    }
+
+    fn set(&self, _dp: &mut INode, _off: u32, _inum: u32) -> i32 {
+        -1
+    }
+
+    fn entry(&self, dp: &mut INode, off: u32, inum: &mut u32, name: &mut [u8]) -> u32 {
+        let st = self.state();
+        *inum = 0;
+        let d = match st.dinode(dp.inum) {
+            Some(d) if dp.ty == T_DIR => d,
+            _ => return dp.size,
+        };
+        let mut o = off;
+        while let Some((_, next, i, ft)) = st.scan(&d, dp.size, o, |n| {
+            if n.len() > NAMEMAX {
+                return false;
+            }
+            name[..n.len()].copy_from_slice(n);
+            name[n.len()] = 0;
+            true
+        }) {
+            o = next;
+            if st.shown(i, ft) {
+                *inum = i;
+                return next;
+            }
+        }
+        dp.size
+    }
+}
@@ -0,0 +1,1517 @@
+// FAT32: disks that other systems read and write too.
+//
+// A FAT32 volume is a boot sector with the volume's geometry,
+// reserved sectors, one or more copies of the file allocation
+// table, and clusters of sectors. The FAT chains the clusters
+// of each file and directory. A directory is an array of
+// 32-byte entries, each an 8.3 short name with the attributes,
+// first cluster and size of a file; a long name goes in extra
+// entries just before it, in UTF-16, last part first.
+//
+// FAT has no inodes, so the inode numbers are made up. A
+// directory's is its first cluster, which never moves. A
+// file's is FILE plus the index of a node, which keeps where
+// the file's entry is for as long as the inode cache may ask;
+// a rename moves the node to the new entry. Nodes that nothing
+// refers to are recycled, least recently used first.
+//
+// There are no hard links, devices or symbolic links, and no
+// log: the buffer cache writes sectors back a few seconds
+// later, and a crash can leave lost clusters for fsck.vfat to
+// find. Names are matched without regard to ASCII case, as
+// other systems do.
+
+use core::cell::{Cell, UnsafeCell};
+
+use crate::{
+    buf::BUFFER_CACHE,
+    file::INode,
+    fs::MINBSIZE,
+    ide::IDE,
+    param::{NDISK, NINODE},
+    rtc::{unixtime, RtcDate},
+    sleeplock::SleepLockC,
+    stat::{T_DIR, T_FILE},
+    vfs::{FileSystem, INodeOperations},
+};
+
+const SECTOR: u32 = MINBSIZE as u32; // bytes per sector, the only size supported
+const ENTRY: u32 = 32; // bytes per directory entry
+const EPS: u32 = SECTOR / ENTRY; // directory entries per sector
+const MASK: u32 = 0x0fff_ffff; // FAT entries have 28 bits
+const EOC: u32 = 0x0fff_ffff; // ends a chain
+const CLEAN: u32 = 0x0800_0000; // in FAT entry 1: unmounted cleanly
+const FILE: u32 = 0x8000_0000; // set in the inode numbers of files
+const NNODE: usize = 4 * NINODE; // file nodes per volume
+const MAXDIR: u32 = 65536 * ENTRY; // largest directory, in bytes
+const MAXLFN: usize = 255; // longest name, in UTF-16 units
+const LFNCHARS: usize = 13; // name units per long name entry
+const NLFN: usize = 20; // most long name entries per name
+
+// Byte offsets of the name units in a long name entry.
+const LFNPOS: [usize; LFNCHARS] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+
+// Entry attributes.
+const ATTR_VOLUME: u8 = 0x08;
+const ATTR_DIR: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+const ATTR_LFN: u8 = 0x0f; // read-only, hidden, system and volume
+const DELETED: u8 = 0xe5; // first name byte of a free entry
+const LOWER_BASE: u8 = 0x08; // short name's base is lower case
+const LOWER_EXT: u8 = 0x10; // and its extension
+
+const DOT: &[u8; 11] = b".          ";
+const DOTDOT: &[u8; 11] = b"..         ";
+
+#[derive(Clone, Copy)]
+struct Entry([u8; ENTRY as usize]);
+
+impl Entry {
+    // A short entry for a file or directory made at time.
+    fn new(short: &[u8; 11], attr: u8, cluster: u32, size: u32, time: u32) -> Self {
+        let mut e = Entry([0; ENTRY as usize]);
+        e.0[..11].copy_from_slice(short);
+        e.0[11] = attr;
+        let (date, t) = fat_time(time);
+        e.set_u16(14, t);
+        e.set_u16(16, date);
+        e.set_cluster(cluster);
+        e.set_u32(28, size);
+        e.set_times(time, time);
+        e
+    }
+
+    // Long name entry ord of a name whose short entry has the
+    // checksum sum, holding the units part.
+    fn lfn(ord: u8, sum: u8, part: &[u16]) -> Self {
+        let mut e = Entry([0; ENTRY as usize]);
+        e.0[0] = ord;
+        e.0[11] = ATTR_LFN;
+        e.0[13] = sum;
+        for (i, &p) in LFNPOS.iter().enumerate() {
+            // The name ends with a 0 unless it fills the entry;
+            // the rest is padding.
+            let u = if i < part.len() {
+                part[i]
+            } else if i == part.len() {
+                0
+            } else {
+                0xffff
+            };
+            e.set_u16(p, u);
+        }
+        e
+    }
+
+    fn u16(&self, i: usize) -> u16 {
+        u16::from_le_bytes([self.0[i], self.0[i + 1]])
+    }
+
+    fn set_u16(&mut self, i: usize, v: u16) {
+        self.0[i..i + 2].copy_from_slice(&v.to_le_bytes());
+    }
+
+    fn set_u32(&mut self, i: usize, v: u32) {
+        self.0[i..i + 4].copy_from_slice(&v.to_le_bytes());
+    }
+
+    fn attr(&self) -> u8 {
+        self.0[11]
+    }
+
+    fn is_lfn(&self) -> bool {
+        self.attr() & 0x3f == ATTR_LFN
+    }
+
+    fn is_dir(&self) -> bool {
+        self.attr() & ATTR_DIR != 0
+    }
+
+    fn short(&self) -> &[u8; 11] {
+        unsafe { &*(self.0.as_ptr() as *const [u8; 11]) }
+    }
+
+    fn is_dot(&self) -> bool {
+        self.short() == DOT || self.short() == DOTDOT
+    }
+
+    fn cluster(&self) -> u32 {
+        (self.u16(20) as u32) << 16 | self.u16(26) as u32
+    }
+
+    fn set_cluster(&mut self, c: u32) {
+        self.set_u16(20, (c >> 16) as u16);
+        self.set_u16(26, c as u16);
+    }
+
+    fn size(&self) -> u32 {
+        u32::from_le_bytes([self.0[28], self.0[29], self.0[30], self.0[31]])
+    }
+
+    fn mtime(&self) -> u32 {
+        unix_time(self.u16(24), self.u16(22))
+    }
+
+    // FAT keeps only the date of the last access.
+    fn atime(&self) -> u32 {
+        unix_time(self.u16(18), 0)
+    }
+
+    fn set_times(&mut self, mtime: u32, atime: u32) {
+        let (date, time) = fat_time(mtime);
+        self.set_u16(22, time);
+        self.set_u16(24, date);
+        self.set_u16(18, fat_time(atime).0);
+    }
+
+    // The short name as a file name, lower-cased where the
+    // case bits say so. Returns its length.
+    fn short_name(&self, out: &mut [u8]) -> usize {
+        let case = self.0[12];
+        let base = trim(&self.0[..8]);
+        let ext = trim(&self.0[8..11]);
+        let mut n = 0;
+        for (i, &c) in base.iter().enumerate() {
+            out[n] = match c {
+                0x05 if i == 0 => DELETED,
+                _ if case & LOWER_BASE != 0 => c.to_ascii_lowercase(),
+                _ => c,
+            };
+            n += 1;
+        }
+        if !ext.is_empty() {
+            out[n] = b'.';
+            n += 1;
+            for &c in ext {
+                out[n] = if case & LOWER_EXT != 0 {
+                    c.to_ascii_lowercase()
+                } else {
+                    c
+                };
+                n += 1;
+            }
+        }
+        n
+    }
+}
+
+// s without its trailing spaces.
+fn trim(s: &[u8]) -> &[u8] {
+    let n = s.iter().rposition(|&c| c != b' ').map_or(0, |i| i + 1);
+    &s[..n]
+}
+
+// Checksum of a short name, which its long name entries carry.
+fn checksum(short: &[u8; 11]) -> u8 {
+    short
+        .iter()
+        .fold(0u8, |sum, &c| (sum >> 1 | sum << 7).wrapping_add(c))
+}
+
+// FAT keeps times as local dates and times, to two seconds;
+// xv6 takes them to be UTC. Returns (date, time).
+fn fat_time(t: u32) -> (u16, u16) {
+    let d = RtcDate::from_unix(t);
+    if d.year < 1980 {
+        return (1 << 5 | 1, 0);
+    }
+    if d.year > 2107 {
+        return (127 << 9 | 12 << 5 | 31, 23 << 11 | 59 << 5 | 29);
+    }
+    let date = (d.year - 1980) << 9 | d.month << 5 | d.day;
+    let time = d.hour << 11 | d.minute << 5 | d.second / 2;
+    (date as u16, time as u16)
+}
+
+fn unix_time(date: u16, time: u16) -> u32 {
+    if date == 0 {
+        return 0;
+    }
+    let (date, time) = (date as u32, time as u32);
+    RtcDate {
+        second: (time & 31) * 2,
+        minute: time >> 5 & 63,
+        hour: time >> 11,
+        day: (date & 31).max(1),
+        month: (date >> 5 & 15).clamp(1, 12),
+        year: (1980 + (date >> 9)).min(2105), // as far as a u32 goes
+    }
+    .to_unix()
+}
+
+// Decode the UTF-8 name s, calling f on each of its UTF-16
+// units. Returns false if s is malformed, or f returns false.
+fn utf16(s: &[u8], mut f: impl FnMut(u16) -> bool) -> bool {
+    let mut i = 0;
+    while i < s.len() {
+        let (len, min) = match s[i] {
+            0x00..=0x7f => (1, 0),
+            0xc0..=0xdf => (2, 0x80),
+            0xe0..=0xef => (3, 0x800),
+            0xf0..=0xf7 => (4, 0x10000),
+            _ => return false,
+        };
+        if i + len > s.len() {
+            return false;
+        }
+        let mut c = match len {
+            1 => s[i] as u32,
+            _ => (s[i] & (0x7f >> len)) as u32,
+        };
+        for &b in &s[i + 1..i + len] {
+            if b & 0xc0 != 0x80 {
+                return false;
+            }
+            c = c << 6 | (b & 0x3f) as u32;
+        }
+        if c < min || c > 0x10ffff || (0xd800..0xe000).contains(&c) {
+            return false;
+        }
+        let ok = if c >= 0x10000 {
+            let c = c - 0x10000;
+            f(0xd800 | (c >> 10) as u16) && f(0xdc00 | (c & 0x3ff) as u16)
+        } else {
+            f(c as u16)
+        };
+        if !ok {
+            return false;
+        }
+        i += len;
+    }
+    true
+}
+
+// Encode the UTF-16 name units as UTF-8 into out, with a
+// trailing NUL. Returns the length, or None if out is too small.
+fn utf8(units: &[u16], out: &mut [u8]) -> Option<usize> {
+    let mut n = 0;
+    let mut i = 0;
+    while i < units.len() {
+        let mut c = units[i] as u32;
+        i += 1;
+        if (0xd800..0xdc00).contains(&c) && i < units.len() && units[i] & 0xfc00 == 0xdc00 {
+            c = 0x10000 + ((c & 0x3ff) << 10 | (units[i] & 0x3ff) as u32);
+            i += 1;
+        } else if (0xd800..0xe000).contains(&c) {
+            c = '?' as u32;
+        }
+        let mut b = [0; 4];
+        let s = char::from_u32(c).unwrap_or('?').encode_utf8(&mut b);
+        if n + s.len() >= out.len() {
+            return None;
+        }
+        out[n..n + s.len()].copy_from_slice(s.as_bytes());
+        n += s.len();
+    }
+    out[n] = 0;
+    Some(n)
+}
+
+// Does the long name units spell name, ignoring ASCII case?
+fn lfn_eq(units: &[u16], name: &[u8]) -> bool {
+    let mut i = 0;
+    let fold = |u: u16| match u {
+        0x61..=0x7a => u - 0x20,
+        _ => u,
+    };
+    utf16(name, |u| {
+        i += 1;
+        i <= units.len() && fold(units[i - 1]) == fold(u)
+    }) && i == units.len()
+}
+
+// Characters a short name may hold, besides letters and digits.
+fn short_char(c: u8) -> bool {
+    c.is_ascii_uppercase() || c.is_ascii_digit() || b"!#$%&'()-@^_`{}~".contains(&c)
+}
+
+// name's short entry name, if name is a plain upper case 8.3
+// name that needs no long name.
+fn exact_short(name: &[u8]) -> Option<[u8; 11]> {
+    let (base, ext) = match name.iter().position(|&c| c == b'.') {
+        Some(i) => (&name[..i], &name[i + 1..]),
+        None => (name, &b""[..]),
+    };
+    if base.is_empty() || base.len() > 8 || ext.len() > 3 || name.ends_with(b".") {
+        return None;
+    }
+    if !base.iter().chain(ext).all(|&c| short_char(c)) {
+        return None;
+    }
+    let mut short = [b' '; 11];
+    short[..base.len()].copy_from_slice(base);
+    short[8..8 + ext.len()].copy_from_slice(ext);
+    Some(short)
+}
+
+// The short name BASE~n for a name that needs a long one: its
+// letters upper-cased, other characters that cannot be in a
+// short name made '_', and its extension, if any, kept.
+fn basis_short(name: &[u8], n: u32) -> [u8; 11] {
+    let dot = name.iter().rposition(|&c| c == b'.').filter(|&i| i > 0);
+    let (base, ext) = match dot {
+        Some(i) => (&name[..i], &name[i + 1..]),
+        None => (name, &b""[..]),
+    };
+    let conv = |c: u8| match c.to_ascii_uppercase() {
+        c if short_char(c) => c,
+        _ => b'_',
+    };
+    let mut short = [b' '; 11];
+    let mut tail = [0u8; 8];
+    let mut t = tail.len();
+    let mut m = n;
+    while m > 0 {
+        t -= 1;
+        tail[t] = b'0' + (m % 10) as u8;
+        m /= 10;
+    }
+    t -= 1;
+    tail[t] = b'~';
+    let keep = 8 - (tail.len() - t);
+    let mut k = 0;
+    for &c in base.iter().filter(|&&c| c != b' ' && c != b'.') {
+        if k == keep {
+            break;
+        }
+        short[k] = conv(c);
+        k += 1;
+    }
+    if k == 0 {
+        short[0] = b'_';
+        k = 1;
+    }
+    short[k..k + tail.len() - t].copy_from_slice(&tail[t..]);
+    for (i, &c) in ext.iter().filter(|&&c| c != b' ').take(3).enumerate() {
+        short[8 + i] = conv(c);
+    }
+    short
+}
+
+// Can name be the name of a file on a FAT volume?
+fn valid_name(name: &[u8]) -> bool {
+    let mut n = 0;
+    !name.is_empty()
+        && !name.ends_with(b".")
+        && !name.ends_with(b" ")
+        && name
+            .iter()
+            .all(|&c| c >= 0x20 && !b"\"*/:<>?\\|".contains(&c))
+        && utf16(name, |_| {
+            n += 1;
+            n <= MAXLFN
+        })
+}
+
+// A long name, gathered from its entries.
+struct Lfn {
+    units: [u16; NLFN * LFNCHARS],
+    len: usize,
+    next: u8,   // ordinal of the entry expected next
+    sum: u8,    // checksum they all carry
+    some: bool, // gathering one?
+    start: u32, // directory offset of its first entry
+}
+
+impl Lfn {
+    fn new() -> Self {
+        Lfn {
+            units: [0; NLFN * LFNCHARS],
+            len: 0,
+            next: 0,
+            sum: 0,
+            some: false,
+            start: 0,
+        }
+    }
+
+    fn add(&mut self, off: u32, e: &Entry) {
+        let ord = e.0[0] & 0x1f;
+        if e.0[0] & 0x40 != 0 {
+            self.some = ord != 0 && ord as usize <= NLFN;
+            self.len = ord as usize * LFNCHARS;
+            self.sum = e.0[13];
+            self.start = off;
+        } else if !self.some || ord != self.next || e.0[13] != self.sum {
+            self.some = false;
+        }
+        if !self.some {
+            return;
+        }
+        let at = (ord as usize - 1) * LFNCHARS;
+        for (i, &p) in LFNPOS.iter().enumerate() {
+            let u = e.u16(p);
+            if u == 0 && at + i < self.len {
+                self.len = at + i;
+            }
+            self.units[at + i] = u;
+        }
+        self.next = ord - 1;
+    }
+
+    // The long name of the short entry e, or nothing if the
+    // entries before it did not spell one out for it.
+    fn name(&self, e: &Entry) -> &[u16] {
+        if self.some && self.next == 0 && self.sum == checksum(e.short()) {
+            &self.units[..self.len]
+        } else {
+            &[]
+        }
+    }
+}
+
+// A directory entry in use, as scan() finds it.
+struct Found {
+    off: u32,   // directory offset of the short entry
+    start: u32, // of its first long name entry, or off
+    loc: u32,   // where the short entry is: sector * EPS + slot
+    entry: Entry,
+}
+
+// A file the inode cache may refer to.
+#[derive(Clone, Copy)]
+struct Node {
+    used: bool,
+    loc: u32, // where its entry is, or 0 once it has none
+    attr: u8,
+    cluster: u32, // first cluster, or 0 if it is empty
+    size: u32,
+    nlink: u16,
+    atime: u32,
+    mtime: u32,
+    ctime: u32,
+    stamp: u32, // when it was last looked up
+}
+
+const FREE: Node = Node {
+    used: false,
+    loc: 0,
+    attr: 0,
+    cluster: 0,
+    size: 0,
+    nlink: 0,
+    atime: 0,
+    mtime: 0,
+    ctime: 0,
+    stamp: 0,
+};
+
+struct State {
+    dev: u32,
+    fat: u32,                      // first sector of the FAT read
+    fatsize: u32,                  // sectors per FAT
+    nfat: u32,                     // FATs written, from fat on
+    data: u32,                     // first sector of cluster 2
+    spc: u32,                      // sectors per cluster
+    nclusters: u32,                // clusters, numbered from 2
+    root: u32,                     // first cluster of the root directory
+    fsinfo: u32,                   // sector of the FSInfo, or 0
+    free: u32,                     // free clusters
+    hint: u32,                     // where to look for one
+    time: u32,                     // when it was mounted
+    clock: u32,                    // stamps nodes
+    cursor: Cell<(u32, u32, u32)>, // (first, n, cluster n) of the last walk
+    nodes: [Node; NNODE],
+}
+
+pub struct Fat32 {
+    dev: u32,
+    lock: UnsafeCell<SleepLockC>,
+    state: UnsafeCell<State>,
+}
+
+// One for each disk.
+pub static mut FAT32FS: [Fat32; NDISK] = {
+    let mut fs = [const { Fat32::new() }; NDISK];
+    let mut i = 0;
+    while i < NDISK {
+        fs[i].dev = i as u32;
+        i += 1;
+    }
+    fs
+};
+
+extern "C" {
+    fn iref(dev: u32, inum: u32) -> i32;
+}
+
+impl State {
+    // Run f on sector s, writing it back if write is set.
+    fn sector<R>(&self, s: u32, write: bool, f: impl FnOnce(&mut [u8]) -> R) -> R {
+        unsafe {
+            let buf = BUFFER_CACHE.read(self.dev as usize, s as usize);
+            let r = f(&mut buf.data()[..SECTOR as usize]);
+            if write {
+                buf.write_later();
+            }
+            buf.release(&mut BUFFER_CACHE);
+            r
+        }
+    }
+
+    // Fill sector s from f, without reading it first.
+    fn overwrite(&self, s: u32, f: impl FnOnce(&mut [u8])) {
+        unsafe {
+            let buf = BUFFER_CACHE.get(self.dev as usize, s as usize);
+            f(&mut buf.data()[..SECTOR as usize]);
+            buf.write_later();
+            buf.release(&mut BUFFER_CACHE);
+        }
+    }
+
+    fn fat_get(&self, c: u32) -> u32 {
+        let o = (c % (SECTOR / 4) * 4) as usize;
+        self.sector(self.fat + c / (SECTOR / 4), false, |d| {
+            u32::from_le_bytes([d[o], d[o + 1], d[o + 2], d[o + 3]]) & MASK
+        })
+    }
+
+    // Set FAT entry c in every copy, keeping the top four bits,
+    // which are reserved.
+    fn fat_set(&self, c: u32, v: u32) {
+        let o = (c % (SECTOR / 4) * 4) as usize;
+        for i in 0..self.nfat {
+            self.sector(self.fat + i * self.fatsize + c / (SECTOR / 4), true, |d| {
+                let old = u32::from_le_bytes([d[o], d[o + 1], d[o + 2], d[o + 3]]);
+                d[o..o + 4].copy_from_slice(&(old & !MASK | v & MASK).to_le_bytes());
+            });
+        }
+    }
+
+    fn valid(&self, c: u32) -> bool {
+        c >= 2 && c < self.nclusters + 2
+    }
+
+    // The cluster after c in its chain, if any.
+    fn next(&self, c: u32) -> Option<u32> {
+        let n = self.fat_get(c);
+        if self.valid(n) {
+            Some(n)
+        } else {
+            None
+        }
+    }
+
+    // Where to start walking the chain at first to cluster n:
+    // where the last walk stopped, if it can be. Reading or
+    // writing a file in order asks for each cluster in turn.
+    fn start(&self, first: u32, n: u32) -> (u32, u32) {
+        match self.cursor.get() {
+            (f, i, c) if f == first && i <= n => (i, c),
+            _ => (0, first),
+        }
+    }
+
+    // Cluster n of the chain at first.
+    fn nth(&self, first: u32, n: u32) -> Option<u32> {
+        if !self.valid(first) {
+            return None;
+        }
+        let (mut i, mut c) = self.start(first, n);
+        while i < n {
+            c = self.next(c)?;
+            i += 1;
+        }
+        self.cursor.set((first, n, c));
+        Some(c)
+    }
+
+    fn chain_len(&self, first: u32) -> u32 {
+        let mut n = 0;
+        let mut c = Some(first).filter(|&c| self.valid(c));
+        while let Some(cl) = c {
+            n += 1;
+            if n >= self.nclusters {
+                break;
+            }
+            c = self.next(cl);
+        }
+        n
+    }
+
+    fn cluster_bytes(&self) -> u32 {
+        self.spc * SECTOR
+    }
+
+    fn sector_of(&self, c: u32) -> u32 {
+        self.data + (c - 2) * self.spc
+    }
+
+    // Allocate a cluster and chain it after prev, unless prev
+    // is 0. A directory's cluster is zeroed, which marks the end
+    // of its entries.
+    fn alloc_cluster(&mut self, prev: u32, zero: bool) -> Option<u32> {
+        if self.free == 0 {
+            return None;
+        }
+        let mut c = self.hint;
+        for _ in 0..self.nclusters {
+            if !self.valid(c) {
+                c = 2;
+            }
+            if self.fat_get(c) == 0 {
+                if zero {
+                    for s in 0..self.spc {
+                        self.overwrite(self.sector_of(c) + s, |d| d.fill(0));
+                    }
+                }
+                self.fat_set(c, EOC);
+                if prev != 0 {
+                    self.fat_set(prev, c);
+                }
+                self.free -= 1;
+                self.hint = c + 1;
+                return Some(c);
+            }
+            c += 1;
+        }
+        self.free = 0;
+        None
+    }
+
+    fn free_chain(&mut self, first: u32) {
+        self.cursor.set((0, 0, 0));
+        let mut c = Some(first).filter(|&c| self.valid(c));
+        let mut n = 0;
+        while let Some(cl) = c {
+            c = self.next(cl);
+            self.fat_set(cl, 0);
+            self.free += 1;
+            n += 1;
+            if n >= self.nclusters {
+                break;
+            }
+        }
+    }
+
+    // Cut the chain at *first to the clusters that hold size
+    // bytes.
+    fn trim(&mut self, first: &mut u32, size: u32) {
+        let keep = (size as u64 + self.cluster_bytes() as u64 - 1) / self.cluster_bytes() as u64;
+        if keep == 0 {
+            self.free_chain(*first);
+            *first = 0;
+        } else if let Some(last) = self.nth(*first, keep as u32 - 1) {
+            if let Some(rest) = self.next(last) {
+                self.fat_set(last, EOC);
+                self.free_chain(rest);
+            }
+        }
+    }
+
+    // Copy the chain at first, from off, to dst. Returns the
+    // bytes copied, short if the chain ends.
+    fn read_chain(&self, first: u32, dst: &mut [u8], off: u32) -> usize {
+        let cb = self.cluster_bytes();
+        let mut c = self.nth(first, off / cb);
+        let mut done = 0;
+        while done < dst.len() {
+            let Some(cl) = c else {
+                break;
+            };
+            let o = off + done as u32;
+            let at = (o % SECTOR) as usize;
+            let m = (dst.len() - done).min(SECTOR as usize - at);
+            let dst = &mut dst[done..done + m];
+            self.sector(self.sector_of(cl) + o % cb / SECTOR, false, |d| {
+                dst.copy_from_slice(&d[at..at + m])
+            });
+            done += m;
+            if (o + m as u32) % cb == 0 {
+                c = self.next(cl);
+            }
+        }
+        done
+    }
+
+    // Copy src into the chain at *first, from off, adding the
+    // clusters it needs; *first is 0 for an empty chain. Returns
+    // the bytes copied, short once the volume is full.
+    fn write_chain(&mut self, first: &mut u32, src: &[u8], off: u32) -> usize {
+        let cb = self.cluster_bytes();
+        let mut c = None;
+        let mut done = 0;
+        while done < src.len() {
+            let o = off + done as u32;
+            let cl = match c {
+                Some(cl) => cl,
+                None => match self.reach(first, o / cb) {
+                    Some(cl) => cl,
+                    None => break,
+                },
+            };
+            let at = (o % SECTOR) as usize;
+            let m = (src.len() - done).min(SECTOR as usize - at);
+            let src = &src[done..done + m];
+            let s = self.sector_of(cl) + o % cb / SECTOR;
+            if m == SECTOR as usize {
+                self.overwrite(s, |d| d.copy_from_slice(src));
+            } else {
+                self.sector(s, true, |d| d[at..at + m].copy_from_slice(src));
+            }
+            done += m;
+            c = Some(cl).filter(|_| (o + m as u32) % cb != 0);
+        }
+        done
+    }
+
+    // Cluster n of the chain at *first, lengthening the chain
+    // to reach it.
+    fn reach(&mut self, first: &mut u32, n: u32) -> Option<u32> {
+        if !self.valid(*first) {
+            *first = self.alloc_cluster(0, false)?;
+        }
+        let (mut i, mut c) = self.start(*first, n);
+        while i < n {
+            c = match self.next(c) {
+                Some(next) => next,
+                None => self.alloc_cluster(c, false)?,
+            };
+            i += 1;
+            self.cursor.set((*first, i, c));
+        }
+        Some(c)
+    }
+
+    // Write zeroes to the chain at *first, from off to end.
+    // Returns how far it got.
+    fn zero_chain(&mut self, first: &mut u32, off: u32, end: u32) -> u32 {
+        const ZEROES: [u8; SECTOR as usize] = [0; SECTOR as usize];
+        let mut o = off;
+        while o < end {
+            let m = (end - o).min(SECTOR - o % SECTOR);
+            let n = self.write_chain(first, &ZEROES[..m as usize], o) as u32;
+            o += n;
+            if n < m {
+                break;
+            }
+        }
+        o
+    }
+
+    // Where the entry at off in the directory at first is.
+    fn loc(&self, first: u32, off: u32) -> Option<u32> {
+        let cb = self.cluster_bytes();
+        let c = self.nth(first, off / cb)?;
+        Some((self.sector_of(c) + off % cb / SECTOR) * EPS + off % SECTOR / ENTRY)
+    }
+
+    fn get_entry(&self, loc: u32) -> Entry {
+        let at = (loc % EPS * ENTRY) as usize;
+        self.sector(loc / EPS, false, |d| {
+            let mut e = Entry([0; ENTRY as usize]);
+            e.0.copy_from_slice(&d[at..at + ENTRY as usize]);
+            e
+        })
+    }
+
+    fn put_entry(&self, loc: u32, e: &Entry) {
+        let at = (loc % EPS * ENTRY) as usize;
+        self.sector(loc / EPS, true, |d| {
+            d[at..at + ENTRY as usize].copy_from_slice(&e.0)
+        });
+    }
+
+    // Walk the entries of the directory at first from off on,
+    // calling f on each in use with its long name, if it has
+    // one, until f says stop. Returns that entry.
+    fn scan(
+        &self,
+        first: u32,
+        off: u32,
+        mut f: impl FnMut(&Found, &[u16]) -> bool,
+    ) -> Option<Found> {
+        let cb = self.cluster_bytes();
+        let mut o = off - off % ENTRY;
+        let mut c = self.nth(first, o / cb)?;
+        let mut lfn = Lfn::new();
+        loop {
+            if o >= MAXDIR {
+                return None;
+            }
+            if o % cb == 0 && o != off - off % ENTRY {
+                c = self.next(c)?;
+            }
+            let loc = (self.sector_of(c) + o % cb / SECTOR) * EPS + o % SECTOR / ENTRY;
+            let e = self.get_entry(loc);
+            match e.0[0] {
+                0 => return None, // no more entries
+                DELETED => lfn.some = false,
+                _ if e.is_lfn() => lfn.add(o, &e),
+                _ if e.attr() & ATTR_VOLUME != 0 => lfn.some = false,
+                _ => {
+                    let name = lfn.name(&e);
+                    let found = Found {
+                        off: o,
+                        start: if name.is_empty() { o } else { lfn.start },
+                        loc,
+                        entry: e,
+                    };
+                    if f(&found, name) {
+                        return Some(found);
+                    }
+                    lfn.some = false;
+                }
+            }
+            o += ENTRY;
+        }
+    }
+
+    // The entry in use at off in the directory at first.
+    fn entry_at(&self, first: u32, off: u32) -> Option<Found> {
+        self.scan(first, 0, |f, _| f.off >= off)
+            .filter(|f| f.off == off)
+    }
+
+    // Offset of n free entries in a row in directory dp,
+    // adding a cluster to it if there are none.
+    fn free_run(&mut self, dp: &mut INode, n: u32) -> Option<u32> {
+        let cb = self.cluster_bytes();
+        let mut c = self.nth(dp.inum, 0)?;
+        let (mut o, mut start, mut run) = (0, 0, 0);
+        loop {
+            if o % cb == 0 && o != 0 {
+                c = match self.next(c) {
+                    Some(next) => next,
+                    None if o + cb <= MAXDIR => {
+                        let next = self.alloc_cluster(c, true)?;
+                        dp.size = o + cb;
+                        next
+                    }
+                    None => return None,
+                };
+            }
+            let loc = (self.sector_of(c) + o % cb / SECTOR) * EPS + o % SECTOR / ENTRY;
+            match self.get_entry(loc).0[0] {
+                0 | DELETED => {
+                    if run == 0 {
+                        start = o;
+                    }
+                    run += 1;
+                    if run == n {
+                        return Some(start);
+                    }
+                }
+                _ => run = 0,
+            }
+            o += ENTRY;
+        }
+    }
+
+    fn node(&mut self, inum: u32) -> &mut Node {
+        match self.nodes.get_mut((inum & !FILE) as usize) {
+            Some(n) if inum & FILE != 0 && n.used => n,
+            _ => panic!("fat32: no node"),
+        }
+    }
+
+    // A node for a new file, recycling the least recently used
+    // one that the inode cache does not refer to. Between a
+    // lookup and its iget() the node is unreferenced, but it is
+    // the most recently used, so it is the last one taken.
+    fn alloc_node(&mut self, n: Node) -> u32 {
+        let mut pick = None;
+        for i in 0..NNODE {
+            let old = &self.nodes[i];
+            if !old.used {
+                pick = Some(i);
+                break;
+            }
+            if old.loc != 0
+                && pick.map_or(true, |p: usize| old.stamp < self.nodes[p].stamp)
+                && unsafe { iref(self.dev, FILE | i as u32) } == 0
+            {
+                pick = Some(i);
+            }
+        }
+        let Some(i) = pick else {
+            return 0;
+        };
+        self.clock += 1;
+        self.nodes[i] = Node {
+            used: true,
+            stamp: self.clock,
+            ..n
+        };
+        FILE | i as u32
+    }
+
+    // The inode number of the entry found in directory dp, or
+    // 0 if there is no room to give it one.
+    fn inum(&mut self, dp: &INode, f: &Found) -> u32 {
+        let e = &f.entry;
+        if e.is_dir() {
+            return match e.cluster() {
+                _ if e.short() == DOT => dp.inum,
+                c if self.valid(c) => c,
+                0 if e.short() == DOTDOT => self.root,
+                _ => 0,
+            };
+        }
+        self.clock += 1;
+        let clock = self.clock;
+        if let Some(i) = self.nodes.iter().position(|n| n.used && n.loc == f.loc) {
+            self.nodes[i].stamp = clock;
+            return FILE | i as u32;
+        }
+        self.alloc_node(Node {
+            loc: f.loc,
+            attr: e.attr(),
+            cluster: e.cluster(),
+            size: e.size(),
+            nlink: 1,
+            atime: e.atime(),
+            mtime: e.mtime(),
+            ctime: e.mtime(),
+            ..FREE
+        })
+    }
+
+    // The node whose entry is at loc no longer has one.
+    fn detach(&mut self, loc: u32) {
+        for n in self.nodes.iter_mut().filter(|n| n.used && n.loc == loc) {
+            n.loc = 0;
+        }
+    }
+
+    // First cluster of ip's content.
+    fn first(&mut self, ip: &INode) -> u32 {
+        if ip.inum & FILE != 0 {
+            self.node(ip.inum).cluster
+        } else {
+            ip.inum
+        }
+    }
+
+    // A short entry for inode inum, named short.
+    fn entry_for(&mut self, short: &[u8; 11], inum: u32) -> Entry {
+        if inum & FILE == 0 {
+            return Entry::new(short, ATTR_DIR, inum, 0, unixtime());
+        }
+        let n = *self.node(inum);
+        let mut e = Entry::new(short, n.attr, n.cluster, n.size, n.mtime);
+        e.set_times(n.mtime, n.atime);
+        e
+    }
+
+    // Write ip's node back to its entry.
+    fn update(&mut self, ip: &mut INode) {
+        ip.ctime = unixtime();
+        if ip.inum & FILE == 0 {
+            return;
+        }
+        let n = self.node(ip.inum);
+        n.nlink = ip.nlink;
+        n.size = ip.size;
+        n.atime = ip.atime;
+        n.mtime = ip.mtime;
+        n.ctime = ip.ctime;
+        let n = *n;
+        if n.loc != 0 {
+            let mut e = self.get_entry(n.loc);
+            e.set_cluster(n.cluster);
+            e.set_u32(28, n.size);
+            e.set_times(n.mtime, n.atime);
+            self.put_entry(n.loc, &e);
+        }
+    }
+
+    // Make the file ip size bytes long; growing it writes
+    // zeroes, since FAT has no holes.
+    fn resize(&mut self, ip: &mut INode, size: u32) -> bool {
+        let mut first = self.first(ip);
+        let ok = if size < ip.size {
+            self.trim(&mut first, size);
+            ip.size = size;
+            true
+        } else {
+            let end = self.zero_chain(&mut first, ip.size, size);
+            self.trim(&mut first, end);
+            ip.size = end;
+            end == size
+        };
+        self.node(ip.inum).cluster = first;
+        ip.mtime = unixtime();
+        self.update(ip);
+        ok
+    }
+
+    fn mount(&mut self) -> bool {
+        let mut b = [0u8; 64];
+        if !self.sector(0, false, |d| {
+            b.copy_from_slice(&d[..64]);
+            d[510] == 0x55 && d[511] == 0xaa
+        }) {
+            return false;
+        }
+        let half = |i: usize| u16::from_le_bytes([b[i], b[i + 1]]) as u32;
+        let word = |i: usize| u32::from_le_bytes([b[i], b[i + 1], b[i + 2], b[i + 3]]);
+        let (spc, nfat, rsvd, fatsize) = (b[13] as u32, b[16] as u32, half(14), word(36));
+        let total = if half(19) != 0 { half(19) } else { word(32) };
+        // FAT12 and FAT16 have a fixed root directory and a
+        // 16-bit FAT size; FAT32 has neither.
+        if half(11) != SECTOR
+            || !spc.is_power_of_two()
+            || rsvd == 0
+            || nfat == 0
+            || half(17) != 0
+            || half(22) != 0
+            || fatsize == 0
+            || total as usize > unsafe { IDE.as_ref().unwrap().sectors(self.dev as usize) }
+        {
+            return false;
+        }
+        let data = rsvd + nfat * fatsize;
+        if total <= data {
+            return false;
+        }
+        self.nclusters = (total - data) / spc;
+        self.spc = spc;
+        self.data = data;
+        self.fatsize = fatsize;
+        self.root = word(44);
+        if self.nclusters == 0
+            || fatsize * (SECTOR / 4) < self.nclusters + 2
+            || !self.valid(self.root)
+        {
+            return false;
+        }
+        // Unless the FATs are mirrored, only the active one is
+        // read and written.
+        let flags = half(40);
+        if flags & 0x80 != 0 {
+            if flags & 0xf >= nfat {
+                return false;
+            }
+            self.fat = rsvd + (flags & 0xf) * fatsize;
+            self.nfat = 1;
+        } else {
+            self.fat = rsvd;
+            self.nfat = nfat;
+        }
+
+        // Count the free clusters rather than trust the FSInfo,
+        // and mark its count unknown while the volume is mounted.
+        self.free = 0;
+        let last = self.nclusters + 2;
+        for s in 0..(last + SECTOR / 4 - 1) / (SECTOR / 4) {
+            let c0 = s * (SECTOR / 4);
+            self.free += self.sector(self.fat + s, false, |d| {
+                d.chunks(4)
+                    .enumerate()
+                    .filter(|&(i, e)| {
+                        let c = c0 + i as u32;
+                        c >= 2
+                            && c < last
+                            && u32::from_le_bytes([e[0], e[1], e[2], e[3]]) & MASK == 0
+                    })
+                    .count() as u32
+            });
+        }
+        self.hint = 2;
+        self.fsinfo = 0;
+        let fsinfo = half(48);
+        if fsinfo != 0 && fsinfo < rsvd {
+            let hint = self.sector(fsinfo, false, |d| {
+                let get = |i: usize| u32::from_le_bytes([d[i], d[i + 1], d[i + 2], d[i + 3]]);
+                if get(0) == 0x4161_5252 && get(484) == 0x6141_7272 {
+                    Some(get(492))
+                } else {
+                    None
+                }
+            });
+            if let Some(hint) = hint {
+                self.sector(fsinfo, true, |d| {
+                    d[488..492].copy_from_slice(&u32::MAX.to_le_bytes())
+                });
+                self.fsinfo = fsinfo;
+                self.hint = hint;
+            }
+        }
+        let v = self.fat_get(1);
+        self.fat_set(1, v & !CLEAN);
+
+        self.time = unixtime();
+        self.clock = 0;
+        self.cursor.set((0, 0, 0));
+        self.nodes = [FREE; NNODE];
+        true
+    }
+
+    fn unmount(&mut self) {
+        if self.fsinfo != 0 {
+            let (free, hint) = (self.free, self.hint);
+            self.sector(self.fsinfo, true, |d| {
+                d[488..492].copy_from_slice(&free.to_le_bytes());
+                d[492..496].copy_from_slice(&hint.to_le_bytes());
+            });
+        }
+        let v = self.fat_get(1);
+        self.fat_set(1, v | CLEAN);
+    }
+}
+
+impl Fat32 {
+    const fn new() -> Self {
+        Self {
+            dev: 0,
+            lock: UnsafeCell::new(SleepLockC::new()),
+            state: UnsafeCell::new(State {
+                dev: 0,
+                fat: 0,
+                fatsize: 0,
+                nfat: 0,
+                data: 0,
+                spc: 0,
+                nclusters: 0,
+                root: 0,
+                fsinfo: 0,
+                free: 0,
+                hint: 0,
+                time: 0,
+                clock: 0,
+                cursor: Cell::new((0, 0, 0)),
+                nodes: [FREE; NNODE],
+            }),
+        }
+    }
+
+    // Run f with the volume locked: its operations take turns.
+    fn locked<R>(&self, f: impl FnOnce(&mut State) -> R) -> R {
+        unsafe {
+            (*self.lock.get()).acquire();
+            let r = f(&mut *self.state.get());
+            (*self.lock.get()).release();
+            r
+        }
+    }
+}
+
+impl FileSystem for Fat32 {
+    fn mount(&self, opts: &[u8]) -> bool {
+        if !opts.is_empty() {
+            return false;
+        }
+        if !unsafe { BUFFER_CACHE.set_block_size(self.dev as usize, MINBSIZE) } {
+            return false;
+        }
+        self.locked(|st| {
+            st.dev = self.dev;
+            st.mount()
+        })
+    }
+
+    fn unmount(&self) {
+        self.locked(|st| st.unmount())
+    }
+
+    fn dev(&self) -> u32 {
+        self.dev
+    }
+
+    fn root(&self) -> u32 {
+        self.locked(|st| st.root)
+    }
+
+    fn block_size(&self) -> u32 {
+        self.locked(|st| st.cluster_bytes())
+    }
+
+    // A directory gets its first cluster now, since that is its
+    // inode number; a file gets a node, and its entry when it
+    // is linked.
+    fn alloc(&self, ty: i16) -> u32 {
+        self.locked(|st| match ty as u16 {
+            T_DIR => st.alloc_cluster(0, true).unwrap_or(0),
+            T_FILE => {
+                let time = unixtime();
+                st.alloc_node(Node {
+                    attr: ATTR_ARCHIVE,
+                    atime: time,
+                    mtime: time,
+                    ctime: time,
+                    ..FREE
+                })
+            }
+            _ => 0,
+        })
+    }
+
+    fn load(&self, ip: &mut INode) {
+        self.locked(|st| {
+            ip.major = 0;
+            ip.minor = 0;
+            if ip.inum & FILE != 0 {
+                let n = *st.node(ip.inum);
+                ip.ty = T_FILE;
+                ip.nlink = n.nlink;
+                ip.size = n.size;
+                ip.atime = n.atime;
+                ip.mtime = n.mtime;
+                ip.ctime = n.ctime;
+                return;
+            }
+            // A directory's times are in its "." entry, and its
+            // links are its entry, "." and the ".." of each
+            // subdirectory, as xv6 counts them.
+            let mut time = st.time;
+            let mut nlink = 1;
+            st.scan(ip.inum, 0, |f, _| {
+                if f.entry.short() == DOT {
+                    time = f.entry.mtime();
+                } else if f.entry.is_dir() && !f.entry.is_dot() {
+                    nlink += 1;
+                }
+                false
+            });
+            ip.ty = T_DIR;
+            ip.nlink = nlink;
+            ip.size = st.chain_len(ip.inum) * st.cluster_bytes();
+            ip.atime = time;
+            ip.mtime = time;
+            ip.ctime = time;
+        })
+    }
+
+    fn update(&self, ip: &mut INode) {
+        self.locked(|st| st.update(ip))
+    }
+
+    fn free(&self, ip: &mut INode) {
+        self.locked(|st| {
+            let first = st.first(ip);
+            st.free_chain(first);
+            if ip.inum & FILE != 0 {
+                *st.node(ip.inum) = FREE;
+            }
+        });
+        ip.ty = 0;
+    }
+}
+
+impl INodeOperations for Fat32 {
+    fn read(&self, ip: &mut INode, dst: &mut [u8], off: u32) -> i32 {
+        if off.checked_add(dst.len() as u32).is_none() {
+            return -1;
+        }
+        if off >= ip.size {
+            return 0;
+        }
+        let n = dst.len().min((ip.size - off) as usize);
+        ip.atime = unixtime();
+        self.locked(|st| {
+            let first = st.first(ip);
+            st.read_chain(first, &mut dst[..n], off) as i32
+        })
+    }
+
+    fn write(&self, ip: &mut INode, src: &[u8], off: u32) -> i32 {
+        if ip.ty != T_FILE || off.checked_add(src.len() as u32).is_none() {
+            return -1;
+        }
+        self.locked(|st| {
+            if off > ip.size && !st.resize(ip, off) {
+                return -1;
+            }
+            let mut first = st.first(ip);
+            let n = st.write_chain(&mut first, src, off) as u32;
+            st.node(ip.inum).cluster = first;
+            if n == 0 && !src.is_empty() {
+                st.trim(&mut first, ip.size);
+                st.node(ip.inum).cluster = first;
+                return -1;
+            }
+            if off + n > ip.size {
+                ip.size = off + n;
+            }
+            ip.mtime = unixtime();
+            st.update(ip);
+            n as i32
+        })
+    }
+
+    fn truncate(&self, ip: &mut INode, size: u32, _budget: i32) -> i32 {
+        if ip.ty != T_FILE {
+            return -1;
+        }
+        match self.locked(|st| st.resize(ip, size)) {
+            true => 1,
+            false => -1,
+        }
+    }
+
+    fn allocate(&self, ip: &mut INode, off: u32, n: u32) -> i32 {
+        let end = match off.checked_add(n) {
+            Some(end) if n != 0 && ip.ty == T_FILE => end,
+            _ => return -1,
+        };
+        if end > ip.size {
+            self.locked(|st| st.resize(ip, end));
+        }
+        match ip.size.min(end) {
+            covered if covered > off => (covered - off) as i32,
+            _ => -1,
+        }
+    }
+
+    fn lookup(&self, dp: &mut INode, name: &[u8], off: &mut u32) -> u32 {
+        self.locked(|st| {
+            // The root has no "." or ".." entries.
+            if dp.inum == st.root && (name == b"." || name == b"..") {
+                *off = dp.size;
+                return st.root;
+            }
+            let mut short = [0; 12];
+            let found = st.scan(dp.inum, 0, |f, lfn| {
+                let n = f.entry.short_name(&mut short);
+                lfn_eq(lfn, name) || short[..n].eq_ignore_ascii_case(name)
+            });
+            match found {
+                Some(f) => {
+                    *off = f.off;
+                    st.inum(dp, &f)
+                }
+                None => 0,
+            }
+        })
+    }
+
+    fn link(&self, dp: &mut INode, name: &[u8], inum: u32) -> i32 {
+        self.locked(|st| {
+            if name == b"." || name == b".." {
+                let (short, c) = match name {
+                    b"." => (DOT, dp.inum),
+                    _ if inum == st.root => (DOTDOT, 0),
+                    _ => (DOTDOT, inum),
+                };
+                let off = match st.free_run(dp, 1) {
+                    Some(off) => off,
+                    None => return -1,
+                };
+                let e = Entry::new(short, ATTR_DIR, c, 0, unixtime());
+                st.put_entry(st.loc(dp.inum, off).unwrap(), &e);
+                return 0;
+            }
+            // A file with a second link on the way is being
+            // hard linked, which FAT has no room for; with one
+            // it is being renamed.
+            if !valid_name(name) || inum & FILE != 0 && st.node(inum).nlink > 1 {
+                return -1;
+            }
+
+            let mut units = [0u16; MAXLFN];
+            let mut len = 0;
+            let (short, nlfn) = match exact_short(name) {
+                Some(short) => (short, 0),
+                None => {
+                    utf16(name, |u| {
+                        units[len] = u;
+                        len += 1;
+                        true
+                    });
+                    let mut n = 1;
+                    let short = loop {
+                        let short = basis_short(name, n);
+                        if st
+                            .scan(dp.inum, 0, |f, _| f.entry.short() == &short)
+                            .is_none()
+                        {
+                            break short;
+                        }
+                        n += 1;
+                        if n > 999_999 {
+                            return -1;
+                        }
+                    };
+                    (short, (len + LFNCHARS - 1) / LFNCHARS)
+                }
+            };
+            let off = match st.free_run(dp, nlfn as u32 + 1) {
+                Some(off) => off,
+                None => return -1,
+            };
+
+            let sum = checksum(&short);
+            for i in 0..nlfn {
+                let ord = (nlfn - i) as u8;
+                let part = &units[(ord as usize - 1) * LFNCHARS..len.min(ord as usize * LFNCHARS)];
+                let e = Entry::lfn(if i == 0 { ord | 0x40 } else { ord }, sum, part);
+                st.put_entry(st.loc(dp.inum, off + i as u32 * ENTRY).unwrap(), &e);
+            }
+            let loc = st.loc(dp.inum, off + nlfn as u32 * ENTRY).unwrap();
+            let e = st.entry_for(&short, inum);
+            st.put_entry(loc, &e);
+            if inum & FILE != 0 {
+                st.node(inum).loc = loc;
+            }
+            0
+        })
+    }
+
+    fn remove(&self, dp: &mut INode, off: u32) -> i32 {
+        self.locked(|st| {
+            let f = match st.entry_at(dp.inum, off) {
+                Some(f) if !f.entry.is_dot() => f,
+                _ => return -1,
+            };
+            for o in (f.start..=f.off).step_by(ENTRY as usize) {
+                let loc = st.loc(dp.inum, o).unwrap();
+                let mut e = st.get_entry(loc);
+                e.0[0] = DELETED;
+                st.put_entry(loc, &e);
+            }
+            st.detach(f.loc);
+            0
+        })
+    }
+
+    fn set(&self, dp: &mut INode, off: u32, inum: u32) -> i32 {
+        self.locked(|st| {
+            let Some(f) = st.entry_at(dp.inum, off) else {
+                return -1;
+            };
+            let mut e = f.entry;
+            if e.short() == DOTDOT {
+                e.set_cluster(if inum == st.root { 0 } else { inum });
+                st.put_entry(f.loc, &e);
+                return 0;
+            }
+            if e.is_dot() {
+                return -1;
+            }
+            let short = *e.short();
+            let case = e.0[12];
+            st.detach(f.loc);
+            let mut e = st.entry_for(&short, inum);
+            e.0[12] = case;
+            st.put_entry(f.loc, &e);
+            if inum & FILE != 0 {
+                st.node(inum).loc = f.loc;
+            }
+            0
+        })
+    }
+
+    fn entry(&self, dp: &mut INode, off: u32, inum: &mut u32, name: &mut [u8]) -> u32 {
+        self.locked(|st| {
+            let mut o = off;
+            *inum = 0;
+            while let Some(f) = st.scan(dp.inum, o, |f, lfn| {
+                // Long names that do not fit go by their short
+                // names.
+                if lfn.is_empty() || utf8(lfn, name).is_none() {
+                    let n = f.entry.short_name(name);
+                    name[n] = 0;
+                }
+                true
+            }) {
+                o = f.off + ENTRY;
+                *inum = st.inum(dp, &f);
+                if *inum != 0 {
+                    return o;
+                }
+            }
+            dp.size
+        })
+    }
+}
@@ -0,0 +1,25 @@
+pub const O_RDONLY: u32 = 0x000;
+pub const O_WRONLY: u32 = 0x001;
+pub const O_RDWR: u32 = 0x002;
+pub const O_CREATE: u32 = 0x200;
+pub const O_TRUNC: u32 = 0x400; // truncate a regular file to length 0
+pub const O_APPEND: u32 = 0x800; // every write goes to the end of the file
+pub const O_EXCL: u32 = 0x1000; // with O_CREATE, fail if the file exists
+pub const O_CLOEXEC: u32 = 0x2000; // close the descriptor on exec
+pub const O_NONBLOCK: u32 = 0x4000; // reads and writes fail instead of sleeping
+pub const O_NOFOLLOW: u32 = 0x8000; // fail if the last path element is a symbolic link
+
+// File descriptor flags
+pub const FD_CLOEXEC: u8 = 1;
+
+// fcntl() commands
+pub const F_DUPFD: i32 = 0; // duplicate onto the lowest free descriptor >= arg
+pub const F_GETFD: i32 = 1; // get descriptor flags
+pub const F_SETFD: i32 = 2; // set descriptor flags
+pub const F_GETFL: i32 = 3; // get access mode and file status flags
+pub const F_SETFL: i32 = 4; // set O_APPEND and O_NONBLOCK
+
+// lseek() whence values
+pub const SEEK_SET: i32 = 0; // offset is absolute
+pub const SEEK_CUR: i32 = 1; // offset is relative to the current offset
+pub const SEEK_END: i32 = 2; // offset is relative to the end of the file
@@ -0,0 +1,284 @@
+use crate::{
+    fcntl::FD_CLOEXEC,
+    file::File,
+    kalloc::{kalloc_zeroed, kfree},
+    mmu::PGSIZE,
+    param::{NOFILE, NOFILEMAX},
+    proc::my_process,
+};
+
+// Descriptors past the first NOFILE live in a page of their own.
+#[repr(C)]
+struct FdPage {
+    ofile: [*mut File; NOFILEMAX],
+    flags: [u8; NOFILEMAX],
+}
+
+const _: () = assert!(core::mem::size_of::<FdPage>() <= PGSIZE);
+
+// Per-process file descriptor table.
+//
+// A process starts with NOFILE descriptors stored in the table
+// itself. The first time it needs more, the table moves to a
+// kalloc'd page holding NOFILEMAX of them, and stays there until
+// the process exits. fork() gives the child a copy of the table
+// that refers to the same open files.
+#[repr(C)]
+pub struct FdTable {
+    nfd: usize,                 // number of usable descriptors
+    big: *mut FdPage,           // descriptors once past NOFILE, or null
+    ofile: [*mut File; NOFILE], // open files while big is null
+    flags: [u8; NOFILE],        // FD_CLOEXEC per descriptor while big is null
+}
+
+impl FdTable {
+    pub const fn new() -> Self {
+        Self {
+            nfd: NOFILE,
+            big: core::ptr::null_mut(),
+            ofile: [core::ptr::null_mut(); NOFILE],
+            flags: [0; NOFILE],
+        }
+    }
+
+    fn slots(&mut self) -> (&mut [*mut File], &mut [u8]) {
+        if self.big.is_null() {
+            (&mut self.ofile, &mut self.flags)
+        } else {
+            let big = unsafe { &mut *self.big };
+            (&mut big.ofile, &mut big.flags)
+        }
+    }
+
+    // Make room for descriptor fd.
+    fn grow(&mut self, fd: usize) -> bool {
+        if fd < self.nfd {
+            return true;
+        }
+        if fd >= NOFILEMAX {
+            return false;
+        }
+
+        let Some(page) = kalloc_zeroed() else {
+            return false;
+        };
+        let big = unsafe { &mut *(page as *mut FdPage) };
+        big.ofile[..NOFILE].copy_from_slice(&self.ofile);
+        big.flags[..NOFILE].copy_from_slice(&self.flags);
+        self.ofile = [core::ptr::null_mut(); NOFILE];
+        self.flags = [0; NOFILE];
+        self.big = big;
+        self.nfd = NOFILEMAX;
+        true
+    }
+
+    pub fn get(&mut self, fd: usize) -> Option<*mut File> {
+        if fd >= self.nfd {
+            return None;
+        }
+        let f = self.slots().0[fd];
+        if f.is_null() {
+            None
+        } else {
+            Some(f)
+        }
+    }
+
+    // Install f at the lowest free descriptor not below min.
+    // Takes over the caller's file reference on success.
+    pub fn alloc(&mut self, f: *mut File, min: usize) -> Option<usize> {
+        let fd = (min..NOFILEMAX).find(|&fd| fd >= self.nfd || self.slots().0[fd].is_null())?;
+        if !self.grow(fd) {
+            return None;
+        }
+        let (ofile, flags) = self.slots();
+        ofile[fd] = f;
+        flags[fd] = 0;
+        Some(fd)
+    }
+
+    // Make room for descriptor fd now, so that a later install
+    // of fd cannot fail.
+    pub fn reserve(&mut self, fd: usize) -> bool {
+        self.grow(fd)
+    }
+
+    // Install f at descriptor fd, which must be free.
+    pub fn install(&mut self, fd: usize, f: *mut File) -> bool {
+        if !self.grow(fd) || !self.slots().0[fd].is_null() {
+            return false;
+        }
+        let (ofile, flags) = self.slots();
+        ofile[fd] = f;
+        flags[fd] = 0;
+        true
+    }
+
+    // Detach descriptor fd and hand its file reference to the caller.
+    pub fn remove(&mut self, fd: usize) -> Option<*mut File> {
+        let f = self.get(fd)?;
+        let (ofile, flags) = self.slots();
+        ofile[fd] = core::ptr::null_mut();
+        flags[fd] = 0;
+        Some(f)
+    }
+
+    pub fn flags(&mut self, fd: usize) -> Option<u8> {
+        self.get(fd)?;
+        Some(self.slots().1[fd])
+    }
+
+    pub fn set_flags(&mut self, fd: usize, value: u8) -> bool {
+        if self.get(fd).is_none() {
+            return false;
+        }
+        self.slots().1[fd] = value;
+        true
+    }
+
+    // Fill this (empty) table with duplicates of src's descriptors.
+    pub fn copy_from(&mut self, src: &mut FdTable) -> bool {
+        *self = FdTable::new();
+        if !src.big.is_null() && !self.grow(NOFILE) {
+            return false;
+        }
+
+        let (src_ofile, src_flags) = src.slots();
+        let (ofile, flags) = self.slots();
+        for fd in 0..src_ofile.len() {
+            if !src_ofile[fd].is_null() {
+                ofile[fd] = unsafe { filedup(src_ofile[fd]) };
+                flags[fd] = src_flags[fd];
+            }
+        }
+        true
+    }
+
+    // Close every descriptor and give back the table's page.
+    pub fn close_all(&mut self) {
+        for f in self.slots().0.iter_mut() {
+            if !f.is_null() {
+                unsafe { fileclose(*f) };
+                *f = core::ptr::null_mut();
+            }
+        }
+        if !self.big.is_null() {
+            kfree(self.big as usize);
+        }
+        *self = FdTable::new();
+    }
+
+    // Close descriptors marked close-on-exec.
+    pub fn close_on_exec(&mut self) {
+        let (ofile, flags) = self.slots();
+        for fd in 0..ofile.len() {
+            if !ofile[fd].is_null() && flags[fd] & FD_CLOEXEC != 0 {
+                unsafe { fileclose(ofile[fd]) };
+                ofile[fd] = core::ptr::null_mut();
+                flags[fd] = 0;
+            }
+        }
+    }
+}
+
+extern "C" {
+    fn filedup(f: *mut File) -> *mut File;
+    fn fileclose(f: *mut File);
+}
+
+fn my_fdtable() -> &'static mut FdTable {
+    unsafe { &mut (*my_process().unwrap()).fdt }
+}
+
+mod binding {
+    use super::*;
+
+    #[no_mangle]
+    extern "C" fn fdtabinit(t: *mut FdTable) {
+        unsafe { *t = FdTable::new() };
+    }
+
+    #[no_mangle]
+    extern "C" fn fdtabcopy(dst: *mut FdTable, src: *mut FdTable) -> i32 {
+        match unsafe { (*dst).copy_from(&mut *src) } {
+            true => 0,
+            false => -1,
+        }
+    }
+
+    #[no_mangle]
+    extern "C" fn fdtabclose(t: *mut FdTable) {
+        unsafe { (*t).close_all() };
+    }
+
+    #[no_mangle]
+    extern "C" fn fdtabcloseexec(t: *mut FdTable) {
+        unsafe { (*t).close_on_exec() };
+    }
+
+    #[no_mangle]
+    extern "C" fn fdlookup(fd: i32) -> *mut File {
+        if fd < 0 {
+            return core::ptr::null_mut();
+        }
+        my_fdtable()
+            .get(fd as usize)
+            .unwrap_or(core::ptr::null_mut())
+    }
+
+    #[no_mangle]
+    extern "C" fn fdalloc(f: *mut File, min: i32) -> i32 {
+        if min < 0 {
+            return -1;
+        }
+        match my_fdtable().alloc(f, min as usize) {
+            Some(fd) => fd as i32,
+            None => -1,
+        }
+    }
+
+    #[no_mangle]
+    extern "C" fn fdreserve(fd: i32) -> i32 {
+        if fd < 0 || !my_fdtable().reserve(fd as usize) {
+            return -1;
+        }
+        0
+    }
+
+    #[no_mangle]
+    extern "C" fn fdinstall(fd: i32, f: *mut File) -> i32 {
+        if fd < 0 || !my_fdtable().install(fd as usize, f) {
+            return -1;
+        }
+        0
+    }
+
+    #[no_mangle]
+    extern "C" fn fdremove(fd: i32) -> *mut File {
+        if fd < 0 {
+            return core::ptr::null_mut();
+        }
+        my_fdtable()
+            .remove(fd as usize)
+            .unwrap_or(core::ptr::null_mut())
+    }
+
+    #[no_mangle]
+    extern "C" fn fdgetflags(fd: i32) -> i32 {
+        if fd < 0 {
+            return -1;
+        }
+        match my_fdtable().flags(fd as usize) {
+            Some(flags) => flags as i32,
+            None => -1,
+        }
+    }
+
+    #[no_mangle]
+    extern "C" fn fdsetflags(fd: i32, flags: i32) -> i32 {
+        if fd < 0 || !my_fdtable().set_flags(fd as usize, flags as u8) {
+            return -1;
+        }
+        0
+    }
+}
@@ -1,12 +1,13 @@
 use arrayvec::ArrayVec;
 
 use crate::{
-    fs::NDIRECT,
+    fcntl::{O_APPEND, O_NONBLOCK, SEEK_CUR, SEEK_END, SEEK_SET},
+    fs::{MINBSIZE, NADDRS},
     param::{MAXOPBLOCKS, NFILE},
     pipe::{pipeclose, piperead, pipewrite, Pipe},
     sleeplock::SleepLockC,
     spinlock::SpinLockC,
-    stat::Stat,
+    stat::{Stat, T_DEV},
 };
 
 #[repr(C)]
@@ -27,6 +28,7 @@ pub struct File {
     pub pipe: *mut Pipe,
     pub ip: *mut INode,
     pub offset: u32,
+    pub flags: u32, // O_APPEND, O_NONBLOCK
 }
 
 impl File {
@@ -39,6 +41,7 @@ impl File {
             pipe: core::ptr::null_mut(),
             ip: core::ptr::null_mut(),
             offset: 0,
+            flags: 0,
         }
     }
 }
@@ -46,18 +49,22 @@ impl File {
 // in-memory copy of an inode
 #[repr(C)]
 pub struct INode {
-    dev: u32,         // Device number
-    inum: u32,        // Inode number
+    pub dev: u32,     // Device number
+    pub inum: u32,    // Inode number
     ref_count: u32,   //   Reference count
     lock: SleepLockC, // protects everything below here
     valid: i32,       // inode has been read from disk?
+    seqoff: u32,      // offset just past the last read, for read-ahead
 
-    ty: u16, // copy of disk inode
-    major: u16,
-    minor: u16,
-    nlink: u16,
-    size: u32,
-    addrs: [u32; NDIRECT + 1],
+    pub ty: u16, // copy of disk inode
+    pub major: u16,
+    pub minor: u16,
+    pub nlink: u16,
+    pub size: u32,
+    pub atime: u32, // kept in memory by readi until the next iupdate
+    pub mtime: u32,
+    pub ctime: u32,
+    pub addrs: [u32; NADDRS],
 }
 
 struct FileTable {
@@ -146,22 +153,49 @@ impl FileTable {
         match f.kind {
             FileKind::Pipe => unsafe {
                 unsafe {
-                    return piperead(f.pipe, buf.as_mut_ptr(), buf.len());
+                    return piperead(
+                        f.pipe,
+                        buf.as_mut_ptr(),
+                        buf.len(),
+                        f.flags & O_NONBLOCK != 0,
+                    );
                 }
             },
             FileKind::INode => unsafe {
                 ilock(f.ip);
+                if (*f.ip).ty == T_DEV {
+                    let read = devread(f.ip, buf.as_mut_ptr(), f.offset, buf.len() as i32, f.flags);
+                    if read > 0 {
+                        f.offset += read as u32;
+                    }
+                    iunlock(f.ip);
+                    return read;
+                }
                 let read = readi(f.ip, buf.as_mut_ptr(), f.offset, buf.len() as u32);
                 if read > 0 {
-                    f.offset += read;
+                    f.offset += read as u32;
                 }
                 iunlock(f.ip);
-                return read as i32;
+                return read;
             },
             _ => panic!("fileread"),
         }
     }
 
+    // Read from f at offset off without moving f's offset.
+    pub fn pread(&self, f: &File, buf: &mut [u8], off: u32) -> i32 {
+        if f.readable == 0 || f.kind != FileKind::INode {
+            return -1;
+        }
+
+        unsafe {
+            ilock(f.ip);
+            let read = readi(f.ip, buf.as_mut_ptr(), off, buf.len() as u32);
+            iunlock(f.ip);
+            read
+        }
+    }
+
     pub fn write(&self, f: &mut File, buf: &[u8]) -> i32 {
         if f.writable == 0 {
             return -1;
@@ -170,46 +204,102 @@ impl FileTable {
         match f.kind {
             FileKind::Pipe => unsafe {
                 unsafe {
-                    return pipewrite(f.pipe, buf.as_ptr(), buf.len());
+                    return pipewrite(f.pipe, buf.as_ptr(), buf.len(), f.flags & O_NONBLOCK != 0);
                 }
             },
             FileKind::INode => unsafe {
-                // write a few blocks at a time to avoid exceeding
-                // the maximum log transaction size, including
-                // i-node, indirect block, allocation blocks,
-                // and 2 blocks of slop for non-aligned writes.
-                // this really belongs lower down, since writei()
-                // might be writing a device like the console.
-                const MAX: usize = ((MAXOPBLOCKS - 1 - 1 - 2) / 2) * 512;
-                let mut i = 0;
-                let n = buf.len();
-                while i < n {
-                    let mut n1 = n - i;
-                    if n1 > MAX {
-                        n1 = MAX;
-                    }
+                let append = f.flags & O_APPEND != 0;
+                return self.write_inode(f.ip, buf, &mut f.offset, append);
+            },
+            _ => panic!("filewrite"),
+        }
+    }
 
-                    begin_op();
-                    ilock(f.ip);
-                    let read = writei(f.ip, buf.as_ptr().add(i), f.offset, n1 as u32);
-                    if read > 0 {
-                        f.offset += read as u32;
-                    }
-                    iunlock(f.ip);
-                    end_op();
+    // Write to f at offset off without moving f's offset.
+    pub fn pwrite(&self, f: &File, buf: &[u8], off: u32) -> i32 {
+        if f.writable == 0 || f.kind != FileKind::INode {
+            return -1;
+        }
 
-                    if read < 0 {
-                        break;
-                    }
-                    if read as usize != n1 {
-                        panic!("short filewrite");
-                    }
-                    i += read as usize;
-                }
-                return if i == n { n as i32 } else { -1 };
+        let mut off = off;
+        unsafe { self.write_inode(f.ip, buf, &mut off, false) }
+    }
+
+    // Write buf to ip at *off, advancing *off past each chunk written.
+    // With append set, each chunk first moves *off to the end of the file.
+    unsafe fn write_inode(&self, ip: *mut INode, buf: &[u8], off: &mut u32, append: bool) -> i32 {
+        // write a few blocks at a time to avoid exceeding
+        // the maximum log transaction size, including
+        // i-node, up to 5 indirect blocks where the write
+        // crosses from one indirect tree into the next,
+        // 2 allocation blocks, and 1 block of slop for
+        // non-aligned writes. Counting in MINBSIZE blocks keeps
+        // this safe for any block size.
+        // this really belongs lower down, since writei()
+        // might be writing a device like the console.
+        const MAX: usize = (MAXOPBLOCKS - 1 - 5 - 2 - 1) * MINBSIZE;
+        let mut i = 0;
+        let n = buf.len();
+        while i < n {
+            let mut n1 = n - i;
+            if n1 > MAX {
+                n1 = MAX;
+            }
+
+            begin_op();
+            ilock(ip);
+            if append {
+                *off = (*ip).size;
+            }
+            let read = writei(ip, buf.as_ptr().add(i), *off, n1 as u32);
+            if read > 0 {
+                *off += read as u32;
+            }
+            iunlock(ip);
+            end_op();
+
+            if read < 0 {
+                break;
+            }
+            i += read as usize;
+            if read as usize != n1 {
+                // out of space
+                break;
+            }
+        }
+        if i == n {
+            n as i32
+        } else {
+            -1
+        }
+    }
+
+    // Move f's offset according to whence.  Seeking past the end
+    // of a file is allowed; a later write leaves a hole.
+    // Returns the new offset, or -1.
+    pub fn seek(&self, f: &mut File, offset: i32, whence: i32) -> i32 {
+        if f.kind != FileKind::INode {
+            return -1;
+        }
+
+        let base = match whence {
+            SEEK_SET => 0,
+            SEEK_CUR => f.offset as i64,
+            SEEK_END => unsafe {
+                ilock(f.ip);
+                let size = (*f.ip).size;
+                iunlock(f.ip);
+                size as i64
             },
-            _ => panic!("filewrite"),
+            _ => return -1,
+        };
+
+        let new = base + offset as i64;
+        if new < 0 || new > i32::MAX as i64 {
+            return -1;
         }
+        f.offset = new as u32;
+        new as i32
     }
 }
 
@@ -220,8 +310,9 @@ extern "C" {
     fn iunlock(ip: *mut INode);
     fn end_op();
     fn writei(ip: *mut INode, buf: *const u8, offset: u32, n: u32) -> i32;
-    fn readi(ip: *mut INode, buf: *mut u8, offset: u32, n: u32) -> u32;
+    fn readi(ip: *mut INode, buf: *mut u8, offset: u32, n: u32) -> i32;
     fn stati(ip: *mut INode, st: *mut Stat);
+    fn devread(ip: *mut INode, dst: *mut u8, off: u32, n: i32, flags: u32) -> i32;
 }
 
 static mut FILE_TABLE: FileTable = FileTable::new();
@@ -261,4 +352,19 @@ mod binding {
     extern "C" fn filewrite(f: *mut File, buf: *const u8, n: usize) -> i32 {
         unsafe { FILE_TABLE.write(&mut *f, core::slice::from_raw_parts(buf, n)) }
     }
+
+    #[no_mangle]
+    extern "C" fn filepread(f: *mut File, buf: *mut u8, n: usize, off: u32) -> i32 {
+        unsafe { FILE_TABLE.pread(&*f, core::slice::from_raw_parts_mut(buf, n), off) }
+    }
+
+    #[no_mangle]
+    extern "C" fn filepwrite(f: *mut File, buf: *const u8, n: usize, off: u32) -> i32 {
+        unsafe { FILE_TABLE.pwrite(&*f, core::slice::from_raw_parts(buf, n), off) }
+    }
+
+    #[no_mangle]
+    extern "C" fn fileseek(f: *mut File, offset: i32, whence: i32) -> i32 {
+        unsafe { FILE_TABLE.seek(&mut *f, offset, whence) }
+    }
 }
@@ -2,72 +2,26 @@
 // Both the kernel and user programs use this header file.
 
 use arrayvec::ArrayVec;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 use crate::{
     buf::{Buffer, BUFFER_CACHE},
     file::INode,
-    param::NINODE,
+    param::{NDISK, NINODE, ROOTDEV},
     spinlock::SpinLockC,
+    vfs::{FileSystem, INodeOperations},
 };
 
-pub const ROOTINO: usize = 1; // root i-number
-pub const BSIZE: usize = 512; // block size
-
-// Disk layout:
-// [ boot block | super block | log | inode blocks |
-//                                          free bit map | data blocks]
-//
-// mkfs computes the super block and builds an initial file system. The
-// super block describes the disk layout:
-#[repr(C)]
-#[derive(Clone)]
-pub struct SuperBlock {
-    size: usize,       // Size of file system image (blocks)
-    nblocks: usize,    // Number of data blocks
-    ninodes: usize,    // Number of inodes.
-    nlog: usize,       // Number of log blocks
-    logstart: usize,   // Block number of first log block
-    inodestart: usize, // Block number of first inode block
-    bmapstart: usize,  // Block number of first free map block
-}
+// The format itself lives in the ondisk crate, which mkfs and the
+// other host tools share. mkfs chooses the block size, computes the
+// super block and builds an initial file system.
+pub use ondisk::{SuperBlock, MAXBSIZE, MINBSIZE, NADDRS, NAMEMAX, ROOTINO, SBOFF};
 
 pub struct INodeCache {
     lock: SpinLockC,
     inodes: ArrayVec<INode, NINODE>,
 }
 
-pub const NDIRECT: usize = 12;
-pub const NINDIRECT: usize = BSIZE / core::mem::size_of::<u32>();
-pub const MAXFILE: usize = NDIRECT + NINDIRECT;
-
-pub struct INodeOnDisk {
-    kind: u16,
-    major: u16,
-    minor: u16,
-    nlink: u16,
-    size: u32,
-    addrs: [u32; NDIRECT + 1],
-}
-
-const IPB: usize = BSIZE / core::mem::size_of::<INodeOnDisk>();
-
-const fn iblock(i: usize, sb: &SuperBlock) -> usize {
-    i / IPB + sb.inodestart
-}
-
-const BPB: usize = BSIZE * 8;
-
-const fn bblock(b: usize, sb: &SuperBlock) -> usize {
-    b / BPB + sb.bmapstart
-}
-
-const DIRSIZ: usize = 14;
-
-struct DirectoryEntry {
-    inum: u16,
-    name: [u8; DIRSIZ],
-}
-
 extern "C" {
     fn log_write(buf: *mut Buffer);
 }
@@ -82,9 +36,14 @@ pub fn read_inode(ip: *const INode, dst: usize, offset: usize, n: usize) -> usiz
 
 pub fn read_superblock(dev: usize) -> SuperBlock {
     unsafe {
-        let buf = BUFFER_CACHE.read(dev, 1);
-        let sb = buf.data.as_ptr() as *const SuperBlock;
-        let sb = (*sb).clone();
+        let mut buf = BUFFER_CACHE.read(dev, 0);
+        let bsize = buf.size;
+        if SBOFF >= bsize {
+            buf.release(&mut BUFFER_CACHE);
+            buf = BUFFER_CACHE.read(dev, SBOFF / bsize);
+        }
+        let sb = buf.data()[SBOFF % bsize..].as_ptr() as *const SuperBlock;
+        let sb = *sb;
         buf.release(&mut BUFFER_CACHE);
         sb
     }
@@ -93,21 +52,21 @@ pub fn read_superblock(dev: usize) -> SuperBlock {
 pub fn zero_block(dev: usize, bno: usize) {
     unsafe {
         let buf = BUFFER_CACHE.read(dev, bno);
-        core::ptr::write_bytes(buf.data.as_mut_ptr(), 0, buf.data.len());
+        core::ptr::write_bytes(buf.data().as_mut_ptr(), 0, buf.size);
         log_write(buf);
         buf.release(&mut BUFFER_CACHE);
     }
 }
 
 pub unsafe fn allocate_block(sb: &SuperBlock, dev: usize) -> usize {
-    let mut b = 0;
-    for b in (0..sb.size).step_by(BPB) {
-        let buf = BUFFER_CACHE.read(dev, bblock(b, sb));
+    let bpb = sb.bpb() as usize;
+    for b in (0..sb.size as usize).step_by(bpb) {
+        let buf = BUFFER_CACHE.read(dev, sb.bblock(b as u32) as usize);
         let mut bi = 0;
-        while bi < BPB && b + bi < sb.size {
+        while bi < bpb && b + bi < sb.size as usize {
             let m = 1 << (bi % 8);
-            if (buf.data[bi / 8] & m) == 0 {
-                buf.data[bi / 8] |= m;
+            if (buf.data()[bi / 8] & m) == 0 {
+                buf.data()[bi / 8] |= m;
                 log_write(buf);
                 buf.release(&mut BUFFER_CACHE);
                 zero_block(dev, b + bi);
@@ -121,17 +80,184 @@ pub unsafe fn allocate_block(sb: &SuperBlock, dev: usize) -> usize {
 }
 
 pub unsafe fn free_block(sb: &SuperBlock, dev: usize, bno: usize) {
-    let buf = BUFFER_CACHE.read(dev, bblock(bno, sb));
-    let bi = bno % BPB;
+    let buf = BUFFER_CACHE.read(dev, sb.bblock(bno as u32) as usize);
+    let bi = bno % sb.bpb() as usize;
     let m = 1 << (bi % 8);
-    if buf.data[bi / 8] & m == 0 {
+    if buf.data()[bi / 8] & m == 0 {
         panic!("freeing free block");
     }
-    buf.data[bi / 8] &= !m;
+    buf.data()[bi / 8] &= !m;
     log_write(buf);
     buf.release(&mut BUFFER_CACHE);
 }
 
+// The xv6 file system on disk dev. Its code is in fs.c, behind
+// the xv6*() functions; the super block of each disk is kept
+// there too, read by xv6mount().
+//
+// Only the root disk has a log (log.c), so no other disk could
+// be written crash-safely: those mount only read-only.
+pub struct Xv6FileSystem {
+    dev: u32,
+    readonly: AtomicBool,
+}
+
+pub static XV6FS: [Xv6FileSystem; NDISK] = {
+    let mut fs = [const {
+        Xv6FileSystem {
+            dev: 0,
+            readonly: AtomicBool::new(false),
+        }
+    }; NDISK];
+    let mut i = 0;
+    while i < NDISK {
+        fs[i].dev = i as u32;
+        i += 1;
+    }
+    fs
+};
+
+extern "C" {
+    fn xv6mount(dev: u32) -> i32;
+    fn xv6ialloc(dev: u32, ty: i16) -> u32;
+    fn xv6iload(ip: *mut INode);
+    fn xv6iupdate(ip: *mut INode);
+    fn xv6ifree(ip: *mut INode);
+    fn xv6readi(ip: *mut INode, dst: *mut u8, off: u32, n: u32) -> i32;
+    fn xv6writei(ip: *mut INode, src: *const u8, off: u32, n: u32) -> i32;
+    fn xv6itruncate(ip: *mut INode, size: u32, budget: i32) -> i32;
+    fn xv6ifallocate(ip: *mut INode, off: u32, n: u32) -> i32;
+    fn xv6dirlookup(dp: *mut INode, name: *const u8, poff: *mut u32) -> u32;
+    fn xv6dirlink(dp: *mut INode, name: *const u8, inum: u32) -> i32;
+    fn xv6dirremove(dp: *mut INode, off: u32);
+    fn xv6dirset(dp: *mut INode, off: u32, inum: u32);
+    fn xv6direntat(dp: *mut INode, off: u32, inum: *mut u32, name: *mut u8) -> u32;
+}
+
+// name as a C string, for fs.c.
+fn cname(name: &[u8]) -> [u8; NAMEMAX + 1] {
+    let mut s = [0; NAMEMAX + 1];
+    let n = name.len().min(NAMEMAX);
+    s[..n].copy_from_slice(&name[..n]);
+    s
+}
+
+impl Xv6FileSystem {
+    fn readonly(&self) -> bool {
+        self.readonly.load(Ordering::Relaxed)
+    }
+}
+
+impl FileSystem for Xv6FileSystem {
+    fn mount(&self, opts: &[u8]) -> bool {
+        let readonly = match opts {
+            b"" => false,
+            b"ro" => true,
+            _ => return false,
+        };
+        if !readonly && self.dev != ROOTDEV as u32 {
+            return false;
+        }
+        self.readonly.store(readonly, Ordering::Relaxed);
+        unsafe { xv6mount(self.dev) == 0 }
+    }
+
+    fn dev(&self) -> u32 {
+        self.dev
+    }
+
+    fn root(&self) -> u32 {
+        ROOTINO
+    }
+
+    fn block_size(&self) -> u32 {
+        unsafe { BUFFER_CACHE.block_size(self.dev as usize) as u32 }
+    }
+
+    fn alloc(&self, ty: i16) -> u32 {
+        if self.readonly() {
+            return 0;
+        }
+        unsafe { xv6ialloc(self.dev, ty) }
+    }
+
+    fn load(&self, ip: &mut INode) {
+        unsafe { xv6iload(ip) }
+    }
+
+    // Read-only, ip keeps what the disk says, as on ext2.
+    fn update(&self, ip: &mut INode) {
+        if self.readonly() {
+            return self.load(ip);
+        }
+        unsafe { xv6iupdate(ip) }
+    }
+
+    fn free(&self, ip: &mut INode) {
+        if !self.readonly() {
+            unsafe { xv6ifree(ip) }
+        }
+    }
+}
+
+impl INodeOperations for Xv6FileSystem {
+    fn read(&self, ip: &mut INode, dst: &mut [u8], off: u32) -> i32 {
+        unsafe { xv6readi(ip, dst.as_mut_ptr(), off, dst.len() as u32) }
+    }
+
+    fn write(&self, ip: &mut INode, src: &[u8], off: u32) -> i32 {
+        if self.readonly() {
+            return -1;
+        }
+        unsafe { xv6writei(ip, src.as_ptr(), off, src.len() as u32) }
+    }
+
+    fn truncate(&self, ip: &mut INode, size: u32, budget: i32) -> i32 {
+        if self.readonly() {
+            return -1;
+        }
+        unsafe { xv6itruncate(ip, size, budget) }
+    }
+
+    fn allocate(&self, ip: &mut INode, off: u32, n: u32) -> i32 {
+        if self.readonly() {
+            return -1;
+        }
+        unsafe { xv6ifallocate(ip, off, n) }
+    }
+
+    fn lookup(&self, dp: &mut INode, name: &[u8], off: &mut u32) -> u32 {
+        unsafe { xv6dirlookup(dp, cname(name).as_ptr(), off) }
+    }
+
+    fn link(&self, dp: &mut INode, name: &[u8], inum: u32) -> i32 {
+        if self.readonly() {
+            return -1;
+        }
+        unsafe { xv6dirlink(dp, cname(name).as_ptr(), inum) }
+    }
+
+    fn remove(&self, dp: &mut INode, off: u32) -> i32 {
+        if self.readonly() {
+            return -1;
+        }
+        unsafe { xv6dirremove(dp, off) };
+        0
+    }
+
+    fn set(&self, dp: &mut INode, off: u32, inum: u32) -> i32 {
+        if self.readonly() {
+            return -1;
+        }
+        unsafe { xv6dirset(dp, off, inum) };
+        0
+    }
+
+    fn entry(&self, dp: &mut INode, off: u32, inum: &mut u32, name: &mut [u8]) -> u32 {
+        unsafe { xv6direntat(dp, off, inum, name.as_mut_ptr()) }
+    }
+}
+
 mod binding {
     use super::*;
 
@@ -151,4 +277,11 @@ mod binding {
         let sb = read_superblock(dev);
         unsafe { free_block(&sb, dev, bno) };
     }
+
+    // fs.c buckets names with the hash mkfs and fsck use, so
+    // the two cannot drift.
+    #[no_mangle]
+    extern "C" fn dirhash(name: *const u8, len: u32) -> u32 {
+        ondisk::dirhash(unsafe { core::slice::from_raw_parts(name, len as usize) })
+    }
 }
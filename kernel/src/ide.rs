@@ -1,11 +1,10 @@
 use crate::{
     buf::Buffer,
-    fs::BSIZE,
     ioapic::ioapicenable,
-    param::FSSIZE,
+    param::NDISK,
     proc::{sleep, wakeup},
     spinlock::SpinLockC,
-    trap::IRQ_IDE,
+    trap::{IRQ_IDE, IRQ_IDE2},
     x86::{inb, insl, outb, outsl},
 };
 
@@ -13,38 +12,76 @@ const SECTOR_SIZE: usize = 512;
 const IDE_BSY: u8 = 0x80;
 const IDE_DRDY: u8 = 0x40;
 const IDE_DF: u8 = 0x20;
+const IDE_DRQ: u8 = 0x08;
 const IDE_ERR: u8 = 0x01;
 const IDE_CMD_READ: u8 = 0x20;
 const IDE_CMD_WRITE: u8 = 0x30;
 const IDE_CMD_RDMUL: u8 = 0xc4;
 const IDE_CMD_WRMUL: u8 = 0xc5;
+const IDE_CMD_SETMUL: u8 = 0xc6;
+const IDE_CMD_IDENTIFY: u8 = 0xec;
+
+// Disk n is drive n&1 on channel n>>1: disks 0 and 1 are on the
+// primary channel, 2 and 3 on the secondary one. Each channel
+// has its own ports, interrupt and queue.
+const IDE_BASE: [u16; 2] = [0x1f0, 0x170]; // command block registers
+const IDE_CTL: [u16; 2] = [0x3f6, 0x376]; // device control register
 
 // idequeue points to the buf now being read/written to the disk.
 // idequeue->qnext points to the next buf to be processed.
+// A multi-block read is queued as its first buf, with the rest
+// chained through rnext; run points to the next one to fill.
+// Blocks bigger than a sector move with READ/WRITE MULTIPLE, set
+// to one block per interrupt; multiple holds each disk's setting.
+// sectors holds each disk's size, from IDENTIFY; requests past
+// it are a kernel bug, so file systems and the raw disk device
+// check against it first.
 // You must hold idelock while manipulating queue.
 
 pub static mut IDE: Option<IDE> = None;
 
+struct Channel {
+    queue: *mut Buffer,
+    run: *mut Buffer,
+}
+
 pub struct IDE {
     lock: SpinLockC,
-    queue: *mut Buffer,
-    havedisk1: bool,
+    channels: [Channel; 2],
+    multiple: [usize; NDISK],
+    present: [bool; NDISK],
+    sectors: [usize; NDISK],
 }
 
 impl IDE {
     pub fn new() -> Self {
-        wait_ide();
-
-        // Check if disk 1 is present
-        unsafe {
-            outb(0x1f6, 0xe0 | (1 << 4));
+        wait_ide(0);
+
+        // Disk 0 holds the kernel; check which others are present.
+        // An empty channel reads as 0, or 0xff with no controller.
+        let mut present = [false; NDISK];
+        present[0] = true;
+        for disk in 1..NDISK {
+            let base = IDE_BASE[disk >> 1];
+            unsafe {
+                outb(base + 6, 0xe0 | ((disk & 1) << 4) as u8);
+            }
+            for _i in 0..1000 {
+                let status = unsafe { inb(base + 7) };
+                if status != 0 && status != 0xff {
+                    present[disk] = true;
+                    break;
+                }
+            }
         }
 
-        let mut havedisk1 = false;
-        for _i in 0..1000 {
-            if unsafe { inb(0x1f7) != 0 } {
-                havedisk1 = true;
-                break;
+        // A disk that does not identify itself, like an ATAPI
+        // drive, is of no use.
+        let mut sectors = [0; NDISK];
+        for disk in 0..NDISK {
+            if present[disk] {
+                sectors[disk] = identify(disk);
+                present[disk] = sectors[disk] != 0;
             }
         }
 
@@ -53,10 +90,30 @@ impl IDE {
             outb(0x1f6, 0xe0 | (0 << 4));
         }
 
+        const IDLE: Channel = Channel {
+            queue: core::ptr::null_mut(),
+            run: core::ptr::null_mut(),
+        };
         Self {
             lock: SpinLockC::new(),
-            queue: core::ptr::null_mut(),
-            havedisk1,
+            channels: [IDLE; 2],
+            multiple: [1; NDISK],
+            present,
+            sectors,
+        }
+    }
+
+    // Is disk dev attached?
+    pub fn present(&self, dev: usize) -> bool {
+        dev < NDISK && self.present[dev]
+    }
+
+    // Size of disk dev in sectors, or 0 if it is not attached.
+    pub fn sectors(&self, dev: usize) -> usize {
+        if self.present(dev) {
+            self.sectors[dev]
+        } else {
+            0
         }
     }
 
@@ -66,12 +123,23 @@ impl IDE {
             panic!("IDE::start");
         }
 
-        if unsafe { (*buf).blockno >= FSSIZE } {
-            panic!("incorrect blockno");
+        let mut nblocks = 1;
+        let mut last = buf;
+        unsafe {
+            while !(*last).rnext.is_null() {
+                last = (*last).rnext;
+                nblocks += 1;
+            }
         }
 
-        let sector_per_block = BSIZE / SECTOR_SIZE;
-        let sector = unsafe { (*buf).blockno * sector_per_block as usize };
+        let dev = unsafe { (*buf).dev };
+        let base = IDE_BASE[dev >> 1];
+        let sector_per_block = unsafe { (*buf).size } / SECTOR_SIZE;
+        let sector = unsafe { (*buf).blockno * sector_per_block };
+
+        if unsafe { ((*last).blockno + 1) * sector_per_block > self.sectors[dev] } {
+            panic!("incorrect blockno");
+        }
         let read_cmd = if sector_per_block == 1 {
             IDE_CMD_READ
         } else {
@@ -83,59 +151,108 @@ impl IDE {
             IDE_CMD_WRMUL
         };
 
-        if sector_per_block > 7 {
+        if nblocks * sector_per_block > 255 {
             panic!("IDE::start");
         }
 
-        wait_ide();
+        wait_ide(dev >> 1);
+        if sector_per_block > 1 && self.multiple[dev] != sector_per_block {
+            self.set_multiple(dev, sector_per_block);
+        }
         unsafe {
-            outb(0x3f6, 0); // generate interrupt
-            outb(0x1f2, sector_per_block as u8); // number of sectors
-            outb(0x1f3, (sector & 0xff) as u8);
-            outb(0x1f4, ((sector >> 8) & 0xff) as u8);
-            outb(0x1f5, ((sector >> 16) & 0xff) as u8);
+            outb(IDE_CTL[dev >> 1], 0); // generate interrupt
+            outb(base + 2, (nblocks * sector_per_block) as u8); // number of sectors
+            outb(base + 3, (sector & 0xff) as u8);
+            outb(base + 4, ((sector >> 8) & 0xff) as u8);
+            outb(base + 5, ((sector >> 16) & 0xff) as u8);
             outb(
-                0x1f6,
-                (0xe0 | (((*buf).dev & 1) << 4) | ((sector >> 24) & 0x0f)) as u8,
+                base + 6,
+                (0xe0 | ((dev & 1) << 4) | ((sector >> 24) & 0x0f)) as u8,
             );
 
             if (*buf).flags & Buffer::DIRTY != 0 {
-                outb(0x1f7, write_cmd);
-                outsl(0x1f0, (*buf).data.as_ptr() as *const u32, BSIZE / 4);
+                outb(base + 7, write_cmd);
+                outsl(base, (*buf).data().as_ptr() as *const u32, (*buf).size / 4);
             } else {
-                outb(0x1f7, read_cmd);
+                outb(base + 7, read_cmd);
             }
         }
     }
 
-    pub fn interrupt_handler(&mut self) {
+    // Make READ/WRITE MULTIPLE on disk dev transfer count sectors
+    // per interrupt. Polled, with the disk's interrupt masked.
+    // Caller must hold idelock.
+    fn set_multiple(&mut self, dev: usize, count: usize) {
+        let base = IDE_BASE[dev >> 1];
+        unsafe {
+            outb(IDE_CTL[dev >> 1], 2); // no interrupt for this command
+            outb(base + 2, count as u8);
+            outb(base + 6, (0xe0 | ((dev & 1) << 4)) as u8);
+            outb(base + 7, IDE_CMD_SETMUL);
+        }
+        if !wait_ide(dev >> 1) {
+            panic!("IDE::set_multiple");
+        }
+        self.multiple[dev] = count;
+    }
+
+    pub fn interrupt_handler(&mut self, channel: usize) {
         // First queued buffer is the active request.
         self.lock.acquire();
 
-        let buf = self.queue;
+        let buf = self.channels[channel].queue;
         if buf.is_null() {
             self.lock.release();
             return;
         }
 
-        self.queue = unsafe { (*buf).qnext };
+        // A multi-block read interrupts once per block.
+        let run = self.channels[channel].run;
+        let b = if run.is_null() { buf } else { run };
 
         // Read data if needed.
         unsafe {
-            if (*buf).flags & Buffer::DIRTY == 0 && wait_ide() {
-                insl(0x1f0, (*buf).data.as_ptr() as *mut u32, BSIZE / 4);
+            if (*b).flags & Buffer::DIRTY == 0 && wait_ide(channel) {
+                insl(
+                    IDE_BASE[channel],
+                    (*b).data().as_mut_ptr() as *mut u32,
+                    (*b).size / 4,
+                );
+            }
+            (*b).flags |= Buffer::VALID;
+            (*b).flags &= !(Buffer::DIRTY | Buffer::LATER);
+
+            self.channels[channel].run = (*b).rnext;
+            (*b).rnext = core::ptr::null_mut();
+
+            // No one waits for a block read ahead. The first buf
+            // heads the queue until the whole request is done.
+            // release_ahead() takes the cache lock under idelock;
+            // the cache never holds its lock while calling in here.
+            if b != buf && (*b).flags & Buffer::AHEAD != 0 {
+                (*b).release_ahead();
+            }
+            if !self.channels[channel].run.is_null() {
+                self.lock.release();
+                return;
             }
         }
 
-        // Wake process waiting for this buf.
+        let next = unsafe { (*buf).qnext };
+        self.channels[channel].queue = next;
+
+        // Wake process waiting for this request, or let go of
+        // a read-ahead one, again taking the cache lock under
+        // idelock.
         unsafe {
-            (*buf).flags |= Buffer::VALID;
-            (*buf).flags &= !Buffer::DIRTY;
+            if (*buf).flags & Buffer::AHEAD != 0 {
+                (*buf).release_ahead();
+            }
             wakeup(buf as *const _);
         }
 
-        if !self.queue.is_null() {
-            self.start(self.queue);
+        if !next.is_null() {
+            self.start(next);
         }
 
         self.lock.release();
@@ -153,16 +270,90 @@ impl IDE {
             panic!("IDE::rw: nothing to do");
         }
 
-        if unsafe { (*buf).dev != 0 } && !self.havedisk1 {
-            panic!("IDE::rw: ide disk 1 not present");
+        if !self.present(unsafe { (*buf).dev }) {
+            panic!("IDE::rw: ide disk not present");
         }
 
         self.lock.acquire(); // DOC: acquire-lock
 
+        self.enqueue(buf);
+
+        // Wait for request to finish.
+        unsafe {
+            while (*buf).flags & (Buffer::VALID | Buffer::DIRTY) != Buffer::VALID {
+                sleep(buf as *const _, &self.lock);
+            }
+        }
+
+        self.lock.release();
+    }
+
+    // Read the consecutive blocks held by bufs, which must be
+    // locked and not valid, with a single disk request.
+    pub fn read_run(&mut self, bufs: &[*mut Buffer]) {
+        let first = bufs[0];
+        let last = bufs[bufs.len() - 1];
+
+        self.lock.acquire();
+
+        self.queue_run(bufs);
+
+        // Wait for the whole run; the interrupt handler fills
+        // the bufs in order and wakes first after the last one.
+        unsafe {
+            while (*last).flags & Buffer::VALID == 0 {
+                sleep(first as *const _, &self.lock);
+            }
+        }
+
+        self.lock.release();
+    }
+
+    // Like read_run, but without waiting: the bufs are marked
+    // B_AHEAD, and the interrupt handler releases each one once
+    // it is filled.
+    pub fn read_ahead(&mut self, bufs: &[*mut Buffer]) {
+        self.lock.acquire();
+        self.queue_run(bufs);
+        self.lock.release();
+    }
+
+    // Chain bufs through rnext and queue them as one request.
+    // Caller must hold idelock.
+    fn queue_run(&mut self, bufs: &[*mut Buffer]) {
+        let first = bufs[0];
+        let last = bufs[bufs.len() - 1];
+        unsafe {
+            for (i, &b) in bufs.iter().enumerate() {
+                if !(*b).lock.is_locked()
+                    || (*b).flags & (Buffer::VALID | Buffer::DIRTY) != 0
+                    || (*b).dev != (*first).dev
+                    || (*b).blockno != (*first).blockno + i
+                {
+                    panic!("IDE::read_run");
+                }
+            }
+            if !self.present((*first).dev) {
+                panic!("IDE::read_run: ide disk not present");
+            }
+
+            for w in bufs.windows(2) {
+                (*w[0]).rnext = w[1];
+            }
+            (*last).rnext = core::ptr::null_mut();
+        }
+        self.enqueue(first);
+    }
+
+    // Append buf to its channel's idequeue, starting the disk if
+    // it was idle. Caller must hold idelock.
+    fn enqueue(&mut self, buf: *mut Buffer) {
+        let channel = unsafe { (*buf).dev } >> 1;
+
         // Append buf to idequeue.
         unsafe {
             (*buf).qnext = core::ptr::null_mut();
-            let mut pp = &mut self.queue;
+            let mut pp = &mut self.channels[channel].queue;
             while !(*pp).is_null() {
                 pp = &mut (**pp).qnext;
             }
@@ -170,42 +361,64 @@ impl IDE {
         }
 
         // Start disk if necessary.
-        if self.queue == buf {
+        if self.channels[channel].queue == buf {
             self.start(buf);
         }
-
-        // Wait for request to finish.
-        unsafe {
-            while (*buf).flags & (Buffer::VALID | Buffer::DIRTY) != Buffer::VALID {
-                sleep(buf as *const _, &self.lock);
-            }
-        }
-
-        self.lock.release();
     }
 }
 
-// Wait for IDE disk to become ready.
-fn wait_ide() -> bool {
+// Wait for the selected disk on channel to become ready.
+fn wait_ide(channel: usize) -> bool {
     let mut status;
     while {
-        status = unsafe { inb(0x1f7) };
+        status = unsafe { inb(IDE_BASE[channel] + 7) };
         status & (IDE_BSY | IDE_DRDY) != IDE_DRDY
     } {}
 
     status & (IDE_DF | IDE_ERR) == 0
 }
 
+// The number of sectors disk reaches with 28-bit LBA, which is
+// what start() uses, from IDENTIFY DEVICE words 60-61. Polled,
+// with the disk's interrupt masked, and waiting only for BSY to
+// clear: an ATAPI drive never sets DRDY. Returns 0 if the
+// command fails.
+fn identify(disk: usize) -> usize {
+    let base = IDE_BASE[disk >> 1];
+    let status = || loop {
+        let status = unsafe { inb(base + 7) };
+        if status & IDE_BSY == 0 {
+            return status;
+        }
+    };
+    let mut id = [0u32; SECTOR_SIZE / 4];
+    unsafe {
+        outb(IDE_CTL[disk >> 1], 2); // no interrupt for this command
+        outb(base + 6, (0xe0 | ((disk & 1) << 4)) as u8);
+        status();
+        outb(base + 7, IDE_CMD_IDENTIFY);
+        if status() & (IDE_DF | IDE_ERR | IDE_DRQ) != IDE_DRQ {
+            return 0;
+        }
+        insl(base, id.as_mut_ptr(), id.len());
+    }
+    id[30] as usize
+}
+
 pub fn init_ide(ncpu: usize) {
     ioapicenable(IRQ_IDE, (ncpu - 1) as u32);
+    let ide = IDE::new();
+    if ide.present(2) || ide.present(3) {
+        ioapicenable(IRQ_IDE2, (ncpu - 1) as u32);
+    }
     unsafe {
-        IDE = Some(IDE::new());
+        IDE = Some(ide);
     }
 }
 
-pub fn ide_interrupt() {
+pub fn ide_interrupt(channel: usize) {
     let ide = unsafe { IDE.as_mut().unwrap() };
-    ide.interrupt_handler();
+    ide.interrupt_handler(channel);
 }
 
 mod _binding {
@@ -216,4 +429,20 @@ mod _binding {
         let ide = IDE.as_mut().unwrap();
         ide.rw(b);
     }
+
+    #[no_mangle]
+    unsafe extern "C" fn idepresent(dev: i32) -> i32 {
+        let ide = IDE.as_ref().unwrap();
+        (dev >= 0 && ide.present(dev as usize)) as i32
+    }
+
+    #[no_mangle]
+    unsafe extern "C" fn idesectors(dev: i32) -> u32 {
+        let ide = IDE.as_ref().unwrap();
+        if dev >= 0 {
+            ide.sectors(dev as usize) as u32
+        } else {
+            0
+        }
+    }
 }
@@ -14,30 +14,50 @@ use crate::{param::LOGSIZE, spinlock::SpinLockC};
 // But if it thinks the log is close to running out, it
 // sleeps until the last outstanding end_op() commits.
 //
+// end_op() does not wait for the disk: operations collect in
+// the log until it is nearly full, until logflush() asks for
+// a commit (fsync and sync do), or until the logflush kernel
+// process finds them COMMITTICKS old. A crash loses whole
+// operations that were not yet committed, never part of one.
+//
 // The log is a physical re-do log containing disk blocks.
 // The on-disk log format:
-//   header block, containing block #s for block A, B, C, ...
+//   header blocks, containing block #s for block A, B, C, ...
 //   block A
 //   block B
 //   block C
 //   ...
-// Log appends are synchronous.
+// Log appends are synchronous. Each commit gets the next
+// sequence number, and checksums over the header and the
+// logged blocks let recovery refuse a torn transaction.
+
+#[repr(C)]
+pub struct LogBlock {
+    blockno: u32, // Home location of the block
+    sum: u32,     // Checksum of the logged copy, seeded with seq
+}
 
-// Contents of the header block, used for both the on-disk header block
+// Contents of the header blocks. Used for both the on-disk header
 // and to keep track in memory of logged block# before commit.
 #[repr(C)]
 pub struct LogHeader {
-    n: i32,
-    block: [i32; LOGSIZE],
+    magic: u32, // LOGMAGIC once the log has been written
+    seq: u32,   // Commit sequence number
+    n: u32,     // Number of logged blocks
+    sum: u32,   // Checksum of the header, taken with sum zero
+    block: [LogBlock; LOGSIZE],
 }
 
 #[repr(C)]
 pub struct Log {
     lock: SpinLockC,
-    start: i32,
-    size: i32,
+    start: i32,       // first header block
+    nhead: i32,       // number of header blocks
+    size: i32,        // number of blocks the log can hold
     outstanding: i32, // how many FS sys calls are executing.
     committing: i32,  // in commit(), please wait.
+    flush: i32,       // logflush() wants a commit; begin_op() waits.
+    commits: u32,     // number of commits finished
     dev: i32,
     lh: LogHeader,
 }
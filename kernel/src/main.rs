@@ -19,7 +19,13 @@ use vm::PDE;
 
 mod buf;
 mod console;
+mod dev;
+mod devfs;
 mod elf;
+mod ext2;
+mod fcntl;
+mod fat32;
+mod fdtable;
 mod file;
 mod fs;
 mod ide;
@@ -36,6 +42,7 @@ mod param;
 mod picirq;
 mod pipe;
 mod proc;
+mod rtc;
 mod sleeplock;
 mod spinlock;
 mod stat;
@@ -43,10 +50,12 @@ mod string;
 mod switch;
 mod sync_hack;
 mod syscall;
+mod tmpfs;
 mod trap;
 mod trapasm;
 mod trapvec;
 mod uart;
+mod vfs;
 mod vm;
 mod x86;
 
@@ -134,12 +143,15 @@ const fn entrypgdir() -> Align4096<[PDE; NPDENTRIES]> {
 // doing some setup required for memory allocator to work.
 #[no_mangle]
 unsafe extern "C" fn main() {
+    use crate::buf::BUFFER_CACHE;
+    use crate::dev::meminit;
     use crate::ide::init_ide;
     use crate::ioapic::ioapicinit;
     use crate::lapic::lapicinit;
     use crate::memlayout::{p2v, PHYSTOP};
     use crate::mp::mp_init;
     use crate::picirq::picinit;
+    use crate::rtc::rtcinit;
     use crate::uart::uartinit;
     use crate::vm::{kvm_alloc, seginit};
 
@@ -148,6 +160,7 @@ unsafe extern "C" fn main() {
         fn kinit1(vstart: *const u8, vend: *const u8);
 
         fn consoleinit();
+        fn diskinit();
         fn pinit();
         fn fileinit();
         fn kinit2(vstart: *const u8, vend: *const u8);
@@ -166,8 +179,12 @@ unsafe extern "C" fn main() {
     pinit(); // process table
     fileinit(); // file table
     init_ide(NCPU); // disk
+    diskinit(); // raw disk devices
+    rtcinit(); // wall clock
+    meminit(); // null, zero and random devices
     startothers(); // start other processors
     kinit2(p2v(4 * 1024 * 1024) as _, p2v(PHYSTOP) as _); // must come after startothers()
+    BUFFER_CACHE.init(); // buffer cache
     userinit(); // first user process
     mp_main(); // finish this processor's setup
 }
@@ -1,13 +1,25 @@
 pub const NPROC: usize = 64; // maximum number of processes
 pub const KSTACKSIZE: usize = 4096; // size of per-process kernel stack
 pub const MAXCPU: usize = 8; // maximum number of CPUs
-pub const NOFILE: usize = 16; // open files per process
+pub const NOFILE: usize = 16; // open files per process before the fd table grows
+pub const NOFILEMAX: usize = 512; // open files per process
 pub const NFILE: usize = 100; // open files per system
 pub const NINODE: usize = 50; // maximum number of active i-nodes
 pub const NDEV: usize = 10; // maximum major device number
+pub const NDEVICE: usize = 32; // maximum number of registered devices
 pub const ROOTDEV: usize = 1; // device number of file system root disk
+pub const NDISK: usize = 4; // IDE disks, two on each channel
+pub const NMOUNT: usize = 8; // maximum number of mounted file systems
+pub const TMPFSSIZE: usize = 1024; // default tmpfs size limit, in pages
 pub const MAXARG: usize = 32; // max exec arguments
-pub const MAXOPBLOCKS: usize = 10; // max # of blocks any FS op writes
-pub const LOGSIZE: usize = MAXOPBLOCKS * 3; // max data blocks in on-disk log
-pub const NBUF: usize = MAXOPBLOCKS * 3; // size of disk block cache
-pub const FSSIZE: usize = 1000; // size of file system in blocks
+pub const MAXOPBLOCKS: usize = 16; // max # of blocks any FS op writes
+pub const LOGSIZE: usize = MAXOPBLOCKS * 8; // max data blocks in on-disk log
+pub const BUFFRAC: usize = 16; // the disk block cache takes 1/BUFFRAC of physical memory
+pub const FSSIZE: usize = 20000; // size of a file system mkfs makes, in 512-byte sectors
+pub const MAXRUN: usize = 8; // max blocks read in one disk request
+pub const NSYSMASK: usize = 2; // words in a per-process syscall allowlist
+pub const MAXPATH: usize = 128; // maximum path name, and symbolic link target
+pub const MAXSYMLINKS: usize = 8; // max symbolic links followed in one lookup
+pub const HZ: usize = 100; // timer interrupts per second
+pub const COMMITTICKS: usize = 5 * HZ; // longest a finished FS op waits to be committed
+pub const WRITEBACKTICKS: usize = 5 * HZ; // longest a delayed write stays in the cache
@@ -84,8 +84,10 @@ pub unsafe extern "C" fn pipeclose(p: *mut Pipe, writable: i32) {
     }
 }
 
+// With nonblock set, a write to a full pipe returns the bytes
+// written so far, or -1 if there were none, instead of sleeping.
 #[no_mangle]
-pub unsafe extern "C" fn pipewrite(p: *mut Pipe, addr: *const u8, n: usize) -> i32 {
+pub unsafe extern "C" fn pipewrite(p: *mut Pipe, addr: *const u8, n: usize, nonblock: bool) -> i32 {
     (*p).lock.acquire();
     for i in 0..n {
         while (*p).nwrite == (*p).nread + PIPESIZE {
@@ -93,6 +95,11 @@ pub unsafe extern "C" fn pipewrite(p: *mut Pipe, addr: *const u8, n: usize) -> i
                 (*p).lock.release();
                 return -1;
             }
+            if nonblock {
+                wakeup(&(*p).nread as *const _ as *const c_void);
+                (*p).lock.release();
+                return if i > 0 { i as i32 } else { -1 };
+            }
             wakeup(&(*p).nread as *const _ as *const c_void);
             sleep(&(*p).nwrite as *const _ as *const c_void, &(*p).lock);
         }
@@ -104,11 +111,13 @@ pub unsafe extern "C" fn pipewrite(p: *mut Pipe, addr: *const u8, n: usize) -> i
     return n as i32;
 }
 
+// With nonblock set, a read from an empty pipe whose write end
+// is still open returns -1 instead of sleeping.
 #[no_mangle]
-pub unsafe extern "C" fn piperead(p: *mut Pipe, addr: *mut u8, n: usize) -> i32 {
+pub unsafe extern "C" fn piperead(p: *mut Pipe, addr: *mut u8, n: usize, nonblock: bool) -> i32 {
     (*p).lock.acquire();
     while (*p).nread == (*p).nwrite && (*p).writeopen != 0 {
-        if (*my_process().unwrap()).killed != 0 {
+        if (*my_process().unwrap()).killed != 0 || nonblock {
             (*p).lock.release();
             return -1;
         }
@@ -3,14 +3,16 @@ use core::{ffi::c_void, sync::atomic::AtomicU32};
 use arrayvec::ArrayVec;
 
 use crate::{
-    file::{File, INode},
+    fdtable::FdTable,
+    file::INode,
     interrupt,
     kalloc::kalloc,
     lapic::lapicid,
     mmu::{SegmentDescriptorTable, TaskState, FL_IF},
-    param::{KSTACKSIZE, NOFILE, NPROC},
+    param::{KSTACKSIZE, NPROC, NSYSMASK},
     spinlock::{SpinLock, SpinLockC},
     switch::swtch,
+    syscall::RESTRICT_EPERM,
     trapasm::trapret,
     vm::{uvm_alloc, uvm_dealloc, uvm_switch, PDE},
     x86::{readeflags, TrapFrame},
@@ -75,25 +77,25 @@ pub enum ProcessState {
 // Per-process state
 #[repr(C)]
 pub struct Process {
-    pub sz: usize,           // Size of process memory (bytes)
-    pub pgdir: *mut PDE,     // Page table
-    pub kstack: *const u8,   // Bottom of kernel stack for this process
-    pub state: ProcessState, // Process state
-    pub pid: u32,            // Process ID
-    parent: *const Self,     // Parent process
-    pub tf: *mut TrapFrame,  // Trap frame for current syscall
-    context: *mut Context,   // swtch() here to run process
-    chan: *const c_void,     // If non-zero, sleeping on chan
-    pub killed: i32,         // If non-zero, have been killed
-    ofile: [File; NOFILE],   // Open files
-    cwd: *const INode,       // Current directory
-    name: [i8; 16],          // Process name (debugging)
+    pub sz: usize,             // Size of process memory (bytes)
+    pub pgdir: *mut PDE,       // Page table
+    pub kstack: *const u8,     // Bottom of kernel stack for this process
+    pub state: ProcessState,   // Process state
+    pub pid: u32,              // Process ID
+    parent: *const Self,       // Parent process
+    pub tf: *mut TrapFrame,    // Trap frame for current syscall
+    context: *mut Context,     // swtch() here to run process
+    chan: *const c_void,       // If non-zero, sleeping on chan
+    pub killed: i32,           // If non-zero, have been killed
+    pub fdt: FdTable,          // Open files
+    cwd: *const INode,         // Current directory
+    name: [i8; 16],            // Process name (debugging)
+    sysallow: [u32; NSYSMASK], // Allowed syscalls, bit n = syscall n
+    pub sysmode: i32,          // RESTRICT_EPERM or RESTRICT_KILL
 }
 
 impl Process {
     pub fn create(pid: u32) -> Option<Self> {
-        const FILE_EMPTY: File = File::new();
-
         let stack = kalloc()?;
         let sp = stack + KSTACKSIZE;
 
@@ -126,11 +128,18 @@ impl Process {
             context,
             chan: core::ptr::null(),
             killed: 0,
-            ofile: [FILE_EMPTY; NOFILE],
+            fdt: FdTable::new(),
             cwd: 0 as *const INode,
             name: [0; 16],
+            sysallow: [!0; NSYSMASK],
+            sysmode: RESTRICT_EPERM,
         })
     }
+
+    // Is system call num in this process's allowlist?
+    pub fn may_syscall(&self, num: usize) -> bool {
+        num / 32 < NSYSMASK && self.sysallow[num / 32] & (1 << (num % 32)) != 0
+    }
 }
 
 struct ProcessTable {
@@ -0,0 +1,175 @@
+use crate::{
+    param::HZ,
+    trap::{TICKS, TICKSLOCK},
+    x86::{inb, outb},
+};
+
+const CMOS_PORT: u16 = 0x70;
+const CMOS_RETURN: u16 = 0x71;
+
+// CMOS registers
+const CMOS_SECS: u8 = 0x00;
+const CMOS_MINS: u8 = 0x02;
+const CMOS_HOURS: u8 = 0x04;
+const CMOS_DAY: u8 = 0x07;
+const CMOS_MONTH: u8 = 0x08;
+const CMOS_YEAR: u8 = 0x09;
+const CMOS_STATA: u8 = 0x0a;
+const CMOS_STATB: u8 = 0x0b;
+
+const CMOS_UIP: u8 = 1 << 7; // RTC update in progress
+const CMOS_24H: u8 = 1 << 1; // hours run 0-23, not 1-12 with a pm bit
+const CMOS_BIN: u8 = 1 << 2; // binary values, not BCD
+
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq)]
+pub struct RtcDate {
+    pub second: u32,
+    pub minute: u32,
+    pub hour: u32,
+    pub day: u32,
+    pub month: u32,
+    pub year: u32,
+}
+
+#[repr(C)]
+pub struct TimeVal {
+    sec: u32,  // seconds since 1970-01-01 00:00:00 UTC
+    usec: u32, // and microseconds
+}
+
+fn cmos_read(reg: u8) -> u8 {
+    unsafe {
+        outb(CMOS_PORT, reg);
+        inb(CMOS_RETURN)
+    }
+}
+
+impl RtcDate {
+    fn from_cmos() -> Self {
+        Self {
+            second: cmos_read(CMOS_SECS) as u32,
+            minute: cmos_read(CMOS_MINS) as u32,
+            hour: cmos_read(CMOS_HOURS) as u32,
+            day: cmos_read(CMOS_DAY) as u32,
+            month: cmos_read(CMOS_MONTH) as u32,
+            year: cmos_read(CMOS_YEAR) as u32,
+        }
+    }
+
+    // Seconds since 1970-01-01 00:00:00 UTC.
+    pub fn to_unix(&self) -> u32 {
+        // Days since the epoch of a proleptic Gregorian date,
+        // counting years from March so leap days come last.
+        let (y, m) = if self.month <= 2 {
+            (self.year - 1, self.month + 9)
+        } else {
+            (self.year, self.month - 3)
+        };
+        let era = y / 400;
+        let yoe = y - era * 400;
+        let doy = (153 * m + 2) / 5 + self.day - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = era * 146097 + doe - 719468;
+
+        ((days * 24 + self.hour) * 60 + self.minute) * 60 + self.second
+    }
+
+    // The date t seconds after 1970-01-01 00:00:00 UTC; the
+    // reverse of to_unix().
+    pub fn from_unix(t: u32) -> Self {
+        let days = t / 86400;
+        let era = (days + 719468) / 146097;
+        let doe = days + 719468 - era * 146097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (yoe * 365 + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        Self {
+            second: t % 60,
+            minute: t / 60 % 60,
+            hour: t / 3600 % 24,
+            day: doy - (153 * mp + 2) / 5 + 1,
+            month,
+            year: era * 400 + yoe + (month <= 2) as u32,
+        }
+    }
+}
+
+// Read the date and time from the CMOS RTC, which keeps UTC.
+pub fn cmostime() -> RtcDate {
+    let sb = cmos_read(CMOS_STATB);
+
+    // Make sure CMOS doesn't modify time while we read it.
+    let mut t = loop {
+        let t1 = RtcDate::from_cmos();
+        if cmos_read(CMOS_STATA) & CMOS_UIP != 0 {
+            continue;
+        }
+        if RtcDate::from_cmos() == t1 {
+            break t1;
+        }
+    };
+
+    let pm = sb & CMOS_24H == 0 && t.hour & 0x80 != 0;
+    t.hour &= 0x7f;
+    if sb & CMOS_BIN == 0 {
+        let conv = |x: u32| (x >> 4) * 10 + (x & 0xf);
+        t.second = conv(t.second);
+        t.minute = conv(t.minute);
+        t.hour = conv(t.hour);
+        t.day = conv(t.day);
+        t.month = conv(t.month);
+        t.year = conv(t.year);
+    }
+    if sb & CMOS_24H == 0 {
+        t.hour %= 12;
+        if pm {
+            t.hour += 12;
+        }
+    }
+    t.year += 2000;
+    t
+}
+
+// Wall-clock time. The RTC is read once at boot, before the
+// timer starts ticking; after that the time advances with TICKS.
+static mut BOOTTIME: u32 = 0;
+
+pub fn rtcinit() {
+    unsafe {
+        BOOTTIME = cmostime().to_unix();
+    }
+}
+
+pub fn now() -> TimeVal {
+    let ticks = unsafe {
+        TICKSLOCK.acquire();
+        let ticks = TICKS;
+        TICKSLOCK.release();
+        ticks
+    };
+    TimeVal {
+        sec: unsafe { BOOTTIME } + ticks / HZ as u32,
+        usec: ticks % HZ as u32 * (1000000 / HZ as u32),
+    }
+}
+
+// Seconds since 1970.
+pub fn unixtime() -> u32 {
+    now().sec
+}
+
+mod binding {
+    use super::*;
+
+    #[no_mangle]
+    extern "C" fn unixtime() -> u32 {
+        super::unixtime()
+    }
+
+    #[no_mangle]
+    extern "C" fn gettime(tv: *mut TimeVal) {
+        unsafe { *tv = now() };
+    }
+}
@@ -48,6 +48,15 @@ impl SleepLockC {
         self.lock.release();
         ret
     }
+
+    // Whether no process at all holds the lock; is_locked()
+    // asks only about the current one.
+    pub fn is_free(&mut self) -> bool {
+        self.lock.acquire();
+        let ret = self.locked == 0;
+        self.lock.release();
+        ret
+    }
 }
 
 mod _binding {
@@ -1,14 +1,24 @@
+pub const T_DIR: u16 = 1; // Directory
+pub const T_FILE: u16 = 2; // File
+pub const T_DEV: u16 = 3; // Device
+pub const T_SYMLINK: u16 = 4; // Symbolic link
+
 pub enum FileType {
     Directory,
     File,
     Device,
+    Symlink,
 }
 
 #[repr(C)]
 pub struct Stat {
-    kind: i16,  // Type of file
-    dev: i32,   // File system's disk device
-    ino: u32,   // Inode number
-    nlink: i16, // Number of links to file
-    size: u32,  // Size of file in bytes
+    kind: i16,    // Type of file
+    dev: i32,     // File system's disk device
+    ino: u32,     // Inode number
+    nlink: i16,   // Number of links to file
+    size: u32,    // Size of file in bytes
+    blksize: u32, // Block size of the file system
+    atime: u32,   // Last access (seconds since 1970)
+    mtime: u32,   // Last modification of the contents
+    ctime: u32,   // Last change to the inode
 }
@@ -29,8 +29,36 @@ pub enum SystemCall {
     Link = 19,
     MakeDirectory = 20,
     Close = 21,
+    Restrict = 22,
+    Seek = 23,
+    PositionedRead = 24,
+    PositionedWrite = 25,
+    DuplicateTo = 26,
+    DuplicateToWithFlags = 27,
+    FileControl = 28,
+    GetDirectoryEntries = 29,
+    SymbolicLink = 30,
+    ReadLink = 31,
+    LinkStatus = 32,
+    Time = 33,
+    GetTimeOfDay = 34,
+    SetFileTimes = 35,
+    Rename = 36,
+    Truncate = 37,
+    TruncateFile = 38,
+    AllocateFileSpace = 39,
+    FileSync = 40,
+    Sync = 41,
+    CrashAt = 42,
+    Mount = 43,
+    Unmount = 44,
+    Ioctl = 45,
 }
 
+// restrict() modes: what happens when a filtered call is made.
+pub const RESTRICT_EPERM: i32 = 0; // the call fails with -1
+pub const RESTRICT_KILL: i32 = 1; // the process is killed
+
 // Fetch the int at addr from the current process.
 #[no_mangle]
 extern "C" fn fetchint(addr: usize, ip: *mut i32) -> i32 {
@@ -134,18 +162,101 @@ pub extern "C" fn syscall() {
         fn sys_link() -> u32;
         fn sys_mkdir() -> u32;
         fn sys_close() -> u32;
+        fn sys_restrict() -> u32;
+        fn sys_lseek() -> u32;
+        fn sys_pread() -> u32;
+        fn sys_pwrite() -> u32;
+        fn sys_dup2() -> u32;
+        fn sys_dup3() -> u32;
+        fn sys_fcntl() -> u32;
+        fn sys_getdents() -> u32;
+        fn sys_symlink() -> u32;
+        fn sys_readlink() -> u32;
+        fn sys_lstat() -> u32;
+        fn sys_time() -> u32;
+        fn sys_gettimeofday() -> u32;
+        fn sys_utime() -> u32;
+        fn sys_rename() -> u32;
+        fn sys_truncate() -> u32;
+        fn sys_ftruncate() -> u32;
+        fn sys_fallocate() -> u32;
+        fn sys_fsync() -> u32;
+        fn sys_sync() -> u32;
+        #[cfg(crashtest)]
+        fn sys_crashat() -> u32;
+        fn sys_mount() -> u32;
+        fn sys_umount() -> u32;
+        fn sys_ioctl() -> u32;
+    }
+
+    // Only a CRASHTEST kernel has crash points to arm.
+    #[cfg(not(crashtest))]
+    unsafe extern "C" fn sys_crashat() -> u32 {
+        -1i32 as u32
     }
 
-    const SYSCALLS: [unsafe extern "C" fn() -> u32; 21] = [
-        sys_fork, sys_exit, sys_wait, sys_pipe, sys_read, sys_kill, sys_exec, sys_fstat, sys_chdir,
-        sys_dup, sys_getpid, sys_sbrk, sys_sleep, sys_uptime, sys_open, sys_write, sys_mknod,
-        sys_unlink, sys_link, sys_mkdir, sys_close,
+    const SYSCALLS: [unsafe extern "C" fn() -> u32; 45] = [
+        sys_fork,
+        sys_exit,
+        sys_wait,
+        sys_pipe,
+        sys_read,
+        sys_kill,
+        sys_exec,
+        sys_fstat,
+        sys_chdir,
+        sys_dup,
+        sys_getpid,
+        sys_sbrk,
+        sys_sleep,
+        sys_uptime,
+        sys_open,
+        sys_write,
+        sys_mknod,
+        sys_unlink,
+        sys_link,
+        sys_mkdir,
+        sys_close,
+        sys_restrict,
+        sys_lseek,
+        sys_pread,
+        sys_pwrite,
+        sys_dup2,
+        sys_dup3,
+        sys_fcntl,
+        sys_getdents,
+        sys_symlink,
+        sys_readlink,
+        sys_lstat,
+        sys_time,
+        sys_gettimeofday,
+        sys_utime,
+        sys_rename,
+        sys_truncate,
+        sys_ftruncate,
+        sys_fallocate,
+        sys_fsync,
+        sys_sync,
+        sys_crashat,
+        sys_mount,
+        sys_umount,
+        sys_ioctl,
     ];
 
     let curproc = unsafe { &mut *my_process().unwrap() };
     let mut tf = unsafe { &mut *curproc.tf };
     let num = tf.eax as usize;
 
+    // Filtered calls never reach the handler. A killed process
+    // exits in trap() on its way back to user space.
+    if !curproc.may_syscall(num) {
+        if curproc.sysmode == RESTRICT_KILL {
+            curproc.killed = 1;
+        }
+        tf.eax = -1i32 as u32;
+        return;
+    }
+
     if let Some(syscall) = SYSCALLS.get(num - 1) {
         tf.eax = unsafe { syscall() };
     } else {
@@ -0,0 +1,510 @@
+// tmpfs: a file system kept in memory.
+//
+// A tmpfs keeps its inodes, or nodes, in table pages that are
+// allocated as it fills. A node's content is in pages: the
+// first one, then up to NINDEX more listed by an index page.
+// A directory's content is an array of Dirents. Every page a
+// tmpfs uses counts against its size limit, which the mount
+// option size=N sets, in bytes, or with a k or m suffix in
+// kilobytes or megabytes. Everything is freed at umount.
+//
+// Nothing goes through the buffer cache or the log: a write
+// is a memmove, and a crash loses it all.
+
+use core::mem::size_of;
+
+use crate::{
+    file::INode,
+    fs::NAMEMAX,
+    kalloc::{kalloc_zeroed, kfree},
+    mmu::PGSIZE,
+    param::{NDISK, NMOUNT, TMPFSSIZE},
+    rtc::unixtime,
+    spinlock::SpinLock,
+    stat::T_DIR,
+    vfs::{FileSystem, INodeOperations},
+};
+
+const NINDEX: usize = PGSIZE / size_of::<usize>(); // pages an index page lists
+const MAXFILE: usize = (1 + NINDEX) * PGSIZE; // largest file, in bytes
+const NTABLE: usize = 16; // most node table pages
+const NPERTABLE: usize = PGSIZE / size_of::<Node>(); // nodes per table page
+const ROOT: u32 = 1; // inode number of the root directory
+
+#[repr(C)]
+struct Node {
+    ty: u16, // 0 if free
+    major: u16,
+    minor: u16,
+    nlink: u16,
+    size: u32,
+    atime: u32,
+    mtime: u32,
+    ctime: u32,
+    first: usize, // first page of the content, or 0
+    index: usize, // page listing the rest, or 0
+}
+
+#[repr(C)]
+struct Dirent {
+    inum: u32,               // 0 if free
+    name: [u8; NAMEMAX + 1], // NUL-terminated
+}
+
+const DIRENTSIZE: u32 = size_of::<Dirent>() as u32;
+
+impl Dirent {
+    fn name(&self) -> &[u8] {
+        let n = self.name.iter().position(|&c| c == 0).unwrap_or(NAMEMAX);
+        &self.name[..n]
+    }
+}
+
+struct State {
+    tables: [usize; NTABLE], // node table pages, or 0
+    pages: usize,            // pages in use
+    limit: usize,            // most pages it may use
+}
+
+pub struct TmpFs {
+    dev: u32,
+    state: SpinLock<State>,
+}
+
+// One tmpfs for each mount there can be, numbered after the disks.
+pub static mut TMPFS: [TmpFs; NMOUNT] = {
+    let mut fs = [const { TmpFs::new() }; NMOUNT];
+    let mut i = 0;
+    while i < NMOUNT {
+        fs[i].dev = (NDISK + i) as u32;
+        i += 1;
+    }
+    fs
+};
+
+// The page limit that the mount options opts ask for.
+fn parse_size(opts: &[u8]) -> Option<usize> {
+    if opts.is_empty() {
+        return Some(TMPFSSIZE);
+    }
+    let n = opts.strip_prefix(b"size=")?;
+    let (digits, unit) = match n.last()? {
+        b'k' => (&n[..n.len() - 1], 1024),
+        b'm' => (&n[..n.len() - 1], 1024 * 1024),
+        _ => (n, 1),
+    };
+    if digits.is_empty() {
+        return None;
+    }
+    let mut size: usize = 0;
+    for &c in digits {
+        if !c.is_ascii_digit() {
+            return None;
+        }
+        size = size.checked_mul(10)?.checked_add((c - b'0') as usize)?;
+    }
+    let size = size.checked_mul(unit)?;
+    Some((size + PGSIZE - 1) / PGSIZE)
+}
+
+impl TmpFs {
+    const fn new() -> Self {
+        Self {
+            dev: 0,
+            state: SpinLock::new(State {
+                tables: [0; NTABLE],
+                pages: 0,
+                limit: 0,
+            }),
+        }
+    }
+
+    // Node inum, which must be allocated.
+    fn node(&self, inum: u32) -> &mut Node {
+        let i = (inum as usize).wrapping_sub(1);
+        let table = match self.state.lock().tables.get(i / NPERTABLE) {
+            Some(&table) if table != 0 => table,
+            _ => panic!("tmpfs: no node"),
+        };
+        unsafe { &mut *(table as *mut Node).add(i % NPERTABLE) }
+    }
+
+    // A zeroed page, if the size limit allows one more.
+    fn page_alloc(&self) -> Option<usize> {
+        let mut st = self.state.lock();
+        if st.pages >= st.limit {
+            return None;
+        }
+        let page = kalloc_zeroed()?;
+        st.pages += 1;
+        Some(page)
+    }
+
+    fn page_free(&self, page: usize) {
+        kfree(page);
+        self.state.lock().pages -= 1;
+    }
+
+    // Address of page pn of n's content, allocating it if alloc
+    // is set and there is none yet. 0 if there is none.
+    fn page(&self, n: &mut Node, pn: usize, alloc: bool) -> usize {
+        if pn == 0 {
+            if n.first == 0 && alloc {
+                n.first = self.page_alloc().unwrap_or(0);
+            }
+            return n.first;
+        }
+        if n.index == 0 {
+            if !alloc {
+                return 0;
+            }
+            match self.page_alloc() {
+                Some(page) => n.index = page,
+                None => return 0,
+            }
+        }
+        let slot = unsafe { &mut *(n.index as *mut usize).add(pn - 1) };
+        if *slot == 0 && alloc {
+            *slot = self.page_alloc().unwrap_or(0);
+        }
+        *slot
+    }
+
+    // Free the pages of n's content from page pn on.
+    fn trim(&self, n: &mut Node, pn: usize) {
+        if n.index != 0 {
+            let index = unsafe { &mut *(n.index as *mut [usize; NINDEX]) };
+            for slot in index.iter_mut().skip(pn.max(1) - 1) {
+                if *slot != 0 {
+                    self.page_free(*slot);
+                    *slot = 0;
+                }
+            }
+            if pn <= 1 {
+                self.page_free(n.index);
+                n.index = 0;
+            }
+        }
+        if pn == 0 && n.first != 0 {
+            self.page_free(n.first);
+            n.first = 0;
+        }
+    }
+
+    // Copy n's content at off to dst. Holes read as zeroes.
+    fn copy_out(&self, n: &mut Node, dst: &mut [u8], off: usize) {
+        let mut done = 0;
+        while done < dst.len() {
+            let o = off + done;
+            let m = (dst.len() - done).min(PGSIZE - o % PGSIZE);
+            let page = self.page(n, o / PGSIZE, false);
+            let dst = &mut dst[done..done + m];
+            if page == 0 {
+                dst.fill(0);
+            } else {
+                let src = (page + o % PGSIZE) as *const u8;
+                dst.copy_from_slice(unsafe { core::slice::from_raw_parts(src, m) });
+            }
+            done += m;
+        }
+    }
+
+    // Copy src to n's content at off. Returns the number of
+    // bytes copied, which is short once the tmpfs is full.
+    fn copy_in(&self, n: &mut Node, src: &[u8], off: usize) -> usize {
+        let mut done = 0;
+        while done < src.len() {
+            let o = off + done;
+            let m = (src.len() - done).min(PGSIZE - o % PGSIZE);
+            let page = self.page(n, o / PGSIZE, true);
+            if page == 0 {
+                break;
+            }
+            let dst = (page + o % PGSIZE) as *mut u8;
+            unsafe { core::slice::from_raw_parts_mut(dst, m) }
+                .copy_from_slice(&src[done..done + m]);
+            done += m;
+        }
+        done
+    }
+
+    fn dirent(&self, n: &mut Node, off: u32) -> Dirent {
+        let mut de = Dirent {
+            inum: 0,
+            name: [0; NAMEMAX + 1],
+        };
+        let dst = unsafe {
+            core::slice::from_raw_parts_mut(&mut de as *mut Dirent as *mut u8, DIRENTSIZE as usize)
+        };
+        self.copy_out(n, dst, off as usize);
+        de
+    }
+
+    // Write the entry (name, inum) at off in directory n.
+    fn put_dirent(&self, n: &mut Node, off: u32, name: &[u8], inum: u32) -> bool {
+        let mut de = Dirent {
+            inum,
+            name: [0; NAMEMAX + 1],
+        };
+        let len = name.len().min(NAMEMAX);
+        de.name[..len].copy_from_slice(&name[..len]);
+        let src = unsafe {
+            core::slice::from_raw_parts(&de as *const Dirent as *const u8, DIRENTSIZE as usize)
+        };
+        self.copy_in(n, src, off as usize) == src.len()
+    }
+
+    fn set_inum(&self, n: &mut Node, off: u32, inum: u32) {
+        if self.copy_in(n, &inum.to_ne_bytes(), off as usize) != size_of::<u32>() {
+            panic!("tmpfs: set_inum");
+        }
+    }
+}
+
+impl FileSystem for TmpFs {
+    fn mount(&self, opts: &[u8]) -> bool {
+        let Some(limit) = parse_size(opts) else {
+            return false;
+        };
+        self.state.lock().limit = limit;
+        if self.alloc(T_DIR as i16) != ROOT {
+            self.unmount();
+            return false;
+        }
+        let root = self.node(ROOT);
+        root.nlink = 1;
+        if !self.put_dirent(root, 0, b".", ROOT) || !self.put_dirent(root, DIRENTSIZE, b"..", ROOT)
+        {
+            self.unmount();
+            return false;
+        }
+        root.size = 2 * DIRENTSIZE;
+        true
+    }
+
+    fn unmount(&self) {
+        let tables = {
+            let mut st = self.state.lock();
+            core::mem::replace(&mut st.tables, [0; NTABLE])
+        };
+        for &table in tables.iter().filter(|&&table| table != 0) {
+            let nodes = unsafe { &mut *(table as *mut [Node; NPERTABLE]) };
+            for n in nodes.iter_mut().filter(|n| n.ty != 0) {
+                self.trim(n, 0);
+            }
+            self.page_free(table);
+        }
+    }
+
+    fn dev(&self) -> u32 {
+        self.dev
+    }
+
+    fn root(&self) -> u32 {
+        ROOT
+    }
+
+    fn block_size(&self) -> u32 {
+        PGSIZE as u32
+    }
+
+    fn alloc(&self, ty: i16) -> u32 {
+        let time = unixtime();
+        let mut st = self.state.lock();
+        for t in 0..NTABLE {
+            if st.tables[t] == 0 {
+                if st.pages >= st.limit {
+                    return 0;
+                }
+                let Some(page) = kalloc_zeroed() else {
+                    return 0;
+                };
+                st.tables[t] = page;
+                st.pages += 1;
+            }
+            let nodes = unsafe { &mut *(st.tables[t] as *mut [Node; NPERTABLE]) };
+            if let Some(i) = nodes.iter().position(|n| n.ty == 0) {
+                nodes[i] = Node {
+                    ty: ty as u16,
+                    major: 0,
+                    minor: 0,
+                    nlink: 0,
+                    size: 0,
+                    atime: time,
+                    mtime: time,
+                    ctime: time,
+                    first: 0,
+                    index: 0,
+                };
+                return (t * NPERTABLE + i + 1) as u32;
+            }
+        }
+        0
+    }
+
+    fn load(&self, ip: &mut INode) {
+        let n = self.node(ip.inum);
+        ip.ty = n.ty;
+        ip.major = n.major;
+        ip.minor = n.minor;
+        ip.nlink = n.nlink;
+        ip.size = n.size;
+        ip.atime = n.atime;
+        ip.mtime = n.mtime;
+        ip.ctime = n.ctime;
+    }
+
+    fn update(&self, ip: &mut INode) {
+        let n = self.node(ip.inum);
+        ip.ctime = unixtime();
+        n.ty = ip.ty;
+        n.major = ip.major;
+        n.minor = ip.minor;
+        n.nlink = ip.nlink;
+        n.size = ip.size;
+        n.atime = ip.atime;
+        n.mtime = ip.mtime;
+        n.ctime = ip.ctime;
+    }
+
+    fn free(&self, ip: &mut INode) {
+        let n = self.node(ip.inum);
+        self.trim(n, 0);
+        n.ty = 0;
+        ip.ty = 0;
+    }
+}
+
+impl INodeOperations for TmpFs {
+    fn read(&self, ip: &mut INode, dst: &mut [u8], off: u32) -> i32 {
+        if off.checked_add(dst.len() as u32).is_none() {
+            return -1;
+        }
+        if off >= ip.size {
+            return 0;
+        }
+        let n = dst.len().min((ip.size - off) as usize);
+        ip.atime = unixtime();
+        self.copy_out(self.node(ip.inum), &mut dst[..n], off as usize);
+        n as i32
+    }
+
+    fn write(&self, ip: &mut INode, src: &[u8], off: u32) -> i32 {
+        let end = off as usize + src.len();
+        if off.checked_add(src.len() as u32).is_none() || end > MAXFILE {
+            return -1;
+        }
+        let n = self.copy_in(self.node(ip.inum), src, off as usize);
+        if n == 0 && !src.is_empty() {
+            return -1;
+        }
+        if off as usize + n > ip.size as usize {
+            ip.size = (off as usize + n) as u32;
+        }
+        ip.mtime = unixtime();
+        self.update(ip);
+        n as i32
+    }
+
+    fn truncate(&self, ip: &mut INode, size: u32, _budget: i32) -> i32 {
+        if size as usize > MAXFILE {
+            return -1;
+        }
+        if size < ip.size {
+            let n = self.node(ip.inum);
+            let size = size as usize;
+            self.trim(n, (size + PGSIZE - 1) / PGSIZE);
+            // Zero the rest of the last page, which growing the
+            // file again would bring back.
+            let page = self.page(n, size / PGSIZE, false);
+            if size % PGSIZE != 0 && page != 0 {
+                let tail = (page + size % PGSIZE) as *mut u8;
+                unsafe { core::ptr::write_bytes(tail, 0, PGSIZE - size % PGSIZE) };
+            }
+        }
+        if size != ip.size {
+            ip.size = size;
+            ip.mtime = unixtime();
+        }
+        self.update(ip);
+        1
+    }
+
+    fn allocate(&self, ip: &mut INode, off: u32, n: u32) -> i32 {
+        let (off, n) = (off as usize, n as usize);
+        if n == 0 || off + n > MAXFILE {
+            return -1;
+        }
+        let node = self.node(ip.inum);
+        let mut end = off + n;
+        for pn in off / PGSIZE..=(end - 1) / PGSIZE {
+            if self.page(node, pn, true) == 0 {
+                end = pn * PGSIZE;
+                break;
+            }
+        }
+        if end <= off {
+            return -1;
+        }
+        if end > ip.size as usize {
+            ip.size = end as u32;
+            ip.mtime = unixtime();
+        }
+        self.update(ip);
+        (end - off) as i32
+    }
+
+    fn lookup(&self, dp: &mut INode, name: &[u8], off: &mut u32) -> u32 {
+        let n = self.node(dp.inum);
+        for o in (0..dp.size).step_by(DIRENTSIZE as usize) {
+            let de = self.dirent(n, o);
+            if de.inum != 0 && de.name() == name {
+                *off = o;
+                return de.inum;
+            }
+        }
+        0
+    }
+
+    fn link(&self, dp: &mut INode, name: &[u8], inum: u32) -> i32 {
+        let n = self.node(dp.inum);
+        let off = (0..dp.size)
+            .step_by(DIRENTSIZE as usize)
+            .find(|&o| self.dirent(n, o).inum == 0)
+            .unwrap_or(dp.size);
+        if !self.put_dirent(n, off, name, inum) {
+            return -1;
+        }
+        if off == dp.size {
+            dp.size += DIRENTSIZE;
+            self.update(dp);
+        }
+        0
+    }
+
+    fn remove(&self, dp: &mut INode, off: u32) -> i32 {
+        self.set_inum(self.node(dp.inum), off, 0);
+        0
+    }
+
+    fn set(&self, dp: &mut INode, off: u32, inum: u32) -> i32 {
+        self.set_inum(self.node(dp.inum), off, inum);
+        0
+    }
+
+    fn entry(&self, dp: &mut INode, off: u32, inum: &mut u32, name: &mut [u8]) -> u32 {
+        let n = self.node(dp.inum);
+        *inum = 0;
+        for o in (off - off % DIRENTSIZE..dp.size).step_by(DIRENTSIZE as usize) {
+            let de = self.dirent(n, o);
+            if de.inum != 0 {
+                let len = de.name().len();
+                name[..len].copy_from_slice(de.name());
+                name[len] = 0;
+                *inum = de.inum;
+                return o + DIRENTSIZE;
+            }
+        }
+        dp.size
+    }
+}
@@ -45,6 +45,7 @@ pub const IRQ_TIMER: u32 = 0;
 pub const IRQ_KBD: u32 = 1;
 pub const IRQ_COM1: u32 = 4;
 pub const IRQ_IDE: u32 = 14;
+pub const IRQ_IDE2: u32 = 15;
 pub const IRQ_ERROR: u32 = 19;
 pub const IRQ_SPURIOUS: u32 = 31;
 
@@ -120,10 +121,10 @@ fn init_trap_vector_table(idt: &mut [GateDescriptor; 256]) {
 static mut IDT: [GateDescriptor; 256] = [GateDescriptor::null(); 256];
 
 #[no_mangle]
-static mut TICKS: u32 = 0;
+pub static mut TICKS: u32 = 0;
 
 #[no_mangle]
-static mut TICKSLOCK: SpinLockC = SpinLockC::new();
+pub static mut TICKSLOCK: SpinLockC = SpinLockC::new();
 
 pub fn load_interrupt_descriptor_table() {
     unsafe {
@@ -161,11 +162,14 @@ unsafe fn trap_handler(tf: &mut TrapFrame) {
             lapiceoi();
         }
         const { T_IRQ0 + IRQ_IDE } => {
-            ide_interrupt();
+            ide_interrupt(0);
             lapiceoi();
         }
-        const { T_IRQ0 + IRQ_IDE + 1 } => {
-            // Bochs generates spurious IDE1 interrupts.
+        const { T_IRQ0 + IRQ_IDE2 } => {
+            // Bochs generates spurious IDE1 interrupts; with
+            // nothing queued the handler ignores them.
+            ide_interrupt(1);
+            lapiceoi();
         }
         const { T_IRQ0 + IRQ_KBD } => {
             keyboard_interrupt();
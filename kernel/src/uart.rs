@@ -1,5 +1,7 @@
 use crate::{
     console::consoleintr,
+    dev::{register, DevSw, UART},
+    file::INode,
     x86::{inb, outb},
 };
 
@@ -41,8 +43,32 @@ pub fn uartinit() {
             uartputc(ch as u32);
         }
     }
+
+    register(b"uart", UART, 0, &UARTSW);
+}
+
+// The serial port as a device of its own, for output that
+// should not show on the screen. What arrives on it goes to
+// the console.
+unsafe extern "C" fn uartwrite(
+    _ip: *mut INode,
+    src: *const u8,
+    _off: u32,
+    n: i32,
+    _flags: i32,
+) -> i32 {
+    for i in 0..n.max(0) as usize {
+        uartputc(*src.add(i) as u32);
+    }
+    n
 }
 
+static UARTSW: DevSw = DevSw {
+    read: None,
+    write: Some(uartwrite),
+    ioctl: None,
+};
+
 #[no_mangle]
 pub extern "C" fn uartputc(c: u32) {
     use crate::lapic::microdelay;
@@ -0,0 +1,459 @@
+// Virtual file system.
+//
+// A file system implements FileSystem and INodeOperations. Its
+// inodes live in the inode cache (fs.c), which keeps them by dev
+// and inode number and asks the file system to load, update and
+// free them; readi(), dirlink() and the other inode operations
+// find an inode's file system in the mount table by its dev.
+//
+// The mount table also joins the file systems into one tree.
+// Walking a path, namex() steps from a directory that has a file
+// system mounted on it to that file system's root, and for ".."
+// from a mounted root back to the directory it covers.
+//
+// A file system on IDE disk n has device number n; one with no
+// disk, like a tmpfs, has a number past the disks.
+//
+// Only the root disk has a log (log.c), so an xv6 file system on
+// any other disk mounts only read-only, with the option "ro".
+
+use arrayvec::ArrayVec;
+
+use crate::{
+    buf::BUFFER_CACHE,
+    devfs::DEVFS,
+    ext2::EXT2FS,
+    fat32::FAT32FS,
+    file::INode,
+    fs::{NAMEMAX, XV6FS},
+    ide::IDE,
+    param::{NDISK, NMOUNT},
+    sleeplock::SleepLockC,
+    spinlock::SpinLockC,
+    stat::T_DEV,
+    tmpfs::TMPFS,
+};
+
+pub trait FileSystem: INodeOperations {
+    // Get ready to use the file system, with the mount options
+    // opts. Returns false if its device does not hold one, or
+    // the options are wrong.
+    fn mount(&self, opts: &[u8]) -> bool;
+
+    // Called once nothing uses the file system any more.
+    fn unmount(&self) {}
+
+    // Device number of the file system.
+    fn dev(&self) -> u32;
+
+    // Inode number of the root directory.
+    fn root(&self) -> u32;
+
+    // Block size that stat reports.
+    fn block_size(&self) -> u32;
+
+    // Allocate an inode of type ty. Returns its number, or 0 if
+    // there is no room for one.
+    fn alloc(&self, ty: i16) -> u32;
+
+    // Fill in the type, size and the rest of ip from the file
+    // system.
+    fn load(&self, ip: &mut INode);
+
+    // Write ip's metadata back, stamping its change time.
+    fn update(&self, ip: &mut INode);
+
+    // Free ip and its contents: it has no links left, and
+    // nothing else refers to it.
+    fn free(&self, ip: &mut INode);
+}
+
+// Operations on an inode, which the caller has locked. A
+// directory entry's offset is whatever lookup() and entry() say
+// it is; remove() and set() take it back.
+pub trait INodeOperations {
+    // Read from ip at off; returns the number of bytes read,
+    // or -1.
+    fn read(&self, ip: &mut INode, dst: &mut [u8], off: u32) -> i32;
+
+    // Write to ip at off; returns the number of bytes written,
+    // or -1 if none were.
+    fn write(&self, ip: &mut INode, src: &[u8], off: u32) -> i32;
+
+    // Set the size of ip, freeing no more blocks than a
+    // transaction of budget blocks can hold. Returns 0 if the
+    // caller must call again, 1 when done, or -1.
+    fn truncate(&self, ip: &mut INode, size: u32, budget: i32) -> i32;
+
+    // Allocate the space for n bytes at off. Returns the number
+    // of bytes covered, or -1.
+    fn allocate(&self, ip: &mut INode, off: u32, n: u32) -> i32;
+
+    // Inode number of the entry name in directory dp, or 0.
+    // Sets *off to the entry's offset.
+    fn lookup(&self, dp: &mut INode, name: &[u8], off: &mut u32) -> u32;
+
+    // Add the entry (name, inum) to dp, which has no entry name.
+    fn link(&self, dp: &mut INode, name: &[u8], inum: u32) -> i32;
+
+    // Remove the entry at off from dp. Returns 0, or -1 if the
+    // file system does not allow it.
+    fn remove(&self, dp: &mut INode, off: u32) -> i32;
+
+    // Point the entry at off in dp at inum. Returns 0, or -1 if
+    // the file system does not allow it.
+    fn set(&self, dp: &mut INode, off: u32, inum: u32) -> i32;
+
+    // Find the first entry of dp at or after off, setting *inum
+    // and copying its name, NUL-terminated, to name, which has
+    // room for NAMEMAX+1 bytes. Returns the offset just past it.
+    // At the end of dp, *inum is 0.
+    fn entry(&self, dp: &mut INode, off: u32, inum: &mut u32, name: &mut [u8]) -> u32;
+}
+
+struct Mount {
+    dev: u32,
+    fs: &'static dyn FileSystem,
+    root: *mut INode,    // referenced while mounted
+    covered: *mut INode, // directory mounted on; null for /
+}
+
+struct MountTable {
+    lock: SpinLockC,  // protects mounts
+    busy: SleepLockC, // held by mount and umount
+    mounts: ArrayVec<Mount, NMOUNT>,
+}
+
+extern "C" {
+    fn iget(dev: u32, inum: u32) -> *mut INode;
+    fn idup(ip: *mut INode) -> *mut INode;
+    fn iput(ip: *mut INode);
+    fn irefs(dev: u32) -> i32;
+    fn devread(ip: *mut INode, dst: *mut u8, off: u32, n: i32, flags: u32) -> i32;
+    fn devwrite(ip: *mut INode, src: *const u8, off: u32, n: i32, flags: u32) -> i32;
+}
+
+impl MountTable {
+    const fn new() -> Self {
+        Self {
+            lock: SpinLockC::new(),
+            busy: SleepLockC::new(),
+            mounts: ArrayVec::new_const(),
+        }
+    }
+
+    // The file system of device dev, which must be mounted.
+    fn filesystem(&mut self, dev: u32) -> &'static dyn FileSystem {
+        self.lock.acquire();
+        let fs = self.mounts.iter().find(|m| m.dev == dev).map(|m| m.fs);
+        self.lock.release();
+        match fs {
+            Some(fs) => fs,
+            None => panic!("vfs: no file system"),
+        }
+    }
+
+    // A file system of type ty, on disk if it needs one, that
+    // is not mounted yet. Caller holds lock.
+    fn filesystem_type(&self, ty: &[u8], disk: Option<usize>) -> Option<&'static dyn FileSystem> {
+        let fs: &'static dyn FileSystem = match ty {
+            b"xv6" => {
+                &XV6FS[disk.filter(|&disk| unsafe { IDE.as_ref().unwrap().present(disk) })?]
+            }
+            b"fat32" => unsafe {
+                &FAT32FS[disk.filter(|&disk| IDE.as_ref().unwrap().present(disk))?]
+            },
+            b"ext2" => unsafe {
+                &EXT2FS[disk.filter(|&disk| IDE.as_ref().unwrap().present(disk))?]
+            },
+            b"devfs" => unsafe { &DEVFS },
+            b"tmpfs" => unsafe {
+                TMPFS
+                    .iter()
+                    .find(|fs| self.mounts.iter().all(|m| m.dev != fs.dev()))?
+            },
+            _ => return None,
+        };
+        Some(fs)
+    }
+
+    // Mount the xv6 file system on disk dev as /.
+    fn mount_root(&mut self, dev: u32) {
+        let fs: &'static dyn FileSystem = &XV6FS[dev as usize];
+        if !fs.mount(b"") {
+            panic!("mountroot: bad super block");
+        }
+        let root = unsafe { iget(dev, fs.root()) };
+        self.lock.acquire();
+        self.mounts.push(Mount {
+            dev,
+            fs,
+            root,
+            covered: core::ptr::null_mut(),
+        });
+        self.lock.release();
+    }
+
+    // Mount the file system of type ty, on disk if it has one,
+    // at directory dp, which the caller holds a reference to but
+    // has not locked.
+    fn mount(&mut self, ty: &[u8], disk: Option<usize>, opts: &[u8], dp: *mut INode) -> i32 {
+        self.busy.acquire();
+        self.lock.acquire();
+        let fs = self.filesystem_type(ty, disk).filter(|fs| {
+            !self.mounts.is_full()
+                && self
+                    .mounts
+                    .iter()
+                    .all(|m| m.dev != fs.dev() && m.root != dp)
+        });
+        self.lock.release();
+        let fs = match fs {
+            Some(fs) if fs.mount(opts) => fs,
+            _ => {
+                self.busy.release();
+                return -1;
+            }
+        };
+
+        let dev = fs.dev();
+        let root = unsafe { iget(dev, fs.root()) };
+        let covered = unsafe { idup(dp) };
+        self.lock.acquire();
+        self.mounts.push(Mount {
+            dev,
+            fs,
+            root,
+            covered,
+        });
+        self.lock.release();
+        self.busy.release();
+        0
+    }
+
+    // Unmount the file system whose root is ip, if only the
+    // mount table and the caller refer to its inodes. Drops the
+    // caller's reference to ip.
+    fn unmount(&mut self, ip: *mut INode) -> i32 {
+        self.busy.acquire();
+        self.lock.acquire();
+        let at = self
+            .mounts
+            .iter()
+            .position(|m| m.root == ip && !m.covered.is_null() && unsafe { irefs(m.dev) } == 2);
+        let mount = at.map(|at| self.mounts.remove(at));
+        self.lock.release();
+
+        unsafe { iput(ip) };
+        let r = match mount {
+            Some(m) => unsafe {
+                iput(m.root);
+                iput(m.covered);
+                m.fs.unmount();
+                BUFFER_CACHE.flush(Some(m.dev as usize), true);
+                0
+            },
+            None => -1,
+        };
+        self.busy.release();
+        r
+    }
+
+    // Is a file system mounted on ip?
+    fn mounted(&mut self, ip: *mut INode) -> bool {
+        self.lock.acquire();
+        let mounted = self.mounts.iter().any(|m| m.covered == ip);
+        self.lock.release();
+        mounted
+    }
+
+    // If a file system is mounted on ip, return its root in
+    // place of ip.
+    fn enter(&mut self, ip: *mut INode) -> *mut INode {
+        self.lock.acquire();
+        let root = self.mounts.iter().find(|m| m.covered == ip).map(|m| m.root);
+        let root = root.map(|root| unsafe { idup(root) });
+        self.lock.release();
+        match root {
+            Some(root) => unsafe {
+                iput(ip);
+                root
+            },
+            None => ip,
+        }
+    }
+
+    // If ip is the root of a mounted file system, return the
+    // directory it is mounted on in place of ip.
+    fn leave(&mut self, ip: *mut INode) -> *mut INode {
+        self.lock.acquire();
+        let covered = self.mounts.iter().find(|m| m.root == ip).map(|m| m.covered);
+        let covered = covered
+            .filter(|covered| !covered.is_null())
+            .map(|covered| unsafe { idup(covered) });
+        self.lock.release();
+        match covered {
+            Some(covered) => unsafe {
+                iput(ip);
+                covered
+            },
+            None => ip,
+        }
+    }
+}
+
+static mut MOUNT_TABLE: MountTable = MountTable::new();
+
+// The file system ip belongs to.
+fn filesystem(ip: *const INode) -> &'static dyn FileSystem {
+    unsafe { MOUNT_TABLE.filesystem((*ip).dev) }
+}
+
+// The NUL-terminated string at s, without the NUL.
+unsafe fn cstr<'a>(s: *const u8) -> &'a [u8] {
+    let mut n = 0;
+    while *s.add(n) != 0 {
+        n += 1;
+    }
+    core::slice::from_raw_parts(s, n)
+}
+
+mod binding {
+    use super::*;
+
+    #[no_mangle]
+    extern "C" fn mountroot(dev: u32) {
+        unsafe { MOUNT_TABLE.mount_root(dev) }
+    }
+
+    #[no_mangle]
+    unsafe extern "C" fn vfsmount(
+        ty: *const u8,
+        disk: i32,
+        opts: *const u8,
+        dp: *mut INode,
+    ) -> i32 {
+        let disk = if (0..NDISK as i32).contains(&disk) {
+            Some(disk as usize)
+        } else {
+            None
+        };
+        MOUNT_TABLE.mount(cstr(ty), disk, cstr(opts), dp)
+    }
+
+    #[no_mangle]
+    extern "C" fn vfsumount(ip: *mut INode) -> i32 {
+        unsafe { MOUNT_TABLE.unmount(ip) }
+    }
+
+    #[no_mangle]
+    extern "C" fn vfsmounted(ip: *mut INode) -> i32 {
+        unsafe { MOUNT_TABLE.mounted(ip) as i32 }
+    }
+
+    #[no_mangle]
+    extern "C" fn vfsenter(ip: *mut INode) -> *mut INode {
+        unsafe { MOUNT_TABLE.enter(ip) }
+    }
+
+    #[no_mangle]
+    extern "C" fn vfsleave(ip: *mut INode) -> *mut INode {
+        unsafe { MOUNT_TABLE.leave(ip) }
+    }
+
+    #[no_mangle]
+    extern "C" fn vfsroot(dev: u32) -> u32 {
+        unsafe { MOUNT_TABLE.filesystem(dev).root() }
+    }
+
+    #[no_mangle]
+    extern "C" fn vfsblksize(dev: u32) -> u32 {
+        unsafe { MOUNT_TABLE.filesystem(dev).block_size() }
+    }
+
+    #[no_mangle]
+    extern "C" fn vfsialloc(dev: u32, ty: i16) -> u32 {
+        unsafe { MOUNT_TABLE.filesystem(dev).alloc(ty) }
+    }
+
+    #[no_mangle]
+    unsafe extern "C" fn vfsload(ip: *mut INode) {
+        filesystem(ip).load(&mut *ip)
+    }
+
+    #[no_mangle]
+    unsafe extern "C" fn vfsfree(ip: *mut INode) {
+        filesystem(ip).free(&mut *ip)
+    }
+
+    #[no_mangle]
+    unsafe extern "C" fn vfslookup(dp: *mut INode, name: *const u8, poff: *mut u32) -> u32 {
+        let mut off = 0;
+        let inum = filesystem(dp).lookup(&mut *dp, cstr(name), &mut off);
+        if inum != 0 && !poff.is_null() {
+            *poff = off;
+        }
+        inum
+    }
+
+    #[no_mangle]
+    unsafe extern "C" fn iupdate(ip: *mut INode) {
+        filesystem(ip).update(&mut *ip)
+    }
+
+    #[no_mangle]
+    unsafe extern "C" fn readi(ip: *mut INode, dst: *mut u8, off: u32, n: u32) -> i32 {
+        if (*ip).ty == T_DEV {
+            return devread(ip, dst, off, n as i32, 0);
+        }
+        let dst = core::slice::from_raw_parts_mut(dst, n as usize);
+        filesystem(ip).read(&mut *ip, dst, off)
+    }
+
+    #[no_mangle]
+    unsafe extern "C" fn writei(ip: *mut INode, src: *const u8, off: u32, n: u32) -> i32 {
+        if (*ip).ty == T_DEV {
+            return devwrite(ip, src, off, n as i32, 0);
+        }
+        let src = core::slice::from_raw_parts(src, n as usize);
+        filesystem(ip).write(&mut *ip, src, off)
+    }
+
+    #[no_mangle]
+    unsafe extern "C" fn itruncate(ip: *mut INode, size: u32, budget: i32) -> i32 {
+        filesystem(ip).truncate(&mut *ip, size, budget)
+    }
+
+    #[no_mangle]
+    unsafe extern "C" fn ifallocate(ip: *mut INode, off: u32, n: u32) -> i32 {
+        filesystem(ip).allocate(&mut *ip, off, n)
+    }
+
+    // Write a new directory entry (name, inum) into the directory dp.
+    // Fails if dp already has an entry name.
+    #[no_mangle]
+    unsafe extern "C" fn dirlink(dp: *mut INode, name: *const u8, inum: u32) -> i32 {
+        let fs = filesystem(dp);
+        let name = cstr(name);
+        if fs.lookup(&mut *dp, name, &mut 0) != 0 {
+            return -1;
+        }
+        fs.link(&mut *dp, name, inum)
+    }
+
+    #[no_mangle]
+    unsafe extern "C" fn dirremove(dp: *mut INode, off: u32) -> i32 {
+        filesystem(dp).remove(&mut *dp, off)
+    }
+
+    #[no_mangle]
+    unsafe extern "C" fn dirset(dp: *mut INode, off: u32, inum: u32) -> i32 {
+        filesystem(dp).set(&mut *dp, off, inum)
+    }
+
+    #[no_mangle]
+    unsafe extern "C" fn direntat(dp: *mut INode, off: u32, inum: *mut u32, name: *mut u8) -> u32 {
+        let name = core::slice::from_raw_parts_mut(name, NAMEMAX + 1);
+        filesystem(dp).entry(&mut *dp, off, &mut *inum, name)
+    }
+}
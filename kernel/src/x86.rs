@@ -94,6 +94,13 @@ pub unsafe fn readeflags() -> u32 {
     eflags
 }
 
+// Cycles since reset.
+pub unsafe fn rdtsc() -> u64 {
+    let (lo, hi): (u32, u32);
+    asm!("rdtsc", out("eax") lo, out("edx") hi, options(nomem, nostack));
+    (hi as u64) << 32 | lo as u64
+}
+
 pub unsafe fn cli() {
     asm!("cli");
 }
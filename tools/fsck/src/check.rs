@@ -0,0 +1,1390 @@
+// The checks: walk each inode's blocks, the directory tree
+// and the free map, and report every way they disagree. With
+// repair on, each problem is fixed as it is found:
+//
+//  - pointers to blocks outside the data area are cleared, and
+//    files that map blocks past their end grow to cover them;
+//  - a file sharing a block with an earlier inode gets its own
+//    copy of the block (of the whole extent, with extents);
+//  - bad "." and ".." entries are rewritten, and entries with
+//    bad names, naming free inodes or a directory's second name
+//    are cleared;
+//  - with FS_LONGNAMES, a directory block is cut short at its
+//    first bad record, and a bad hash index is dropped, leaving
+//    the buckets as plain blocks;
+//  - inodes in no directory are freed, and nlink is set to the
+//    number of names;
+//  - the free map is rewritten to mark exactly the blocks in use.
+
+use alloc::{
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+
+use crate::dir;
+use crate::image::*;
+
+pub struct Problem {
+    pub msg: String,
+    pub fixed: bool,
+}
+
+pub struct Report {
+    pub problems: Vec<Problem>,
+    pub inodes: u32, // inodes in use
+    pub blocks: u32, // blocks marked in use
+}
+
+// Problems with the super block that make the rest of the
+// image impossible to read.
+pub fn layout(sb: &SuperBlock) -> Result<(), String> {
+    let sbblock = (SBOFF / sb.bsize as usize) as u32;
+    let ninodeblocks = sb.ninodes.div_ceil(sb.ipb());
+    let nbitmap = sb.size.div_ceil(sb.bpb());
+    let ends = |start: u32, n: u32| start as u64 + n as u64;
+
+    if sb.features & !(FS_EXTENTS | FS_LONGNAMES) != 0 {
+        return Err(format!("unknown features {:#x}", sb.features));
+    }
+    if sb.logstart <= sbblock || ends(sb.logstart, sb.nlog) > sb.inodestart as u64 {
+        return Err("log overlaps super block or inodes".into());
+    }
+    if ends(sb.inodestart, ninodeblocks) > sb.bmapstart as u64 {
+        return Err(format!("{} inodes overlap the free map", sb.ninodes));
+    }
+    if ends(sb.bmapstart, nbitmap) > sb.datastart() as u64 {
+        return Err("free map overlaps data blocks".into());
+    }
+    let maxinodes = match sb.features & FS_LONGNAMES {
+        0 => u16::MAX as u32 + 1,
+        _ => u32::MAX,
+    };
+    if sb.ninodes <= ROOTINO || sb.ninodes > maxinodes {
+        return Err(format!("bad inode count {}", sb.ninodes));
+    }
+    Ok(())
+}
+
+// Where an inode keeps a block address, so a repair can
+// change it.
+#[derive(Clone, Copy)]
+enum Ptr {
+    Addr(usize),          // addrs[i]
+    Indirect(u32, usize), // entry i of indirect block b
+    Extent(usize),        // extent i, which holds the block
+}
+
+// A block an inode uses: where it points from, and the file
+// block it holds, if it is not an indirect or extent block.
+#[derive(Clone, Copy)]
+struct Claim {
+    ptr: Ptr,
+    bn: Option<u64>,
+    addr: u32,
+}
+
+struct Entry {
+    off: u32, // byte offset in the directory
+    inum: u32,
+    name: String,
+}
+
+const NODOTDOT: u32 = u32::MAX; // directory has no ".." entry
+
+struct Checker<'a, D> {
+    img: &'a mut Image<D>,
+    repair: bool,
+    problems: Vec<Problem>,
+    inodes: Vec<INodeOnDisk>,
+    owner: Vec<u32>,                // inode using each block, or 0
+    dups: Vec<(u32, Claim, usize)>, // claims on blocks already owned, and their problem
+    data: Vec<Vec<(u32, u32)>>,     // (file block, disk block) of each directory
+    entries: Vec<Vec<Entry>>,       // entries of each directory but . and ..
+    dotdot: Vec<u32>,               // what each directory's ".." names
+    dotdotoff: Vec<u32>,            // and where, with FS_LONGNAMES
+}
+
+impl<'a, D: Disk> Checker<'a, D> {
+    // Record a problem. Returns whether to fix it.
+    fn problem(&mut self, msg: String) -> bool {
+        self.problems.push(Problem {
+            msg,
+            fixed: self.repair,
+        });
+        self.repair
+    }
+
+    // The fix for the last problem could not be made.
+    fn unfixed(&mut self) {
+        if let Some(p) = self.problems.last_mut() {
+            p.fixed = false;
+        }
+    }
+
+    fn inrange(&self, b: u32) -> bool {
+        b >= self.img.sb.datastart() && b < self.img.sb.size
+    }
+
+    fn set_inode(&mut self, inum: u32, ip: INodeOnDisk) -> Result<(), Error> {
+        self.img.write_inode(inum, &ip)?;
+        self.inodes[inum as usize] = ip;
+        Ok(())
+    }
+
+    // Point ptr of inode inum at addr. Indirect blocks that
+    // have been copied for inum are found in copies.
+    fn set_pointer(
+        &mut self,
+        inum: u32,
+        ptr: Ptr,
+        addr: u32,
+        copies: &BTreeMap<(u32, u32), u32>,
+    ) -> Result<(), Error> {
+        match ptr {
+            Ptr::Addr(i) => {
+                let mut ip = self.inodes[inum as usize];
+                ip.addrs[i] = addr;
+                self.set_inode(inum, ip)
+            }
+            Ptr::Indirect(b, i) => {
+                let b = *copies.get(&(inum, b)).unwrap_or(&b);
+                let mut data = self.img.block(b)?;
+                put_u32(&mut data, 4 * i, addr);
+                self.img.write_block(b, &data)
+            }
+            Ptr::Extent(_) => unreachable!("extents move whole"),
+        }
+    }
+
+    // Add the claim of inode inum on addr, found at ptr; or, if
+    // addr is out of range, report it and clear the pointer.
+    // Returns whether addr is in range.
+    fn pointer(
+        &mut self,
+        inum: u32,
+        ptr: Ptr,
+        bn: Option<u64>,
+        addr: u32,
+        claims: &mut Vec<Claim>,
+    ) -> Result<bool, Error> {
+        if self.inrange(addr) {
+            claims.push(Claim { ptr, bn, addr });
+            return Ok(true);
+        }
+        if self.problem(format!("inode {}: block {} out of range", inum, addr)) {
+            self.set_pointer(inum, ptr, 0, &BTreeMap::new())?;
+        }
+        Ok(false)
+    }
+
+    // Collect the blocks of the tree rooted at addr, depth
+    // levels of indirect blocks above data blocks that map the
+    // file from block base on.
+    fn tree(
+        &mut self,
+        inum: u32,
+        ptr: Ptr,
+        addr: u32,
+        depth: u32,
+        base: u64,
+        claims: &mut Vec<Claim>,
+    ) -> Result<(), Error> {
+        let bn = if depth == 0 { Some(base) } else { None };
+        if !self.pointer(inum, ptr, bn, addr, claims)? || depth == 0 {
+            return Ok(());
+        }
+        let nind = self.img.sb.nindirect();
+        let per = (nind as u64).pow(depth - 1);
+        let b = self.img.block(addr)?;
+        for j in 0..nind as usize {
+            let a = u32_at(&b, 4 * j);
+            if a != 0 {
+                self.tree(
+                    inum,
+                    Ptr::Indirect(addr, j),
+                    a,
+                    depth - 1,
+                    base + j as u64 * per,
+                    claims,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    // The extents of inode inum, up to the first unused one.
+    fn extent_list(&mut self, inum: u32) -> Result<Vec<Extent>, Error> {
+        let ip = &self.inodes[inum as usize];
+        let mut ext: Vec<Extent> = (0..NEXTENT)
+            .map(|i| Extent {
+                off: ip.addrs[3 * i],
+                start: ip.addrs[3 * i + 1],
+                len: ip.addrs[3 * i + 2],
+            })
+            .collect();
+        let xb = ip.addrs[EXTBLK];
+        if xb != 0 && self.inrange(xb) {
+            let b = self.img.block(xb)?;
+            ext.extend((0..NXEXTENT).map(|i| Extent::parse(&b[i * EXTENTSIZE..])));
+        }
+        let n = ext.iter().position(|e| e.len == 0).unwrap_or(ext.len());
+        ext.truncate(n);
+        Ok(ext)
+    }
+
+    // Replace the extents of inode inum with ext, which must fit
+    // in the inode and the overflow block it has.
+    fn set_extents(&mut self, inum: u32, ext: &[Extent]) -> Result<(), Error> {
+        let mut ip = self.inodes[inum as usize];
+        for i in 0..NEXTENT {
+            let e = ext.get(i).copied().unwrap_or_default();
+            ip.addrs[3 * i] = e.off;
+            ip.addrs[3 * i + 1] = e.start;
+            ip.addrs[3 * i + 2] = e.len;
+        }
+        let xb = ip.addrs[EXTBLK];
+        if xb != 0 && self.inrange(xb) {
+            let mut b = self.img.block(xb)?;
+            for i in 0..NXEXTENT {
+                let e = ext.get(NEXTENT + i).copied().unwrap_or_default();
+                e.encode(&mut b[i * EXTENTSIZE..]);
+            }
+            self.img.write_block(xb, &b)?;
+        }
+        self.set_inode(inum, ip)
+    }
+
+    // The blocks of inode inum, mapped with extents. Bad
+    // extents are dropped when repairing.
+    fn extents(&mut self, inum: u32, claims: &mut Vec<Claim>) -> Result<(), Error> {
+        let xb = self.inodes[inum as usize].addrs[EXTBLK];
+        if xb != 0 {
+            self.pointer(inum, Ptr::Addr(EXTBLK), None, xb, claims)?;
+        }
+
+        let ext = self.extent_list(inum)?;
+        let mut keep = Vec::new();
+        let mut next = 0u64;
+        for (i, e) in ext.iter().enumerate() {
+            let end = e.start as u64 + e.len as u64;
+            let mut bad = false;
+            if (e.off as u64) < next {
+                bad |= self.problem(format!("inode {}: extents overlap or out of order", inum));
+            }
+            if !self.inrange(e.start) || end > self.img.sb.size as u64 {
+                self.problem(format!(
+                    "inode {}: extent of blocks {}..{} out of range",
+                    inum, e.start, end
+                ));
+                if !self.repair {
+                    continue;
+                }
+                bad = true;
+            }
+            if bad {
+                continue;
+            }
+            next = e.off as u64 + e.len as u64;
+            let ptr = Ptr::Extent(if self.repair { keep.len() } else { i });
+            for j in 0..e.len {
+                claims.push(Claim {
+                    ptr,
+                    bn: Some(e.off as u64 + j as u64),
+                    addr: e.start + j,
+                });
+            }
+            keep.push(*e);
+        }
+        if keep.len() != ext.len() && self.repair {
+            self.set_extents(inum, &keep)?;
+        }
+        Ok(())
+    }
+
+    // Claim every block of inode inum.
+    fn inode(&mut self, inum: u32) -> Result<(), Error> {
+        let mut claims = Vec::new();
+        if self.img.sb.features & FS_EXTENTS != 0 {
+            self.extents(inum, &mut claims)?;
+        } else {
+            let addrs = self.inodes[inum as usize].addrs;
+            for (bn, &a) in addrs[..NDIRECT].iter().enumerate() {
+                if a != 0 {
+                    self.pointer(inum, Ptr::Addr(bn), Some(bn as u64), a, &mut claims)?;
+                }
+            }
+            let nind = self.img.sb.nindirect() as u64;
+            let (mut base, mut n) = (NDIRECT as u64, nind);
+            for (i, &a) in addrs[NDIRECT..].iter().enumerate() {
+                if a != 0 {
+                    let ptr = Ptr::Addr(NDIRECT + i);
+                    self.tree(inum, ptr, a, i as u32 + 1, base, &mut claims)?;
+                }
+                base += n;
+                n *= nind;
+            }
+        }
+
+        let ip = self.inodes[inum as usize];
+        let bs = self.img.bsize() as u64;
+        let nbn = (ip.size as u64).div_ceil(bs);
+        let mut end = nbn;
+        for c in claims {
+            match self.owner[c.addr as usize] {
+                0 => self.owner[c.addr as usize] = inum,
+                other => {
+                    self.problem(format!(
+                        "block {} used by inode {} and inode {}",
+                        c.addr, other, inum
+                    ));
+                    self.dups.push((inum, c, self.problems.len() - 1));
+                }
+            }
+            if let Some(bn) = c.bn {
+                if bn >= nbn {
+                    self.problem(format!(
+                        "inode {}: block {} mapped past the end of the file",
+                        inum, c.addr
+                    ));
+                    end = end.max(bn + 1);
+                }
+                if ip.kind == T_DIR {
+                    self.data[inum as usize].push((bn as u32, c.addr));
+                }
+            }
+        }
+        if end > nbn && self.repair {
+            let mut ip = ip;
+            ip.size = (end * bs).min(u32::MAX as u64) as u32;
+            self.set_inode(inum, ip)?;
+        }
+        Ok(())
+    }
+
+    // A free run of n data blocks, if there is one.
+    fn free_run(&self, n: u32) -> Option<u32> {
+        let first = self.img.sb.datastart();
+        let mut start = first;
+        for b in first..self.img.sb.size {
+            if self.owner[b as usize] != 0 {
+                start = b + 1;
+            } else if b + 1 - start == n {
+                return Some(start);
+            }
+        }
+        None
+    }
+
+    // Give each inode that shares a block with an earlier one a
+    // copy of its own. The copy of an indirect block still names
+    // the shared blocks below it, which get copies in turn.
+    fn copy_dups(&mut self) -> Result<(), Error> {
+        let mut copies = BTreeMap::new(); // (inode, indirect block) -> copy
+        let mut moved = Vec::new(); // (inode, extent) already copied
+        let dups = core::mem::take(&mut self.dups);
+        for (k, &(inum, c, _)) in dups.iter().enumerate() {
+            let (old, len) = match c.ptr {
+                Ptr::Extent(i) if moved.contains(&(inum, i)) => continue,
+                Ptr::Extent(i) => {
+                    let e = self.extent_list(inum)?[i];
+                    (e.start, e.len)
+                }
+                _ => (c.addr, 1),
+            };
+            let new = match self.free_run(len) {
+                Some(b) => b,
+                None => {
+                    // Out of space: leave this and the rest shared.
+                    for &(_, _, p) in &dups[k..] {
+                        self.problems[p].fixed = false;
+                    }
+                    break;
+                }
+            };
+            // The blocks of a moved extent that were not shared
+            // are free now.
+            let shared: Vec<u32> = dups
+                .iter()
+                .filter(|d| {
+                    d.0 == inum
+                        && matches!((d.1.ptr, c.ptr), (Ptr::Extent(a), Ptr::Extent(b)) if a == b)
+                })
+                .map(|d| d.1.addr)
+                .collect();
+            for j in 0..len {
+                let data = self.img.block(old + j)?;
+                self.img.write_block(new + j, &data)?;
+                self.owner[(new + j) as usize] = inum;
+                if matches!(c.ptr, Ptr::Extent(_)) && !shared.contains(&(old + j)) {
+                    self.owner[(old + j) as usize] = 0;
+                }
+                for d in self.data[inum as usize].iter_mut() {
+                    if d.1 == old + j {
+                        d.1 = new + j;
+                    }
+                }
+            }
+            match c.ptr {
+                Ptr::Extent(i) => {
+                    let mut ext = self.extent_list(inum)?;
+                    ext[i].start = new;
+                    self.set_extents(inum, &ext)?;
+                    moved.push((inum, i));
+                }
+                ptr => {
+                    self.set_pointer(inum, ptr, new, &copies)?;
+                    if c.bn.is_none() {
+                        copies.insert((inum, old), new);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Disk block holding file block bn of directory dir.
+    fn dir_block(&self, dir: u32, bn: u32) -> Option<u32> {
+        let data = &self.data[dir as usize];
+        data.iter().find(|d| d.0 == bn).map(|d| d.1)
+    }
+
+    // Point entry off of directory dir at inum, named name. A
+    // DirRecord keeps its name. Returns false if the directory
+    // has no block there.
+    fn set_entry(&mut self, dir: u32, off: u32, inum: u32, name: &[u8]) -> Result<bool, Error> {
+        let bs = self.img.bsize() as u32;
+        let addr = match self.dir_block(dir, off / bs) {
+            Some(a) => a,
+            None => return Ok(false),
+        };
+        let mut b = self.img.block(addr)?;
+        if self.img.sb.features & FS_LONGNAMES != 0 {
+            put_u32(&mut b, (off % bs) as usize, inum);
+            return self.img.write_block(addr, &b).map(|_| true);
+        }
+        DirectoryEntry::new(inum as u16, name).encode(&mut b[(off % bs) as usize..]);
+        self.img.write_block(addr, &b)?;
+        let ip = &self.inodes[dir as usize];
+        if ip.size < off + DIRENTSIZE as u32 {
+            let mut ip = *ip;
+            ip.size = off + DIRENTSIZE as u32;
+            self.set_inode(dir, ip)?;
+        }
+        Ok(true)
+    }
+
+    // Add an entry to directory dir, which holds DirRecords, in
+    // the bucket its name hashes to if dir has an index. Returns
+    // false if there is no room.
+    fn add_entry(&mut self, dir: u32, name: &[u8], inum: u32) -> Result<bool, Error> {
+        let nb = self.inodes[dir as usize].size / self.img.bsize() as u32;
+        let mut bns: Vec<u32> = (0..nb).collect();
+        if let (true, Some(a)) = (nb > 1, self.dir_block(dir, 0)) {
+            if let Some((depth, slots)) = dir::index(&self.img.block(a)?) {
+                bns.clear();
+                let mut bn = slots[dirhash(name) as usize & ((1 << depth) - 1)];
+                while bn != 0 && bns.len() < nb as usize {
+                    bns.push(bn);
+                    bn = match self.dir_block(dir, bn) {
+                        Some(a) => dir::tail(&self.img.block(a)?),
+                        None => 0,
+                    };
+                }
+            }
+        }
+        for bn in bns {
+            if let Some(a) = self.dir_block(dir, bn) {
+                let mut b = self.img.block(a)?;
+                if dir::add(&mut b, name, inum) {
+                    self.img.write_block(a, &b)?;
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    // Point directory d's ".." at p.
+    fn set_dotdot(&mut self, d: u32, p: u32) -> Result<bool, Error> {
+        if self.img.sb.features & FS_LONGNAMES == 0 {
+            return self.set_entry(d, DIRENTSIZE as u32, p, b"..");
+        }
+        match self.dotdot[d as usize] {
+            NODOTDOT => self.add_entry(d, b"..", p),
+            _ => self.set_entry(d, self.dotdotoff[d as usize], p, b".."),
+        }
+    }
+
+    // Check an entry of directory dir other than "." and "..",
+    // adding it to entries if it is good.
+    fn entry(
+        &mut self,
+        dir: u32,
+        off: u32,
+        target: u32,
+        name: &[u8],
+        entries: &mut Vec<Entry>,
+    ) -> Result<(), Error> {
+        let bad = name == b"." || name == b".." || !dir::good_name(name);
+        let name = String::from_utf8_lossy(name).into_owned();
+        let msg = if bad {
+            format!("directory {}: bad name {:?}", dir, name)
+        } else if target >= self.img.sb.ninodes || self.inodes[target as usize].kind == 0 {
+            format!("directory {}: {:?} names free inode {}", dir, name, target)
+        } else {
+            entries.push(Entry {
+                off,
+                inum: target,
+                name,
+            });
+            return Ok(());
+        };
+        if self.problem(msg) {
+            self.set_entry(dir, off, 0, name.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    // Read the entries of directory inum.
+    fn directory(&mut self, inum: u32) -> Result<(), Error> {
+        if self.img.sb.features & FS_LONGNAMES != 0 {
+            return self.long_directory(inum);
+        }
+        let mut ip = self.inodes[inum as usize];
+        if !(ip.size as usize).is_multiple_of(DIRENTSIZE) {
+            let msg = format!(
+                "directory {}: size {} is not a whole number of entries",
+                inum, ip.size
+            );
+            if self.problem(msg) {
+                ip.size -= ip.size % DIRENTSIZE as u32;
+                self.set_inode(inum, ip)?;
+            }
+        }
+        let bs = self.img.bsize();
+        self.data[inum as usize].sort_unstable();
+        let zero = vec![0; bs];
+
+        let mut entries = Vec::new();
+        let mut block = (u32::MAX, zero.clone());
+        self.dotdot[inum as usize] = NODOTDOT;
+        for i in 0..(ip.size as usize / DIRENTSIZE).max(2) {
+            let off = (i * DIRENTSIZE) as u32;
+            let bn = off / bs as u32;
+            if block.0 != bn {
+                let data = &self.data[inum as usize];
+                block = match data.binary_search_by_key(&bn, |&(bn, _)| bn) {
+                    Ok(j) => (bn, self.img.block(data[j].1)?),
+                    Err(_) => (bn, zero.clone()),
+                };
+            }
+            let de = DirectoryEntry::parse(&block.1[off as usize % bs..]);
+            let name = String::from_utf8_lossy(de.name()).into_owned();
+            let target = de.inum as u32;
+
+            match i {
+                0 if name == "." && target == inum => continue,
+                0 => {
+                    if self.problem(format!("directory {}: first entry is not \".\"", inum))
+                        && !self.set_entry(inum, 0, inum, b".")?
+                    {
+                        self.unfixed();
+                    }
+                    continue;
+                }
+                1 if name == ".." => {
+                    self.dotdot[inum as usize] = target;
+                    continue;
+                }
+                // Fixed when the tree walk finds the parent.
+                1 => {
+                    self.problem(format!("directory {}: second entry is not \"..\"", inum));
+                    continue;
+                }
+                _ => {}
+            }
+            if target != 0 {
+                self.entry(inum, off, target, de.name(), &mut entries)?;
+            }
+        }
+        self.entries[inum as usize] = entries;
+        Ok(())
+    }
+
+    // Read the entries of directory inum, which holds DirRecords.
+    fn long_directory(&mut self, inum: u32) -> Result<(), Error> {
+        let mut ip = self.inodes[inum as usize];
+        let bs = self.img.bsize();
+        if !(ip.size as usize).is_multiple_of(bs) {
+            let msg = format!(
+                "directory {}: size {} is not a whole number of blocks",
+                inum, ip.size
+            );
+            if self.problem(msg) {
+                ip.size -= ip.size % bs as u32;
+                self.set_inode(inum, ip)?;
+            }
+        }
+        let nb = ip.size / bs as u32;
+        self.data[inum as usize].sort_unstable();
+        let mut blocks = Vec::new();
+        for bn in 0..nb {
+            blocks.push(match self.dir_block(inum, bn) {
+                Some(a) => Some(self.img.block(a)?),
+                None => {
+                    self.problem(format!("directory {}: block {} is a hole", inum, bn));
+                    self.unfixed();
+                    None
+                }
+            });
+        }
+
+        // Drop a bad index: block 0 becomes a block of records
+        // and the buckets ones the kernel scans in turn.
+        let mut skip = 0;
+        if let (true, Some(b0)) = (nb > 1, blocks.first().and_then(|b| b.as_ref())) {
+            if dir::index(b0).is_some() {
+                skip = 1;
+                if let Some(why) = bad_index(&blocks) {
+                    let msg = format!("directory {}: bad hash index: {}", inum, why);
+                    if self.problem(msg) {
+                        for (bn, b) in blocks.iter_mut().enumerate() {
+                            let b = match b {
+                                Some(b) => b,
+                                None => continue,
+                            };
+                            if bn == 0 {
+                                *b = dir::empty_block(bs);
+                            } else if dir::tail(b) != 0 {
+                                dir::set_tail(b, 0);
+                            } else {
+                                continue;
+                            }
+                            let a = self.dir_block(inum, bn as u32).unwrap();
+                            self.img.write_block(a, b)?;
+                        }
+                        skip = 0;
+                    }
+                }
+            }
+        }
+
+        let mut entries = Vec::new();
+        let mut dot = false;
+        self.dotdot[inum as usize] = NODOTDOT;
+        for (bn, b) in blocks.iter_mut().enumerate().skip(skip) {
+            let b = match b {
+                Some(b) => b,
+                None => continue,
+            };
+            let (recs, bad) = dir::records(b);
+            if let Some(off) = bad {
+                let msg = format!("directory {}: block {}: bad record at {}", inum, bn, off);
+                if self.problem(msg) {
+                    b[off..bs - DIRTAIL].fill(0);
+                    DirRecord {
+                        reclen: (bs - DIRTAIL - off) as u16,
+                        ..Default::default()
+                    }
+                    .encode(&mut b[off..]);
+                    let a = self.dir_block(inum, bn as u32).unwrap();
+                    self.img.write_block(a, b)?;
+                }
+            }
+            for r in recs.into_iter().filter(|r| r.rec.inum != 0) {
+                let off = (bn * bs + r.off) as u32;
+                let target = r.rec.inum;
+                if r.name == b"." && !dot {
+                    dot = true;
+                    if target != inum {
+                        let msg = format!("directory {}: \".\" names {}", inum, target);
+                        if self.problem(msg) {
+                            self.set_entry(inum, off, inum, b".")?;
+                        }
+                    }
+                } else if r.name == b".." && self.dotdot[inum as usize] == NODOTDOT {
+                    self.dotdot[inum as usize] = target;
+                    self.dotdotoff[inum as usize] = off;
+                } else {
+                    self.entry(inum, off, target, &r.name, &mut entries)?;
+                }
+            }
+        }
+        if !dot
+            && self.problem(format!("directory {}: no \".\" entry", inum))
+            && !self.add_entry(inum, b".", inum)?
+        {
+            self.unfixed();
+        }
+        // Fixed when the tree walk finds the parent.
+        if self.dotdot[inum as usize] == NODOTDOT {
+            self.problem(format!("directory {}: no \"..\" entry", inum));
+        }
+        self.entries[inum as usize] = entries;
+        Ok(())
+    }
+
+    // Free inode inum and the blocks it holds.
+    fn clear(&mut self, inum: u32) -> Result<(), Error> {
+        for o in self.owner.iter_mut() {
+            if *o == inum {
+                *o = 0;
+            }
+        }
+        self.set_inode(inum, INodeOnDisk::default())
+    }
+}
+
+// Check the file system in img, and repair it if repair is set.
+pub fn check<D: Disk>(img: &mut Image<D>, repair: bool) -> Result<Report, Error> {
+    let sb = img.sb;
+    let ninodes = sb.ninodes as usize;
+    let mut inodes = Vec::with_capacity(ninodes);
+    for i in 0..sb.ninodes {
+        inodes.push(img.inode(i)?);
+    }
+    let mut c = Checker {
+        img,
+        repair,
+        problems: Vec::new(),
+        inodes,
+        owner: vec![0; sb.size as usize],
+        dups: Vec::new(),
+        data: vec![Vec::new(); ninodes],
+        entries: (0..ninodes).map(|_| Vec::new()).collect(),
+        dotdot: vec![0; ninodes],
+        dotdotoff: vec![0; ninodes],
+    };
+
+    // Every inode in use, and the blocks it holds.
+    for inum in 1..sb.ninodes {
+        match c.inodes[inum as usize].kind {
+            0 => {}
+            T_DIR | T_FILE | T_DEV | T_SYMLINK => c.inode(inum)?,
+            k => {
+                if c.problem(format!("inode {}: unknown type {}", inum, k)) {
+                    c.clear(inum)?;
+                }
+                c.inodes[inum as usize].kind = 0;
+            }
+        }
+    }
+    if repair {
+        c.copy_dups()?;
+    }
+    for inum in 1..sb.ninodes {
+        if c.inodes[inum as usize].kind == T_DIR {
+            c.directory(inum)?;
+        }
+    }
+
+    // Walk the tree from the root, counting the names of each
+    // inode. A directory's ".." counts as a name of its parent;
+    // its "." does not count.
+    let mut links = vec![0u32; ninodes];
+    let mut parent = vec![0u32; ninodes];
+    let root = ROOTINO as usize;
+    if c.inodes[root].kind != T_DIR {
+        c.problem("root inode is not a directory".to_string());
+        c.unfixed();
+    } else {
+        parent[root] = ROOTINO;
+        links[root] = 1; // the root's own ".."
+        let mut queue = vec![ROOTINO];
+        while let Some(d) = queue.pop() {
+            let mut dd = c.dotdot[d as usize];
+            let p = parent[d as usize];
+            if dd != p {
+                let fix = match dd {
+                    NODOTDOT => repair,
+                    _ => c.problem(format!(
+                        "directory {}: \"..\" names {}, not its parent {}",
+                        d, dd, p
+                    )),
+                };
+                if fix {
+                    if c.set_dotdot(d, p)? {
+                        dd = p;
+                    } else {
+                        c.unfixed();
+                    }
+                }
+            }
+            if d != ROOTINO && dd != NODOTDOT && (dd as usize) < ninodes {
+                links[dd as usize] += 1;
+            }
+            for e in core::mem::take(&mut c.entries[d as usize]) {
+                let i = e.inum as usize;
+                if c.inodes[i].kind == T_DIR && parent[i] != 0 {
+                    let msg = format!(
+                        "directory {} is also named {:?} in directory {}",
+                        e.inum, e.name, d
+                    );
+                    if c.problem(msg) {
+                        c.set_entry(d, e.off, 0, e.name.as_bytes())?;
+                    } else {
+                        links[i] += 1;
+                    }
+                    continue;
+                }
+                links[i] += 1;
+                if c.inodes[i].kind == T_DIR {
+                    parent[i] = d;
+                    queue.push(e.inum);
+                }
+            }
+        }
+    }
+
+    let mut nused = 0;
+    for inum in 1..sb.ninodes {
+        let ip = c.inodes[inum as usize];
+        if ip.kind == 0 {
+            continue;
+        }
+        let n = links[inum as usize];
+        if n == 0 {
+            let msg = format!(
+                "inode {} (type {}, nlink {}) is in no directory",
+                inum, ip.kind, ip.nlink
+            );
+            if c.problem(msg) {
+                c.clear(inum)?;
+                continue;
+            }
+        } else if ip.nlink as u32 != n {
+            let msg = format!("inode {}: nlink {}, should be {}", inum, ip.nlink, n);
+            if c.problem(msg) {
+                let mut ip = ip;
+                ip.nlink = n as u16;
+                c.set_inode(inum, ip)?;
+            }
+        }
+        nused += 1;
+    }
+
+    // The free map must mark exactly the metadata and the
+    // blocks some inode holds. Runs of wrong bits are reported
+    // together.
+    let nmap = sb.size.div_ceil(sb.bpb());
+    let mut map = Vec::new();
+    for m in 0..nmap {
+        map.extend(c.img.block(sb.bmapstart + m)?);
+    }
+    let old = map.clone();
+    let mut nblocks = 0;
+    let mut run: Option<(u32, bool)> = None; // first block, and whether leaked
+    for b in 0..=sb.size {
+        let (byte, bit) = (b as usize / 8, 1 << (b % 8));
+        let wrong = if b < sb.size {
+            let used = b < sb.datastart() || c.owner[b as usize] != 0;
+            let marked = map[byte] & bit != 0;
+            nblocks += marked as u32;
+            if used != marked {
+                map[byte] ^= bit;
+                Some(marked)
+            } else {
+                None
+            }
+        } else {
+            None
+        };
+        if let Some((first, leaked)) = run {
+            if wrong == Some(leaked) {
+                continue;
+            }
+            let blocks = match b - first {
+                1 => format!("block {}", first),
+                _ => format!("blocks {}-{}", first, b - 1),
+            };
+            c.problem(match leaked {
+                true => format!("{} marked in use but in no file", blocks),
+                false => format!("{} in use but marked free", blocks),
+            });
+        }
+        run = wrong.map(|leaked| (b, leaked));
+    }
+    if repair {
+        let bs = sb.bsize as usize;
+        for m in 0..nmap as usize {
+            let r = m * bs..(m + 1) * bs;
+            if map[r.clone()] != old[r.clone()] {
+                c.img.write_block(sb.bmapstart + m as u32, &map[r])?;
+            }
+        }
+    }
+
+    Ok(Report {
+        problems: c.problems,
+        inodes: nused,
+        blocks: nblocks,
+    })
+}
+
+// Why the index in block 0 of a directory of blocks, None where
+// it has a hole, does not describe its buckets, if it does not.
+// Every other block must be in exactly one bucket, and every
+// entry in the bucket its name hashes to.
+fn bad_index(blocks: &[Option<Vec<u8>>]) -> Option<String> {
+    let b0 = blocks[0].as_ref().unwrap();
+    let (depth, slots) = dir::index(b0).unwrap();
+    if depth > dirmaxdepth(b0.len()) {
+        return Some(format!("depth {}", depth));
+    }
+    let nb = blocks.len() as u32;
+    let block = |bn: u32| match bn {
+        0 => None,
+        _ => blocks.get(bn as usize).and_then(|b| b.as_ref()),
+    };
+    let mut head = vec![0; blocks.len()]; // first block of each one's bucket
+    for &s in &slots {
+        if block(s).is_none() {
+            return Some(format!("slot names block {}", s));
+        }
+        if head[s as usize] == s {
+            continue;
+        }
+        let mut bn = s;
+        while bn != 0 {
+            if head[bn as usize] != 0 {
+                return Some(format!("block {} is in two buckets", bn));
+            }
+            head[bn as usize] = s;
+            bn = dir::tail(block(bn).unwrap());
+            if bn != 0 && block(bn).is_none() {
+                return Some(format!("bucket links to block {}", bn));
+            }
+        }
+    }
+    for bn in 1..nb {
+        if head[bn as usize] == 0 {
+            return Some(format!("block {} is in no bucket", bn));
+        }
+        for r in dir::records(block(bn)?).0 {
+            let slot = dirhash(&r.name) as usize & (slots.len() - 1);
+            if r.rec.inum != 0 && slots[slot] != head[bn as usize] {
+                let name = String::from_utf8_lossy(&r.name);
+                return Some(format!("{:?} is in the wrong bucket", name));
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::{self, crc32, LogState};
+
+    const BSIZE: usize = 512;
+    const SIZE: u32 = 200;
+    const NINODES: u32 = 32;
+    const NLOG: u32 = 9;
+
+    // The test file system: the root holds directory "d" and file
+    // "f", and d holds file "g". f is long enough to need an
+    // indirect block without FS_EXTENTS; g is one block.
+    const D: u32 = 2;
+    const F: u32 = 3;
+    const G: u32 = 4;
+
+    struct Mem(Vec<u8>);
+
+    impl Disk for Mem {
+        fn read_at(&mut self, off: u64, buf: &mut [u8]) -> Result<(), Error> {
+            let off = off as usize;
+            buf.copy_from_slice(&self.0[off..off + buf.len()]);
+            Ok(())
+        }
+
+        fn write_at(&mut self, off: u64, buf: &[u8]) -> Result<(), Error> {
+            let off = off as usize;
+            self.0[off..off + buf.len()].copy_from_slice(buf);
+            Ok(())
+        }
+    }
+
+    struct Fs {
+        img: Image<Mem>,
+        next: u32, // next free block
+    }
+
+    impl Fs {
+        fn new(features: u32) -> Self {
+            let mut sb = SuperBlock {
+                size: SIZE,
+                ninodes: NINODES,
+                nlog: NLOG,
+                logstart: 2,
+                inodestart: 2 + NLOG,
+                bmapstart: 2 + NLOG + NINODES / (BSIZE / INODESIZE) as u32,
+                features,
+                bsize: BSIZE as u32,
+                ..SuperBlock::default()
+            };
+            sb.nblocks = SIZE - sb.bmapstart - 1;
+            let mut disk = vec![0; SIZE as usize * BSIZE];
+            sb.encode(&mut disk[SBOFF..]);
+            let img = Image::open(Mem(disk)).unwrap();
+            layout(&img.sb).unwrap();
+
+            let mut fs = Fs {
+                next: img.sb.datastart(),
+                img,
+            };
+            fs.file(F, T_FILE, &contents(F, (NDIRECT + 2) * BSIZE));
+            fs.file(G, T_FILE, &contents(G, BSIZE));
+            fs.dir(D, ROOTINO, &[(G, b"g")]);
+            fs.dir(ROOTINO, ROOTINO, &[(D, b"d"), (F, b"f")]);
+
+            let mut map = vec![0; BSIZE];
+            for b in 0..fs.next as usize {
+                map[b / 8] |= 1 << (b % 8);
+            }
+            fs.img.write_block(fs.img.sb.bmapstart, &map).unwrap();
+            fs
+        }
+
+        // Give inode inum of type kind the contents data, in
+        // blocks allocated in order, its indirect block last.
+        fn file(&mut self, inum: u32, kind: u16, data: &[u8]) {
+            let n = data.len().div_ceil(BSIZE);
+            let start = self.next;
+            self.next += n as u32;
+            let mut ip = INodeOnDisk {
+                kind,
+                nlink: 1,
+                size: data.len() as u32,
+                ..INodeOnDisk::default()
+            };
+            if self.img.sb.features & FS_EXTENTS != 0 {
+                ip.addrs[..3].copy_from_slice(&[0, start, n as u32]);
+            } else {
+                let mut ind = vec![0; BSIZE];
+                for bn in 0..n {
+                    match bn {
+                        bn if bn < NDIRECT => ip.addrs[bn] = start + bn as u32,
+                        bn => put_u32(&mut ind, 4 * (bn - NDIRECT), start + bn as u32),
+                    }
+                }
+                if n > NDIRECT {
+                    ip.addrs[NDIRECT] = self.next;
+                    self.img.write_block(self.next, &ind).unwrap();
+                    self.next += 1;
+                }
+            }
+            for (bn, chunk) in data.chunks(BSIZE).enumerate() {
+                let mut b = vec![0; BSIZE];
+                b[..chunk.len()].copy_from_slice(chunk);
+                self.img.write_block(start + bn as u32, &b).unwrap();
+            }
+            self.img.write_inode(inum, &ip).unwrap();
+        }
+
+        // Make directory inum, in parent, holding entries.
+        fn dir(&mut self, inum: u32, parent: u32, entries: &[(u32, &[u8])]) {
+            let mut all = vec![(inum, b".".to_vec()), (parent, b"..".to_vec())];
+            all.extend(entries.iter().map(|&(i, name)| (i, name.to_vec())));
+            let data = dir::build(self.img.sb.features, BSIZE, &all);
+            self.file(inum, T_DIR, &data);
+            let subdirs = entries
+                .iter()
+                .filter(|&&(i, _)| self.img.inode(i).unwrap().kind == T_DIR)
+                .count();
+            self.edit(inum, |ip| ip.nlink += subdirs as u16);
+        }
+
+        fn edit(&mut self, inum: u32, f: impl FnOnce(&mut INodeOnDisk)) {
+            let mut ip = self.img.inode(inum).unwrap();
+            f(&mut ip);
+            self.img.write_inode(inum, &ip).unwrap();
+        }
+
+        fn edit_block(&mut self, b: u32, f: impl FnOnce(&mut [u8])) {
+            let mut data = self.img.block(b).unwrap();
+            f(&mut data);
+            self.img.write_block(b, &data).unwrap();
+        }
+
+        // Disk block holding file block 0 of inode inum.
+        fn first(&mut self, inum: u32) -> u32 {
+            let ip = self.img.inode(inum).unwrap();
+            match self.img.sb.features & FS_EXTENTS {
+                0 => ip.addrs[0],
+                _ => ip.addrs[1],
+            }
+        }
+
+        // Write a transaction to the log that commits data to
+        // block b.
+        fn commit(&mut self, seq: u32, b: u32, data: &[u8]) {
+            let start = self.img.sb.logstart;
+            let nhead = loghdrblocks(NLOG as usize, BSIZE) as u32;
+            let mut head = vec![0; BSIZE];
+            put_u32(&mut head, 0, LOGMAGIC);
+            put_u32(&mut head, 4, seq);
+            put_u32(&mut head, 8, 1);
+            put_u32(&mut head, LOGHEAD, b);
+            put_u32(&mut head, LOGHEAD + 4, crc32(seq, data));
+            let sum = crc32(0, &head[..LOGHEAD + LOGBLOCK]);
+            put_u32(&mut head, 12, sum);
+            self.img.write_block(start, &head).unwrap();
+            self.img.write_block(start + nhead, data).unwrap();
+        }
+    }
+
+    // Bytes of file inum that differ from block to block.
+    fn contents(inum: u32, n: usize) -> Vec<u8> {
+        (0..n)
+            .map(|i| (i / BSIZE * 7 + i + inum as usize) as u8)
+            .collect()
+    }
+
+    fn problems(img: &mut Image<Mem>) -> Vec<String> {
+        let report = check(img, false).unwrap();
+        report.problems.into_iter().map(|p| p.msg).collect()
+    }
+
+    // Check that fs has exactly the problems expect, and that
+    // repairing it leaves it clean.
+    fn repairs(fs: &mut Fs, expect: &[&str]) {
+        assert_eq!(problems(&mut fs.img), expect);
+        let report = check(&mut fs.img, true).unwrap();
+        assert!(report.problems.iter().all(|p| p.fixed));
+        assert_eq!(problems(&mut fs.img), Vec::<String>::new());
+    }
+
+    #[test]
+    fn clean() {
+        for features in 0..=FS_EXTENTS | FS_LONGNAMES {
+            let mut fs = Fs::new(features);
+            let report = check(&mut fs.img, false).unwrap();
+            assert_eq!(problems(&mut fs.img), Vec::<String>::new());
+            assert_eq!(report.inodes, 4);
+            assert_eq!(report.blocks, fs.next);
+        }
+    }
+
+    #[test]
+    fn dup_indirect() {
+        let mut fs = Fs::new(0);
+        let ind = fs.img.inode(F).unwrap().addrs[NDIRECT];
+        assert_eq!(ind, 32);
+        fs.edit(G, |ip| {
+            ip.addrs[NDIRECT] = ind;
+            ip.size = ((NDIRECT + 2) * BSIZE) as u32;
+        });
+        repairs(
+            &mut fs,
+            &[
+                "block 32 used by inode 3 and inode 4",
+                "block 30 used by inode 3 and inode 4",
+                "block 31 used by inode 3 and inode 4",
+            ],
+        );
+
+        // g has its own copy of the indirect block, naming its
+        // own copies of f's blocks.
+        let copy = fs.img.inode(G).unwrap().addrs[NDIRECT];
+        assert_ne!(copy, ind);
+        let (old, new) = (fs.img.block(ind).unwrap(), fs.img.block(copy).unwrap());
+        for i in 0..2 {
+            let (a, b) = (u32_at(&old, 4 * i), u32_at(&new, 4 * i));
+            assert_ne!(a, b);
+            assert_eq!(fs.img.block(a).unwrap(), fs.img.block(b).unwrap());
+        }
+    }
+
+    #[test]
+    fn dup_extent() {
+        let mut fs = Fs::new(FS_EXTENTS);
+        let shared = fs.first(F);
+        fs.edit(G, |ip| ip.addrs[1] = shared);
+        repairs(
+            &mut fs,
+            &[
+                "block 20 used by inode 3 and inode 4",
+                "block 32 marked in use but in no file",
+            ],
+        );
+        let copy = fs.first(G);
+        assert_ne!(copy, shared);
+        assert_eq!(fs.img.block(copy).unwrap(), fs.img.block(shared).unwrap());
+    }
+
+    #[test]
+    fn out_of_range() {
+        let mut fs = Fs::new(0);
+        fs.edit(F, |ip| ip.addrs[0] = 5);
+        fs.edit_block(32, |b| put_u32(b, 4, SIZE + 5));
+        repairs(
+            &mut fs,
+            &[
+                "inode 3: block 5 out of range",
+                "inode 3: block 205 out of range",
+                "block 20 marked in use but in no file",
+                "block 31 marked in use but in no file",
+            ],
+        );
+        let ip = fs.img.inode(F).unwrap();
+        assert_eq!(ip.addrs[0], 0);
+        assert_eq!(u32_at(&fs.img.block(32).unwrap(), 4), 0);
+
+        let mut fs = Fs::new(FS_EXTENTS);
+        fs.edit(F, |ip| ip.addrs[1] = SIZE - 2);
+        repairs(
+            &mut fs,
+            &[
+                "inode 3: extent of blocks 198..210 out of range",
+                "blocks 20-31 marked in use but in no file",
+            ],
+        );
+        assert_eq!(fs.img.inode(F).unwrap().addrs[2], 0);
+    }
+
+    #[test]
+    fn dir_size() {
+        let mut fs = Fs::new(0);
+        fs.edit(ROOTINO, |ip| ip.size += 3);
+        repairs(
+            &mut fs,
+            &["directory 1: size 67 is not a whole number of entries"],
+        );
+        assert_eq!(fs.img.inode(ROOTINO).unwrap().size, 64);
+
+        let mut fs = Fs::new(FS_LONGNAMES);
+        fs.edit(D, |ip| ip.size += 3);
+        repairs(
+            &mut fs,
+            &["directory 2: size 515 is not a whole number of blocks"],
+        );
+        assert_eq!(fs.img.inode(D).unwrap().size, BSIZE as u32);
+    }
+
+    #[test]
+    fn dots() {
+        let mut fs = Fs::new(0);
+        let (root, d) = (fs.first(ROOTINO), fs.first(D));
+        fs.edit_block(root, |b| {
+            DirectoryEntry::new(ROOTINO as u16, b"x").encode(b)
+        });
+        fs.edit_block(d, |b| put_u16(b, DIRENTSIZE, F as u16));
+        repairs(
+            &mut fs,
+            &[
+                "directory 1: first entry is not \".\"",
+                "directory 2: \"..\" names 3, not its parent 1",
+                "inode 1: nlink 2, should be 1",
+                "inode 3: nlink 1, should be 2",
+            ],
+        );
+        let de = DirectoryEntry::parse(&fs.img.block(root).unwrap());
+        assert_eq!((de.inum as u32, de.name()), (ROOTINO, &b"."[..]));
+
+        // DirRecords: "." at 0, ".." at 12.
+        let mut fs = Fs::new(FS_LONGNAMES);
+        let d = fs.first(D);
+        fs.edit_block(d, |b| {
+            put_u32(b, 0, 0);
+            put_u32(b, 12, 0);
+        });
+        repairs(
+            &mut fs,
+            &[
+                "directory 2: no \".\" entry",
+                "directory 2: no \"..\" entry",
+                "inode 1: nlink 2, should be 1",
+            ],
+        );
+    }
+
+    #[test]
+    fn bad_name() {
+        // d's records: ".", ".." and "g" at 0, 12 and 24.
+        for (c, shown) in [(b'/', "/"), (0, "\\0")] {
+            let mut fs = Fs::new(FS_LONGNAMES);
+            let d = fs.first(D);
+            fs.edit_block(d, |b| b[24 + DIRRECSIZE] = c);
+            let msg = format!("directory 2: bad name \"{}\"", shown);
+            repairs(
+                &mut fs,
+                &[&msg, "inode 4 (type 2, nlink 1) is in no directory"],
+            );
+            assert_eq!(fs.img.inode(G).unwrap().kind, 0);
+        }
+    }
+
+    #[test]
+    fn bad_type() {
+        let mut fs = Fs::new(FS_EXTENTS | FS_LONGNAMES);
+        fs.edit(G, |ip| ip.kind = 9);
+        repairs(
+            &mut fs,
+            &[
+                "inode 4: unknown type 9",
+                "directory 2: \"g\" names free inode 4",
+                "block 32 marked in use but in no file",
+            ],
+        );
+        assert_eq!(fs.img.inode(G).unwrap().kind, 0);
+    }
+
+    // Check fs as the next boot will find it, then recover its
+    // log, as fsck does, and check it clean.
+    fn recovers(fs: &mut Fs) -> LogState {
+        let state = log::read(&mut fs.img).unwrap();
+        if let LogState::Committed { ref blocks, .. } = state {
+            log::install(&mut fs.img, blocks).unwrap();
+        }
+        assert_eq!(problems(&mut fs.img), Vec::<String>::new());
+        log::recover(&mut fs.img, &state).unwrap();
+        assert!(matches!(log::read(&mut fs.img).unwrap(), LogState::Empty));
+        assert_eq!(problems(&mut fs.img), Vec::<String>::new());
+        state
+    }
+
+    #[test]
+    fn committed_log() {
+        let mut fs = Fs::new(0);
+        let g = fs.first(G);
+        let data = contents(F, BSIZE);
+        fs.commit(7, g, &data);
+        match recovers(&mut fs) {
+            LogState::Committed { seq, blocks } => assert_eq!((seq, blocks), (7, vec![g])),
+            _ => panic!("transaction not committed"),
+        }
+        assert_eq!(fs.img.block(g).unwrap(), data);
+    }
+
+    #[test]
+    fn bad_log() {
+        // Each corrupts the log header or the logged block, and
+        // leaves g as it was.
+        type Corrupt = fn(&mut [u8], &mut [u8]);
+        let cases: [(&str, Corrupt); 3] = [
+            ("torn header", |head, _| head[4] ^= 1),
+            ("corrupt block", |_, data| data[0] ^= 1),
+            ("too big", |head, _| put_u32(head, 8, NLOG)),
+        ];
+        for (why, corrupt) in cases {
+            let mut fs = Fs::new(0);
+            let g = fs.first(G);
+            let old = fs.img.block(g).unwrap();
+            let mut data = contents(F, BSIZE);
+            fs.commit(7, g, &data);
+            let start = fs.img.sb.logstart;
+            let mut head = fs.img.block(start).unwrap();
+            corrupt(&mut head, &mut data);
+            fs.img.write_block(start, &head).unwrap();
+            fs.img.write_block(start + 1, &data).unwrap();
+            match recovers(&mut fs) {
+                LogState::Refused { why: w, .. } => assert_eq!(w, why),
+                _ => panic!("{} not refused", why),
+            }
+            assert_eq!(fs.img.block(g).unwrap(), old);
+        }
+    }
+}
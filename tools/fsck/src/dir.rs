@@ -0,0 +1,304 @@
+// Directory contents in either format: DirectoryEntry arrays,
+// or with FS_LONGNAMES, blocks of DirRecords, hashed by an index
+// in block 0 once a directory outgrows one block. The record
+// rules are the kernel's (kernel/c/fs.c), so what passes here
+// the kernel can read.
+
+use alloc::{vec, vec::Vec};
+
+use crate::image::*;
+
+pub struct Record {
+    pub off: usize, // byte offset in the block
+    pub rec: DirRecord,
+    pub name: Vec<u8>,
+}
+
+// The records of a block, in order, and the offset of the first
+// one that is not well formed, if any; the ones before it are good.
+pub fn records(block: &[u8]) -> (Vec<Record>, Option<usize>) {
+    let end = block.len() - DIRTAIL;
+    let mut recs = Vec::new();
+    let mut off = 0;
+    while off < end {
+        let rec = DirRecord::parse(&block[off..]);
+        let len = rec.reclen as usize;
+        if len < dirreclen(rec.namelen as usize) || !len.is_multiple_of(4) || off + len > end {
+            return (recs, Some(off));
+        }
+        let name = block[off + DIRRECSIZE..][..rec.namelen as usize].to_vec();
+        recs.push(Record { off, rec, name });
+        off += len;
+    }
+    (recs, None)
+}
+
+// Whether name can be an entry's. Paths split at "/" and end at
+// a NUL, so no path could reach a name holding either.
+pub fn good_name(name: &[u8]) -> bool {
+    !name.is_empty() && !name.contains(&0) && !name.contains(&b'/')
+}
+
+// File block of the next block in this one's bucket, or 0.
+pub fn tail(block: &[u8]) -> u32 {
+    u32_at(block, block.len() - DIRTAIL)
+}
+
+pub fn set_tail(block: &mut [u8], next: u32) {
+    let n = block.len();
+    put_u32(block, n - DIRTAIL, next);
+}
+
+// A block holding one free record.
+pub fn empty_block(bs: usize) -> Vec<u8> {
+    let mut b = vec![0; bs];
+    DirRecord {
+        reclen: (bs - DIRTAIL) as u16,
+        ..Default::default()
+    }
+    .encode(&mut b);
+    b
+}
+
+// Add an entry to a well-formed block, in a free record or in
+// the slack at the end of a live one. Returns false if it has
+// no room.
+pub fn add(block: &mut [u8], name: &[u8], inum: u32) -> bool {
+    let need = dirreclen(name.len());
+    for r in records(block).0 {
+        let used = if r.rec.inum != 0 {
+            dirreclen(r.rec.namelen as usize)
+        } else {
+            0
+        };
+        if (r.rec.reclen as usize) - used < need {
+            continue;
+        }
+        let off = r.off + used;
+        let reclen = r.rec.reclen as usize - used;
+        if used != 0 {
+            let mut rec = r.rec;
+            rec.reclen = used as u16;
+            rec.encode(&mut block[r.off..]);
+        }
+        DirRecord {
+            inum,
+            reclen: reclen as u16,
+            namelen: name.len() as u8,
+            pad: 0,
+        }
+        .encode(&mut block[off..]);
+        block[off + DIRRECSIZE..][..name.len()].copy_from_slice(name);
+        return true;
+    }
+    false
+}
+
+// The index in block 0 of a directory, as (depth, slots), if the
+// directory has more than one block and this is one. The slots
+// are only read if the depth fits the block.
+pub fn index(block: &[u8]) -> Option<(u32, Vec<u32>)> {
+    if u32_at(block, 0) != DIRINDEXMAGIC {
+        return None;
+    }
+    let depth = u32_at(block, 4);
+    if depth > dirmaxdepth(block.len()) {
+        return Some((depth, Vec::new()));
+    }
+    let slots = (0..1usize << depth)
+        .map(|i| u32_at(block, DIRINDEX + 4 * i))
+        .collect();
+    Some((depth, slots))
+}
+
+// The contents of a directory holding entries: one block if they
+// fit, and otherwise an index just deep enough to give each slot
+// a block of its own, with buckets chained only when the deepest
+// index cannot.
+pub fn build(features: u32, bs: usize, entries: &[(u32, Vec<u8>)]) -> Vec<u8> {
+    if features & FS_LONGNAMES == 0 {
+        let mut data = vec![0; entries.len() * DIRENTSIZE];
+        for (i, (inum, name)) in entries.iter().enumerate() {
+            DirectoryEntry::new(*inum as u16, name).encode(&mut data[i * DIRENTSIZE..]);
+        }
+        return data;
+    }
+
+    let mut one = empty_block(bs);
+    if entries
+        .iter()
+        .all(|(inum, name)| add(&mut one, name, *inum))
+    {
+        return one;
+    }
+    let max = dirmaxdepth(bs);
+    let mut depth = 0;
+    let buckets = loop {
+        let n = 1usize << depth;
+        let mut buckets = vec![vec![empty_block(bs)]; n];
+        let mut fits = true;
+        for (inum, name) in entries {
+            let chain = &mut buckets[dirhash(name) as usize & (n - 1)];
+            if !add(chain.last_mut().unwrap(), name, *inum) {
+                fits = false;
+                if depth < max {
+                    break;
+                }
+                let mut b = empty_block(bs);
+                add(&mut b, name, *inum);
+                chain.push(b);
+            }
+        }
+        if fits || depth == max {
+            break buckets;
+        }
+        depth += 1;
+    };
+
+    // Slot i's bucket starts at block 1+i; overflow blocks follow.
+    let n = buckets.len();
+    let mut data = vec![0; bs];
+    put_u32(&mut data, 0, DIRINDEXMAGIC);
+    put_u32(&mut data, 4, depth);
+    for i in 0..n {
+        put_u32(&mut data, DIRINDEX + 4 * i, 1 + i as u32);
+    }
+    let mut next = 1 + n as u32;
+    let mut overflow = Vec::new();
+    for mut chain in buckets {
+        let more = chain.len() as u32 - 1;
+        for (j, b) in chain.iter_mut().take(more as usize).enumerate() {
+            set_tail(b, next + j as u32);
+        }
+        next += more;
+        let mut blocks = chain.into_iter();
+        data.extend(blocks.next().unwrap());
+        overflow.extend(blocks);
+    }
+    data.extend(overflow.concat());
+    data
+}
+
+// The live entries of a directory of data, as (byte offset,
+// inum, name). Stops at a record that is not well formed.
+pub fn entries(features: u32, bs: usize, data: &[u8]) -> Vec<(u32, u32, Vec<u8>)> {
+    if features & FS_LONGNAMES == 0 {
+        return data
+            .chunks_exact(DIRENTSIZE)
+            .enumerate()
+            .map(|(i, d)| (i, DirectoryEntry::parse(d)))
+            .filter(|(_, de)| de.inum != 0)
+            .map(|(i, de)| ((i * DIRENTSIZE) as u32, de.inum as u32, de.name().to_vec()))
+            .collect();
+    }
+    let skip = (data.len() > bs && index(&data[..bs]).is_some()) as usize;
+    let mut ents = Vec::new();
+    for (bn, block) in data.chunks_exact(bs).enumerate().skip(skip) {
+        for r in records(block).0 {
+            if r.rec.inum != 0 {
+                ents.push(((bn * bs + r.off) as u32, r.rec.inum, r.name));
+            }
+        }
+    }
+    ents
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BS: usize = 512;
+
+    // A block holding names, numbered from 1. Each record is as
+    // short as it can be but the last, which runs to the tail.
+    fn block(names: &[&[u8]]) -> Vec<u8> {
+        let mut b = empty_block(BS);
+        for (i, name) in names.iter().enumerate() {
+            assert!(add(&mut b, name, i as u32 + 1));
+        }
+        b
+    }
+
+    #[test]
+    fn spanning_record() {
+        // ".", ".." and "a" at 0, 12 and 24.
+        let mut b = block(&[b".", b"..", b"a"]);
+        let (recs, bad) = records(&b);
+        assert_eq!(bad, None);
+        let offs: Vec<usize> = recs.iter().map(|r| r.off).collect();
+        assert_eq!(offs, [0, 12, 24]);
+        assert_eq!(recs[2].rec.reclen as usize, BS - DIRTAIL - 24);
+
+        // A record may not run into the tail, or on into the next
+        // block, nor be too short for its name or unaligned.
+        for reclen in [BS - 24, BS + 8 - 24, 8, 14] {
+            put_u16(&mut b, 24 + 4, reclen as u16);
+            let (recs, bad) = records(&b);
+            assert_eq!((recs.len(), bad), (2, Some(24)), "reclen {}", reclen);
+        }
+
+        // A directory's entries stop at a bad record, but pick up
+        // again in the next block.
+        let mut data = b;
+        data.extend(block(&[b"b"]));
+        let names: Vec<Vec<u8>> = entries(FS_LONGNAMES, BS, &data)
+            .into_iter()
+            .map(|e| e.2)
+            .collect();
+        assert_eq!(names, [&b"."[..], b"..", b"b"]);
+    }
+
+    #[test]
+    fn longest_name() {
+        // namelen is a byte: NAMEMAX is all a record can hold.
+        assert_eq!(NAMEMAX, u8::MAX as usize);
+        let long = vec![b'x'; NAMEMAX];
+        let mut b = block(&[&long]);
+        let (recs, bad) = records(&b);
+        assert_eq!(bad, None);
+        assert_eq!(
+            (recs[0].rec.namelen as usize, &recs[0].name),
+            (NAMEMAX, &long)
+        );
+        put_u16(&mut b, 4, (dirreclen(NAMEMAX) - 4) as u16);
+        assert_eq!(records(&b).1, Some(0));
+
+        // Two fill more than a block, so three are hashed.
+        let ents: Vec<(u32, Vec<u8>)> = (0..3)
+            .map(|i| {
+                let mut name = long.clone();
+                name[0] = b'a' + i as u8;
+                (i + 2, name)
+            })
+            .collect();
+        let data = build(FS_LONGNAMES, BS, &ents);
+        assert!(data.len() > BS && index(&data[..BS]).is_some());
+        let mut got: Vec<(u32, Vec<u8>)> = entries(FS_LONGNAMES, BS, &data)
+            .into_iter()
+            .map(|e| (e.1, e.2))
+            .collect();
+        got.sort();
+        assert_eq!(got, ents);
+    }
+
+    #[test]
+    fn names() {
+        assert!(good_name(b"a") && good_name(b"...") && good_name(&[b'x'; NAMEMAX]));
+        for name in [&b""[..], b"a\0b", b"\0", b"a/b", b"/"] {
+            assert!(!good_name(name), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn hash() {
+        // Where names are on disk depends on the hash, and the
+        // kernel buckets them with this same function (see
+        // kernel/src/fs.rs): it must never change.
+        assert_eq!(dirhash(b""), 0x811c9dc5);
+        assert_eq!(dirhash(b"a"), 0xe40c292c);
+        assert_eq!(dirhash(b"foobar"), 0xbf9cf968);
+        assert_eq!(dirhash(&[b'x'; NAMEMAX]), 0x6318963f);
+        // Bytes hash unsigned.
+        assert_eq!(dirhash(&[0xff]), 0x7a0b824e);
+    }
+}
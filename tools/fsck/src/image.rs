@@ -0,0 +1,126 @@
+// A file system read and written through a Disk. The on-disk
+// format itself is the ondisk crate's, shared with the kernel.
+
+use alloc::{collections::BTreeMap, vec, vec::Vec};
+use core::fmt;
+
+pub use ondisk::*;
+
+// Where a file system lives: an image file on the host, or a
+// raw disk device inside xv6.
+pub trait Disk {
+    fn read_at(&mut self, off: u64, buf: &mut [u8]) -> Result<(), Error>;
+    fn write_at(&mut self, off: u64, buf: &[u8]) -> Result<(), Error>;
+}
+
+// An image file on the host.
+#[cfg(feature = "std")]
+pub struct ImageFile(pub std::fs::File);
+
+#[cfg(feature = "std")]
+impl Disk for ImageFile {
+    fn read_at(&mut self, off: u64, buf: &mut [u8]) -> Result<(), Error> {
+        use std::os::unix::fs::FileExt;
+        self.0.read_exact_at(buf, off).map_err(|_| Error::Io(off))
+    }
+
+    fn write_at(&mut self, off: u64, buf: &[u8]) -> Result<(), Error> {
+        use std::os::unix::fs::FileExt;
+        self.0.write_all_at(buf, off).map_err(|_| Error::Io(off))
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(u64),           // a read or write at this byte offset failed
+    Bad(&'static str), // not a file system this can check
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(off) => write!(f, "I/O error at byte {}", off),
+            Error::Bad(why) => f.write_str(why),
+        }
+    }
+}
+
+pub struct Image<D> {
+    disk: D,
+    pub sb: SuperBlock,
+    log: BTreeMap<u32, Vec<u8>>, // committed blocks, not yet installed
+    dirty: bool,                 // something has been written
+}
+
+impl<D: Disk> Image<D> {
+    // Read the super block. Fails unless its geometry makes
+    // sense; the caller checks that the disk is big enough.
+    pub fn open(mut disk: D) -> Result<Self, Error> {
+        let mut b = [0; MINBSIZE];
+        disk.read_at(SBOFF as u64, &mut b)?;
+        let sb = SuperBlock::parse(&b);
+        let bs = sb.bsize as usize;
+        if !(MINBSIZE..=MAXBSIZE).contains(&bs) || !bs.is_power_of_two() {
+            return Err(Error::Bad("bad block size in super block"));
+        }
+        if sb.nblocks > sb.size {
+            return Err(Error::Bad("more data blocks than blocks"));
+        }
+        Ok(Image {
+            disk,
+            sb,
+            log: BTreeMap::new(),
+            dirty: false,
+        })
+    }
+
+    pub fn bsize(&self) -> usize {
+        self.sb.bsize as usize
+    }
+
+    // Contents of block b, which must be below sb.size.
+    pub fn block(&mut self, b: u32) -> Result<Vec<u8>, Error> {
+        assert!(b < self.sb.size, "block {} out of range", b);
+        if let Some(data) = self.log.get(&b) {
+            return Ok(data.clone());
+        }
+        let mut data = vec![0; self.bsize()];
+        self.disk
+            .read_at(b as u64 * self.sb.bsize as u64, &mut data)?;
+        Ok(data)
+    }
+
+    pub fn write_block(&mut self, b: u32, data: &[u8]) -> Result<(), Error> {
+        assert!(b < self.sb.size, "block {} out of range", b);
+        if let Some(old) = self.log.get_mut(&b) {
+            old.copy_from_slice(data);
+        }
+        self.dirty = true;
+        self.disk.write_at(b as u64 * self.sb.bsize as u64, data)
+    }
+
+    // Whether anything has been written to the disk.
+    pub fn dirty(&self) -> bool {
+        self.dirty
+    }
+
+    // Make reads of block b see data without writing it, as for
+    // a log that is checked but not installed.
+    pub fn overlay(&mut self, b: u32, data: Vec<u8>) {
+        self.log.insert(b, data);
+    }
+
+    pub fn inode(&mut self, inum: u32) -> Result<INodeOnDisk, Error> {
+        let b = self.block(self.sb.iblock(inum))?;
+        Ok(INodeOnDisk::parse(
+            &b[(inum % self.sb.ipb()) as usize * INODESIZE..],
+        ))
+    }
+
+    pub fn write_inode(&mut self, inum: u32, ip: &INodeOnDisk) -> Result<(), Error> {
+        let bn = self.sb.iblock(inum);
+        let mut b = self.block(bn)?;
+        ip.encode(&mut b[(inum % self.sb.ipb()) as usize * INODESIZE..]);
+        self.write_block(bn, &b)
+    }
+}
@@ -0,0 +1,15 @@
+// Checking and repairing xv6 file systems. Everything reads and
+// writes through a Disk, so the same code runs on the host on
+// an image file (src/main.rs) and inside xv6 on a raw disk
+// device (xv6/).
+
+#![no_std]
+
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
+pub mod check;
+pub mod dir;
+pub mod image;
+pub mod log;
@@ -0,0 +1,125 @@
+// The log, read the way recovery in kernel/c/log.c reads it,
+// so a check can see the file system the next boot will.
+
+use alloc::vec::Vec;
+
+use crate::image::{
+    loghdrblocks, put_u32, u32_at, Disk, Error, Image, LOGBLOCK, LOGHEAD, LOGMAGIC,
+};
+
+pub enum LogState {
+    Unused, // never written, as mkfs leaves it
+    Empty,  // no transaction waiting
+    Committed { seq: u32, blocks: Vec<u32> },
+    Refused { seq: u32, why: &'static str },
+}
+
+// CRC-32 of p, continuing from crc.
+pub fn crc32(crc: u32, p: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &c in p {
+        crc ^= c as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                0xedb88320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+pub fn read<D: Disk>(img: &mut Image<D>) -> Result<LogState, Error> {
+    let sb = img.sb;
+    let bs = img.bsize();
+    let nhead = loghdrblocks(sb.nlog as usize, bs);
+    if sb.nlog as usize <= nhead {
+        return Ok(LogState::Unused);
+    }
+    let mut head = Vec::with_capacity(nhead * bs);
+    for i in 0..nhead as u32 {
+        head.extend_from_slice(&img.block(sb.logstart + i)?);
+    }
+
+    if u32_at(&head, 0) != LOGMAGIC {
+        return Ok(LogState::Unused);
+    }
+    let seq = u32_at(&head, 4);
+    let n = u32_at(&head, 8) as usize;
+    let sum = u32_at(&head, 12);
+    if n > sb.nlog as usize - nhead {
+        return Ok(LogState::Refused {
+            seq,
+            why: "too big",
+        });
+    }
+    let mut h = head[..LOGHEAD + n * LOGBLOCK].to_vec();
+    h[12..16].copy_from_slice(&[0; 4]);
+    if crc32(0, &h) != sum {
+        return Ok(LogState::Refused {
+            seq,
+            why: "torn header",
+        });
+    }
+
+    let mut blocks = Vec::with_capacity(n);
+    for i in 0..n {
+        let blockno = u32_at(&head, LOGHEAD + i * LOGBLOCK);
+        let sum = u32_at(&head, LOGHEAD + i * LOGBLOCK + 4);
+        if blockno >= sb.size {
+            return Ok(LogState::Refused {
+                seq,
+                why: "block out of range",
+            });
+        }
+        if crc32(seq, &img.block(sb.logstart + (nhead + i) as u32)?) != sum {
+            return Ok(LogState::Refused {
+                seq,
+                why: "corrupt block",
+            });
+        }
+        blocks.push(blockno);
+    }
+    Ok(if n == 0 {
+        LogState::Empty
+    } else {
+        LogState::Committed { seq, blocks }
+    })
+}
+
+// Make reads see the committed blocks at their home
+// locations, without writing anything.
+pub fn install<D: Disk>(img: &mut Image<D>, blocks: &[u32]) -> Result<(), Error> {
+    let start = img.sb.logstart + loghdrblocks(img.sb.nlog as usize, img.bsize()) as u32;
+    for (i, &b) in blocks.iter().enumerate() {
+        let data = img.block(start + i as u32)?;
+        img.overlay(b, data);
+    }
+    Ok(())
+}
+
+// Recover as the kernel would: copy the committed blocks home,
+// if any, then write an empty header.
+pub fn recover<D: Disk>(img: &mut Image<D>, state: &LogState) -> Result<(), Error> {
+    let seq = match *state {
+        LogState::Unused | LogState::Empty => return Ok(()),
+        LogState::Committed { seq, ref blocks } => {
+            let start = img.sb.logstart + loghdrblocks(img.sb.nlog as usize, img.bsize()) as u32;
+            for (i, &b) in blocks.iter().enumerate() {
+                let data = img.block(start + i as u32)?;
+                img.write_block(b, &data)?;
+            }
+            seq
+        }
+        LogState::Refused { seq, .. } => seq,
+    };
+    let mut head = img.block(img.sb.logstart)?;
+    put_u32(&mut head, 0, LOGMAGIC);
+    put_u32(&mut head, 4, seq);
+    put_u32(&mut head, 8, 0);
+    put_u32(&mut head, 12, 0);
+    let sum = crc32(0, &head[..LOGHEAD]);
+    put_u32(&mut head, 12, sum);
+    img.write_block(img.sb.logstart, &head)
+}
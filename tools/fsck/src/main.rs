@@ -0,0 +1,110 @@
+// fsck: check, and with -y repair, an xv6 file system image.
+//
+//   fsck [-y] fs.img
+//
+// Without -y, checks the image as the kernel would find it on
+// its next boot: if the log holds a committed transaction, the
+// check sees it installed (in memory; the image is not
+// written). With -y, recovers the log first, as the kernel
+// would, then fixes each problem found and checks again.
+// Prints each problem found, then a summary. Exits 0 if the
+// image is clean (after repair, with -y), 1 if it has problems
+// and 2 if it cannot be checked at all.
+
+use std::{env, fs::OpenOptions, process::exit};
+
+use fsck::{
+    check,
+    image::{Error, Image, ImageFile},
+    log::{self, LogState},
+};
+
+fn fsck(path: &str, repair: bool) -> Result<bool, Error> {
+    let file = OpenOptions::new().read(true).write(repair).open(path);
+    let file = file.unwrap_or_else(|e| {
+        eprintln!("fsck: {}: {}", path, e);
+        exit(2);
+    });
+    let len = file.metadata().map(|m| m.len()).unwrap_or(0);
+    let mut img = Image::open(ImageFile(file))?;
+    if img.sb.size as u64 * img.sb.bsize as u64 > len {
+        return Err(Error::Bad("super block size is past the end of the image"));
+    }
+    if let Err(e) = check::layout(&img.sb) {
+        eprintln!("fsck: {}: {}", path, e);
+        exit(2);
+    }
+
+    let state = log::read(&mut img)?;
+    match state {
+        LogState::Unused | LogState::Empty => {}
+        LogState::Committed { seq, ref blocks } if repair => {
+            println!(
+                "{}: log holds transaction {} of {} blocks, recovered",
+                path,
+                seq,
+                blocks.len()
+            );
+        }
+        LogState::Committed { seq, ref blocks } => {
+            println!(
+                "{}: log holds transaction {} of {} blocks, checked as recovered",
+                path,
+                seq,
+                blocks.len()
+            );
+            log::install(&mut img, blocks)?;
+        }
+        LogState::Refused { seq, why } => {
+            let not = if repair { "not" } else { "would not be" };
+            println!(
+                "{}: log: {}, transaction {} {} recovered",
+                path, why, seq, not
+            );
+        }
+    }
+    if repair {
+        log::recover(&mut img, &state)?;
+    }
+
+    let mut report = check::check(&mut img, repair)?;
+    for p in &report.problems {
+        let fixed = if p.fixed { " (fixed)" } else { "" };
+        println!("{}: {}{}", path, p.msg, fixed);
+    }
+    if repair && !report.problems.is_empty() {
+        report = check::check(&mut img, false)?;
+        for p in &report.problems {
+            println!("{}: {} (remains)", path, p.msg);
+        }
+    }
+    let verdict = match report.problems.len() {
+        0 => "clean".to_string(),
+        1 => "1 problem".to_string(),
+        n => format!("{} problems", n),
+    };
+    println!(
+        "{}: {}, {}/{} inodes, {}/{} blocks in use",
+        path, verdict, report.inodes, img.sb.ninodes, report.blocks, img.sb.size
+    );
+    Ok(report.problems.is_empty())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let (repair, path) = match &args[1..] {
+        [path] => (false, path),
+        [y, path] if y == "-y" => (true, path),
+        _ => {
+            eprintln!("Usage: fsck [-y] fs.img");
+            exit(2);
+        }
+    };
+    match fsck(path, repair) {
+        Ok(clean) => exit(if clean { 0 } else { 1 }),
+        Err(e) => {
+            eprintln!("fsck: {}: {}", path, e);
+            exit(2);
+        }
+    }
+}
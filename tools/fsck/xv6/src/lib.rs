@@ -0,0 +1,202 @@
+// fsck as an xv6 user program: the checker in ../src, run on a
+// raw disk device and linked with xv6's user library.
+//
+//   fsck [-y] [disk]
+//
+// Checks disk, by default /dev/disk1, the root disk.
+// The disk device reads through the buffer cache, so a check of
+// the running root file system sees what the kernel sees; it is
+// only reliable when nothing else is using the file system.
+// With -y, fixes what it finds. A repair of the root changes
+// blocks behind the kernel's back: reboot after it.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{format, string::String, vec::Vec};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    fmt::{self, Write},
+    panic::PanicInfo,
+    slice,
+};
+
+use fsck::{
+    check,
+    image::{Disk, Error, Image},
+    log::{self, LogState},
+};
+
+extern "C" {
+    fn open(path: *const u8, omode: i32) -> i32;
+    fn close(fd: i32) -> i32;
+    fn write(fd: i32, buf: *const u8, n: i32) -> i32;
+    fn pread(fd: i32, buf: *mut u8, n: i32, off: u32) -> i32;
+    fn pwrite(fd: i32, buf: *const u8, n: i32, off: u32) -> i32;
+    fn exit() -> !;
+    fn malloc(n: u32) -> *mut u8;
+    fn free(p: *mut u8);
+}
+
+const O_RDONLY: i32 = 0x000;
+const O_RDWR: i32 = 0x002;
+
+struct Output(i32);
+
+impl Write for Output {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        unsafe { write(self.0, s.as_ptr(), s.len() as i32) };
+        Ok(())
+    }
+}
+
+macro_rules! println {
+    ($($arg:tt)*) => {{ let _ = writeln!(Output(1), $($arg)*); }};
+}
+
+macro_rules! eprintln {
+    ($($arg:tt)*) => {{ let _ = writeln!(Output(2), $($arg)*); }};
+}
+
+// malloc's blocks are 8-byte aligned, which is enough for
+// everything fsck allocates.
+struct Malloc;
+
+unsafe impl GlobalAlloc for Malloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if layout.align() > 8 {
+            return core::ptr::null_mut();
+        }
+        malloc(layout.size() as u32)
+    }
+
+    unsafe fn dealloc(&self, p: *mut u8, _layout: Layout) {
+        free(p)
+    }
+}
+
+#[global_allocator]
+static MALLOC: Malloc = Malloc;
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    eprintln!("fsck: {}", info);
+    unsafe { exit() }
+}
+
+struct RawDisk(i32);
+
+impl Disk for RawDisk {
+    fn read_at(&mut self, off: u64, buf: &mut [u8]) -> Result<(), Error> {
+        let n = buf.len() as i32;
+        if off > u32::MAX as u64 || unsafe { pread(self.0, buf.as_mut_ptr(), n, off as u32) } != n {
+            return Err(Error::Io(off));
+        }
+        Ok(())
+    }
+
+    fn write_at(&mut self, off: u64, buf: &[u8]) -> Result<(), Error> {
+        let n = buf.len() as i32;
+        if off > u32::MAX as u64 || unsafe { pwrite(self.0, buf.as_ptr(), n, off as u32) } != n {
+            return Err(Error::Io(off));
+        }
+        Ok(())
+    }
+}
+
+fn fsck(path: &str, disk: RawDisk, repair: bool) -> Result<(), Error> {
+    let mut img = Image::open(disk)?;
+    if let Err(e) = check::layout(&img.sb) {
+        eprintln!("fsck: {}: {}", path, e);
+        return Ok(());
+    }
+
+    match log::read(&mut img)? {
+        LogState::Unused | LogState::Empty => {}
+        // The kernel is committing: its log is not fsck's to
+        // recover.
+        LogState::Committed { .. } if repair => {
+            eprintln!("fsck: {}: log busy, try again", path);
+            return Ok(());
+        }
+        LogState::Committed { seq, blocks } => {
+            println!(
+                "{}: log holds transaction {} of {} blocks, checked as recovered",
+                path,
+                seq,
+                blocks.len()
+            );
+            log::install(&mut img, &blocks)?;
+        }
+        LogState::Refused { seq, why } => {
+            println!("{}: log: {}, transaction {}", path, why, seq);
+        }
+    }
+
+    let mut report = check::check(&mut img, repair)?;
+    for p in &report.problems {
+        let fixed = if p.fixed { " (fixed)" } else { "" };
+        println!("{}: {}{}", path, p.msg, fixed);
+    }
+    if repair && !report.problems.is_empty() {
+        report = check::check(&mut img, false)?;
+        for p in &report.problems {
+            println!("{}: {} (remains)", path, p.msg);
+        }
+    }
+    let verdict = match report.problems.len() {
+        0 => String::from("clean"),
+        1 => String::from("1 problem"),
+        n => format!("{} problems", n),
+    };
+    println!(
+        "{}: {}, {}/{} inodes, {}/{} blocks in use",
+        path, verdict, report.inodes, img.sb.ninodes, report.blocks, img.sb.size
+    );
+    if img.dirty() {
+        println!("{}: file system modified, reboot now", path);
+    }
+    Ok(())
+}
+
+// The NUL-terminated string at s.
+unsafe fn cstr<'a>(s: *const u8) -> &'a str {
+    let mut n = 0;
+    while *s.add(n) != 0 {
+        n += 1;
+    }
+    core::str::from_utf8(slice::from_raw_parts(s, n)).unwrap_or("?")
+}
+
+#[no_mangle]
+unsafe extern "C" fn main(argc: i32, argv: *const *const u8) -> ! {
+    let args = slice::from_raw_parts(argv, argc as usize);
+    let args: Vec<&str> = args.iter().skip(1).map(|&a| cstr(a)).collect();
+    let (repair, path) = match args[..] {
+        [] => (false, "/dev/disk1"),
+        [path] if path != "-y" => (false, path),
+        ["-y"] => (true, "/dev/disk1"),
+        ["-y", path] => (true, path),
+        _ => {
+            eprintln!("usage: fsck [-y] [disk]");
+            unsafe { exit() }
+        }
+    };
+
+    let mut cpath = String::from(path);
+    cpath.push('\0');
+    let omode = if repair { O_RDWR } else { O_RDONLY };
+    let fd = unsafe { open(cpath.as_ptr(), omode) };
+    if fd < 0 {
+        eprintln!("fsck: cannot open {}", path);
+        unsafe { exit() }
+    }
+    if let Err(e) = fsck(path, RawDisk(fd), repair) {
+        eprintln!("fsck: {}: {}", path, e);
+    }
+    unsafe {
+        close(fd);
+        exit()
+    }
+}
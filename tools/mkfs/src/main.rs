@@ -0,0 +1,516 @@
+// mkfs: make an xv6 file system image.
+//
+//   mkfs [-e] [-n] [-b bsize] [-s size] [-i ninodes] [-l logblocks]
+//        [-d dir] [-m manifest] fs.img [path ...]
+//
+//   -e           map file blocks with extents
+//   -n           names up to NAMEMAX bytes, in hashed directories
+//   -b bsize     block size, a power of two from 512 to 4096
+//   -s size      image size in blocks (default: FSSIZE sectors)
+//   -i ninodes   number of inodes (default 200)
+//   -l logblocks blocks the log can hold (at most and by default LOGSIZE)
+//   -d dir       copy the contents of dir into the root
+//   -m manifest  list every inode made in manifest ("-" for stdout)
+//
+// Each path is copied into the root under its own name, a
+// directory with everything below it. A leading "_" is dropped
+// from the names of files: the binaries are named _rm, _cat,
+// etc. to keep the build operating system from trying to
+// execute them in place of system binaries like rm and cat.
+
+use std::{
+    collections::HashMap,
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    os::unix::fs::{FileExt, MetadataExt},
+    path::Path,
+    process::exit,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use fsck::dir;
+use ondisk::*;
+
+const MAXSECTORS: u64 = 1 << 28; // sectors the kernel's 28-bit LBA disk driver addresses
+
+// As in xv6/param.h.
+const FSSIZE: u64 = 20000; // default image size, in 512-byte sectors
+const MAXOPBLOCKS: usize = 16; // max # of blocks any FS op writes
+const LOGSIZE: usize = MAXOPBLOCKS * 8; // max data blocks in on-disk log
+const MAXPATH: usize = 128; // longest symbolic link target
+
+const NINODES: u32 = 200;
+
+struct Mkfs {
+    file: File,
+    sb: SuperBlock,
+    inodes: Vec<INodeOnDisk>,        // inode i is inodes[i]
+    freeblock: u32,                  // the first free block that we can allocate
+    links: HashMap<(u64, u64), u32>, // inode of each host file with several names
+    manifest: Vec<(u32, String)>,
+    now: u32,
+}
+
+impl Mkfs {
+    fn bsize(&self) -> usize {
+        self.sb.bsize as usize
+    }
+
+    fn write_block(&self, b: u32, data: &[u8]) -> io::Result<()> {
+        self.file
+            .write_all_at(data, b as u64 * self.sb.bsize as u64)
+    }
+
+    fn balloc(&mut self) -> Result<u32, String> {
+        if self.freeblock >= self.sb.size {
+            return Err("out of blocks".into());
+        }
+        self.freeblock += 1;
+        Ok(self.freeblock - 1)
+    }
+
+    fn ialloc(&mut self, kind: u16, mtime: u32) -> Result<u32, String> {
+        let inum = self.inodes.len() as u32;
+        if inum >= self.sb.ninodes {
+            return Err("out of inodes".into());
+        }
+        self.inodes.push(INodeOnDisk {
+            kind,
+            nlink: 1,
+            atime: mtime,
+            mtime,
+            ctime: self.now,
+            ..INodeOnDisk::default()
+        });
+        Ok(inum)
+    }
+
+    // Block bn of inode inum, allocating it and any indirect
+    // blocks on the way to it. Indirect blocks are kept in ind
+    // until the whole file is written.
+    fn bmap(
+        &mut self,
+        inum: u32,
+        bn: usize,
+        ind: &mut HashMap<u32, Vec<u8>>,
+    ) -> Result<u32, String> {
+        if bn < NDIRECT {
+            let b = self.balloc()?;
+            self.inodes[inum as usize].addrs[bn] = b;
+            return Ok(b);
+        }
+
+        // Tree i is i+1 levels deep and maps n blocks.
+        let nind = self.sb.nindirect() as usize;
+        let (mut bn, mut i, mut n) = (bn - NDIRECT, 0, nind);
+        while bn >= n {
+            bn -= n;
+            i += 1;
+            n *= nind;
+        }
+        let mut x = self.inodes[inum as usize].addrs[NDIRECT + i];
+        if x == 0 {
+            x = self.balloc()?;
+            self.inodes[inum as usize].addrs[NDIRECT + i] = x;
+            ind.insert(x, vec![0; self.bsize()]);
+        }
+        let mut per = n / nind;
+        loop {
+            let slot = 4 * (bn / per);
+            let mut next = u32_at(&ind[&x], slot);
+            if next == 0 {
+                next = self.balloc()?;
+                put_u32(ind.get_mut(&x).unwrap(), slot, next);
+                if per > 1 {
+                    ind.insert(next, vec![0; self.bsize()]);
+                }
+            }
+            if per == 1 {
+                return Ok(next);
+            }
+            x = next;
+            bn %= per;
+            per /= nind;
+        }
+    }
+
+    // Give inode inum the contents data.
+    fn write_file(&mut self, inum: u32, data: &[u8]) -> Result<(), String> {
+        let bs = self.bsize();
+        let nblocks = data.len().div_ceil(bs);
+        if nblocks > maxfile(bs) || data.len() > u32::MAX as usize {
+            return Err("file too big".into());
+        }
+        let io = |e: io::Error| e.to_string();
+
+        if self.sb.features & FS_EXTENTS != 0 {
+            // A file written whole fits in one extent.
+            let start = self.freeblock;
+            if nblocks as u64 > (self.sb.size - start) as u64 {
+                return Err("out of blocks".into());
+            }
+            self.freeblock += nblocks as u32;
+            let ip = &mut self.inodes[inum as usize];
+            if nblocks > 0 {
+                ip.addrs[..3].copy_from_slice(&[0, start, nblocks as u32]);
+            }
+            ip.size = data.len() as u32;
+            return self.write_padded(start, data).map_err(io);
+        }
+
+        let mut ind = HashMap::new();
+        for (bn, chunk) in data.chunks(bs).enumerate() {
+            let b = self.bmap(inum, bn, &mut ind)?;
+            self.write_padded(b, chunk).map_err(io)?;
+        }
+        for (b, block) in ind {
+            self.write_block(b, &block).map_err(io)?;
+        }
+        self.inodes[inum as usize].size = data.len() as u32;
+        Ok(())
+    }
+
+    // Write data from block b on, padding the last block.
+    fn write_padded(&self, b: u32, data: &[u8]) -> io::Result<()> {
+        let bs = self.bsize();
+        let whole = data.len() / bs * bs;
+        self.file
+            .write_all_at(&data[..whole], b as u64 * bs as u64)?;
+        if whole < data.len() {
+            let mut last = vec![0; bs];
+            last[..data.len() - whole].copy_from_slice(&data[whole..]);
+            self.write_block(b + (whole / bs) as u32, &last)?;
+        }
+        Ok(())
+    }
+
+    // Copy the host file, directory or symbolic link at host
+    // into the image as path. Returns its inode, or None if it
+    // is something else, which is skipped.
+    fn copy(&mut self, host: &Path, path: &str, parent: u32) -> Result<Option<u32>, String> {
+        let err = |e: io::Error| format!("{}: {}", host.display(), e);
+        let md = fs::symlink_metadata(host).map_err(err)?;
+        let mtime = md.mtime().clamp(0, u32::MAX as i64) as u32;
+
+        if md.is_dir() {
+            let inum = self.ialloc(T_DIR, mtime)?;
+            self.manifest.push((inum, path.to_string()));
+            self.directory(host, path, inum, parent)?;
+            return Ok(Some(inum));
+        }
+        if md.nlink() > 1 {
+            if let Some(&inum) = self.links.get(&(md.dev(), md.ino())) {
+                self.inodes[inum as usize].nlink += 1;
+                self.manifest.push((inum, path.to_string()));
+                return Ok(Some(inum));
+            }
+        }
+        let (kind, data) = if md.file_type().is_symlink() {
+            let target = fs::read_link(host).map_err(err)?;
+            let target = target.to_string_lossy().into_owned().into_bytes();
+            if target.is_empty() || target.len() >= MAXPATH {
+                return Err(format!("{}: link target too long", host.display()));
+            }
+            (T_SYMLINK, target)
+        } else if md.is_file() {
+            (T_FILE, fs::read(host).map_err(err)?)
+        } else {
+            eprintln!("mkfs: skipping {}: not a file or directory", host.display());
+            return Ok(None);
+        };
+        let inum = self.ialloc(kind, mtime)?;
+        self.write_file(inum, &data)
+            .map_err(|e| format!("{}: {}", host.display(), e))?;
+        if md.nlink() > 1 {
+            self.links.insert((md.dev(), md.ino()), inum);
+        }
+        self.manifest.push((inum, path.to_string()));
+        Ok(Some(inum))
+    }
+
+    // Fill directory inum, named path, with ".", ".." and a copy
+    // of each entry of the host directory host.
+    fn directory(&mut self, host: &Path, path: &str, inum: u32, parent: u32) -> Result<(), String> {
+        let mut entries = vec![(inum, b".".to_vec()), (parent, b"..".to_vec())];
+        entries.extend(self.children(host, path, inum)?);
+        self.link(inum, entries)
+    }
+
+    // Copy each entry of the host directory host, in name order,
+    // for directory inum, named path. Returns their entries.
+    fn children(
+        &mut self,
+        host: &Path,
+        path: &str,
+        inum: u32,
+    ) -> Result<Vec<(u32, Vec<u8>)>, String> {
+        let err = |e: io::Error| format!("{}: {}", host.display(), e);
+        let mut names: Vec<_> = fs::read_dir(host)
+            .map_err(err)?
+            .map(|e| e.map(|e| e.file_name()))
+            .collect::<Result<_, _>>()
+            .map_err(err)?;
+        names.sort();
+        let mut entries = Vec::new();
+        for name in names {
+            let name = name.to_string_lossy().into_owned();
+            let child = match path {
+                "/" => format!("/{}", name),
+                _ => format!("{}/{}", path, name),
+            };
+            if let Some(c) = self.copy(&host.join(&name), &child, inum)? {
+                entries.push((c, name.into_bytes()));
+            }
+        }
+        Ok(entries)
+    }
+
+    // Write the entries of directory inum, each an inode and a
+    // name, and count the ".." of each subdirectory as a link
+    // to inum.
+    fn link(&mut self, inum: u32, entries: Vec<(u32, Vec<u8>)>) -> Result<(), String> {
+        let max = match self.sb.features & FS_LONGNAMES {
+            0 => DIRSIZ,
+            _ => NAMEMAX,
+        };
+        for (i, (e, name)) in entries.iter().enumerate() {
+            if name.len() > max {
+                let name = String::from_utf8_lossy(name);
+                return Err(format!("{}: name longer than {} bytes", name, max));
+            }
+            if entries[..i].iter().any(|(_, n)| n == name) {
+                return Err(format!("{}: named twice", String::from_utf8_lossy(name)));
+            }
+            if i >= 2 && self.inodes[*e as usize].kind == T_DIR {
+                self.inodes[inum as usize].nlink += 1;
+            }
+        }
+        let data = dir::build(self.sb.features, self.bsize(), &entries);
+        self.write_file(inum, &data)
+    }
+
+    // Write the inodes and the free map, marking every block
+    // allocated so far, and the super block.
+    fn finish(&mut self) -> io::Result<()> {
+        let sb = self.sb;
+        let bs = self.bsize();
+        for (i, ip) in self.inodes.iter().enumerate() {
+            let i = i as u32;
+            let mut b = vec![0; bs];
+            self.file
+                .read_exact_at(&mut b, sb.iblock(i) as u64 * bs as u64)?;
+            ip.encode(&mut b[(i % sb.ipb()) as usize * INODESIZE..]);
+            self.write_block(sb.iblock(i), &b)?;
+        }
+
+        let mut map = vec![0u8; sb.size.div_ceil(sb.bpb()) as usize * bs];
+        for b in 0..self.freeblock as usize {
+            map[b / 8] |= 1 << (b % 8);
+        }
+        for (i, block) in map.chunks(bs).enumerate() {
+            self.write_block(sb.bmapstart + i as u32, block)?;
+        }
+
+        let sbblock = (SBOFF / bs) as u32;
+        let mut b = vec![0; bs];
+        self.file
+            .read_exact_at(&mut b, sbblock as u64 * bs as u64)?;
+        sb.encode(&mut b[SBOFF % bs..]);
+        self.write_block(sbblock, &b)
+    }
+
+    fn write_manifest(&self, out: &mut dyn Write) -> io::Result<()> {
+        let sb = &self.sb;
+        writeln!(
+            out,
+            "# {} blocks of {} bytes, {} inodes, {} log blocks, features {:#x}",
+            sb.size, sb.bsize, sb.ninodes, sb.nlog, sb.features
+        )?;
+        writeln!(out, "# inum type nlink size path")?;
+        for (inum, path) in &self.manifest {
+            let ip = &self.inodes[*inum as usize];
+            let kind = match ip.kind {
+                T_DIR => "dir",
+                T_SYMLINK => "symlink",
+                _ => "file",
+            };
+            writeln!(out, "{} {} {} {} {}", inum, kind, ip.nlink, ip.size, path)?;
+        }
+        Ok(())
+    }
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "Usage: mkfs [-e] [-n] [-b bsize] [-s size] [-i ninodes] [-l logblocks] \
+         [-d dir] [-m manifest] fs.img [path ...]"
+    );
+    exit(1);
+}
+
+fn fail(msg: &str) -> ! {
+    eprintln!("mkfs: {}", msg);
+    exit(1);
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut features = 0;
+    let mut bsize = MINBSIZE;
+    let mut size = None;
+    let mut ninodes = NINODES;
+    let mut nlogdata = LOGSIZE;
+    let mut tree = None;
+    let mut manifest = None;
+
+    let mut i = 0;
+    while i < args.len() && args[i].starts_with('-') && args[i] != "-" {
+        let arg = |i: usize| args.get(i + 1).unwrap_or_else(|| usage());
+        let num = |i: usize| arg(i).parse::<u64>().unwrap_or_else(|_| usage());
+        match args[i].as_str() {
+            "-e" => features |= FS_EXTENTS,
+            "-n" => features |= FS_LONGNAMES,
+            "-b" => bsize = num(i) as usize,
+            "-s" => size = Some(num(i)),
+            "-i" => ninodes = num(i).min(u32::MAX as u64) as u32,
+            "-l" => nlogdata = num(i) as usize,
+            "-d" => tree = Some(arg(i).clone()),
+            "-m" => manifest = Some(arg(i).clone()),
+            _ => usage(),
+        }
+        i += if args[i] == "-e" || args[i] == "-n" {
+            1
+        } else {
+            2
+        };
+    }
+    let (img, paths) = match args.get(i) {
+        Some(img) => (img, &args[i + 1..]),
+        None => usage(),
+    };
+
+    if !(MINBSIZE..=MAXBSIZE).contains(&bsize) || !bsize.is_power_of_two() {
+        fail(&format!(
+            "block size must be a power of two from {} to {}",
+            MINBSIZE, MAXBSIZE
+        ));
+    }
+    // The kernel pins every logged block in its buffer cache,
+    // which holds no more than LOGSIZE of them.
+    if !(MAXOPBLOCKS..=LOGSIZE).contains(&nlogdata) {
+        fail(&format!(
+            "the log must hold from {} to {} blocks",
+            MAXOPBLOCKS, LOGSIZE
+        ));
+    }
+    // Without -n, directory entries hold 16-bit inode numbers.
+    let maxinodes = match features & FS_LONGNAMES {
+        0 => u16::MAX as u32 + 1,
+        _ => u32::MAX,
+    };
+    if ninodes <= ROOTINO || ninodes > maxinodes {
+        fail(&format!("the inode count must be from 2 to {}", maxinodes));
+    }
+    let fssize = size.unwrap_or(FSSIZE / (bsize / 512) as u64);
+    if fssize > u32::MAX as u64 {
+        fail("image too big");
+    }
+    if fssize * bsize as u64 > MAXSECTORS * 512 {
+        eprintln!(
+            "mkfs: warning: the kernel reads only the first {} bytes of a disk",
+            MAXSECTORS * 512
+        );
+    }
+
+    // 1 fs block = bsize/512 disk sectors. The log starts in the
+    // block after the one holding the super block.
+    let mut sb = SuperBlock {
+        bsize: bsize as u32,
+        size: fssize as u32,
+        ninodes,
+        features,
+        ..SuperBlock::default()
+    };
+    let nbitmap = sb.size.div_ceil(sb.bpb());
+    let ninodeblocks = ninodes.div_ceil(sb.ipb());
+    let sbblock = (SBOFF / bsize) as u32;
+    let mut nlog = nlogdata + loghdrblocks(nlogdata, bsize);
+    while nlog - loghdrblocks(nlog, bsize) < nlogdata {
+        nlog += 1;
+    }
+    let nmeta = sbblock as u64 + 1 + nlog as u64 + ninodeblocks as u64 + nbitmap as u64;
+    if nmeta >= fssize {
+        fail(&format!("{} blocks leave no room for data", fssize));
+    }
+    sb.nlog = nlog as u32;
+    sb.nblocks = (fssize - nmeta) as u32;
+    sb.logstart = sbblock + 1;
+    sb.inodestart = sb.logstart + sb.nlog;
+    sb.bmapstart = sb.inodestart + ninodeblocks;
+
+    println!(
+        "nmeta {} (boot, super, log blocks {} inode blocks {}, bitmap blocks {}) blocks {} total {} bsize {}",
+        nmeta, nlog, ninodeblocks, nbitmap, sb.nblocks, sb.size, bsize
+    );
+
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(img)
+        .unwrap_or_else(|e| fail(&format!("{}: {}", img, e)));
+    if let Err(e) = file.set_len(fssize * bsize as u64) {
+        fail(&format!("{}: {}", img, e));
+    }
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as u32);
+    let mut fs = Mkfs {
+        file,
+        sb,
+        inodes: vec![INodeOnDisk::default()], // inode 0 is never used
+        freeblock: nmeta as u32,
+        links: HashMap::new(),
+        manifest: Vec::new(),
+        now,
+    };
+
+    let made = (|| -> Result<(), String> {
+        let root = fs.ialloc(T_DIR, now)?;
+        fs.manifest.push((root, "/".to_string()));
+        let mut entries = vec![(root, b".".to_vec()), (root, b"..".to_vec())];
+        if let Some(dir) = &tree {
+            entries.extend(fs.children(Path::new(dir), "/", root)?);
+        }
+        for p in paths {
+            let host = Path::new(p);
+            let base = host
+                .file_name()
+                .ok_or(format!("{}: no name to copy it as", p))?;
+            let mut name = base.to_string_lossy().into_owned();
+            if !host.is_dir() && name.starts_with('_') {
+                name.remove(0);
+            }
+            if let Some(inum) = fs.copy(host, &format!("/{}", name), root)? {
+                entries.push((inum, name.into_bytes()));
+            }
+        }
+        fs.link(root, entries)?;
+        fs.finish().map_err(|e| format!("{}: {}", img, e))
+    })();
+    if let Err(e) = made {
+        fail(&e);
+    }
+
+    if let Some(m) = manifest {
+        let r = match m.as_str() {
+            "-" => fs.write_manifest(&mut io::stdout()),
+            _ => File::create(&m).and_then(|mut f| fs.write_manifest(&mut f)),
+        };
+        if let Err(e) = r {
+            fail(&format!("{}: {}", m, e));
+        }
+    }
+}
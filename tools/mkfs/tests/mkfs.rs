@@ -0,0 +1,273 @@
+// Make images with the mkfs binary and read them back through
+// the fsck crate, as the kernel would find them.
+
+use std::{
+    fs::{self, File},
+    os::unix::fs::symlink,
+    path::Path,
+    process::Command,
+};
+
+use fsck::{
+    check, dir,
+    image::*,
+    log::{self, LogState},
+};
+use testutil::{contents, Scratch};
+
+type Img = Image<ImageFile>;
+
+// Run mkfs in s, on fs.img. Returns the image, or what mkfs
+// printed on failure.
+fn mkfs(s: &Scratch, args: &[&str]) -> Result<Img, String> {
+    let out = Command::new(env!("CARGO_BIN_EXE_mkfs"))
+        .current_dir(s.dir())
+        .args(args)
+        .output()
+        .unwrap();
+    if !out.status.success() {
+        return Err(String::from_utf8_lossy(&out.stderr).into_owned());
+    }
+    let file = File::open(s.path("fs.img")).unwrap();
+    Ok(Image::open(ImageFile(file)).unwrap())
+}
+
+// What mkfs prints when it refuses to make an image.
+fn fails(s: &Scratch, args: &[&str]) -> String {
+    match mkfs(s, args) {
+        Ok(_) => panic!("mkfs {:?} made an image", args),
+        Err(e) => e,
+    }
+}
+
+// The disk blocks below indirect block addr, depth levels up.
+fn tree(img: &mut Img, addr: u32, depth: u32, blocks: &mut Vec<u32>) {
+    if depth == 0 {
+        blocks.push(addr);
+        return;
+    }
+    let b = img.block(addr).unwrap();
+    for a in b.chunks_exact(4).map(|c| u32_at(c, 0)) {
+        if a != 0 {
+            tree(img, a, depth - 1, blocks);
+        }
+    }
+}
+
+// The contents of inode inum, which has no holes.
+fn read(img: &mut Img, inum: u32) -> Vec<u8> {
+    let ip = img.inode(inum).unwrap();
+    let mut blocks = Vec::new();
+    if img.sb.features & FS_EXTENTS != 0 {
+        let mut ext: Vec<Extent> = (0..NEXTENT)
+            .map(|i| Extent {
+                off: ip.addrs[3 * i],
+                start: ip.addrs[3 * i + 1],
+                len: ip.addrs[3 * i + 2],
+            })
+            .collect();
+        if ip.addrs[EXTBLK] != 0 {
+            let b = img.block(ip.addrs[EXTBLK]).unwrap();
+            ext.extend((0..NXEXTENT).map(|i| Extent::parse(&b[i * EXTENTSIZE..])));
+        }
+        for e in ext.iter().take_while(|e| e.len != 0) {
+            assert_eq!(e.off as usize, blocks.len());
+            blocks.extend(e.start..e.start + e.len);
+        }
+    } else {
+        blocks.extend(ip.addrs[..NDIRECT].iter().filter(|&&a| a != 0));
+        for (i, &a) in ip.addrs[NDIRECT..].iter().enumerate() {
+            if a != 0 {
+                tree(img, a, i as u32 + 1, &mut blocks);
+            }
+        }
+    }
+    let mut data = Vec::new();
+    for b in blocks {
+        data.extend(img.block(b).unwrap());
+    }
+    assert!(data.len() >= ip.size as usize);
+    data.truncate(ip.size as usize);
+    data
+}
+
+// The entries of directory inum, as (name, inum).
+fn entries(img: &mut Img, inum: u32) -> Vec<(String, u32)> {
+    let data = read(img, inum);
+    let (features, bs) = (img.sb.features, img.bsize());
+    dir::entries(features, bs, &data)
+        .into_iter()
+        .map(|(_, i, name)| (String::from_utf8(name).unwrap(), i))
+        .collect()
+}
+
+// The inode at path, from the root.
+fn namei(img: &mut Img, path: &str) -> u32 {
+    let mut inum = ROOTINO;
+    for name in path.split('/').filter(|n| !n.is_empty()) {
+        let ents = entries(img, inum);
+        inum = match ents.iter().find(|(n, _)| n == name) {
+            Some(&(_, i)) => i,
+            None => panic!("{}: no {}", path, name),
+        };
+    }
+    inum
+}
+
+// Check img as fsck would, and return how many inodes it uses.
+fn fsck(img: &mut Img) -> u32 {
+    check::layout(&img.sb).unwrap();
+    assert!(matches!(log::read(img).unwrap(), LogState::Unused));
+    let report = check::check(img, false).unwrap();
+    let problems: Vec<String> = report.problems.into_iter().map(|p| p.msg).collect();
+    assert_eq!(problems, Vec::<String>::new());
+    report.inodes
+}
+
+#[test]
+fn super_block() {
+    let s = Scratch::new("mkfs-super_block");
+    let img = mkfs(&s, &["fs.img"]).unwrap();
+    let sb = img.sb;
+    assert_eq!((sb.size, sb.nblocks, sb.ninodes), (20000, 19812, 200));
+    assert_eq!((sb.nlog, sb.logstart, sb.inodestart), (131, 2, 133));
+    assert_eq!((sb.bmapstart, sb.features, sb.bsize), (183, 0, 512));
+    assert_eq!(fs::metadata(s.path("fs.img")).unwrap().len(), 20000 * 512);
+
+    // With 4 KiB blocks the super block still starts at byte
+    // 512, in block 0, and the log follows it.
+    let img = mkfs(
+        &s,
+        &[
+            "-e", "-n", "-b", "4096", "-s", "3000", "-i", "100", "-l", "32", "fs.img",
+        ],
+    )
+    .unwrap();
+    let sb = img.sb;
+    assert_eq!((sb.size, sb.nblocks, sb.ninodes), (3000, 2961, 100));
+    assert_eq!((sb.nlog, sb.logstart, sb.inodestart), (33, 1, 34));
+    assert_eq!(sb.bmapstart, 38);
+    assert_eq!((sb.features, sb.bsize), (FS_EXTENTS | FS_LONGNAMES, 4096));
+    let raw = fs::read(s.path("fs.img")).unwrap();
+    assert_eq!(SuperBlock::parse(&raw[SBOFF..]).bsize, 4096);
+
+    let e = fails(&s, &["-b", "1000", "fs.img"]);
+    assert!(e.contains("block size must be a power of two from 512 to 4096"));
+    let e = fails(&s, &["-l", "8", "fs.img"]);
+    assert!(e.contains("the log must hold from 16 to 128 blocks"));
+    let e = fails(&s, &["-l", "129", "fs.img"]);
+    assert!(e.contains("the log must hold from 16 to 128 blocks"));
+    let e = fails(&s, &["-i", "70000", "fs.img"]);
+    assert!(e.contains("the inode count must be from 2 to 65536"));
+}
+
+#[test]
+fn names() {
+    let s = Scratch::new("mkfs-names");
+    fs::write(s.path("_cat"), b"cat").unwrap();
+    fs::create_dir(s.path("_d")).unwrap();
+    fs::write(s.path("_d/_x"), b"x").unwrap();
+    let mut img = mkfs(&s, &["fs.img", "_cat", "_d"]).unwrap();
+
+    // Only files named on the command line lose their "_".
+    let names: Vec<String> = entries(&mut img, ROOTINO)
+        .into_iter()
+        .map(|e| e.0)
+        .collect();
+    assert_eq!(names, [".", "..", "cat", "_d"]);
+    let x = namei(&mut img, "_d/_x");
+    assert_eq!(read(&mut img, x), b"x");
+    fsck(&mut img);
+
+    let long = "n".repeat(NAMEMAX);
+    fs::write(s.path(&long), b"long").unwrap();
+    let e = fails(&s, &["fs.img", &long]);
+    assert!(e.contains("name longer than 14 bytes"));
+    let mut img = mkfs(&s, &["-n", "fs.img", &long]).unwrap();
+    let inum = namei(&mut img, &long);
+    assert_eq!(read(&mut img, inum), b"long");
+    fsck(&mut img);
+}
+
+#[test]
+fn extents() {
+    let s = Scratch::new("mkfs-extents");
+    fs::write(s.path("a"), vec![1; 3 * 512]).unwrap();
+    fs::write(s.path("b"), vec![2; 10]).unwrap();
+    let mut img = mkfs(&s, &["-e", "fs.img", "a", "b"]).unwrap();
+    let (a, b) = (namei(&mut img, "a"), namei(&mut img, "b"));
+    let (a, b) = (img.inode(a).unwrap(), img.inode(b).unwrap());
+    assert_eq!(a.addrs[..3], [0, img.sb.datastart(), 3]);
+    assert_eq!(b.addrs[..3], [0, img.sb.datastart() + 3, 1]);
+    assert!(a.addrs[3..].iter().all(|&x| x == 0));
+    assert_eq!((a.size, b.size), (3 * 512, 10));
+    fsck(&mut img);
+}
+
+#[test]
+fn hashed_directory() {
+    let s = Scratch::new("mkfs-hashed_directory");
+    fs::create_dir(s.path("d")).unwrap();
+    for i in 0..100 {
+        fs::write(s.path(&format!("d/file{}", i)), format!("{}", i)).unwrap();
+    }
+    let mut img = mkfs(&s, &["-n", "fs.img", "d"]).unwrap();
+    let d = namei(&mut img, "d");
+    let data = read(&mut img, d);
+    let (depth, slots) = dir::index(&data[..512]).unwrap();
+    assert!(depth > 0 && slots.len() == 1 << depth);
+    assert_eq!(entries(&mut img, d).len(), 102);
+    for i in 0..100 {
+        let f = namei(&mut img, &format!("d/file{}", i));
+        assert_eq!(read(&mut img, f), format!("{}", i).as_bytes());
+    }
+    fsck(&mut img);
+}
+
+fn make_tree(root: &Path) {
+    fs::create_dir(root).unwrap();
+    fs::create_dir(root.join("sub")).unwrap();
+    for (i, &n) in [0, 1, 511, 512, 513, 5000, 80000].iter().enumerate() {
+        fs::write(root.join(format!("f{}", n)), contents(i, n)).unwrap();
+    }
+    fs::hard_link(root.join("f5000"), root.join("sub/h")).unwrap();
+    symlink("f1", root.join("ln")).unwrap();
+}
+
+#[test]
+fn round_trip() {
+    let s = Scratch::new("mkfs-round_trip");
+    make_tree(&s.path("t"));
+    for features in 0..=FS_EXTENTS | FS_LONGNAMES {
+        for bsize in ["512", "4096"] {
+            let mut args = vec!["-b", bsize, "-d", "t", "fs.img"];
+            if features & FS_EXTENTS != 0 {
+                args.insert(0, "-e");
+            }
+            if features & FS_LONGNAMES != 0 {
+                args.insert(0, "-n");
+            }
+            let mut img = mkfs(&s, &args).unwrap();
+            assert_eq!(img.sb.features, features);
+
+            for n in [0, 1, 511, 512, 513, 5000, 80000] {
+                let name = format!("f{}", n);
+                let inum = namei(&mut img, &name);
+                let want = fs::read(s.path("t").join(&name)).unwrap();
+                assert_eq!(read(&mut img, inum), want, "{} {:?}", name, args);
+            }
+            let (f, h) = (namei(&mut img, "f5000"), namei(&mut img, "sub/h"));
+            assert_eq!(f, h);
+            assert_eq!(img.inode(f).unwrap().nlink, 2);
+            let ln = namei(&mut img, "ln");
+            assert_eq!(img.inode(ln).unwrap().kind, T_SYMLINK);
+            assert_eq!(read(&mut img, ln), b"f1");
+            let sub = namei(&mut img, "sub");
+            assert_eq!(img.inode(sub).unwrap().nlink, 1);
+            assert_eq!(img.inode(ROOTINO).unwrap().nlink, 2);
+
+            // The root, sub, seven files (one also sub/h) and ln.
+            assert_eq!(fsck(&mut img), 10);
+        }
+    }
+}
@@ -0,0 +1,466 @@
+// The on-disk format of the xv6 file system, as xv6/fs.h lays
+// it out, shared by the kernel (kernel/src/fs.rs) and the host
+// tools. Everything on disk is little-endian; the structs are
+// repr(C) so the kernel can also use them in place.
+//
+// Disk layout:
+// [ boot block | super block | log | inode blocks |
+//                                          free bit map | data blocks]
+//
+// The super block sits at byte SBOFF whatever the block size, so
+// with blocks bigger than 512 bytes it shares block 0 with the
+// boot block.
+
+#![no_std]
+
+pub const ROOTINO: u32 = 1; // root i-number
+pub const MINBSIZE: usize = 512; // smallest block size
+pub const MAXBSIZE: usize = 4096; // largest block size
+pub const SBOFF: usize = 512; // byte offset of the super block on disk
+
+pub const FS_EXTENTS: u32 = 0x1; // inodes map their blocks with extents
+pub const FS_LONGNAMES: u32 = 0x2; // directories hold DirRecords, hashed when big
+
+pub const T_DIR: u16 = 1; // Directory
+pub const T_FILE: u16 = 2; // File
+pub const T_DEV: u16 = 3; // Device
+pub const T_SYMLINK: u16 = 4; // Symbolic link
+
+pub fn u16_at(b: &[u8], off: usize) -> u16 {
+    u16::from_le_bytes([b[off], b[off + 1]])
+}
+
+pub fn u32_at(b: &[u8], off: usize) -> u32 {
+    u32::from_le_bytes([b[off], b[off + 1], b[off + 2], b[off + 3]])
+}
+
+pub fn put_u16(b: &mut [u8], off: usize, v: u16) {
+    b[off..off + 2].copy_from_slice(&v.to_le_bytes());
+}
+
+pub fn put_u32(b: &mut [u8], off: usize, v: u32) {
+    b[off..off + 4].copy_from_slice(&v.to_le_bytes());
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SuperBlock {
+    pub size: u32,       // Size of file system image (blocks)
+    pub nblocks: u32,    // Number of data blocks
+    pub ninodes: u32,    // Number of inodes.
+    pub nlog: u32,       // Number of log blocks
+    pub logstart: u32,   // Block number of first log block
+    pub inodestart: u32, // Block number of first inode block
+    pub bmapstart: u32,  // Block number of first free map block
+    pub features: u32,   // FS_* feature bits
+    pub bsize: u32,      // Block size (bytes)
+}
+
+pub const SUPERBLOCKSIZE: usize = 36;
+
+impl SuperBlock {
+    pub fn parse(b: &[u8]) -> Self {
+        Self {
+            size: u32_at(b, 0),
+            nblocks: u32_at(b, 4),
+            ninodes: u32_at(b, 8),
+            nlog: u32_at(b, 12),
+            logstart: u32_at(b, 16),
+            inodestart: u32_at(b, 20),
+            bmapstart: u32_at(b, 24),
+            features: u32_at(b, 28),
+            bsize: u32_at(b, 32),
+        }
+    }
+
+    pub fn encode(&self, b: &mut [u8]) {
+        put_u32(b, 0, self.size);
+        put_u32(b, 4, self.nblocks);
+        put_u32(b, 8, self.ninodes);
+        put_u32(b, 12, self.nlog);
+        put_u32(b, 16, self.logstart);
+        put_u32(b, 20, self.inodestart);
+        put_u32(b, 24, self.bmapstart);
+        put_u32(b, 28, self.features);
+        put_u32(b, 32, self.bsize);
+    }
+
+    // Inodes per block.
+    pub const fn ipb(&self) -> u32 {
+        self.bsize / INODESIZE as u32
+    }
+
+    // Block containing inode i
+    pub const fn iblock(&self, i: u32) -> u32 {
+        i / self.ipb() + self.inodestart
+    }
+
+    // Bitmap bits per block
+    pub const fn bpb(&self) -> u32 {
+        self.bsize * 8
+    }
+
+    // Block of free map containing bit for block b
+    pub const fn bblock(&self, b: u32) -> u32 {
+        b / self.bpb() + self.bmapstart
+    }
+
+    // First data block: everything before it is metadata.
+    pub const fn datastart(&self) -> u32 {
+        self.size - self.nblocks
+    }
+
+    pub const fn nindirect(&self) -> u32 {
+        nindirect(self.bsize as usize) as u32
+    }
+}
+
+// The log starts with a header describing the last transaction
+// committed to it, which may span several blocks, followed by
+// the logged copies of the blocks.
+pub const LOGMAGIC: u32 = 0x676f6c78; // "xlog" on disk
+pub const LOGHEAD: usize = 16; // struct loghead: magic, seq, n, sum
+pub const LOGBLOCK: usize = 8; // struct logblock: blockno, sum
+
+// Blocks holding the header of a log of n blocks. (The kernel's
+// compiler predates div_ceil.)
+#[allow(clippy::manual_div_ceil)]
+pub const fn loghdrblocks(n: usize, bs: usize) -> usize {
+    (LOGHEAD + n * LOGBLOCK + bs - 1) / bs
+}
+
+// addrs holds NDIRECT direct block addresses, then the roots
+// of the singly-, doubly- and triply-indirect block trees. How
+// many blocks the trees map depends on the block size bs.
+pub const NDIRECT: usize = 10;
+pub const NADDRS: usize = NDIRECT + 3;
+
+pub const fn nindirect(bs: usize) -> usize {
+    bs / core::mem::size_of::<u32>()
+}
+
+pub const fn maxfile(bs: usize) -> usize {
+    let n = nindirect(bs);
+    NDIRECT + n + n * n + n * n * n
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct INodeOnDisk {
+    pub kind: u16,  // File type
+    pub major: u16, // Major device number (T_DEV only)
+    pub minor: u16, // Minor device number (T_DEV only)
+    pub nlink: u16, // Number of links to inode in file system
+    pub size: u32,  // Size of file (bytes)
+    pub atime: u32, // Last access (seconds since 1970)
+    pub mtime: u32, // Last modification of the contents
+    pub ctime: u32, // Last change to the inode
+    pub addrs: [u32; NADDRS],
+    pub spare: [u32; 13], // Unused; pads the inode to 128 bytes
+}
+
+pub const INODESIZE: usize = core::mem::size_of::<INodeOnDisk>();
+
+impl INodeOnDisk {
+    pub fn parse(b: &[u8]) -> Self {
+        let mut ip = Self {
+            kind: u16_at(b, 0),
+            major: u16_at(b, 2),
+            minor: u16_at(b, 4),
+            nlink: u16_at(b, 6),
+            size: u32_at(b, 8),
+            atime: u32_at(b, 12),
+            mtime: u32_at(b, 16),
+            ctime: u32_at(b, 20),
+            ..Self::default()
+        };
+        for (i, a) in ip.addrs.iter_mut().enumerate() {
+            *a = u32_at(b, 24 + 4 * i);
+        }
+        for (i, s) in ip.spare.iter_mut().enumerate() {
+            *s = u32_at(b, 24 + 4 * (NADDRS + i));
+        }
+        ip
+    }
+
+    pub fn encode(&self, b: &mut [u8]) {
+        put_u16(b, 0, self.kind);
+        put_u16(b, 2, self.major);
+        put_u16(b, 4, self.minor);
+        put_u16(b, 6, self.nlink);
+        put_u32(b, 8, self.size);
+        put_u32(b, 12, self.atime);
+        put_u32(b, 16, self.mtime);
+        put_u32(b, 20, self.ctime);
+        for (i, &a) in self.addrs.iter().chain(self.spare.iter()).enumerate() {
+            put_u32(b, 24 + 4 * i, a);
+        }
+    }
+}
+
+// With FS_EXTENTS, addrs instead holds NEXTENT extents, then
+// the address of a block whose first MINBSIZE bytes hold NXEXTENT
+// more. Extents are sorted by file block; unused ones have len 0.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Extent {
+    pub off: u32,   // First file block mapped
+    pub start: u32, // First disk block
+    pub len: u32,   // Number of blocks
+}
+
+pub const EXTENTSIZE: usize = core::mem::size_of::<Extent>();
+pub const NEXTENT: usize = 4;
+pub const EXTBLK: usize = NADDRS - 1; // addrs index of the overflow extent block
+pub const NXEXTENT: usize = MINBSIZE / EXTENTSIZE;
+pub const MAXEXTENT: usize = NEXTENT + NXEXTENT;
+
+impl Extent {
+    pub fn parse(b: &[u8]) -> Self {
+        Self {
+            off: u32_at(b, 0),
+            start: u32_at(b, 4),
+            len: u32_at(b, 8),
+        }
+    }
+
+    pub fn encode(&self, b: &mut [u8]) {
+        put_u32(b, 0, self.off);
+        put_u32(b, 4, self.start);
+        put_u32(b, 8, self.len);
+    }
+}
+
+// Directory is a file containing a sequence of DirectoryEntry.
+pub const DIRSIZ: usize = 14;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DirectoryEntry {
+    pub inum: u16,
+    pub name: [u8; DIRSIZ],
+}
+
+pub const DIRENTSIZE: usize = core::mem::size_of::<DirectoryEntry>();
+
+impl DirectoryEntry {
+    // An entry naming inum; name must be at most DIRSIZ bytes.
+    pub fn new(inum: u16, name: &[u8]) -> Self {
+        let mut de = Self {
+            inum,
+            name: [0; DIRSIZ],
+        };
+        de.name[..name.len()].copy_from_slice(name);
+        de
+    }
+
+    pub fn parse(b: &[u8]) -> Self {
+        let mut name = [0; DIRSIZ];
+        name.copy_from_slice(&b[2..DIRENTSIZE]);
+        Self {
+            inum: u16_at(b, 0),
+            name,
+        }
+    }
+
+    pub fn encode(&self, b: &mut [u8]) {
+        put_u16(b, 0, self.inum);
+        b[2..DIRENTSIZE].copy_from_slice(&self.name);
+    }
+
+    // The name up to its first NUL.
+    pub fn name(&self) -> &[u8] {
+        let n = self.name.iter().position(|&c| c == 0).unwrap_or(DIRSIZ);
+        &self.name[..n]
+    }
+}
+
+// With FS_LONGNAMES, a directory is instead a sequence of blocks,
+// each holding DirRecords that cover its first bsize-DIRTAIL
+// bytes. A record with inum 0 is free space; a live record's
+// slack beyond dirreclen(namelen) can take a new entry. The last
+// DIRTAIL bytes of a block hold the file block of the next block
+// in its hash bucket, or 0.
+pub const NAMEMAX: usize = 255;
+pub const DIRTAIL: usize = 4;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DirRecord {
+    pub inum: u32,
+    pub reclen: u16, // Bytes to the next record
+    pub namelen: u8,
+    pub pad: u8,
+    // followed by namelen bytes of name, not NUL-terminated
+}
+
+pub const DIRRECSIZE: usize = core::mem::size_of::<DirRecord>();
+
+// Bytes a record holding a name of n bytes needs.
+pub fn dirreclen(n: usize) -> usize {
+    (DIRRECSIZE + n + 3) & !3
+}
+
+impl DirRecord {
+    pub fn parse(b: &[u8]) -> Self {
+        Self {
+            inum: u32_at(b, 0),
+            reclen: u16_at(b, 4),
+            namelen: b[6],
+            pad: b[7],
+        }
+    }
+
+    pub fn encode(&self, b: &mut [u8]) {
+        put_u32(b, 0, self.inum);
+        put_u16(b, 4, self.reclen);
+        b[6] = self.namelen;
+        b[7] = self.pad;
+    }
+}
+
+// A directory that outgrows one block gets an index in file
+// block 0: the magic, the depth, then 1<<depth u32 slots, each
+// the file block of the bucket holding the names whose dirhash
+// ends in the slot's bits. The index fits in its block; past
+// that, buckets grow chains.
+pub const DIRINDEXMAGIC: u32 = 0x78646978; // "xidx" on disk
+pub const DIRINDEX: usize = 8;
+
+// Deepest index that fits a block of bs bytes.
+pub fn dirmaxdepth(bs: usize) -> u32 {
+    let mut d = 0;
+    while DIRINDEX + (4 << (d + 1)) <= bs {
+        d += 1;
+    }
+    d
+}
+
+// FNV-1a hash of a name.
+pub fn dirhash(name: &[u8]) -> u32 {
+    let mut h: u32 = 2166136261;
+    for &c in name {
+        h = (h ^ c as u32).wrapping_mul(16777619);
+    }
+    h
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn super_block() {
+        assert_eq!(SUPERBLOCKSIZE, core::mem::size_of::<SuperBlock>());
+        let sb = SuperBlock {
+            size: 20000,
+            nblocks: 19812,
+            ninodes: 200,
+            nlog: 131,
+            logstart: 2,
+            inodestart: 133,
+            bmapstart: 183,
+            features: FS_EXTENTS | FS_LONGNAMES,
+            bsize: 512,
+        };
+        let mut b = [0xff; SUPERBLOCKSIZE + 4];
+        sb.encode(&mut b);
+        let fields = [20000, 19812, 200, 131, 2, 133, 183, 3, 512];
+        for (i, &v) in fields.iter().enumerate() {
+            assert_eq!(u32_at(&b, 4 * i), v);
+        }
+        assert_eq!(u32_at(&b, SUPERBLOCKSIZE), 0xffffffff);
+        let mut again = [0xff; SUPERBLOCKSIZE + 4];
+        SuperBlock::parse(&b).encode(&mut again);
+        assert_eq!(again, b);
+
+        assert_eq!(sb.ipb(), 4);
+        assert_eq!((sb.iblock(0), sb.iblock(7), sb.iblock(8)), (133, 134, 135));
+        assert_eq!(sb.bpb(), 4096);
+        assert_eq!((sb.bblock(4095), sb.bblock(4096)), (183, 184));
+        assert_eq!(sb.datastart(), 188);
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(loghdrblocks(128, 512), 3);
+        assert_eq!(loghdrblocks(62, 512), 1);
+        assert_eq!(loghdrblocks(63, 512), 2);
+        assert_eq!(nindirect(4096), 1024);
+        assert_eq!(maxfile(512), NDIRECT + 128 + 128 * 128 + 128 * 128 * 128);
+        assert_eq!((dirmaxdepth(512), dirmaxdepth(4096)), (6, 9));
+    }
+
+    #[test]
+    fn inode() {
+        assert_eq!(INODESIZE, 128);
+        let mut ip = INodeOnDisk {
+            kind: T_SYMLINK,
+            major: 1,
+            minor: 2,
+            nlink: 3,
+            size: 4,
+            atime: 5,
+            mtime: 6,
+            ctime: 7,
+            ..INodeOnDisk::default()
+        };
+        for (i, a) in ip.addrs.iter_mut().enumerate() {
+            *a = 100 + i as u32;
+        }
+        ip.spare[12] = 0xdeadbeef;
+        let mut b = [0; INODESIZE];
+        ip.encode(&mut b);
+        assert_eq!(b[..8], [4, 0, 1, 0, 2, 0, 3, 0]);
+        assert_eq!((u32_at(&b, 8), u32_at(&b, 20)), (4, 7));
+        assert_eq!(u32_at(&b, 24), 100);
+        assert_eq!(u32_at(&b, 24 + 4 * (NADDRS - 1)), 100 + NADDRS as u32 - 1);
+        assert_eq!(u32_at(&b, INODESIZE - 4), 0xdeadbeef);
+        let mut again = [0; INODESIZE];
+        INodeOnDisk::parse(&b).encode(&mut again);
+        assert_eq!(again, b);
+    }
+
+    #[test]
+    fn extents() {
+        assert_eq!(EXTENTSIZE, 12);
+        // The extents in addrs leave its last slot for the
+        // overflow block, which holds a MINBSIZE block's worth.
+        assert_eq!(NEXTENT * EXTENTSIZE / 4, EXTBLK);
+        assert_eq!((NXEXTENT, MAXEXTENT), (42, 46));
+        let e = Extent {
+            off: 1,
+            start: 2,
+            len: 3,
+        };
+        let mut b = [0; EXTENTSIZE];
+        e.encode(&mut b);
+        assert_eq!((u32_at(&b, 0), u32_at(&b, 4), u32_at(&b, 8)), (1, 2, 3));
+        let e = Extent::parse(&b);
+        assert_eq!((e.off, e.start, e.len), (1, 2, 3));
+    }
+
+    #[test]
+    fn directories() {
+        assert_eq!(DIRENTSIZE, 16);
+        let mut b = [0xff; DIRENTSIZE];
+        DirectoryEntry::new(7, b"cat").encode(&mut b);
+        assert_eq!(b[..6], [7, 0, b'c', b'a', b't', 0]);
+        assert_eq!(DirectoryEntry::parse(&b).name(), b"cat");
+        let full = DirectoryEntry::new(7, b"fourteen_bytes");
+        assert_eq!(full.name(), b"fourteen_bytes");
+
+        assert_eq!(DIRRECSIZE, 8);
+        assert_eq!((dirreclen(0), dirreclen(1), dirreclen(4)), (8, 12, 12));
+        assert_eq!((dirreclen(5), dirreclen(NAMEMAX)), (16, 264));
+        let rec = DirRecord {
+            inum: 0x01020304,
+            reclen: 12,
+            namelen: 1,
+            pad: 0,
+        };
+        let mut b = [0; DIRRECSIZE];
+        rec.encode(&mut b);
+        assert_eq!(b, [4, 3, 2, 1, 12, 0, 1, 0]);
+    }
+}
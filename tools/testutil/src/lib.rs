@@ -0,0 +1,40 @@
+// Fixtures shared by the tools' integration tests.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process,
+};
+
+// A scratch directory for one test, removed when it is dropped.
+pub struct Scratch(PathBuf);
+
+impl Scratch {
+    // An empty directory, named for the test and this process so
+    // that tests running at once do not collide.
+    pub fn new(name: &str) -> Self {
+        let dir = env::temp_dir().join(format!("{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        Scratch(dir)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+// Bytes that differ from block to block of a file.
+pub fn contents(seed: usize, n: usize) -> Vec<u8> {
+    (0..n).map(|i| (i * 31 / 512 + i + seed) as u8).collect()
+}
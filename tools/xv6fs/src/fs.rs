@@ -0,0 +1,609 @@
+// Files and directories in an image, mapped, allocated and
+// linked the way kernel/c/fs.c does it, but written straight to
+// the image rather than through the log.
+
+use std::collections::HashSet;
+
+use fsck::{dir, image::*};
+
+pub struct Fs<D> {
+    pub img: Image<D>,
+    map: Vec<u8>, // the free map, written back by sync
+    now: u32,
+}
+
+// The blocks of an inode: (file block, disk block) for each
+// data block, and the indirect or extent blocks holding them.
+pub struct Blocks {
+    pub data: Vec<(u32, u32)>,
+    pub meta: Vec<u32>,
+}
+
+// Indirect blocks needed to map n blocks, nind to a block.
+fn nindblocks(n: u64, nind: u64) -> u64 {
+    let mut n = n.saturating_sub(NDIRECT as u64);
+    let (mut total, mut tree) = (0, nind);
+    while n > 0 {
+        let k = n.min(tree);
+        let mut per = nind;
+        while per <= tree {
+            total += k.div_ceil(per);
+            per *= nind;
+        }
+        n -= k;
+        tree *= nind;
+    }
+    total
+}
+
+impl<D: Disk> Fs<D> {
+    pub fn new(mut img: Image<D>, now: u32) -> Result<Self, Error> {
+        let sb = img.sb;
+        let mut map = Vec::new();
+        for b in sb.bmapstart..sb.bblock(sb.size - 1) + 1 {
+            map.extend_from_slice(&img.block(b)?);
+        }
+        Ok(Fs { img, map, now })
+    }
+
+    // Write the free map back.
+    pub fn sync(&mut self) -> Result<(), Error> {
+        let bs = self.img.bsize();
+        for (i, b) in self.map.chunks(bs).enumerate() {
+            let bn = self.img.sb.bmapstart + i as u32;
+            if self.img.block(bn)? != b {
+                self.img.write_block(bn, b)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn extents(&self) -> bool {
+        self.img.sb.features & FS_EXTENTS != 0
+    }
+
+    // Blocks.
+
+    pub fn inuse(&self, b: u32) -> bool {
+        self.map[b as usize / 8] & (1 << (b % 8)) != 0
+    }
+
+    fn mark(&mut self, b: u32, used: bool) {
+        let m = 1 << (b % 8);
+        if used {
+            self.map[b as usize / 8] |= m;
+        } else {
+            self.map[b as usize / 8] &= !m;
+        }
+    }
+
+    pub fn nfree(&self) -> u32 {
+        let sb = self.img.sb;
+        (sb.datastart()..sb.size)
+            .filter(|&b| !self.inuse(b))
+            .count() as u32
+    }
+
+    // Allocate block b, zeroed, if it is free.
+    fn balloc_at(&mut self, b: u32) -> Result<bool, Error> {
+        if b < self.img.sb.datastart() || b >= self.img.sb.size || self.inuse(b) {
+            return Ok(false);
+        }
+        self.mark(b, true);
+        self.img.write_block(b, &vec![0; self.img.bsize()])?;
+        Ok(true)
+    }
+
+    // Allocate a zeroed block at the start of the first run of
+    // want free blocks at or after goal, else the first free
+    // block after goal, else any free block.
+    fn balloc_run(&mut self, goal: u32, want: u32) -> Result<u32, Error> {
+        let sb = self.img.sb;
+        let goal = if goal < sb.size { goal } else { 0 };
+        let (mut start, mut len, mut first) = (0, 0, None);
+        for b in goal.max(sb.datastart())..sb.size {
+            if self.inuse(b) {
+                len = 0;
+                continue;
+            }
+            if len == 0 {
+                start = b;
+            }
+            len += 1;
+            first = first.or(Some(start));
+            if len == want {
+                break;
+            }
+        }
+        let b = match (len == want, first) {
+            (true, _) => start,
+            (false, Some(b)) => b,
+            (false, None) => return self.balloc(),
+        };
+        self.balloc_at(b)?;
+        Ok(b)
+    }
+
+    fn balloc(&mut self) -> Result<u32, Error> {
+        let sb = self.img.sb;
+        match (sb.datastart()..sb.size).find(|&b| !self.inuse(b)) {
+            Some(b) => {
+                self.balloc_at(b)?;
+                Ok(b)
+            }
+            None => Err(Error::Bad("out of blocks")),
+        }
+    }
+
+    // Inodes.
+
+    pub fn inode(&mut self, inum: u32) -> Result<INodeOnDisk, Error> {
+        if inum == 0 || inum >= self.img.sb.ninodes {
+            return Err(Error::Bad("inode number out of range"));
+        }
+        self.img.inode(inum)
+    }
+
+    // Write ip back, as iupdate does.
+    fn iupdate(&mut self, inum: u32, ip: &mut INodeOnDisk) -> Result<(), Error> {
+        ip.ctime = self.now;
+        self.img.write_inode(inum, ip)
+    }
+
+    fn ialloc(&mut self, kind: u16) -> Result<u32, Error> {
+        for inum in 1..self.img.sb.ninodes {
+            if self.img.inode(inum)?.kind == 0 {
+                let mut ip = INodeOnDisk {
+                    kind,
+                    nlink: 1,
+                    atime: self.now,
+                    mtime: self.now,
+                    ..INodeOnDisk::default()
+                };
+                self.iupdate(inum, &mut ip)?;
+                return Ok(inum);
+            }
+        }
+        Err(Error::Bad("out of inodes"))
+    }
+
+    // Mapping.
+
+    fn check(&self, b: u32) -> Result<u32, Error> {
+        if b < self.img.sb.datastart() || b >= self.img.sb.size {
+            return Err(Error::Bad("block out of range (run fsck)"));
+        }
+        Ok(b)
+    }
+
+    // The extents of ip, up to the first unused one.
+    pub fn extent_list(&mut self, ip: &INodeOnDisk) -> Result<Vec<Extent>, Error> {
+        let mut ext: Vec<Extent> = (0..NEXTENT)
+            .map(|i| Extent {
+                off: ip.addrs[3 * i],
+                start: ip.addrs[3 * i + 1],
+                len: ip.addrs[3 * i + 2],
+            })
+            .collect();
+        if ip.addrs[EXTBLK] != 0 {
+            let b = self.img.block(self.check(ip.addrs[EXTBLK])?)?;
+            ext.extend((0..NXEXTENT).map(|i| Extent::parse(&b[i * EXTENTSIZE..])));
+        }
+        let n = ext.iter().position(|e| e.len == 0).unwrap_or(ext.len());
+        ext.truncate(n);
+        Ok(ext)
+    }
+
+    // Store ext back into ip and its overflow block, which it
+    // must already have if they do not fit in the inode.
+    fn save_extents(&mut self, ip: &mut INodeOnDisk, ext: &[Extent]) -> Result<(), Error> {
+        for i in 0..NEXTENT {
+            let e = ext.get(i).copied().unwrap_or_default();
+            ip.addrs[3 * i..3 * i + 3].copy_from_slice(&[e.off, e.start, e.len]);
+        }
+        if ip.addrs[EXTBLK] != 0 {
+            let mut b = vec![0; self.img.bsize()];
+            for (i, e) in ext.iter().skip(NEXTENT).enumerate() {
+                e.encode(&mut b[i * EXTENTSIZE..]);
+            }
+            self.img.write_block(ip.addrs[EXTBLK], &b)?;
+        }
+        Ok(())
+    }
+
+    // Every block of ip.
+    pub fn blocks(&mut self, ip: &INodeOnDisk) -> Result<Blocks, Error> {
+        let mut bl = Blocks {
+            data: Vec::new(),
+            meta: Vec::new(),
+        };
+        if ip.kind == T_DEV {
+            return Ok(bl);
+        }
+        if self.extents() {
+            for e in self.extent_list(ip)? {
+                for j in 0..e.len {
+                    bl.data.push((e.off + j, self.check(e.start + j)?));
+                }
+            }
+            if ip.addrs[EXTBLK] != 0 {
+                bl.meta.push(ip.addrs[EXTBLK]);
+            }
+            return Ok(bl);
+        }
+        let nind = self.img.sb.nindirect();
+        let mut base = NDIRECT as u32;
+        for (i, &a) in ip.addrs.iter().enumerate() {
+            if i < NDIRECT {
+                if a != 0 {
+                    bl.data.push((i as u32, self.check(a)?));
+                }
+                continue;
+            }
+            let depth = (i - NDIRECT + 1) as u32;
+            if a != 0 {
+                self.tree(a, depth, base, &mut bl)?;
+            }
+            base = base.saturating_add(nind.saturating_pow(depth));
+        }
+        Ok(bl)
+    }
+
+    // Add the blocks of the tree at addr, depth levels of
+    // indirect blocks mapping the file from block base on.
+    fn tree(&mut self, addr: u32, depth: u32, base: u32, bl: &mut Blocks) -> Result<(), Error> {
+        let addr = self.check(addr)?;
+        if depth == 0 {
+            bl.data.push((base, addr));
+            return Ok(());
+        }
+        bl.meta.push(addr);
+        let nind = self.img.sb.nindirect();
+        let per = nind.pow(depth - 1);
+        let b = self.img.block(addr)?;
+        for j in 0..nind {
+            let a = u32_at(&b, 4 * j as usize);
+            if a != 0 {
+                self.tree(a, depth - 1, base + j * per, bl)?;
+            }
+        }
+        Ok(())
+    }
+
+    // The disk block holding file block bn of ip, allocating it
+    // if need be. want is the number of blocks the caller is
+    // about to write, as a hint for where to put them.
+    fn bmap(&mut self, ip: &mut INodeOnDisk, bn: u32, want: u32) -> Result<u32, Error> {
+        if self.extents() {
+            return self.ebmap(ip, bn, want);
+        }
+        if (bn as usize) < NDIRECT {
+            if ip.addrs[bn as usize] == 0 {
+                ip.addrs[bn as usize] = self.balloc()?;
+            }
+            return Ok(ip.addrs[bn as usize]);
+        }
+
+        // Tree i is i+1 levels deep and maps n blocks.
+        let nind = self.img.sb.nindirect() as u64;
+        let (mut bn, mut i, mut n) = (bn as u64 - NDIRECT as u64, 0, nind);
+        while bn >= n {
+            bn -= n;
+            i += 1;
+            n *= nind;
+        }
+        if ip.addrs[NDIRECT + i] == 0 {
+            ip.addrs[NDIRECT + i] = self.balloc()?;
+        }
+        let mut addr = ip.addrs[NDIRECT + i];
+        let mut per = n / nind;
+        loop {
+            let mut b = self.img.block(addr)?;
+            let slot = 4 * (bn / per) as usize;
+            let mut next = u32_at(&b, slot);
+            if next == 0 {
+                next = self.balloc()?;
+                put_u32(&mut b, slot, next);
+                self.img.write_block(addr, &b)?;
+            }
+            if per == 1 {
+                return Ok(next);
+            }
+            addr = next;
+            bn %= per;
+            per /= nind;
+        }
+    }
+
+    // Extent version of bmap: grow the extent before bn if it
+    // ends just before it and the next disk block is free, else
+    // start a new one.
+    fn ebmap(&mut self, ip: &mut INodeOnDisk, bn: u32, want: u32) -> Result<u32, Error> {
+        let mut ext = self.extent_list(ip)?;
+        let mut i = 0;
+        while i < ext.len() && ext[i].off <= bn {
+            let e = ext[i];
+            if bn < e.off + e.len {
+                return Ok(e.start + (bn - e.off));
+            }
+            i += 1;
+        }
+
+        let mut goal = 0;
+        if i > 0 {
+            let e = ext[i - 1];
+            goal = e.start + (bn - e.off);
+            if e.off + e.len == bn && self.balloc_at(goal)? {
+                ext[i - 1].len += 1;
+                self.save_extents(ip, &ext)?;
+                return Ok(goal);
+            }
+        }
+        if ext.len() == MAXEXTENT {
+            return Err(Error::Bad("out of extents"));
+        }
+        if ext.len() >= NEXTENT && ip.addrs[EXTBLK] == 0 {
+            ip.addrs[EXTBLK] = self.balloc()?;
+        }
+        let addr = self.balloc_run(goal, want)?;
+        ext.insert(
+            i,
+            Extent {
+                off: bn,
+                start: addr,
+                len: 1,
+            },
+        );
+        self.save_extents(ip, &ext)?;
+        Ok(addr)
+    }
+
+    // Reading and writing.
+
+    pub fn read(&mut self, ip: &INodeOnDisk) -> Result<Vec<u8>, Error> {
+        let bs = self.img.bsize();
+        let mut data = vec![0; ip.size as usize];
+        for (bn, addr) in self.blocks(ip)?.data {
+            let off = bn as usize * bs;
+            if off < data.len() {
+                let n = bs.min(data.len() - off);
+                data[off..off + n].copy_from_slice(&self.img.block(addr)?[..n]);
+            }
+        }
+        Ok(data)
+    }
+
+    // Write data into inode inum at byte off, growing it if need
+    // be.
+    fn write_at(&mut self, inum: u32, off: u32, data: &[u8]) -> Result<(), Error> {
+        let mut ip = self.inode(inum)?;
+        let bs = self.img.bsize();
+        let end = off as u64 + data.len() as u64;
+        if end > (maxfile(bs) * bs) as u64 || end > u32::MAX as u64 {
+            return Err(Error::Bad("file too big"));
+        }
+        let mut done = 0;
+        let mut r = Ok(());
+        while done < data.len() {
+            let pos = off as usize + done;
+            let want = (end as usize - pos).div_ceil(bs) as u32;
+            let addr = match self.bmap(&mut ip, (pos / bs) as u32, want) {
+                Ok(addr) => addr,
+                Err(e) => {
+                    r = Err(e);
+                    break;
+                }
+            };
+            let n = (bs - pos % bs).min(data.len() - done);
+            let mut b = self.img.block(addr)?;
+            b[pos % bs..pos % bs + n].copy_from_slice(&data[done..done + n]);
+            self.img.write_block(addr, &b)?;
+            done += n;
+        }
+        ip.size = ip.size.max(off + done as u32);
+        ip.mtime = self.now;
+        self.iupdate(inum, &mut ip)?;
+        r
+    }
+
+    // Free every block of ip, as itrunc does. Caller must write
+    // ip back.
+    fn trunc(&mut self, ip: &mut INodeOnDisk) -> Result<(), Error> {
+        let bl = self.blocks(ip)?;
+        for b in bl.data.iter().map(|&(_, b)| b).chain(bl.meta) {
+            self.mark(b, false);
+        }
+        ip.addrs = [0; NADDRS];
+        ip.size = 0;
+        Ok(())
+    }
+
+    // Replace the contents of inode inum with data, failing
+    // before anything changes if there are too few free blocks.
+    // With extents, it can still run out of them part way.
+    pub fn write(&mut self, inum: u32, data: &[u8]) -> Result<(), Error> {
+        let mut ip = self.inode(inum)?;
+        let bs = self.img.bsize() as u64;
+        let n = (data.len() as u64).div_ceil(bs);
+        let extra = if self.extents() {
+            1
+        } else {
+            nindblocks(n, self.img.sb.nindirect() as u64)
+        };
+        let bl = self.blocks(&ip)?;
+        let have = self.nfree() as u64 + (bl.data.len() + bl.meta.len()) as u64;
+        if n + extra > have {
+            return Err(Error::Bad("out of blocks"));
+        }
+        self.trunc(&mut ip)?;
+        self.iupdate(inum, &mut ip)?;
+        self.write_at(inum, 0, data)
+    }
+
+    // Directories.
+
+    // The entries of directory dir, as (inode, name), in
+    // directory order.
+    pub fn entries(&mut self, dir: u32) -> Result<Vec<(u32, Vec<u8>)>, Error> {
+        let ip = self.inode(dir)?;
+        if ip.kind != T_DIR {
+            return Err(Error::Bad("not a directory"));
+        }
+        let data = self.read(&ip)?;
+        let (features, bs) = (self.img.sb.features, self.img.bsize());
+        Ok(dir::entries(features, bs, &data)
+            .into_iter()
+            .map(|(_, inum, name)| (inum, name))
+            .collect())
+    }
+
+    // Rewrite directory dir to hold entries, laid out afresh.
+    fn set_entries(&mut self, dir: u32, entries: &[(u32, Vec<u8>)]) -> Result<(), Error> {
+        let (features, bs) = (self.img.sb.features, self.img.bsize());
+        self.write(dir, &dir::build(features, bs, entries))
+    }
+
+    // The inode directory dir names name.
+    fn dirlookup(&mut self, dir: u32, name: &[u8]) -> Result<Option<u32>, Error> {
+        let ents = self.entries(dir)?;
+        Ok(ents.into_iter().find(|(_, n)| n == name).map(|(i, _)| i))
+    }
+
+    // The inode at path. Paths start at the root whether or not
+    // they begin with "/", and symbolic links are not followed.
+    pub fn namei(&mut self, path: &str) -> Result<u32, Error> {
+        let mut inum = ROOTINO;
+        for name in path.split('/').filter(|s| !s.is_empty()) {
+            match self.dirlookup(inum, name.as_bytes())? {
+                Some(i) => inum = i,
+                None => return Err(Error::Bad("no such file or directory")),
+            }
+        }
+        Ok(inum)
+    }
+
+    // The directory holding path, and the last element of path.
+    fn nameiparent<'a>(&mut self, path: &'a str) -> Result<(u32, &'a str), Error> {
+        let path = path.trim_end_matches('/');
+        let (dir, name) = path.rsplit_once('/').unwrap_or(("", path));
+        if name.is_empty() || name == "." || name == ".." {
+            return Err(Error::Bad("not a name in a directory"));
+        }
+        let dir = self.namei(dir)?;
+        if self.inode(dir)?.kind != T_DIR {
+            return Err(Error::Bad("not a directory"));
+        }
+        Ok((dir, name))
+    }
+
+    // Add an entry for inum named name to directory dir.
+    fn dirlink(&mut self, dir: u32, name: &str, inum: u32) -> Result<(), Error> {
+        let mut ents = self.entries(dir)?;
+        if ents.iter().any(|(_, n)| n == name.as_bytes()) {
+            return Err(Error::Bad("file exists"));
+        }
+        ents.push((inum, name.as_bytes().to_vec()));
+        self.set_entries(dir, &ents)
+    }
+
+    // Make path an empty file, directory or symbolic link, or
+    // return what is already there if it is a file or directory
+    // of that kind.
+    pub fn create(&mut self, path: &str, kind: u16) -> Result<u32, Error> {
+        let (dir, name) = self.nameiparent(path)?;
+        if let Some(inum) = self.dirlookup(dir, name.as_bytes())? {
+            if self.inode(inum)?.kind == kind && kind != T_SYMLINK {
+                return Ok(inum);
+            }
+            return Err(Error::Bad("file exists"));
+        }
+        let max = match self.img.sb.features & FS_LONGNAMES {
+            0 => DIRSIZ,
+            _ => NAMEMAX,
+        };
+        if name.len() > max {
+            return Err(Error::Bad("name too long"));
+        }
+
+        let inum = self.ialloc(kind)?;
+        if kind == T_DIR {
+            let dots = [(inum, b".".to_vec()), (dir, b"..".to_vec())];
+            self.set_entries(inum, &dots)?;
+            let mut dp = self.inode(dir)?;
+            dp.nlink += 1;
+            self.iupdate(dir, &mut dp)?;
+        }
+        self.dirlink(dir, name, inum)?;
+        Ok(inum)
+    }
+
+    // Remove the name path, freeing the inode it names if that
+    // was its last name. Directories must be empty.
+    pub fn unlink(&mut self, path: &str) -> Result<(), Error> {
+        let (dir, name) = self.nameiparent(path)?;
+        let mut ents = self.entries(dir)?;
+        let i = match ents.iter().position(|(_, n)| n == name.as_bytes()) {
+            Some(i) => i,
+            None => return Err(Error::Bad("no such file or directory")),
+        };
+        let inum = ents[i].0;
+        let mut ip = self.inode(inum)?;
+        if ip.kind == T_DIR {
+            let sub = self.entries(inum)?;
+            if sub.iter().any(|(_, n)| n != b"." && n != b"..") {
+                return Err(Error::Bad("directory not empty"));
+            }
+        }
+
+        ents.remove(i);
+        self.set_entries(dir, &ents)?;
+        if ip.kind == T_DIR {
+            let mut dp = self.inode(dir)?;
+            dp.nlink = dp.nlink.saturating_sub(1);
+            self.iupdate(dir, &mut dp)?;
+        }
+        ip.nlink = ip.nlink.saturating_sub(1);
+        if ip.nlink == 0 {
+            self.trunc(&mut ip)?;
+            ip.kind = 0;
+        }
+        self.iupdate(inum, &mut ip)
+    }
+
+    // Every name below directory dir, named path, depth first:
+    // (path, inode) for each, in directory order. A directory
+    // reached twice is listed but not entered again.
+    pub fn walk(&mut self, dir: u32, path: &str) -> Result<Vec<(String, u32)>, Error> {
+        let mut out = vec![(path.to_string(), dir)];
+        let mut seen = HashSet::new();
+        self.walk1(dir, path, &mut seen, &mut out)?;
+        Ok(out)
+    }
+
+    fn walk1(
+        &mut self,
+        dir: u32,
+        path: &str,
+        seen: &mut HashSet<u32>,
+        out: &mut Vec<(String, u32)>,
+    ) -> Result<(), Error> {
+        if !seen.insert(dir) {
+            return Ok(());
+        }
+        for (inum, name) in self.entries(dir)? {
+            let name = String::from_utf8_lossy(&name).into_owned();
+            if name == "." || name == ".." {
+                continue;
+            }
+            let child = match path {
+                "/" => format!("/{}", name),
+                _ => format!("{}/{}", path, name),
+            };
+            out.push((child.clone(), inum));
+            if self.inode(inum)?.kind == T_DIR {
+                self.walk1(inum, &child, seen, out)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,412 @@
+// xv6fs: look inside, and change, an xv6 file system image
+// without booting xv6.
+//
+//   xv6fs fs.img ls [path]
+//   xv6fs fs.img cat path
+//   xv6fs fs.img stat path
+//   xv6fs fs.img tree [path]
+//   xv6fs fs.img extract path [hostpath]
+//   xv6fs fs.img put hostpath path
+//   xv6fs fs.img rm path
+//   xv6fs fs.img log
+//   xv6fs fs.img bitmap
+//
+// Paths in the image start at the root, and symbolic links in
+// them are not followed. Reads see the image as the kernel
+// would on its next boot, with any committed transaction in
+// the log installed. put and rm recover the log first, as the
+// kernel would, then write the image directly.
+//
+// tree prints a line for each name, in the format of mkfs's
+// manifest. extract copies a file, symbolic link or directory
+// tree out to hostpath (by default, its name in the current
+// directory); put copies one in, replacing a file of the same
+// name; a file it cannot write whole is removed. rm removes a
+// name, and a directory only if it is empty.
+
+mod fs;
+
+use std::{
+    env,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    os::unix::fs::{symlink, FileExt},
+    path::Path,
+    process::exit,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use fsck::{
+    check,
+    image::*,
+    log::{self, LogState},
+};
+
+use crate::fs::Fs;
+
+type Image = fsck::image::Image<ImageFile>;
+
+fn kind_name(kind: u16) -> &'static str {
+    match kind {
+        T_DIR => "dir",
+        T_FILE => "file",
+        T_DEV => "dev",
+        T_SYMLINK => "symlink",
+        _ => "free",
+    }
+}
+
+fn ls(fs: &mut Fs<ImageFile>, path: &str) -> Result<(), String> {
+    let err = |e: Error| format!("{}: {}", path, e);
+    let inum = fs.namei(path).map_err(err)?;
+    let ip = fs.inode(inum).map_err(err)?;
+    let names = if ip.kind == T_DIR {
+        let ents = fs.entries(inum).map_err(err)?;
+        ents.into_iter()
+            .map(|(i, name)| (String::from_utf8_lossy(&name).into_owned(), i))
+            .collect()
+    } else {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        vec![(name.to_string(), inum)]
+    };
+    for (name, inum) in names {
+        let ip = fs.inode(inum).map_err(|e| format!("{}: {}", name, e))?;
+        print!("{:<14} {} {} {}", name, ip.kind, inum, ip.size);
+        if ip.kind == T_SYMLINK {
+            let target = fs.read(&ip).map_err(err)?;
+            print!(" -> {}", String::from_utf8_lossy(&target));
+        }
+        println!();
+    }
+    Ok(())
+}
+
+fn cat(fs: &mut Fs<ImageFile>, path: &str) -> Result<(), String> {
+    let err = |e: Error| format!("{}: {}", path, e);
+    let inum = fs.namei(path).map_err(err)?;
+    let ip = fs.inode(inum).map_err(err)?;
+    if ip.kind == T_DIR || ip.kind == T_DEV {
+        return Err(format!("{}: not a file", path));
+    }
+    let data = fs.read(&ip).map_err(err)?;
+    io::stdout().write_all(&data).map_err(|e| e.to_string())
+}
+
+fn stat(fs: &mut Fs<ImageFile>, path: &str) -> Result<(), String> {
+    let err = |e: Error| format!("{}: {}", path, e);
+    let inum = fs.namei(path).map_err(err)?;
+    let ip = fs.inode(inum).map_err(err)?;
+    println!("inode {}: {} ({})", inum, kind_name(ip.kind), ip.kind);
+    if ip.kind == T_DEV {
+        println!("device {}, {}", ip.major, ip.minor);
+    }
+    println!("nlink {}, size {}", ip.nlink, ip.size);
+    println!("atime {}, mtime {}, ctime {}", ip.atime, ip.mtime, ip.ctime);
+    let addrs: Vec<String> = ip.addrs.iter().map(|a| a.to_string()).collect();
+    println!("addrs {}", addrs.join(" "));
+    if fs.img.sb.features & FS_EXTENTS != 0 && ip.kind != T_DEV {
+        for e in fs.extent_list(&ip).map_err(err)? {
+            println!(
+                "extent: file blocks {}..{} at {}..{}",
+                e.off,
+                e.off + e.len,
+                e.start,
+                e.start + e.len
+            );
+        }
+    }
+    let bl = fs.blocks(&ip).map_err(err)?;
+    let meta: Vec<String> = bl.meta.iter().map(|b| b.to_string()).collect();
+    println!(
+        "{} data blocks, {} mapping blocks {}",
+        bl.data.len(),
+        bl.meta.len(),
+        meta.join(" ")
+    );
+    Ok(())
+}
+
+fn tree(fs: &mut Fs<ImageFile>, path: &str) -> Result<(), String> {
+    let err = |e: Error| format!("{}: {}", path, e);
+    let inum = fs.namei(path).map_err(err)?;
+    let top = match path.trim_end_matches('/') {
+        "" => "/".to_string(),
+        p if p.starts_with('/') => p.to_string(),
+        p => format!("/{}", p),
+    };
+    let names = if fs.inode(inum).map_err(err)?.kind == T_DIR {
+        fs.walk(inum, &top).map_err(err)?
+    } else {
+        vec![(top, inum)]
+    };
+    for (name, inum) in names {
+        let ip = fs.inode(inum).map_err(|e| format!("{}: {}", name, e))?;
+        println!(
+            "{} {} {} {} {}",
+            inum,
+            kind_name(ip.kind),
+            ip.nlink,
+            ip.size,
+            name
+        );
+    }
+    Ok(())
+}
+
+// Copy inode inum out to host, and everything below it if it is
+// a directory.
+fn extract(fs: &mut Fs<ImageFile>, inum: u32, host: &Path) -> Result<(), String> {
+    let err = |e: Error| format!("{}: {}", host.display(), e);
+    let ioerr = |e: io::Error| format!("{}: {}", host.display(), e);
+    let ip = fs.inode(inum).map_err(err)?;
+    match ip.kind {
+        T_DIR => {
+            if !host.is_dir() {
+                std::fs::create_dir(host).map_err(ioerr)?;
+            }
+            for (i, name) in fs.entries(inum).map_err(err)? {
+                let name = String::from_utf8_lossy(&name).into_owned();
+                if name == "." || name == ".." || name.contains('/') {
+                    continue;
+                }
+                extract(fs, i, &host.join(name))?;
+            }
+        }
+        T_FILE => {
+            let data = fs.read(&ip).map_err(err)?;
+            let f = File::create(host).map_err(ioerr)?;
+            f.write_all_at(&data, 0).map_err(ioerr)?;
+            let mtime = UNIX_EPOCH + Duration::from_secs(ip.mtime as u64);
+            f.set_modified(mtime).map_err(ioerr)?;
+        }
+        T_SYMLINK => {
+            let target = fs.read(&ip).map_err(err)?;
+            symlink(String::from_utf8_lossy(&target).as_ref(), host).map_err(ioerr)?;
+        }
+        _ => eprintln!("xv6fs: skipping {}: a device", host.display()),
+    }
+    Ok(())
+}
+
+// Copy the host file, symbolic link or directory tree at host
+// into the image as path.
+fn put(fs: &mut Fs<ImageFile>, host: &Path, path: &str) -> Result<(), String> {
+    let err = |e: Error| format!("{}: {}", path, e);
+    let ioerr = |e: io::Error| format!("{}: {}", host.display(), e);
+    let md = std::fs::symlink_metadata(host).map_err(ioerr)?;
+    if md.is_dir() {
+        fs.create(path, T_DIR).map_err(err)?;
+        let mut names: Vec<_> = std::fs::read_dir(host)
+            .map_err(ioerr)?
+            .map(|e| e.map(|e| e.file_name()))
+            .collect::<Result<_, _>>()
+            .map_err(ioerr)?;
+        names.sort();
+        for name in names {
+            let child = format!("{}/{}", path.trim_end_matches('/'), name.to_string_lossy());
+            put(fs, &host.join(&name), &child)?;
+        }
+        return Ok(());
+    }
+    let (kind, data) = if md.file_type().is_symlink() {
+        let target = std::fs::read_link(host).map_err(ioerr)?;
+        (
+            T_SYMLINK,
+            target.to_string_lossy().into_owned().into_bytes(),
+        )
+    } else if md.is_file() {
+        (T_FILE, std::fs::read(host).map_err(ioerr)?)
+    } else {
+        eprintln!(
+            "xv6fs: skipping {}: not a file or directory",
+            host.display()
+        );
+        return Ok(());
+    };
+    let inum = fs.create(path, kind).map_err(err)?;
+    if let Err(e) = fs.write(inum, &data) {
+        // Leave no half-written file behind.
+        let _ = fs.unlink(path);
+        return Err(err(e));
+    }
+    Ok(())
+}
+
+fn rm(fs: &mut Fs<ImageFile>, path: &str) -> Result<(), String> {
+    fs.unlink(path).map_err(|e| format!("{}: {}", path, e))
+}
+
+fn dumplog(img: &mut Image) -> Result<(), Error> {
+    let sb = img.sb;
+    let bs = img.bsize();
+    let nhead = loghdrblocks(sb.nlog as usize, bs);
+    println!(
+        "log: blocks {}..{}, {} of header, room for {}",
+        sb.logstart,
+        sb.logstart + sb.nlog,
+        nhead,
+        (sb.nlog as usize).saturating_sub(nhead)
+    );
+    let mut head = Vec::new();
+    for i in 0..nhead as u32 {
+        head.extend_from_slice(&img.block(sb.logstart + i)?);
+    }
+    let magic = u32_at(&head, 0);
+    let n = u32_at(&head, 8) as usize;
+    println!(
+        "magic {:#010x}{}, seq {}, n {}, sum {:#010x}",
+        magic,
+        if magic == LOGMAGIC { " (xlog)" } else { "" },
+        u32_at(&head, 4),
+        n,
+        u32_at(&head, 12)
+    );
+    let room = (head.len() - LOGHEAD) / LOGBLOCK;
+    for i in 0..n.min(room) {
+        let off = LOGHEAD + i * LOGBLOCK;
+        println!(
+            "  {}: block {}, sum {:#010x}",
+            i,
+            u32_at(&head, off),
+            u32_at(&head, off + 4)
+        );
+    }
+    match log::read(img)? {
+        LogState::Unused => println!("unused"),
+        LogState::Empty => println!("empty"),
+        LogState::Committed { seq, blocks } => println!(
+            "transaction {} of {} blocks committed, not yet installed",
+            seq,
+            blocks.len()
+        ),
+        LogState::Refused { seq, why } => {
+            println!("transaction {}: {}, would not be recovered", seq, why)
+        }
+    }
+    Ok(())
+}
+
+// Print the blocks marked in use, as runs.
+fn bitmap(fs: &mut Fs<ImageFile>) {
+    let sb = fs.img.sb;
+    println!(
+        "bitmap: blocks {}..{}, data blocks {}..{}",
+        sb.bmapstart,
+        sb.bblock(sb.size - 1) + 1,
+        sb.datastart(),
+        sb.size
+    );
+    let mut runs = Vec::new();
+    let mut b = 0;
+    while b < sb.size {
+        if !fs.inuse(b) {
+            b += 1;
+            continue;
+        }
+        let start = b;
+        while b < sb.size && fs.inuse(b) {
+            b += 1;
+        }
+        runs.push(match b - start {
+            1 => start.to_string(),
+            _ => format!("{}-{}", start, b - 1),
+        });
+    }
+    println!("in use: {}", runs.join(" "));
+    let nfree = fs.nfree();
+    println!("{} blocks in use, {} free", sb.size - nfree, nfree);
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "Usage: xv6fs fs.img ls|cat|stat|tree|extract|put|rm|log|bitmap [args]\n\
+         \x20 ls [path], cat path, stat path, tree [path],\n\
+         \x20 extract path [hostpath], put hostpath path, rm path, log, bitmap"
+    );
+    exit(2);
+}
+
+fn run(path: &str, cmd: &str, args: &[String]) -> Result<(), String> {
+    let writes = cmd == "put" || cmd == "rm";
+    let file = OpenOptions::new()
+        .read(true)
+        .write(writes)
+        .open(path)
+        .map_err(|e| format!("{}: {}", path, e))?;
+    let err = |e: Error| format!("{}: {}", path, e);
+    let len = file.metadata().map(|m| m.len()).unwrap_or(0);
+    let mut img = Image::open(ImageFile(file)).map_err(err)?;
+    if img.sb.size as u64 * img.sb.bsize as u64 > len {
+        return Err(format!(
+            "{}: super block size is past the end of the image",
+            path
+        ));
+    }
+    check::layout(&img.sb).map_err(|e| format!("{}: {}", path, e))?;
+
+    if cmd == "log" {
+        return dumplog(&mut img).map_err(err);
+    }
+    let state = log::read(&mut img).map_err(err)?;
+    if let LogState::Committed { seq, ref blocks } = state {
+        if writes {
+            eprintln!(
+                "xv6fs: {}: recovering transaction {} from the log",
+                path, seq
+            );
+            log::recover(&mut img, &state).map_err(err)?;
+        } else {
+            log::install(&mut img, blocks).map_err(err)?;
+        }
+    }
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as u32);
+    let mut fs = Fs::new(img, now).map_err(err)?;
+    let arg = |i: usize| args.get(i).map(String::as_str);
+    match (cmd, args.len()) {
+        ("ls", 0..=1) => ls(&mut fs, arg(0).unwrap_or("/")),
+        ("cat", 1) => cat(&mut fs, &args[0]),
+        ("stat", 1) => stat(&mut fs, &args[0]),
+        ("tree", 0..=1) => tree(&mut fs, arg(0).unwrap_or("/")),
+        ("extract", 1..=2) => {
+            let inum = fs
+                .namei(&args[0])
+                .map_err(|e| format!("{}: {}", args[0], e))?;
+            let name = args[0]
+                .trim_end_matches('/')
+                .rsplit('/')
+                .next()
+                .unwrap_or("");
+            let host = arg(1).unwrap_or(if name.is_empty() { "root" } else { name });
+            extract(&mut fs, inum, Path::new(host))
+        }
+        ("put", 2) => {
+            let r = put(&mut fs, Path::new(&args[0]), &args[1]);
+            fs.sync().map_err(err)?;
+            r
+        }
+        ("rm", 1) => {
+            let r = rm(&mut fs, &args[0]);
+            fs.sync().map_err(err)?;
+            r
+        }
+        ("bitmap", 0) => {
+            bitmap(&mut fs);
+            Ok(())
+        }
+        _ => usage(),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 3 {
+        usage();
+    }
+    if let Err(e) = run(&args[1], &args[2], &args[3..]) {
+        eprintln!("xv6fs: {}", e);
+        exit(1);
+    }
+}
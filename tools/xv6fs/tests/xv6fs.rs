@@ -0,0 +1,221 @@
+// Run xv6fs on images made by mkfs, and check with fsck that
+// what it leaves has no leaked or doubly used blocks.
+
+use std::{
+    env,
+    fs::{self, File},
+    path::{Path, PathBuf},
+    process::Command,
+    sync::Once,
+};
+
+use fsck::{check, image::*};
+use testutil::{contents, Scratch};
+
+// The mkfs binary, built next to this package's.
+fn mkfs_bin() -> PathBuf {
+    static BUILT: Once = Once::new();
+    let xv6fs = Path::new(env!("CARGO_BIN_EXE_xv6fs"));
+    BUILT.call_once(|| {
+        let mut cargo = Command::new(env!("CARGO"));
+        cargo.args(["build", "-q", "-p", "mkfs"]);
+        if xv6fs.parent().unwrap().ends_with("release") {
+            cargo.arg("--release");
+        }
+        assert!(cargo.status().unwrap().success(), "cannot build mkfs");
+    });
+    xv6fs.with_file_name("mkfs")
+}
+
+// A scratch directory holding fs.img.
+struct Fixture(Scratch);
+
+impl Fixture {
+    // An image made by mkfs with args, holding a file "a", a file
+    // "b" of three blocks and a directory "d" holding "c".
+    fn new(name: &str, args: &[&str]) -> Self {
+        let s = Fixture(Scratch::new(&format!("xv6fs-{}", name)));
+        fs::create_dir_all(s.path("t/d")).unwrap();
+        fs::write(s.path("t/a"), b"hello\n").unwrap();
+        fs::write(s.path("t/b"), contents(1, 3 * 512)).unwrap();
+        fs::write(s.path("t/d/c"), b"c").unwrap();
+        let ok = Command::new(mkfs_bin())
+            .current_dir(s.0.dir())
+            .args(args)
+            .args(["-d", "t", "fs.img"])
+            .output()
+            .unwrap()
+            .status
+            .success();
+        assert!(ok, "mkfs {:?} failed", args);
+        s
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.0.path(name)
+    }
+
+    // Run xv6fs on fs.img. Returns what it prints, or on failure
+    // what it prints on stderr.
+    fn run(&self, args: &[&str]) -> Result<Vec<u8>, String> {
+        let out = Command::new(env!("CARGO_BIN_EXE_xv6fs"))
+            .current_dir(self.0.dir())
+            .arg("fs.img")
+            .args(args)
+            .output()
+            .unwrap();
+        match out.status.success() {
+            true => Ok(out.stdout),
+            false => Err(String::from_utf8_lossy(&out.stderr).into_owned()),
+        }
+    }
+
+    fn ok(&self, args: &[&str]) -> String {
+        let out = self
+            .run(args)
+            .unwrap_or_else(|e| panic!("{:?}: {}", args, e));
+        String::from_utf8(out).unwrap()
+    }
+
+    fn fails(&self, args: &[&str]) -> String {
+        match self.run(args) {
+            Ok(_) => panic!("xv6fs {:?} did not fail", args),
+            Err(e) => e,
+        }
+    }
+
+    // The names ls lists in path, sorted: a hashed directory
+    // lists them in bucket order.
+    fn ls(&self, path: &str) -> Vec<String> {
+        let out = self.ok(&["ls", path]);
+        let mut names: Vec<String> = out
+            .lines()
+            .map(|l| l.split_whitespace().next().unwrap().to_string())
+            .collect();
+        names.sort();
+        names
+    }
+
+    // Check the image with fsck, and return the blocks in use.
+    fn fsck(&self) -> u32 {
+        let file = File::open(self.path("fs.img")).unwrap();
+        let mut img = Image::open(ImageFile(file)).unwrap();
+        let report = check::check(&mut img, false).unwrap();
+        let problems: Vec<String> = report.problems.into_iter().map(|p| p.msg).collect();
+        assert_eq!(problems, Vec::<String>::new());
+        report.blocks
+    }
+}
+
+// Each test runs on every kind of image mkfs makes.
+const KINDS: [&[&str]; 4] = [&[], &["-e"], &["-n"], &["-e", "-n", "-b", "4096"]];
+
+#[test]
+fn read() {
+    for (k, args) in KINDS.iter().enumerate() {
+        let s = Fixture::new(&format!("read{}", k), args);
+        assert_eq!(s.ls("/"), [".", "..", "a", "b", "d"]);
+        assert_eq!(s.ls("d"), [".", "..", "c"]);
+        let a = s.ok(&["ls", "a"]);
+        let f: Vec<&str> = a.split_whitespace().collect();
+        assert_eq!((f[0], f[1], f[3]), ("a", "2", "6"));
+        assert!(s
+            .fails(&["ls", "nothere"])
+            .contains("no such file or directory"));
+
+        assert_eq!(s.run(&["cat", "b"]).unwrap(), contents(1, 3 * 512));
+        assert!(s.fails(&["cat", "d"]).contains("not a file"));
+        s.ok(&["extract", "d", "out"]);
+        assert_eq!(fs::read(s.path("out/c")).unwrap(), b"c");
+        s.fsck();
+    }
+}
+
+#[test]
+fn put() {
+    for (k, args) in KINDS.iter().enumerate() {
+        let s = Fixture::new(&format!("put{}", k), args);
+        let used = s.fsck();
+
+        // A new file, big enough to need indirect blocks.
+        let big = contents(2, 80000);
+        fs::write(s.path("big"), &big).unwrap();
+        s.ok(&["put", "big", "d/big"]);
+        assert_eq!(s.run(&["cat", "d/big"]).unwrap(), big);
+        s.fsck();
+
+        // Over an existing file, smaller and then bigger: the old
+        // blocks must be freed, not leaked or shared.
+        fs::write(s.path("small"), b"small").unwrap();
+        s.ok(&["put", "small", "d/big"]);
+        assert_eq!(s.run(&["cat", "d/big"]).unwrap(), b"small");
+        assert_eq!(s.fsck(), used + 1);
+        s.ok(&["put", "big", "a"]);
+        assert_eq!(s.run(&["cat", "a"]).unwrap(), big);
+        s.fsck();
+
+        s.ok(&["rm", "d/big"]);
+        fs::write(s.path("a"), b"hello\n").unwrap();
+        s.ok(&["put", "a", "a"]);
+        assert_eq!(s.fsck(), used);
+
+        assert!(s
+            .fails(&["put", "small", "d/c/x"])
+            .contains("not a directory"));
+        assert!(s.fails(&["put", "small", "d"]).contains("file exists"));
+        assert!(s
+            .fails(&["put", "small", "nothere/x"])
+            .contains("no such file"));
+        assert_eq!(s.fsck(), used);
+    }
+}
+
+#[test]
+fn directories() {
+    for (k, args) in KINDS.iter().enumerate() {
+        let s = Fixture::new(&format!("directories{}", k), args);
+        let used = s.fsck();
+
+        // put makes directories, as many as it needs.
+        fs::create_dir_all(s.path("new/e")).unwrap();
+        fs::write(s.path("new/e/f"), contents(3, 5000)).unwrap();
+        s.ok(&["put", "new", "d/new"]);
+        assert_eq!(s.ls("d/new"), [".", "..", "e"]);
+        assert_eq!(s.run(&["cat", "d/new/e/f"]).unwrap(), contents(3, 5000));
+        let tree = s.ok(&["tree", "d"]);
+        let names: Vec<&str> = tree
+            .lines()
+            .map(|l| l.rsplit(' ').next().unwrap())
+            .collect();
+        assert_eq!(names, ["/d", "/d/c", "/d/new", "/d/new/e", "/d/new/e/f"]);
+        s.fsck();
+
+        // Many names: with -n, d outgrows a block and is hashed.
+        for i in 0..60 {
+            let name = format!("d/file{}", i);
+            s.ok(&["put", "t/d/c", &name]);
+        }
+        assert_eq!(s.ls("d").len(), 64);
+        s.fsck();
+        for i in 0..60 {
+            s.ok(&["rm", &format!("d/file{}", i)]);
+        }
+        s.fsck();
+
+        assert!(s.fails(&["rm", "d/new"]).contains("directory not empty"));
+        s.ok(&["rm", "d/new/e/f"]);
+        s.ok(&["rm", "d/new/e"]);
+        s.ok(&["rm", "d/new"]);
+        assert!(s
+            .fails(&["rm", "d/new"])
+            .contains("no such file or directory"));
+        assert_eq!(s.ls("d"), [".", "..", "c"]);
+        s.fsck();
+
+        s.ok(&["rm", "d/c"]);
+        s.ok(&["rm", "d"]);
+        assert_eq!(s.ls("/"), [".", "..", "a", "b"]);
+        let left = s.fsck();
+        assert!(left < used);
+    }
+}
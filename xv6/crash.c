@@ -0,0 +1,40 @@
+// crash: arm a crash point in the log, to test recovery.
+// Built only with CRASHTEST=1, as the kernel half is.
+//
+//   crash logged n           halt after the n'th commit from now writes the log
+//   crash committed n        halt after it writes the log header
+//   crash install n nblock   halt after it installs nblock blocks
+//   crash now                halt between commits
+//   crash off                disarm
+
+#include "types.h"
+#include "stat.h"
+#include "user.h"
+#include "crash.h"
+
+char *points[] = {
+  [CRASH_NONE]      "off",
+  [CRASH_LOGGED]    "logged",
+  [CRASH_COMMITTED] "committed",
+  [CRASH_INSTALL]   "install",
+  [CRASH_NOW]       "now",
+};
+#define NPOINT (sizeof(points)/sizeof(points[0]))
+
+int
+main(int argc, char *argv[])
+{
+  int n, nblock;
+  uint point;
+
+  for(point = 0; point < NPOINT; point++)
+    if(argc > 1 && strcmp(argv[1], points[point]) == 0)
+      break;
+  n = argc > 2 ? atoi(argv[2]) : 0;
+  nblock = argc > 3 ? atoi(argv[3]) : 0;
+  if(point == NPOINT || crashat(point, n, nblock) < 0){
+    printf(2, "usage: crash logged|committed n | install n nblock | now | off\n");
+    exit();
+  }
+  exit();
+}
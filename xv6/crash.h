@@ -0,0 +1,7 @@
+// Crash points for crashat(): where commit() in log.c halts
+// the machine as if its power had failed, to test recovery.
+#define CRASH_NONE      0  // disarm
+#define CRASH_LOGGED    1  // after write_log(), before the commit point
+#define CRASH_COMMITTED 2  // after write_head(), the commit point
+#define CRASH_INSTALL   3  // partway through install_trans()
+#define CRASH_NOW       4  // at once, between commits
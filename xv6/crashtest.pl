@@ -0,0 +1,116 @@
+#!/usr/bin/perl -w
+
+# Crash-test log recovery. Each run boots xv6 on a copy of
+# fs.img, arms a crash point in commit() (see crash.c), runs a
+# workload until the machine halts there, boots again on the
+# same disk so that the log recovers, and checks the result
+# with fsck. Runs cover every crash point of every commit the
+# workload makes, until it makes no more.
+#
+# Run "make crashtest", or from this directory after
+# "make CRASHTEST=1 fs.img xv6.img fsck":
+#	./crashtest.pl [-k] ['cmd; cmd; ...']
+# -k keeps crash-*.img of each run that fails the check.
+
+use strict;
+use IPC::Open2;
+use IO::Select;
+
+my $QEMU = $ENV{QEMU} || "qemu-system-i386";
+my $FSCK = $ENV{FSCK} || "../tools/target/release/fsck";
+my $CPUS = $ENV{CPUS} || 2;
+my $TIMEOUT = 120;	# seconds a boot may take
+
+my $keep = 0;
+if(@ARGV && $ARGV[0] eq "-k"){
+	$keep = 1;
+	shift @ARGV;
+}
+my $workload = $ARGV[0] ||
+	"mkdir d; echo hello > d/a; ln d/a b; mv b d/c; rm d/a; " .
+	"stressfs; rm stressfs0 stressfs1; mkdir d/e; mv d/e f; sync";
+my @workload = split(/\s*;\s*/, $workload);
+
+my @QEMUOPTS = ("-nographic", "-no-reboot",
+	"-drive", "file=crash.img,index=1,media=disk,format=raw",
+	"-drive", "file=xv6.img,index=0,media=disk,format=raw",
+	"-smp", $CPUS, "-m", "512");
+
+# Boot on crash.img and type each command at a shell prompt.
+# Returns where the machine halted, or undef if it did not,
+# and everything it printed.
+sub boot {
+	my @cmds = @_;
+	my ($out, $in);
+	my $pid = open2($out, $in, $QEMU, @QEMUOPTS);
+	my $sel = IO::Select->new($out);
+	my $deadline = time + $TIMEOUT;
+	my ($buf, $pos, $where) = ("", 0);
+
+	while(time < $deadline && $sel->can_read($deadline - time)){
+		last if !sysread($out, $buf, 4096, length $buf);
+		if($buf =~ /crash: halted ([^\r\n]*)\r?\n/){
+			$where = $1;
+			last;
+		}
+		if(@cmds && substr($buf, $pos) =~ /(^|\n)\$ /){
+			print $in shift(@cmds), "\n";
+			$in->flush;
+			$pos = length $buf;
+		}
+	}
+	kill 'TERM', $pid;
+	waitpid($pid, 0);
+	return ($where, $buf);
+}
+
+my ($runs, $failed) = (0, 0);
+
+# Crash with "crash @arm" armed. Returns 0 if the workload
+# finished before reaching the crash point.
+sub run {
+	my $arm = join(" ", @_);
+	system("cp", "fs.img", "crash.img") == 0 or die "cp: $!\n";
+
+	my ($where, $out) = boot("crash $arm", @workload, "crash now");
+	if(!defined $where){
+		print "crash $arm: did not halt; output was:\n$out\n";
+		$runs++;
+		$failed++;
+		return 0;
+	}
+	return 0 if $where eq "between commits";
+
+	# Recovery runs when the kernel first uses the disk.
+	my ($where2, $out2) = boot("crash now");
+	my @log = $out2 =~ /^(log: [^\r\n]*)/mg;
+	my $check = `$FSCK crash.img 2>&1`;
+	my $ok = defined $where2 && $? == 0;
+
+	$runs++;
+	printf("%-20s halted %-22s %s\n", "crash $arm", $where, $ok ? "ok" : "FAILED");
+	print map("\t$_\n", @log);
+	if(!$ok){
+		$failed++;
+		print "\tsecond boot did not halt\n" if !defined $where2;
+		print map("\t$_\n", split(/\n/, $check));
+		if($keep){
+			(my $name = "crash-$arm.img") =~ s/ /-/g;
+			system("cp", "crash.img", $name);
+		}
+	}
+	return 1;
+}
+
+for(my $n = 1; ; $n++){
+	my $more = run("logged", $n);
+	$more |= run("committed", $n);
+	for(my $k = 1; run("install", $n, $k); $k *= 2){
+		$more = 1;
+	}
+	last if !$more;
+}
+unlink("crash.img");
+
+print "$runs runs, $failed failed\n";
+exit($failed ? 1 : 0);
@@ -0,0 +1,22 @@
+#include "types.h"
+#include "user.h"
+#include "date.h"
+
+#define Z(x) ((x) < 10 ? "0" : "")  // pad to two digits
+
+int
+main(int argc, char *argv[])
+{
+  struct timeval tv;
+  struct rtcdate r;
+
+  if(gettimeofday(&tv) < 0){
+    printf(2, "date: gettimeofday failed\n");
+    exit();
+  }
+  gmtime(tv.sec, &r);
+  printf(1, "%d-%s%d-%s%d %s%d:%s%d:%s%d UTC\n", r.year, Z(r.month), r.month,
+         Z(r.day), r.day, Z(r.hour), r.hour, Z(r.minute), r.minute,
+         Z(r.second), r.second);
+  exit();
+}
@@ -0,0 +1,19 @@
+struct rtcdate {
+  uint second;
+  uint minute;
+  uint hour;
+  uint day;
+  uint month;
+  uint year;
+};
+
+struct timeval {
+  uint sec;   // seconds since 1970-01-01 00:00:00 UTC
+  uint usec;  // and microseconds
+};
+
+// New times for utime()
+struct utimbuf {
+  uint atime;  // access time
+  uint mtime;  // modification time
+};
@@ -1,16 +1,21 @@
 // On-disk file system format.
 // Both the kernel and user programs use this header file.
+// tools/ondisk describes the same format to Rust; change both.
 
 
-#define ROOTINO 1  // root i-number
-#define BSIZE 512  // block size
+#define ROOTINO 1      // root i-number
+#define MINBSIZE 512   // smallest block size
+#define MAXBSIZE 4096  // largest block size
+#define SBOFF 512      // byte offset of the super block on disk
 
 // Disk layout:
 // [ boot block | super block | log | inode blocks |
 //                                          free bit map | data blocks]
 //
-// mkfs computes the super block and builds an initial file system. The
-// super block describes the disk layout:
+// mkfs chooses the block size, computes the super block and builds
+// an initial file system. The super block sits at byte SBOFF whatever
+// the block size, so with blocks bigger than 512 bytes it shares
+// block 0 with the boot block. It describes the disk layout:
 struct superblock {
   uint size;         // Size of file system image (blocks)
   uint nblocks;      // Number of data blocks
@@ -19,11 +24,46 @@ struct superblock {
   uint logstart;     // Block number of first log block
   uint inodestart;   // Block number of first inode block
   uint bmapstart;    // Block number of first free map block
+  uint features;     // FS_* feature bits
+  uint bsize;        // Block size (bytes)
 };
 
-#define NDIRECT 12
-#define NINDIRECT (BSIZE / sizeof(uint))
-#define MAXFILE (NDIRECT + NINDIRECT)
+#define FS_EXTENTS 0x1    // inodes map their blocks with extents
+#define FS_LONGNAMES 0x2  // directories hold dirrecs, hashed when big
+
+// The log starts with a header describing the last transaction
+// committed to it, which may span several blocks, followed by
+// the logged copies of the blocks. Checksums over the header
+// and over each copy tell a whole transaction from one torn by
+// a crash; recovery installs only whole ones.
+#define LOGMAGIC 0x676f6c78  // "xlog" on disk
+
+struct loghead {
+  uint magic;   // LOGMAGIC once the log has been written
+  uint seq;     // Commit sequence number
+  uint n;       // Number of logged blocks
+  uint sum;     // Checksum of the header, taken with sum zero
+  // followed by n struct logblocks
+};
+
+struct logblock {
+  uint blockno; // Home location of the block
+  uint sum;     // Checksum of the logged copy, seeded with seq
+};
+
+// Blocks holding the header of a log of n blocks.
+#define LOGHDRBLOCKS(n, bs) \
+  ((sizeof(struct loghead) + (n) * sizeof(struct logblock) + (bs) - 1) / (bs))
+
+// addrs[] holds NDIRECT direct block addresses, then the roots
+// of the singly-, doubly- and triply-indirect block trees. How
+// many blocks the trees map depends on the block size bs.
+#define NDIRECT 10
+#define NINDIRECT(bs) ((bs) / sizeof(uint))
+#define NDINDIRECT(bs) (NINDIRECT(bs) * NINDIRECT(bs))
+#define NTINDIRECT(bs) (NDINDIRECT(bs) * NINDIRECT(bs))
+#define NADDRS (NDIRECT + 3)
+#define MAXFILE(bs) (NDIRECT + NINDIRECT(bs) + NDINDIRECT(bs) + NTINDIRECT(bs))
 
 // On-disk inode structure
 struct dinode {
@@ -32,20 +72,38 @@ struct dinode {
   short minor;          // Minor device number (T_DEV only)
   short nlink;          // Number of links to inode in file system
   uint size;            // Size of file (bytes)
-  uint addrs[NDIRECT+1];   // Data block addresses
+  uint atime;           // Last access (seconds since 1970)
+  uint mtime;           // Last modification of the contents
+  uint ctime;           // Last change to the inode
+  uint addrs[NADDRS];   // Data block addresses
+  uint spare[13];       // Unused; pads the inode to 128 bytes
+};
+
+// With FS_EXTENTS, addrs[] instead holds NEXTENT extents, then
+// the address of a block whose first MINBSIZE bytes hold NXEXTENT
+// more. Extents are sorted by file block; unused ones have len 0.
+struct extent {
+  uint off;     // First file block mapped
+  uint start;   // First disk block
+  uint len;     // Number of blocks
 };
 
+#define NEXTENT 4
+#define EXTBLK (NADDRS-1)  // addrs[] index of the overflow extent block
+#define NXEXTENT (MINBSIZE / sizeof(struct extent))
+#define MAXEXTENT (NEXTENT + NXEXTENT)
+
 // Inodes per block.
-#define IPB           (BSIZE / sizeof(struct dinode))
+#define IPB(sb)       ((sb).bsize / sizeof(struct dinode))
 
 // Block containing inode i
-#define IBLOCK(i, sb)     ((i) / IPB + sb.inodestart)
+#define IBLOCK(i, sb)     ((i) / IPB(sb) + (sb).inodestart)
 
 // Bitmap bits per block
-#define BPB           (BSIZE*8)
+#define BPB(sb)       ((sb).bsize*8)
 
 // Block of free map containing bit for block b
-#define BBLOCK(b, sb) (b/BPB + sb.bmapstart)
+#define BBLOCK(b, sb) ((b)/BPB(sb) + (sb).bmapstart)
 
 // Directory is a file containing a sequence of dirent structures.
 #define DIRSIZ 14
@@ -55,3 +113,33 @@ struct dirent {
   char name[DIRSIZ];
 };
 
+// With FS_LONGNAMES, a directory is instead a sequence of blocks,
+// each holding dirrecs that cover its first bsize-DIRTAIL bytes.
+// A record with inum 0 is free space; a live record's slack
+// beyond DIRRECLEN(namelen) can take a new entry. The last
+// DIRTAIL bytes of a block hold the file block of the next block
+// in its hash bucket, or 0.
+#define NAMEMAX 255
+#define DIRTAIL 4
+
+struct dirrec {
+  uint inum;
+  ushort reclen;   // Bytes to the next record
+  uchar namelen;
+  uchar pad;
+  // followed by namelen bytes of name, not NUL-terminated
+};
+
+#define DIRRECLEN(n) ((sizeof(struct dirrec) + (n) + 3) & ~3)
+
+// A directory that outgrows one block gets an index in file
+// block 0: 1<<depth slots, each the file block of the bucket
+// holding the names whose FNV-1a hash ends in the slot's bits.
+// The index fits in its block; past that, buckets grow chains.
+#define DIRINDEXMAGIC 0x78646978  // "xidx" on disk
+
+struct dirindex {
+  uint magic;
+  uint depth;
+  // followed by 1<<depth uint slots
+};
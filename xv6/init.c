@@ -12,13 +12,19 @@ main(void)
 {
   int pid, wpid;
 
-  if(open("console", O_RDWR) < 0){
-    mknod("console", 1, 1);
-    open("console", O_RDWR);
-  }
+  // The devices are in /dev.
+  mkdir("/dev");
+  mount("devfs", "/dev", "devfs", 0);
+  if(open("/dev/console", O_RDWR) < 0)
+    exit();
   dup(0);  // stdout
   dup(0);  // stderr
 
+  // Temporary files live in memory.
+  mkdir("/tmp");
+  if(mount("tmpfs", "/tmp", "tmpfs", 0) < 0)
+    printf(1, "init: cannot mount tmpfs on /tmp\n");
+
   for(;;){
     printf(1, "init: starting sh\n");
     pid = fork();
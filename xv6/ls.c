@@ -1,12 +1,18 @@
 #include "types.h"
 #include "stat.h"
 #include "user.h"
-#include "fs.h"
+#include "param.h"
+#include "date.h"
+
+#define NAMEWIDTH 14  // names are padded to this many columns
+#define Z(x) ((x) < 10 ? "0" : "")  // pad to two digits
+
+int lflag;  // -l: also show links and modification time
 
 char*
 fmtname(char *path)
 {
-  static char buf[DIRSIZ+1];
+  static char buf[NAMEWIDTH+1];
   char *p;
 
   // Find first character after last slash.
@@ -15,23 +21,49 @@ fmtname(char *path)
   p++;
 
   // Return blank-padded name.
-  if(strlen(p) >= DIRSIZ)
+  if(strlen(p) >= NAMEWIDTH)
     return p;
   memmove(buf, p, strlen(p));
-  memset(buf+strlen(p), ' ', DIRSIZ-strlen(p));
+  memset(buf+strlen(p), ' ', NAMEWIDTH-strlen(p));
   return buf;
 }
 
+// Print a line for path; a symbolic link also shows its target.
+void
+lsline(char *path, struct stat *st)
+{
+  char target[MAXPATH];
+  struct rtcdate r;
+  int n;
+
+  printf(1, "%s %d %d %d", fmtname(path), st->type, st->ino, st->size);
+  if(lflag){
+    gmtime(st->mtime, &r);
+    printf(1, " %d %d-%s%d-%s%d %s%d:%s%d", st->nlink, r.year, Z(r.month),
+           r.month, Z(r.day), r.day, Z(r.hour), r.hour, Z(r.minute), r.minute);
+  }
+  if(st->type == T_SYMLINK && (n = readlink(path, target, sizeof(target)-1)) >= 0){
+    target[n] = 0;
+    printf(1, " -> %s", target);
+  }
+  printf(1, "\n");
+}
+
 void
 ls(char *path)
 {
   char buf[512], *p;
   int fd;
-  struct dirent de;
+  struct dir *d;
+  struct dentry *de;
   struct stat st;
 
   if((fd = open(path, 0)) < 0){
-    printf(2, "ls: cannot open %s\n", path);
+    // Perhaps a link to nothing.
+    if(lstat(path, &st) == 0 && st.type == T_SYMLINK)
+      lsline(path, &st);
+    else
+      printf(2, "ls: cannot open %s\n", path);
     return;
   }
 
@@ -43,29 +75,37 @@ ls(char *path)
 
   switch(st.type){
   case T_FILE:
-    printf(1, "%s %d %d %d\n", fmtname(path), st.type, st.ino, st.size);
+    lsline(path, &st);
     break;
 
   case T_DIR:
-    if(strlen(path) + 1 + DIRSIZ + 1 > sizeof buf){
+    if(strlen(path) + 2 > sizeof buf){
       printf(1, "ls: path too long\n");
       break;
     }
+    // Read the directory just stat'd: opening path again could
+    // find another one, if it was renamed meanwhile.
+    if((d = fdopendir(fd)) == 0){
+      printf(2, "ls: cannot open %s\n", path);
+      break;
+    }
     strcpy(buf, path);
     p = buf+strlen(buf);
     *p++ = '/';
-    while(read(fd, &de, sizeof(de)) == sizeof(de)){
-      if(de.inum == 0)
+    while((de = readdir(d)) != 0){
+      if(p - buf + strlen(de->name) + 1 > sizeof buf){
+        printf(1, "ls: path too long\n");
         continue;
-      memmove(p, de.name, DIRSIZ);
-      p[DIRSIZ] = 0;
-      if(stat(buf, &st) < 0){
+      }
+      strcpy(p, de->name);
+      if(lstat(buf, &st) < 0){
         printf(1, "ls: cannot stat %s\n", buf);
         continue;
       }
-      printf(1, "%s %d %d %d\n", fmtname(buf), st.type, st.ino, st.size);
+      lsline(buf, &st);
     }
-    break;
+    closedir(d);  // closes fd
+    return;
   }
   close(fd);
 }
@@ -75,6 +115,11 @@ main(int argc, char *argv[])
 {
   int i;
 
+  if(argc > 1 && strcmp(argv[1], "-l") == 0){
+    lflag = 1;
+    argc--;
+    argv++;
+  }
   if(argc < 2){
     ls(".");
     exit();
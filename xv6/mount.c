@@ -0,0 +1,27 @@
+// mount: put a file system at a directory.
+//   mount src dir [type [options]]
+// src is the disk device, or for a tmpfs only a name.
+// The type defaults to xv6. An xv6 disk other than the root
+// has no log, so it mounts only read-only, with options ro.
+
+#include "types.h"
+#include "stat.h"
+#include "user.h"
+
+int
+main(int argc, char *argv[])
+{
+  char *type, *opts;
+
+  if(argc < 3 || argc > 5){
+    printf(2, "Usage: mount src dir [type [options]]\n");
+    printf(2, "  an xv6 disk other than the root needs options ro\n");
+    exit();
+  }
+
+  type = argc >= 4 ? argv[3] : "xv6";
+  opts = argc == 5 ? argv[4] : 0;
+  if(mount(argv[1], argv[2], type, opts) < 0)
+    printf(2, "mount: cannot mount %s on %s\n", argv[1], argv[2]);
+  exit();
+}
@@ -0,0 +1,35 @@
+#include "types.h"
+#include "stat.h"
+#include "user.h"
+#include "param.h"
+
+int
+main(int argc, char *argv[])
+{
+  char buf[MAXPATH], *new, *p;
+  struct stat st;
+
+  if(argc != 3){
+    printf(2, "Usage: mv old new\n");
+    exit();
+  }
+
+  // Moving onto a directory puts old inside it.
+  new = argv[2];
+  if(stat(new, &st) >= 0 && st.type == T_DIR){
+    for(p = argv[1] + strlen(argv[1]); p > argv[1] && p[-1] != '/'; p--)
+      ;
+    if(strlen(new) + 1 + strlen(p) + 1 > sizeof(buf)){
+      printf(2, "mv: path too long\n");
+      exit();
+    }
+    strcpy(buf, new);
+    strcpy(buf + strlen(buf), "/");
+    strcpy(buf + strlen(buf), p);
+    new = buf;
+  }
+
+  if(rename(argv[1], new) < 0)
+    printf(2, "mv %s %s: failed\n", argv[1], argv[2]);
+  exit();
+}
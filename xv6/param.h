@@ -1,14 +1,26 @@
 #define NPROC        64  // maximum number of processes
 #define KSTACKSIZE 4096  // size of per-process kernel stack
 #define MAXCPU        8  // maximum number of CPUs
-#define NOFILE       16  // open files per process
+#define NOFILE       16  // open files per process before the fd table grows
+#define NOFILEMAX   512  // open files per process
 #define NFILE       100  // open files per system
 #define NINODE       50  // maximum number of active i-nodes
 #define NDEV         10  // maximum major device number
+#define NDEVICE      32  // maximum number of registered devices
 #define ROOTDEV       1  // device number of file system root disk
+#define NDISK         4  // IDE disks, two on each channel
+#define NMOUNT        8  // maximum number of mounted file systems
+#define TMPFSSIZE  1024  // default tmpfs size limit, in pages
 #define MAXARG       32  // max exec arguments
-#define MAXOPBLOCKS  10  // max # of blocks any FS op writes
-#define LOGSIZE      (MAXOPBLOCKS*3)  // max data blocks in on-disk log
-#define NBUF         (MAXOPBLOCKS*3)  // size of disk block cache
-#define FSSIZE       1000  // size of file system in blocks
+#define MAXOPBLOCKS  16  // max # of blocks any FS op writes
+#define LOGSIZE      (MAXOPBLOCKS*8)  // max data blocks in on-disk log
+#define BUFFRAC      16  // the disk block cache takes 1/BUFFRAC of physical memory
+#define FSSIZE       20000  // size of a file system mkfs makes, in 512-byte sectors
+#define MAXRUN        8  // max blocks read in one disk request
+#define NSYSMASK      2  // words in a per-process syscall allowlist
+#define MAXPATH     128  // maximum path name, and symbolic link target
+#define MAXSYMLINKS   8  // max symbolic links followed in one lookup
+#define HZ          100  // timer interrupts per second
+#define COMMITTICKS (5*HZ)  // longest a finished FS op waits to be committed
+#define WRITEBACKTICKS (5*HZ)  // longest a delayed write stays in the cache
 
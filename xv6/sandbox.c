@@ -0,0 +1,47 @@
+// sandbox: run a program with a narrowed system call allowlist.
+//   sandbox [-k] prog args...
+// Without -k a forbidden call fails with -1; with -k it kills
+// the program.
+
+#include "types.h"
+#include "stat.h"
+#include "user.h"
+#include "param.h"
+#include "syscall.h"
+
+int allowed[] = {
+  SYS_exit, SYS_read, SYS_write, SYS_close, SYS_fstat, SYS_dup,
+  SYS_getpid, SYS_sbrk, SYS_sleep, SYS_uptime, SYS_exec,
+};
+
+int
+main(int argc, char *argv[])
+{
+  uint allow[NSYSMASK];
+  int i, mode;
+
+  mode = RESTRICT_EPERM;
+  if(argc > 1 && strcmp(argv[1], "-k") == 0){
+    mode = RESTRICT_KILL;
+    argv++;
+    argc--;
+  }
+  if(argc < 2){
+    printf(2, "usage: sandbox [-k] prog args...\n");
+    exit();
+  }
+
+  memset(allow, 0, sizeof(allow));
+  for(i = 0; i < sizeof(allowed)/sizeof(allowed[0]); i++)
+    allow[allowed[i]/32] |= 1 << (allowed[i]%32);
+  if(sysrestrict(allow, mode) < 0){
+    printf(2, "sandbox: restrict failed\n");
+    exit();
+  }
+
+  // exec stays allowed so the program can be started; the
+  // allowlist carries over into it.
+  exec(argv[1], argv+1);
+  printf(2, "sandbox: exec %s failed\n", argv[1]);
+  exit();
+}
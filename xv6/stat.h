@@ -1,11 +1,28 @@
 #define T_DIR  1   // Directory
 #define T_FILE 2   // File
 #define T_DEV  3   // Device
+#define T_SYMLINK 4  // Symbolic link
 
 struct stat {
-  short type;  // Type of file
-  int dev;     // File system's disk device
-  uint ino;    // Inode number
-  short nlink; // Number of links to file
-  uint size;   // Size of file in bytes
+  short type;   // Type of file
+  int dev;      // File system's disk device
+  uint ino;     // Inode number
+  short nlink;  // Number of links to file
+  uint size;    // Size of file in bytes
+  uint blksize; // Block size of the file system
+  uint atime;   // Last access (seconds since 1970)
+  uint mtime;   // Last modification of the contents
+  uint ctime;   // Last change to the inode
 };
+
+// Directory entry as returned by getdents(). Records are
+// packed back to back, each reclen bytes long.
+struct dentry {
+  uint ino;       // Inode number
+  ushort reclen;  // Length of this record in bytes
+  short type;     // Type of file
+  char name[];    // Null-terminated file name
+};
+
+// Length of a record holding a name of namelen bytes.
+#define DENTRYLEN(namelen) ((sizeof(struct dentry) + (namelen) + 1 + 3) & ~3)
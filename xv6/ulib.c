@@ -1,5 +1,6 @@
 #include "types.h"
 #include "stat.h"
+#include "date.h"
 #include "fcntl.h"
 #include "user.h"
 #include "x86.h"
@@ -81,6 +82,30 @@ stat(const char *n, struct stat *st)
   return r;
 }
 
+// Break t, in seconds since 1970, into a UTC date.
+void
+gmtime(uint t, struct rtcdate *r)
+{
+  uint days, era, doe, yoe, doy, mp;
+
+  r->second = t % 60;
+  t /= 60;
+  r->minute = t % 60;
+  t /= 60;
+  r->hour = t % 24;
+
+  // Count years from March, so that leap days come last.
+  days = t / 24 + 719468;
+  era = days / 146097;
+  doe = days - era * 146097;
+  yoe = (doe - doe/1460 + doe/36524 - doe/146096) / 365;
+  doy = doe - (365*yoe + yoe/4 - yoe/100);
+  mp = (5*doy + 2) / 153;
+  r->day = doy - (153*mp + 2)/5 + 1;
+  r->month = mp < 10 ? mp + 3 : mp - 9;
+  r->year = era*400 + yoe + (r->month <= 2);
+}
+
 int
 atoi(const char *s)
 {
@@ -104,3 +129,68 @@ memmove(void *vdst, const void *vsrc, int n)
     *dst++ = *src++;
   return vdst;
 }
+
+// An open directory: a descriptor and a buffer of getdents records.
+struct dir {
+  int fd;
+  int pos;         // Offset of the next record in buf
+  int len;         // Bytes of records in buf
+  char buf[512];
+};
+
+struct dir*
+opendir(const char *path)
+{
+  struct dir *d;
+  int fd;
+
+  fd = open(path, O_RDONLY);
+  if(fd < 0)
+    return 0;
+  if((d = fdopendir(fd)) == 0)
+    close(fd);
+  return d;
+}
+
+// Read the directory open on fd; closedir() closes fd.
+struct dir*
+fdopendir(int fd)
+{
+  struct dir *d;
+
+  d = malloc(sizeof(*d));
+  if(d == 0)
+    return 0;
+  d->fd = fd;
+  d->pos = 0;
+  d->len = 0;
+  return d;
+}
+
+// Return the next entry of d, or 0 at the end of the directory.
+// The entry is overwritten by the next call.
+struct dentry*
+readdir(struct dir *d)
+{
+  struct dentry *de;
+
+  if(d->pos >= d->len){
+    d->pos = 0;
+    d->len = getdents(d->fd, d->buf, sizeof(d->buf));
+    if(d->len <= 0)
+      return 0;
+  }
+  de = (struct dentry*)(d->buf + d->pos);
+  d->pos += de->reclen;
+  return de;
+}
+
+int
+closedir(struct dir *d)
+{
+  int r;
+
+  r = close(d->fd);
+  free(d);
+  return r;
+}
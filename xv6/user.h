@@ -1,5 +1,9 @@
 struct stat;
 struct rtcdate;
+struct timeval;
+struct utimbuf;
+struct dentry;
+struct dir;
 
 // system calls
 int fork(void);
@@ -23,6 +27,30 @@ int getpid(void);
 char* sbrk(int);
 int sleep(int);
 int uptime(void);
+int sysrestrict(uint*, int);
+int lseek(int, int, int);
+int pread(int, void*, int, uint);
+int pwrite(int, const void*, int, uint);
+int dup2(int, int);
+int dup3(int, int, int);
+int fcntl(int, int, int);
+int getdents(int, void*, int);
+int symlink(const char*, const char*);
+int readlink(const char*, char*, int);
+int lstat(const char*, struct stat*);
+int time(void);
+int gettimeofday(struct timeval*);
+int utime(const char*, struct utimbuf*);
+int rename(const char*, const char*);
+int truncate(const char*, int);
+int ftruncate(int, int);
+int fallocate(int, int, int);
+int fsync(int);
+int sync(void);
+int crashat(int, int, int);
+int mount(const char*, const char*, const char*, const char*);
+int umount(const char*);
+int ioctl(int, int, void*);
 
 // ulib.c
 int stat(const char*, struct stat*);
@@ -37,3 +65,8 @@ void* memset(void*, int, uint);
 void* malloc(uint);
 void free(void*);
 int atoi(const char*);
+void gmtime(uint, struct rtcdate*);
+struct dir* opendir(const char*);
+struct dir* fdopendir(int);
+struct dentry* readdir(struct dir*);
+int closedir(struct dir*);
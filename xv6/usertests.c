@@ -7,6 +7,8 @@
 #include "syscall.h"
 #include "traps.h"
 #include "memlayout.h"
+#include "date.h"
+#include "crash.h"
 
 char buf[8192];
 char name[3];
@@ -208,6 +210,9 @@ void writetest(void)
   printf(stdout, "small file test ok\n");
 }
 
+// 4 MiB: well past the singly-indirect blocks.
+#define BIGBLOCKS 8192
+
 void writetest1(void)
 {
   int i, fd, n;
@@ -221,7 +226,7 @@ void writetest1(void)
     exit();
   }
 
-  for (i = 0; i < MAXFILE; i++)
+  for (i = 0; i < BIGBLOCKS; i++)
   {
     ((int *)buf)[0] = i;
     if (write(fd, buf, 512) != 512)
@@ -246,7 +251,7 @@ void writetest1(void)
     i = read(fd, buf, 512);
     if (i == 0)
     {
-      if (n == MAXFILE - 1)
+      if (n != BIGBLOCKS)
       {
         printf(stdout, "read only %d blocks from big", n);
         exit();
@@ -275,6 +280,135 @@ void writetest1(void)
   printf(stdout, "big files ok\n");
 }
 
+// Sparse writes into the triply-indirect blocks, up to the
+// last block a file can have. File offsets are ints, so with
+// big blocks only the blocks below 2GB are tried.
+void hugefiletest(void)
+{
+  int fd, i, n;
+  uint bn[3], bs;
+  struct stat st;
+
+  printf(1, "huge file test\n");
+  unlink("huge");
+  fd = open("huge", O_CREATE | O_RDWR);
+  if (fd < 0 || fstat(fd, &st) < 0)
+  {
+    printf(1, "create huge failed\n");
+    exit();
+  }
+  bs = st.blksize;
+  bn[0] = NDIRECT + NINDIRECT(bs) + NDINDIRECT(bs) - 1;
+  bn[1] = bn[0] + 5 * NDINDIRECT(bs) + 4;
+  bn[2] = MAXFILE(bs) - 1;
+  for (n = 0; n < 3 && bn[n] < 0x7fffffff / bs; n++)
+    ;
+
+  for (i = 0; i < n; i++)
+  {
+    ((uint *)buf)[0] = bn[i];
+    if (pwrite(fd, buf, bs, bn[i] * bs) != bs)
+    {
+      printf(1, "write block %d of huge failed\n", bn[i]);
+      exit();
+    }
+  }
+  if (n == 3 && pwrite(fd, buf, 1, MAXFILE(bs) * bs) >= 0)
+  {
+    printf(1, "write past MAXFILE succeeded\n");
+    exit();
+  }
+  for (i = n - 1; i >= 0; i--)
+  {
+    if (pread(fd, buf, bs, bn[i] * bs) != bs || ((uint *)buf)[0] != bn[i])
+    {
+      printf(1, "read block %d of huge failed\n", bn[i]);
+      exit();
+    }
+  }
+  if (n > 1 && (pread(fd, buf, bs, (bn[1] - 1) * bs) != bs ||
+                ((uint *)buf)[0] != 0))
+  {
+    printf(1, "hole in huge does not read as zeroes\n");
+    exit();
+  }
+  close(fd);
+  if (unlink("huge") < 0)
+  {
+    printf(1, "unlink huge failed\n");
+    exit();
+  }
+  printf(1, "huge file test ok\n");
+}
+
+// Two files growing in turn get interleaved blocks (and, with
+// FS_EXTENTS, an extent per block); large reads must still
+// piece each file together.
+void interleavetest(void)
+{
+  int fd[2], i, j, k, bs;
+  struct stat st;
+
+  printf(1, "interleave test\n");
+  for (j = 0; j < 2; j++)
+  {
+    name[0] = 'i';
+    name[1] = '0' + j;
+    name[2] = '\0';
+    unlink(name);
+    fd[j] = open(name, O_CREATE | O_RDWR);
+    if (fd[j] < 0)
+    {
+      printf(1, "create %s failed\n", name);
+      exit();
+    }
+  }
+  if (fstat(fd[0], &st) < 0)
+  {
+    printf(1, "fstat failed\n");
+    exit();
+  }
+  bs = st.blksize;
+  for (i = 0; i < 40; i++)
+  {
+    for (j = 0; j < 2; j++)
+    {
+      memset(buf, 'a' + j, bs);
+      ((int *)buf)[0] = i;
+      if (write(fd[j], buf, bs) != bs)
+      {
+        printf(1, "interleaved write %d failed\n", i);
+        exit();
+      }
+    }
+  }
+  for (j = 0; j < 2; j++)
+  {
+    if (pread(fd[j], buf, sizeof(buf), 3 * bs) != sizeof(buf))
+    {
+      printf(1, "interleaved read failed\n");
+      exit();
+    }
+    for (i = 0; i < sizeof(buf) / bs; i++)
+    {
+      for (k = sizeof(int); k < bs; k++)
+      {
+        if (buf[i * bs + k] != 'a' + j)
+          break;
+      }
+      if (((int *)buf)[i * bs / sizeof(int)] != 3 + i || k != bs)
+      {
+        printf(1, "interleaved read got wrong block\n");
+        exit();
+      }
+    }
+    close(fd[j]);
+    name[1] = '0' + j;
+    unlink(name);
+  }
+  printf(1, "interleave test ok\n");
+}
+
 void createtest(void)
 {
   int i, fd;
@@ -876,11 +1010,8 @@ void concreate(void)
   char file[3];
   int i, pid, n, fd;
   char fa[40];
-  struct
-  {
-    ushort inum;
-    char name[14];
-  } de;
+  struct dir *d;
+  struct dentry *de;
 
   printf(1, "concreate test\n");
   file[0] = 'C';
@@ -915,30 +1046,28 @@ void concreate(void)
   }
 
   memset(fa, 0, sizeof(fa));
-  fd = open(".", 0);
+  d = opendir(".");
   n = 0;
-  while (read(fd, &de, sizeof(de)) > 0)
+  while ((de = readdir(d)) != 0)
   {
-    if (de.inum == 0)
-      continue;
-    if (de.name[0] == 'C' && de.name[2] == '\0')
+    if (de->name[0] == 'C' && de->name[2] == '\0')
     {
-      i = de.name[1] - '0';
+      i = de->name[1] - '0';
       if (i < 0 || i >= sizeof(fa))
       {
-        printf(1, "concreate weird file %s\n", de.name);
+        printf(1, "concreate weird file %s\n", de->name);
         exit();
       }
       if (fa[i])
       {
-        printf(1, "concreate duplicate file %s\n", de.name);
+        printf(1, "concreate duplicate file %s\n", de->name);
         exit();
       }
       fa[i] = 1;
       n++;
     }
   }
-  close(fd);
+  closedir(d);
 
   if (n != 40)
   {
@@ -1022,6 +1151,19 @@ void linkunlink()
 }
 
 // directory that uses indirect blocks
+// Does the file system keep names longer than DIRSIZ? If not,
+// a name one byte longer is cut to the DIRSIZ bytes before it.
+int longnames(void)
+{
+  int r;
+
+  close(open("123456789012345", O_CREATE));
+  r = open("12345678901234", 0);
+  close(r);
+  unlink("123456789012345");
+  return r < 0;
+}
+
 void bigdir(void)
 {
   int i, fd;
@@ -1068,6 +1210,214 @@ void bigdir(void)
   printf(1, "bigdir ok\n");
 }
 
+// Names of NAMEMAX bytes work as directories and files, come back
+// whole from getdents, and a longer one is cut to NAMEMAX.
+void longnametest(void)
+{
+  char dir[NAMEMAX + 2], file[NAMEMAX + 2], path[2 * NAMEMAX + 2];
+  int fd, n;
+  struct dir *d;
+  struct dentry *de;
+
+  printf(1, "longname test\n");
+  if (!longnames())
+  {
+    printf(1, "longname ok: names are cut to %d\n", DIRSIZ);
+    return;
+  }
+  memset(dir, 'd', NAMEMAX);
+  dir[NAMEMAX] = '\0';
+  memset(file, 'f', NAMEMAX);
+  file[NAMEMAX] = '\0';
+  strcpy(path, dir);
+  path[NAMEMAX] = '/';
+  strcpy(path + NAMEMAX + 1, file);
+
+  if (mkdir(dir) != 0)
+  {
+    printf(1, "mkdir long name failed\n");
+    exit();
+  }
+  fd = open(path, O_CREATE | O_RDWR);
+  if (fd < 0 || write(fd, "long", 4) != 4)
+  {
+    printf(1, "create long path failed\n");
+    exit();
+  }
+  close(fd);
+
+  // A name differing only in its last byte is another file.
+  file[NAMEMAX - 1] = 'g';
+  if (chdir(dir) != 0 || open(file, 0) >= 0)
+  {
+    printf(1, "long names differing at the end are the same\n");
+    exit();
+  }
+  // One byte more is cut off, naming the file made above.
+  file[NAMEMAX - 1] = 'f';
+  file[NAMEMAX] = 'x';
+  file[NAMEMAX + 1] = '\0';
+  fd = open(file, O_RDONLY);
+  if (fd < 0 || read(fd, buf, sizeof(buf)) != 4)
+  {
+    printf(1, "name longer than %d not cut\n", NAMEMAX);
+    exit();
+  }
+  close(fd);
+  file[NAMEMAX] = '\0';
+  if (chdir("..") != 0)
+  {
+    printf(1, "chdir .. failed\n");
+    exit();
+  }
+
+  d = opendir(dir);
+  if (d == 0)
+  {
+    printf(1, "opendir long name failed\n");
+    exit();
+  }
+  n = 0;
+  while ((de = readdir(d)) != 0)
+  {
+    if (strcmp(de->name, ".") == 0 || strcmp(de->name, "..") == 0)
+      continue;
+    if (strcmp(de->name, file) != 0 || de->type != T_FILE)
+    {
+      printf(1, "getdents returned bad long entry\n");
+      exit();
+    }
+    n++;
+  }
+  closedir(d);
+  if (n != 1)
+  {
+    printf(1, "getdents returned %d long entries\n", n);
+    exit();
+  }
+
+  if (unlink(path) != 0 || unlink(dir) != 0)
+  {
+    printf(1, "unlink long names failed\n");
+    exit();
+  }
+  printf(1, "longname ok\n");
+}
+
+// Enough names to make a directory index itself and split its
+// buckets: each can be found, getdents sees each once, and the
+// directory empties.
+void hashdirtest(void)
+{
+  enum { N = 1000 };
+  static char seen[N];
+  char name[8];
+  int i, fd, n;
+  struct dir *d;
+  struct dentry *de;
+
+  printf(1, "hashdir test\n");
+  if (mkdir("hd") != 0 || chdir("hd") != 0)
+  {
+    printf(1, "mkdir hd failed\n");
+    exit();
+  }
+  fd = open("hf", O_CREATE);
+  if (fd < 0)
+  {
+    printf(1, "create hd/hf failed\n");
+    exit();
+  }
+  close(fd);
+
+  name[0] = 'h';
+  name[4] = '\0';
+  for (i = 0; i < N; i++)
+  {
+    name[1] = '0' + i / 100;
+    name[2] = '0' + i / 10 % 10;
+    name[3] = '0' + i % 10;
+    if (link("hf", name) != 0)
+    {
+      printf(1, "hashdir link %s failed\n", name);
+      exit();
+    }
+  }
+  unlink("hf");
+  for (i = 0; i < N; i++)
+  {
+    name[1] = '0' + i / 100;
+    name[2] = '0' + i / 10 % 10;
+    name[3] = '0' + i % 10;
+    fd = open(name, O_RDONLY);
+    if (fd < 0)
+    {
+      printf(1, "hashdir open %s failed\n", name);
+      exit();
+    }
+    close(fd);
+  }
+
+  d = opendir(".");
+  if (d == 0)
+  {
+    printf(1, "opendir hd failed\n");
+    exit();
+  }
+  n = 0;
+  while ((de = readdir(d)) != 0)
+  {
+    n++;
+    if (strcmp(de->name, ".") == 0 || strcmp(de->name, "..") == 0)
+      continue;
+    i = atoi(de->name + 1);
+    if (de->name[0] != 'h' || strlen(de->name) != 4 || seen[i]++)
+    {
+      printf(1, "hashdir getdents returned %s\n", de->name);
+      exit();
+    }
+  }
+  closedir(d);
+  if (n != N + 2)
+  {
+    printf(1, "hashdir getdents returned %d entries\n", n);
+    exit();
+  }
+
+  if (chdir("..") != 0 || unlink("hd") == 0)
+  {
+    printf(1, "unlink of full hd succeeded\n");
+    exit();
+  }
+  if (chdir("hd") != 0)
+  {
+    printf(1, "chdir hd failed\n");
+    exit();
+  }
+  for (i = 0; i < N; i++)
+  {
+    name[1] = '0' + i / 100;
+    name[2] = '0' + i / 10 % 10;
+    name[3] = '0' + i % 10;
+    if (unlink(name) != 0)
+    {
+      printf(1, "hashdir unlink %s failed\n", name);
+      exit();
+    }
+  }
+  if (chdir("..") != 0)
+  {
+    printf(1, "chdir .. failed\n");
+    exit();
+  }
+  if (unlink("hd") != 0)
+  {
+    printf(1, "unlink of empty hd failed\n");
+    exit();
+  }
+  printf(1, "hashdir ok\n");
+}
+
 void subdir(void)
 {
   int fd, cc;
@@ -1390,6 +1740,11 @@ void fourteen(void)
 
   // DIRSIZ is 14.
   printf(1, "fourteen test\n");
+  if (longnames())
+  {
+    printf(1, "fourteen ok: names are not cut to 14\n");
+    return;
+  }
 
   if (mkdir("12345678901234") != 0)
   {
@@ -1965,6 +2320,1498 @@ void uio()
   printf(1, "uio test done\n");
 }
 
+// O_TRUNC, O_APPEND, O_EXCL, O_CLOEXEC and O_NONBLOCK.
+void openflagstest(void)
+{
+  int fd, pid;
+  struct stat st;
+  char b[8];
+  char *echoargs[] = {"echo", "hello", 0};
+
+  printf(1, "open flags test\n");
+  unlink("flagsfile");
+  fd = open("flagsfile", O_CREATE | O_EXCL | O_RDWR);
+  if (fd < 0 || write(fd, "abcdef", 6) != 6)
+  {
+    printf(1, "create flagsfile failed\n");
+    exit();
+  }
+  close(fd);
+  if (open("flagsfile", O_CREATE | O_EXCL | O_RDWR) >= 0)
+  {
+    printf(1, "O_EXCL opened an existing file\n");
+    exit();
+  }
+
+  fd = open("flagsfile", O_WRONLY | O_APPEND);
+  if (fd < 0 || write(fd, "gh", 2) != 2)
+  {
+    printf(1, "append to flagsfile failed\n");
+    exit();
+  }
+  close(fd);
+  fd = open("flagsfile", O_RDONLY);
+  if (read(fd, b, sizeof(b)) != 8 || b[0] != 'a' || b[6] != 'g')
+  {
+    printf(1, "O_APPEND wrote in the wrong place\n");
+    exit();
+  }
+  close(fd);
+
+  fd = open("flagsfile", O_WRONLY | O_TRUNC);
+  if (fd < 0 || fstat(fd, &st) < 0 || st.size != 0)
+  {
+    printf(1, "O_TRUNC did not truncate\n");
+    exit();
+  }
+  close(fd);
+
+  // echo's stdout is closed by exec, so nothing reaches the file.
+  pid = fork();
+  if (pid == 0)
+  {
+    close(1);
+    if (open("flagsfile", O_WRONLY | O_CLOEXEC) != 1)
+    {
+      printf(2, "open O_CLOEXEC failed\n");
+      exit();
+    }
+    exec("echo", echoargs);
+    printf(2, "exec echo failed\n");
+    exit();
+  }
+  wait();
+  if (stat("flagsfile", &st) < 0 || st.size != 0)
+  {
+    printf(1, "O_CLOEXEC descriptor survived exec\n");
+    exit();
+  }
+  unlink("flagsfile");
+
+  fd = open("/dev/console", O_RDONLY | O_NONBLOCK);
+  if (fd < 0 || read(fd, b, 1) != -1)
+  {
+    printf(1, "O_NONBLOCK console read blocked or returned data\n");
+    exit();
+  }
+  close(fd);
+  printf(1, "open flags test ok\n");
+}
+
+// dup2, dup3 and fcntl, including descriptors past NOFILE.
+void duptest(void)
+{
+  int fd, i, pid, p[2];
+  char c;
+
+  printf(1, "dup test\n");
+  if (dup2(1, 100) != 100 || write(100, "", 0) != 0)
+  {
+    printf(1, "dup2 past NOFILE failed\n");
+    exit();
+  }
+  if (dup2(100, 100) != 100 || dup3(100, 100, 0) >= 0)
+  {
+    printf(1, "dup2/dup3 onto itself wrong\n");
+    exit();
+  }
+  if (dup3(1, 100, O_CLOEXEC) != 100 || fcntl(100, F_GETFD, 0) != FD_CLOEXEC)
+  {
+    printf(1, "dup3 O_CLOEXEC failed\n");
+    exit();
+  }
+  if (fcntl(100, F_SETFD, 0) != 0 || fcntl(100, F_GETFD, 0) != 0)
+  {
+    printf(1, "fcntl F_SETFD failed\n");
+    exit();
+  }
+  if (dup3(1, 101, O_APPEND) >= 0)
+  {
+    printf(1, "dup3 accepted bad flags\n");
+    exit();
+  }
+  fd = fcntl(1, F_DUPFD, 50);
+  if (fd != 50)
+  {
+    printf(1, "fcntl F_DUPFD returned %d\n", fd);
+    exit();
+  }
+  close(50);
+
+  // The child inherits the grown table.
+  pid = fork();
+  if (pid == 0)
+  {
+    if (write(100, "", 0) != 0)
+      printf(1, "fd 100 lost across fork\n");
+    exit();
+  }
+  wait();
+  close(100);
+  if (write(100, "", 0) >= 0)
+  {
+    printf(1, "close(100) failed\n");
+    exit();
+  }
+
+  pid = fork();
+  if (pid == 0)
+  {
+    while (dup(1) >= 0)
+      ;
+    if (write(NOFILEMAX - 1, "", 0) != 0 || dup2(1, NOFILEMAX) >= 0)
+      printf(1, "descriptor table is not NOFILEMAX long\n");
+    exit();
+  }
+  wait();
+
+  if (pipe(p) != 0)
+  {
+    printf(1, "pipe failed\n");
+    exit();
+  }
+  if (fcntl(p[0], F_SETFL, O_NONBLOCK) != 0 ||
+      fcntl(p[0], F_GETFL, 0) != (O_RDONLY | O_NONBLOCK) ||
+      fcntl(p[1], F_GETFL, 0) != O_WRONLY)
+  {
+    printf(1, "fcntl F_SETFL/F_GETFL failed\n");
+    exit();
+  }
+  if (read(p[0], &c, 1) != -1)
+  {
+    printf(1, "O_NONBLOCK pipe read blocked or returned data\n");
+    exit();
+  }
+  for (i = 0; i < 2; i++)
+    close(p[i]);
+  printf(1, "dup test ok\n");
+}
+
+// getdents returns every live entry once, with its type,
+// and readdir walks directories bigger than its buffer.
+void getdentstest(void)
+{
+  char name[8], b[8];
+  int fd, i, n, seen[40];
+  struct dir *d;
+  struct dentry *de;
+
+  printf(1, "getdents test\n");
+  if (mkdir("gdd") != 0 || chdir("gdd") != 0)
+  {
+    printf(1, "mkdir gdd failed\n");
+    exit();
+  }
+  if (mkdir("sub") != 0)
+  {
+    printf(1, "mkdir gdd/sub failed\n");
+    exit();
+  }
+  name[0] = 'f';
+  name[3] = '\0';
+  for (i = 0; i < 40; i++)
+  {
+    name[1] = '0' + i / 10;
+    name[2] = '0' + i % 10;
+    fd = open(name, O_CREATE | O_RDWR);
+    if (fd < 0)
+    {
+      printf(1, "create %s failed\n", name);
+      exit();
+    }
+    close(fd);
+    seen[i] = 0;
+  }
+  // Leave a hole in the directory.
+  unlink("f07");
+
+  d = opendir(".");
+  if (d == 0)
+  {
+    printf(1, "opendir failed\n");
+    exit();
+  }
+  n = 0;
+  while ((de = readdir(d)) != 0)
+  {
+    n++;
+    if (strcmp(de->name, ".") == 0 || strcmp(de->name, "sub") == 0)
+    {
+      if (de->type != T_DIR)
+      {
+        printf(1, "%s is not T_DIR\n", de->name);
+        exit();
+      }
+      continue;
+    }
+    if (strcmp(de->name, "..") == 0)
+      continue;
+    i = atoi(de->name + 1);
+    if (de->name[0] != 'f' || de->type != T_FILE || i == 7 || seen[i]++)
+    {
+      printf(1, "getdents returned bad entry %s\n", de->name);
+      exit();
+    }
+  }
+  closedir(d);
+  if (n != 3 + 39)
+  {
+    printf(1, "getdents returned %d entries\n", n);
+    exit();
+  }
+
+  fd = open(".", O_RDONLY);
+  if (getdents(fd, b, sizeof(b)) >= 0)
+  {
+    printf(1, "getdents filled a too small buffer\n");
+    exit();
+  }
+  close(fd);
+  fd = open("f00", O_RDONLY);
+  if (getdents(fd, buf, sizeof(buf)) >= 0)
+  {
+    printf(1, "getdents on a file succeeded\n");
+    exit();
+  }
+  close(fd);
+
+  for (i = 0; i < 40; i++)
+  {
+    name[1] = '0' + i / 10;
+    name[2] = '0' + i % 10;
+    unlink(name);
+  }
+  unlink("sub");
+  if (chdir("..") != 0 || unlink("gdd") != 0)
+  {
+    printf(1, "unlink gdd failed\n");
+    exit();
+  }
+  printf(1, "getdents test ok\n");
+}
+
+// Open the file path and check that it holds s.
+void checkfile(char *path, char *s)
+{
+  int fd, n;
+
+  fd = open(path, O_RDONLY);
+  if (fd < 0)
+  {
+    printf(1, "open %s failed\n", path);
+    exit();
+  }
+  n = read(fd, buf, sizeof(buf) - 1);
+  close(fd);
+  if (n >= 0)
+    buf[n] = '\0';
+  if (n < 0 || strcmp(buf, s) != 0)
+  {
+    printf(1, "%s has the wrong contents\n", path);
+    exit();
+  }
+}
+
+void symlinktest(void)
+{
+  char target[MAXPATH];
+  struct stat st;
+  int fd, n;
+
+  printf(1, "symlink test\n");
+  if (mkdir("sld") != 0)
+  {
+    printf(1, "mkdir sld failed\n");
+    exit();
+  }
+  fd = open("sld/f", O_CREATE | O_RDWR);
+  if (fd < 0 || write(fd, "data", 4) != 4)
+  {
+    printf(1, "create sld/f failed\n");
+    exit();
+  }
+  close(fd);
+
+  if (symlink("sld/f", "slf") != 0 || symlink("sld", "sldl") != 0 ||
+      symlink("f", "sld/rel") != 0 || symlink("/sld/f", "sla") != 0)
+  {
+    printf(1, "symlink failed\n");
+    exit();
+  }
+  if (symlink("sld", "slf") >= 0 || symlink("", "sle") >= 0)
+  {
+    printf(1, "bad symlink succeeded\n");
+    exit();
+  }
+  checkfile("slf", "data");
+  checkfile("sldl/f", "data");
+  checkfile("sld/rel", "data");
+  checkfile("sldl/rel", "data");
+  checkfile("sla", "data");
+
+  n = readlink("slf", target, sizeof(target) - 1);
+  if (n >= 0)
+    target[n] = '\0';
+  if (n != 5 || strcmp(target, "sld/f") != 0)
+  {
+    printf(1, "readlink slf failed\n");
+    exit();
+  }
+  if (readlink("sld/f", target, sizeof(target)) >= 0)
+  {
+    printf(1, "readlink of a file succeeded\n");
+    exit();
+  }
+  if (lstat("slf", &st) != 0 || st.type != T_SYMLINK || st.size != 5 ||
+      stat("slf", &st) != 0 || st.type != T_FILE)
+  {
+    printf(1, "lstat/stat of slf wrong\n");
+    exit();
+  }
+  if (open("slf", O_RDONLY | O_NOFOLLOW) >= 0)
+  {
+    printf(1, "open O_NOFOLLOW of a link succeeded\n");
+    exit();
+  }
+  if ((fd = open("sldl/f", O_RDONLY | O_NOFOLLOW)) < 0)
+  {
+    printf(1, "O_NOFOLLOW refused a link before the last element\n");
+    exit();
+  }
+  close(fd);
+
+  // Writes through a link go to the target.
+  fd = open("slf", O_CREATE | O_WRONLY | O_TRUNC);
+  if (fd < 0 || write(fd, "new", 3) != 3)
+  {
+    printf(1, "write through slf failed\n");
+    exit();
+  }
+  close(fd);
+  checkfile("sld/f", "new");
+
+  // Loops and dangling links fail to open.
+  if (symlink("loop2", "loop1") != 0 || symlink("loop1", "loop2") != 0 ||
+      symlink("nothere", "dang") != 0)
+  {
+    printf(1, "symlink failed\n");
+    exit();
+  }
+  if (open("loop1", O_RDONLY) >= 0 || open("dang", O_RDONLY) >= 0)
+  {
+    printf(1, "open of a bad link succeeded\n");
+    exit();
+  }
+
+  // O_CREATE through a dangling link creates its target,
+  // relative to the link's directory; not with O_EXCL or
+  // O_NOFOLLOW, and not through a loop.
+  if (open("dang", O_CREATE | O_RDWR | O_EXCL) >= 0 ||
+      open("dang", O_CREATE | O_RDWR | O_NOFOLLOW) >= 0 ||
+      open("loop1", O_CREATE | O_RDWR) >= 0)
+  {
+    printf(1, "create through a link succeeded\n");
+    exit();
+  }
+  fd = open("dang", O_CREATE | O_RDWR);
+  if (fd < 0 || write(fd, "made", 4) != 4)
+  {
+    printf(1, "create through dang failed\n");
+    exit();
+  }
+  close(fd);
+  checkfile("nothere", "made");
+  if (lstat("dang", &st) != 0 || st.type != T_SYMLINK)
+  {
+    printf(1, "create through dang replaced the link\n");
+    exit();
+  }
+  if (symlink("g", "sld/rel2") != 0 || (fd = open("sld/rel2", O_CREATE | O_RDWR)) < 0)
+  {
+    printf(1, "create through sld/rel2 failed\n");
+    exit();
+  }
+  close(fd);
+  if (stat("sld/g", &st) != 0 || st.type != T_FILE || unlink("sld/g") != 0 ||
+      unlink("sld/rel2") != 0 || unlink("nothere") != 0)
+  {
+    printf(1, "create through sld/rel2 made the wrong file\n");
+    exit();
+  }
+
+  // Unlinking a link leaves the target alone.
+  if (unlink("slf") != 0 || unlink("sldl") != 0 || unlink("sla") != 0 ||
+      unlink("loop1") != 0 || unlink("loop2") != 0 || unlink("dang") != 0)
+  {
+    printf(1, "unlink of a link failed\n");
+    exit();
+  }
+  checkfile("sld/f", "new");
+  if (unlink("sld/rel") != 0 || unlink("sld/f") != 0 || unlink("sld") != 0)
+  {
+    printf(1, "unlink sld failed\n");
+    exit();
+  }
+  printf(1, "symlink test ok\n");
+}
+
+void timetest(void)
+{
+  struct timeval tv;
+  struct utimbuf ut;
+  struct stat st;
+  uint t0;
+  int fd;
+
+  printf(1, "time test\n");
+  t0 = time();
+  if (gettimeofday(&tv) < 0 || tv.sec < t0 || tv.usec >= 1000000 ||
+      t0 < 946684800)
+  {
+    printf(1, "bad time %d\n", t0);
+    exit();
+  }
+
+  unlink("tf");
+  fd = open("tf", O_CREATE | O_RDWR);
+  if (fd < 0 || fstat(fd, &st) < 0)
+  {
+    printf(1, "create tf failed\n");
+    exit();
+  }
+  if (st.mtime < t0 || st.mtime > time() || st.atime != st.mtime ||
+      st.ctime != st.mtime)
+  {
+    printf(1, "new file has bad times\n");
+    exit();
+  }
+
+  // Writes move mtime, reads atime.
+  sleep(HZ);
+  if (write(fd, "x", 1) != 1 || fstat(fd, &st) < 0 || st.mtime <= t0)
+  {
+    printf(1, "write did not update mtime\n");
+    exit();
+  }
+  ut.atime = 1000;
+  ut.mtime = 2000;
+  if (utime("tf", &ut) < 0 || fstat(fd, &st) < 0 || st.atime != 1000 ||
+      st.mtime != 2000 || st.ctime <= t0)
+  {
+    printf(1, "utime failed\n");
+    exit();
+  }
+  if (pread(fd, buf, 1, 0) != 1 || fstat(fd, &st) < 0 || st.atime <= t0 ||
+      st.mtime != 2000)
+  {
+    printf(1, "read did not update atime\n");
+    exit();
+  }
+  if (utime("tf", 0) < 0 || stat("tf", &st) < 0 || st.mtime <= t0)
+  {
+    printf(1, "utime to now failed\n");
+    exit();
+  }
+  close(fd);
+  unlink("tf");
+  printf(1, "time test ok\n");
+}
+
+// rename replaces its target, moves directories between
+// parents and keeps link counts right.
+void renametest(void)
+{
+  struct stat st, st2;
+  int fd;
+
+  printf(1, "rename test\n");
+  unlink("rnf1");
+  unlink("rnf2");
+  fd = open("rnf1", O_CREATE | O_RDWR);
+  write(fd, "one", 3);
+  close(fd);
+  fd = open("rnf2", O_CREATE | O_RDWR);
+  write(fd, "two", 3);
+  if (rename("rnf1", "rnf2") < 0 || open("rnf1", O_RDONLY) >= 0)
+  {
+    printf(1, "rename rnf1 rnf2 failed\n");
+    exit();
+  }
+  checkfile("rnf2", "one");
+  if (fstat(fd, &st) < 0 || st.nlink != 0)
+  {
+    printf(1, "replaced file still linked\n");
+    exit();
+  }
+  close(fd);
+  if (rename("rnf2", "rnf2") < 0 || rename("rnf1", "rnf3") == 0)
+  {
+    printf(1, "rename to self or of missing file wrong\n");
+    exit();
+  }
+
+  // Move a directory to a new parent.
+  if (mkdir("rnd1") < 0 || mkdir("rnd1/sub") < 0 || mkdir("rnd2") < 0 ||
+      mkdir("rnd1/sub/x") < 0)
+  {
+    printf(1, "mkdir rnd failed\n");
+    exit();
+  }
+  if (rename("rnd1/sub", "rnd2/sub") < 0 || chdir("rnd2/sub") < 0 ||
+      stat("..", &st) < 0 || chdir("/") < 0 || stat("rnd2", &st2) < 0 ||
+      st.ino != st2.ino)
+  {
+    printf(1, "rename rnd1/sub rnd2/sub failed\n");
+    exit();
+  }
+  if (st2.nlink != 2 || stat("rnd1", &st) < 0 || st.nlink != 1)
+  {
+    printf(1, "rename left bad link counts\n");
+    exit();
+  }
+
+  // A directory cannot go inside itself, or replace a file or
+  // a directory that is not empty; a file cannot replace a
+  // directory.
+  if (rename("rnd2", "rnd2/sub/y") == 0 || rename("rnd2", "rnd2/sub") == 0 ||
+      rename("rnd2", "rnf2") == 0 || rename("rnd1", "rnd2") == 0 ||
+      rename("rnf2", "rnd1") == 0)
+  {
+    printf(1, "bad rename succeeded\n");
+    exit();
+  }
+
+  // An empty directory can be replaced.
+  if (rename("rnd2/sub/x", "rnd1") < 0 || stat("rnd2/sub", &st) < 0 ||
+      st.nlink != 1 || stat("rnd1", &st) < 0 || st.type != T_DIR ||
+      stat(".", &st2) < 0)
+  {
+    printf(1, "rename onto empty directory failed\n");
+    exit();
+  }
+  chdir("rnd1");
+  if (stat("..", &st) < 0 || st.ino != st2.ino)
+  {
+    printf(1, "moved directory has the wrong ..\n");
+    exit();
+  }
+  chdir("/");
+
+  if (unlink("rnd1") < 0 || unlink("rnd2/sub") < 0 || unlink("rnd2") < 0 ||
+      unlink("rnf2") < 0)
+  {
+    printf(1, "unlink rename files failed\n");
+    exit();
+  }
+  printf(1, "rename test ok\n");
+}
+
+// ftruncate shrinks a file across several transactions and
+// grows it with zeroes; fallocate reserves space up front.
+void truncatetest(void)
+{
+  struct stat st;
+  int fd, i, n, bs;
+  uint size;
+
+  printf(1, "truncate test\n");
+  unlink("trf");
+  fd = open("trf", O_CREATE | O_RDWR);
+  if (fd < 0 || fstat(fd, &st) < 0)
+  {
+    printf(1, "create trf failed\n");
+    exit();
+  }
+
+  // More blocks than one transaction can free.
+  bs = st.blksize;
+  n = 4 * MAXOPBLOCKS + NDIRECT;
+  for (i = 0; i < n; i++)
+  {
+    memset(buf, 'a' + i % 26, bs);
+    if (write(fd, buf, bs) != bs)
+    {
+      printf(1, "write trf failed\n");
+      exit();
+    }
+  }
+  size = bs + bs / 2;
+  if (ftruncate(fd, size) < 0 || fstat(fd, &st) < 0 || st.size != size)
+  {
+    printf(1, "ftruncate trf failed\n");
+    exit();
+  }
+
+  // The tail of the last block must not come back.
+  if (ftruncate(fd, 2 * bs) < 0 || fstat(fd, &st) < 0 || st.size != 2 * bs ||
+      pread(fd, buf, 2 * bs, 0) != 2 * bs)
+  {
+    printf(1, "grow trf failed\n");
+    exit();
+  }
+  for (i = 0; i < 2 * bs; i++)
+  {
+    if (buf[i] != (i < bs ? 'a' : i < size ? 'b' : 0))
+    {
+      printf(1, "trf has the wrong contents at %d\n", i);
+      exit();
+    }
+  }
+
+  if (truncate("trf", 0) < 0 || fstat(fd, &st) < 0 || st.size != 0)
+  {
+    printf(1, "truncate trf failed\n");
+    exit();
+  }
+  if (fallocate(fd, bs / 2, 20 * bs) < 0 || fstat(fd, &st) < 0 ||
+      st.size != bs / 2 + 20 * bs || pread(fd, buf, bs, 10 * bs) != bs)
+  {
+    printf(1, "fallocate trf failed\n");
+    exit();
+  }
+  for (i = 0; i < bs; i++)
+  {
+    if (buf[i] != 0)
+    {
+      printf(1, "fallocated block not zero\n");
+      exit();
+    }
+  }
+  close(fd);
+
+  fd = open("trf", O_RDONLY);
+  if (ftruncate(fd, 0) == 0 || truncate(".", 0) == 0)
+  {
+    printf(1, "bad truncate succeeded\n");
+    exit();
+  }
+  close(fd);
+  fd = open("trf", O_RDWR | O_TRUNC);
+  if (fd < 0 || fstat(fd, &st) < 0 || st.size != 0)
+  {
+    printf(1, "open trf with O_TRUNC failed\n");
+    exit();
+  }
+  close(fd);
+  unlink("trf");
+  printf(1, "truncate test ok\n");
+}
+
+// fsync and sync wait for the log to reach the disk.
+void synctest(void)
+{
+  int fd, i;
+
+  printf(1, "sync test\n");
+  fd = open("syf", O_CREATE | O_RDWR);
+  if (fd < 0)
+  {
+    printf(1, "create syf failed\n");
+    exit();
+  }
+  for (i = 0; i < 20; i++)
+  {
+    if (write(fd, "sync", 4) != 4 || fsync(fd) < 0)
+    {
+      printf(1, "write and fsync syf failed\n");
+      exit();
+    }
+  }
+  close(fd);
+  if (fsync(fd) == 0 || unlink("syf") < 0 || sync() < 0)
+  {
+    printf(1, "fsync of closed fd succeeded or sync failed\n");
+    exit();
+  }
+  printf(1, "sync test ok\n");
+}
+
+#ifdef CRASHTEST
+// crashat() refuses crash points it cannot arm. The tests
+// never arm one: reaching it would halt the machine.
+void crashattest(void)
+{
+  printf(1, "crashat test\n");
+  if (crashat(-1, 1, 1) == 0 || crashat(CRASH_NOW + 1, 1, 1) == 0 ||
+      crashat(CRASH_LOGGED, 0, 0) == 0 || crashat(CRASH_INSTALL, 1, 0) == 0)
+  {
+    printf(1, "crashat accepted a bad crash point\n");
+    exit();
+  }
+  if (crashat(CRASH_NONE, 0, 0) < 0)
+  {
+    printf(1, "crashat could not disarm\n");
+    exit();
+  }
+  printf(1, "crashat test ok\n");
+}
+#else
+// Only a kernel built with CRASHTEST has crashat().
+void crashattest(void)
+{
+  printf(1, "crashat test\n");
+  if (crashat(CRASH_NONE, 0, 0) != -1)
+  {
+    printf(1, "crashat worked without CRASHTEST\n");
+    exit();
+  }
+  printf(1, "crashat test ok\n");
+}
+#endif
+
+// The raw root disk reads at the file offset, through the
+// buffer cache: the super block is at byte 512.
+void disktest(void)
+{
+  int fd;
+  struct superblock sb;
+  char b[MINBSIZE];
+
+  printf(1, "disk test\n");
+  fd = open("/dev/disk1", O_RDONLY);
+  if (fd < 0)
+  {
+    printf(1, "open disk1 failed\n");
+    exit();
+  }
+  if (read(fd, b, sizeof(b)) != sizeof(b) || read(fd, &sb, sizeof(sb)) != sizeof(sb))
+  {
+    printf(1, "read disk1 failed\n");
+    exit();
+  }
+  if (sb.bsize < MINBSIZE || (sb.bsize & (sb.bsize - 1)) != 0 || sb.nblocks >= sb.size)
+  {
+    printf(1, "disk1 super block is wrong\n");
+    exit();
+  }
+  if (pread(fd, b, sizeof(b), 512) != sizeof(b) || ((uint *)b)[0] != sb.size)
+  {
+    printf(1, "pread disk1 failed\n");
+    exit();
+  }
+  close(fd);
+  printf(1, "disk test ok\n");
+}
+
+// disk2 mounted on a directory: it mounts only read-only and
+// not while its raw device is open, files there live on another
+// device and cannot be changed, ".." leads back out, and the
+// mount stays put while anything inside is in use.
+void mounttest(void)
+{
+  int fd;
+  struct stat st, cat, root;
+
+  printf(1, "mount test\n");
+  if (mkdir("mnt") < 0)
+  {
+    printf(1, "mkdir mnt failed\n");
+    exit();
+  }
+  fd = open("/dev/disk2", O_RDONLY);
+  if (fd >= 0 && mount("/dev/disk2", "mnt", "xv6", "ro") == 0)
+  {
+    printf(1, "mount with /dev/disk2 open succeeded\n");
+    exit();
+  }
+  if (fd >= 0)
+    close(fd);
+  if (mount("/dev/disk2", "mnt", "xv6", "ro") < 0)
+  {
+    printf(1, "no disk2, skipping mount test\n");
+    unlink("mnt");
+    return;
+  }
+  if (mount("/dev/disk2", "mnt", "xv6", "ro") == 0 || mount("/dev/disk2", "mnt", "nofs", 0) == 0)
+  {
+    printf(1, "mount twice succeeded\n");
+    exit();
+  }
+  if (stat(".", &root) < 0 || stat("mnt/cat", &cat) < 0 || cat.dev == root.dev)
+  {
+    printf(1, "mnt/cat is on the root device\n");
+    exit();
+  }
+  if (open("mnt/mfile", O_CREATE | O_RDWR) >= 0 || mkdir("mnt/mdir") == 0 ||
+      link("mnt/cat", "mnt/cat2") == 0 || unlink("mnt/cat") == 0)
+  {
+    printf(1, "read-only mnt changed\n");
+    exit();
+  }
+  fd = open("mnt/cat", O_WRONLY);
+  if (fd < 0 || write(fd, "x", 1) >= 0)
+  {
+    printf(1, "write to read-only mnt/cat succeeded\n");
+    exit();
+  }
+  if (umount("mnt") == 0)
+  {
+    printf(1, "umount with an open file succeeded\n");
+    exit();
+  }
+  close(fd);
+  if (unlink("mnt") == 0)
+  {
+    printf(1, "unlink of a mount point succeeded\n");
+    exit();
+  }
+  if (stat("mnt/..", &st) < 0 || st.dev != root.dev || st.ino != root.ino)
+  {
+    printf(1, "mnt/.. is not the root directory\n");
+    exit();
+  }
+  if (chdir("mnt") < 0 || umount("/mnt") == 0)
+  {
+    printf(1, "umount of the current directory succeeded\n");
+    exit();
+  }
+  if (chdir("..") < 0 || umount("mnt") < 0)
+  {
+    printf(1, "umount failed\n");
+    exit();
+  }
+  if (open("mnt/cat", O_RDONLY) >= 0)
+  {
+    printf(1, "mnt/cat visible after umount\n");
+    exit();
+  }
+  if (mount("/dev/disk2", "mnt", "xv6", 0) == 0 || mount("/dev/disk2", "mnt", "xv6", "rw") == 0)
+  {
+    printf(1, "read-write mount of disk2 succeeded\n");
+    exit();
+  }
+  if (mount("/dev/disk2", "mnt", "xv6", "ro") < 0)
+  {
+    printf(1, "remount failed\n");
+    exit();
+  }
+  if (stat("mnt/cat", &st) < 0 || st.size != cat.size || st.mtime != cat.mtime)
+  {
+    printf(1, "mnt/cat changed after remount\n");
+    exit();
+  }
+  if (umount("mnt") < 0 || unlink("mnt") < 0)
+  {
+    printf(1, "mount test cleanup failed\n");
+    exit();
+  }
+  printf(1, "mount test ok\n");
+}
+
+// A FAT32 volume on disk2 ("make qemu DISK2=fat.img") keeps
+// long names, finds them in any case, renames, and refuses
+// what FAT cannot record: hard links, devices and symlinks.
+void fattest(void)
+{
+  int fd, i;
+  struct stat st;
+  char buf[32];
+
+  printf(1, "fat test\n");
+  if (mkdir("fat") < 0)
+  {
+    printf(1, "mkdir fat failed\n");
+    exit();
+  }
+  if (mount("/dev/disk2", "fat", "fat32", 0) < 0)
+  {
+    printf(1, "no FAT32 disk2, skipping fat test\n");
+    unlink("fat");
+    return;
+  }
+  fd = open("fat/A Long File Name.txt", O_CREATE | O_RDWR);
+  if (fd < 0)
+  {
+    printf(1, "create fat/A Long File Name.txt failed\n");
+    exit();
+  }
+  for (i = 0; i < 100; i++)
+  {
+    if (write(fd, "0123456789", 10) != 10)
+    {
+      printf(1, "write to fat file failed\n");
+      exit();
+    }
+  }
+  close(fd);
+  fd = open("fat/a long file NAME.TXT", O_RDONLY);
+  if (fd < 0 || fstat(fd, &st) < 0 || st.size != 1000)
+  {
+    printf(1, "fat lookup ignoring case failed\n");
+    exit();
+  }
+  memset(buf, 0, sizeof(buf));
+  if (read(fd, buf, 10) != 10 || strcmp(buf, "0123456789") != 0)
+  {
+    printf(1, "read of fat file failed\n");
+    exit();
+  }
+  close(fd);
+  if (mkdir("fat/Sub Dir") < 0 || rename("fat/A Long File Name.txt", "fat/Sub Dir/moved.txt") < 0)
+  {
+    printf(1, "fat mkdir or rename failed\n");
+    exit();
+  }
+  if (open("fat/A Long File Name.txt", O_RDONLY) >= 0 || stat("fat/sub dir/MOVED.txt", &st) < 0 || st.size != 1000)
+  {
+    printf(1, "fat rename did not move the file\n");
+    exit();
+  }
+  if (link("fat/Sub Dir/moved.txt", "fat/hard") == 0)
+  {
+    printf(1, "fat hard link succeeded\n");
+    exit();
+  }
+  if (mknod("fat/dev", 1, 1) >= 0 || symlink("moved.txt", "fat/Sub Dir/sym") == 0)
+  {
+    printf(1, "fat device or symlink succeeded\n");
+    exit();
+  }
+  if (umount("fat") < 0 || mount("/dev/disk2", "fat", "fat32", 0) < 0)
+  {
+    printf(1, "fat remount failed\n");
+    exit();
+  }
+  memset(buf, 0, sizeof(buf));
+  fd = open("fat/Sub Dir/moved.txt", O_RDONLY);
+  if (fd < 0 || read(fd, buf, 10) != 10 || strcmp(buf, "0123456789") != 0)
+  {
+    printf(1, "fat file lost after remount\n");
+    exit();
+  }
+  close(fd);
+  if (unlink("fat/Sub Dir") == 0)
+  {
+    printf(1, "unlink of a non-empty fat directory succeeded\n");
+    exit();
+  }
+  if (unlink("fat/Sub Dir/moved.txt") < 0 || unlink("fat/Sub Dir") < 0)
+  {
+    printf(1, "fat unlink failed\n");
+    exit();
+  }
+  if (stat("fat/Sub Dir", &st) == 0 || umount("fat") < 0 || unlink("fat") < 0)
+  {
+    printf(1, "fat test cleanup failed\n");
+    exit();
+  }
+  printf(1, "fat test ok\n");
+}
+
+// An ext2 disk2 ("make qemu DISK2=ext2.img") holds the user
+// programs, which read and run from it just as from the root,
+// and nothing on it can be changed.
+void ext2test(void)
+{
+  int fd, fd2, pid, n, i;
+  int fds[2];
+  struct stat st, prog;
+  char buf[64], buf2[64];
+  char *args[] = {"e2/echo", "ext2", "exec", 0};
+
+  printf(1, "ext2 test\n");
+  if (mkdir("e2") < 0)
+  {
+    printf(1, "mkdir e2 failed\n");
+    exit();
+  }
+  if (mount("/dev/disk2", "e2", "ext2", 0) < 0)
+  {
+    printf(1, "no ext2 disk2, skipping ext2 test\n");
+    unlink("e2");
+    return;
+  }
+  if (stat("e2/cat", &st) < 0 || stat("cat", &prog) < 0 || st.type != T_FILE || st.size != prog.size || st.dev == prog.dev)
+  {
+    printf(1, "e2/cat is not the cat program\n");
+    exit();
+  }
+  fd = open("e2/cat", O_RDONLY);
+  fd2 = open("cat", O_RDONLY);
+  while ((n = read(fd, buf, sizeof(buf))) > 0)
+  {
+    if (read(fd2, buf2, sizeof(buf2)) != n)
+      buf2[0] = ~buf[0];
+    for (i = 0; i < n; i++)
+    {
+      if (buf[i] != buf2[i])
+      {
+        printf(1, "e2/cat differs from cat\n");
+        exit();
+      }
+    }
+  }
+  if (n < 0)
+  {
+    printf(1, "read of e2/cat failed\n");
+    exit();
+  }
+  close(fd);
+  close(fd2);
+
+  if (pipe(fds) < 0)
+  {
+    printf(1, "pipe failed\n");
+    exit();
+  }
+  pid = fork();
+  if (pid == 0)
+  {
+    close(1);
+    dup(fds[1]);
+    close(fds[0]);
+    close(fds[1]);
+    exec("e2/echo", args);
+    printf(2, "exec e2/echo failed\n");
+    exit();
+  }
+  close(fds[1]);
+  memset(buf, 0, sizeof(buf));
+  n = read(fds[0], buf, sizeof(buf) - 1);
+  close(fds[0]);
+  wait();
+  if (n != 10 || strcmp(buf, "ext2 exec\n") != 0)
+  {
+    printf(1, "e2/echo did not run\n");
+    exit();
+  }
+
+  fd = open("e2/cat", O_RDWR);
+  if (fd >= 0 && write(fd, "x", 1) == 1)
+  {
+    printf(1, "write to ext2 succeeded\n");
+    exit();
+  }
+  close(fd);
+  if (open("e2/new", O_CREATE | O_RDWR) >= 0 || mkdir("e2/dir") == 0 || unlink("e2/cat") == 0 || link("e2/cat", "e2/cat2") == 0 || rename("e2/cat", "e2/dog") == 0)
+  {
+    printf(1, "ext2 changed\n");
+    exit();
+  }
+  if (umount("e2") < 0 || unlink("e2") < 0)
+  {
+    printf(1, "ext2 test cleanup failed\n");
+    exit();
+  }
+  printf(1, "ext2 test ok\n");
+}
+
+// A tmpfs with a 64k limit holds files, directories and
+// symbolic links, fills up, and takes space back on unlink.
+void tmpfstest(void)
+{
+  int fd, n, tot;
+  struct stat st, dst, root;
+  char target[8];
+
+  printf(1, "tmpfs test\n");
+  if (mkdir("tmpd") < 0)
+  {
+    printf(1, "mkdir tmpd failed\n");
+    exit();
+  }
+  if (mount("tmpfs", "tmpd", "tmpfs", "size=64x") == 0 || mount("tmpfs", "tmpd", "tmpfs", "bogus") == 0)
+  {
+    printf(1, "mount with bad options succeeded\n");
+    exit();
+  }
+  if (mount("tmpfs", "tmpd", "tmpfs", "size=64k") < 0)
+  {
+    printf(1, "mount tmpfs failed\n");
+    exit();
+  }
+
+  fd = open("tmpd/f", O_CREATE | O_RDWR);
+  if (fd < 0 || write(fd, "hello", 5) != 5)
+  {
+    printf(1, "create tmpd/f failed\n");
+    exit();
+  }
+  if (stat(".", &root) < 0 || fstat(fd, &st) < 0 || st.dev == root.dev || st.size != 5 || st.blksize != 4096)
+  {
+    printf(1, "tmpd/f has wrong stat\n");
+    exit();
+  }
+  memset(buf, 0, sizeof(buf));
+  if (pread(fd, buf, sizeof(buf), 0) != 5 || strcmp(buf, "hello") != 0)
+  {
+    printf(1, "read tmpd/f failed\n");
+    exit();
+  }
+  close(fd);
+
+  if (mkdir("tmpd/d") < 0 || symlink("hello", "tmpd/d/l") < 0)
+  {
+    printf(1, "mkdir tmpd/d failed\n");
+    exit();
+  }
+  memset(target, 0, sizeof(target));
+  if (readlink("tmpd/d/l", target, sizeof(target)) != 5 || strcmp(target, "hello") != 0)
+  {
+    printf(1, "readlink tmpd/d/l failed\n");
+    exit();
+  }
+  if (stat("tmpd", &dst) < 0 || stat("tmpd/d/..", &st) < 0 || st.ino != dst.ino || st.dev != dst.dev)
+  {
+    printf(1, "tmpd/d/.. is not tmpd\n");
+    exit();
+  }
+  if (rename("tmpd/f", "tmpd/d/f") < 0 || rename("tmpd/d/f", "tmpf") == 0)
+  {
+    printf(1, "rename in tmpd failed\n");
+    exit();
+  }
+  if (unlink("tmpd/d") == 0)
+  {
+    printf(1, "unlink of non-empty tmpd/d succeeded\n");
+    exit();
+  }
+
+  // Fill it up.
+  fd = open("tmpd/big", O_CREATE | O_RDWR);
+  if (fd < 0)
+  {
+    printf(1, "create tmpd/big failed\n");
+    exit();
+  }
+  memset(buf, 'b', 4096);
+  for (tot = 0; (n = write(fd, buf, 4096)) == 4096; tot += n)
+    ;
+  close(fd);
+  if (tot < 32 * 1024 || tot > 64 * 1024)
+  {
+    printf(1, "tmpd held %d bytes\n", tot);
+    exit();
+  }
+  if (mkdir("tmpd/full") == 0)
+  {
+    printf(1, "mkdir in a full tmpd succeeded\n");
+    exit();
+  }
+  if (unlink("tmpd/big") < 0 || mkdir("tmpd/full") < 0)
+  {
+    printf(1, "unlink in tmpd did not free space\n");
+    exit();
+  }
+
+  if (unlink("tmpd/full") < 0 || unlink("tmpd/d/l") < 0 || unlink("tmpd/d/f") < 0 || unlink("tmpd/d") < 0)
+  {
+    printf(1, "unlink in tmpd failed\n");
+    exit();
+  }
+  if (umount("tmpd") < 0 || unlink("tmpd") < 0)
+  {
+    printf(1, "umount tmpd failed\n");
+    exit();
+  }
+  printf(1, "tmpfs test ok\n");
+}
+
+// /dev lists the registered devices, which read, write and
+// answer ioctl requests, and cannot be added to or removed.
+void devtest(void)
+{
+  int fd, i, n;
+  uint size;
+  struct dir *d;
+  struct dentry *de;
+  char a[16], b[16];
+
+  printf(1, "dev test\n");
+  fd = open("/dev/null", O_RDWR);
+  if (fd < 0 || write(fd, "x", 1) != 1 || read(fd, a, sizeof(a)) != 0)
+  {
+    printf(1, "/dev/null failed\n");
+    exit();
+  }
+  close(fd);
+  fd = open("/dev/zero", O_RDONLY);
+  memset(a, 1, sizeof(a));
+  if (fd < 0 || read(fd, a, sizeof(a)) != sizeof(a))
+  {
+    printf(1, "/dev/zero failed\n");
+    exit();
+  }
+  for (i = 0; i < sizeof(a); i++)
+  {
+    if (a[i] != 0)
+    {
+      printf(1, "/dev/zero read %d\n", a[i]);
+      exit();
+    }
+  }
+  close(fd);
+  fd = open("/dev/random", O_RDONLY);
+  if (fd < 0 || read(fd, a, sizeof(a)) != sizeof(a) || read(fd, b, sizeof(b)) != sizeof(b))
+  {
+    printf(1, "/dev/random failed\n");
+    exit();
+  }
+  for (i = 0; i < sizeof(a) && a[i] == b[i]; i++)
+    ;
+  if (i == sizeof(a))
+  {
+    printf(1, "/dev/random repeated itself\n");
+    exit();
+  }
+  close(fd);
+
+  fd = open("/dev/disk1", O_RDONLY);
+  if (fd < 0 || ioctl(fd, DIOCGSIZE, &size) < 0 || size == 0 || ioctl(fd, FIONREAD, &size) == 0)
+  {
+    printf(1, "ioctl on /dev/disk1 failed\n");
+    exit();
+  }
+  close(fd);
+  fd = open("/dev/console", O_RDONLY);
+  if (fd < 0 || ioctl(fd, FIONREAD, &size) < 0)
+  {
+    printf(1, "ioctl on /dev/console failed\n");
+    exit();
+  }
+  close(fd);
+  fd = open("ioctlfile", O_CREATE | O_RDWR);
+  if (fd < 0 || ioctl(fd, FIONREAD, &size) == 0)
+  {
+    printf(1, "ioctl on a file succeeded\n");
+    exit();
+  }
+  close(fd);
+  unlink("ioctlfile");
+
+  d = opendir("/dev");
+  n = 0;
+  while ((de = readdir(d)) != 0)
+  {
+    if (strcmp(de->name, "console") == 0 || strcmp(de->name, "null") == 0 || strcmp(de->name, "uart") == 0)
+    {
+      if (de->type != T_DEV)
+      {
+        printf(1, "/dev/%s is not a device\n", de->name);
+        exit();
+      }
+      n++;
+    }
+  }
+  closedir(d);
+  if (n != 3)
+  {
+    printf(1, "/dev has %d of console, null and uart\n", n);
+    exit();
+  }
+
+  if (mknod("/dev/new", 4, 0) == 0 || mkdir("/dev/dir") == 0 || unlink("/dev/null") == 0 ||
+      rename("/dev/zero", "/dev/zero2") == 0 || link("/dev/null", "/dev/null2") == 0)
+  {
+    printf(1, "changing /dev succeeded\n");
+    exit();
+  }
+  if (open("/dev/null", O_RDONLY | O_TRUNC) < 0 || open("/dev/zero", O_RDONLY) < 0)
+  {
+    printf(1, "/dev lost a device\n");
+    exit();
+  }
+  printf(1, "dev test ok\n");
+}
+
+// Reading a file front to back, in pieces that do not line up
+// with blocks, sets the buffer cache reading ahead; the reads
+// still return what was written, and so does one after a seek.
+void readaheadtest(void)
+{
+  int fd, i, n, off;
+  char buf[300];
+
+  printf(1, "read-ahead test\n");
+  fd = open("ra", O_CREATE | O_RDWR);
+  if (fd < 0)
+  {
+    printf(1, "create ra failed\n");
+    exit();
+  }
+  for (i = 0; i < 60; i++)
+  {
+    memset(buf, i, 256);
+    if (write(fd, buf, 256) != 256 || write(fd, buf, 256) != 256)
+    {
+      printf(1, "write ra failed\n");
+      exit();
+    }
+  }
+  if (sync() < 0 || lseek(fd, 0, SEEK_SET) != 0)
+  {
+    printf(1, "sync or seek of ra failed\n");
+    exit();
+  }
+  for (off = 0; off < 60 * 512; off += n)
+  {
+    n = read(fd, buf, sizeof(buf));
+    if (n <= 0)
+    {
+      printf(1, "read ra failed at %d\n", off);
+      exit();
+    }
+    for (i = 0; i < n; i++)
+    {
+      if (buf[i] != (char)((off + i) / 512))
+      {
+        printf(1, "read ra wrong byte at %d\n", off + i);
+        exit();
+      }
+    }
+  }
+  if (lseek(fd, 10 * 512 + 7, SEEK_SET) != 10 * 512 + 7 || read(fd, buf, 10) != 10 || buf[0] != 10 || buf[9] != 10)
+  {
+    printf(1, "read ra after seek failed\n");
+    exit();
+  }
+  close(fd);
+  unlink("ra");
+  printf(1, "read-ahead test ok\n");
+}
+
+// lseek past the end leaves a hole that reads as zeroes;
+// pread and pwrite leave the file offset alone.
+void seektest(void)
+{
+  int fd, i, p[2];
+  struct stat st;
+  char b[16];
+
+  printf(1, "seek test\n");
+  unlink("seekfile");
+  fd = open("seekfile", O_CREATE | O_RDWR);
+  if (fd < 0)
+  {
+    printf(1, "create seekfile failed\n");
+    exit();
+  }
+  if (write(fd, "abc", 3) != 3)
+  {
+    printf(1, "write seekfile failed\n");
+    exit();
+  }
+  if (lseek(fd, 10000, SEEK_SET) != 10000 || write(fd, "xyz", 3) != 3)
+  {
+    printf(1, "lseek past end failed\n");
+    exit();
+  }
+  if (fstat(fd, &st) < 0 || st.size != 10003)
+  {
+    printf(1, "seekfile has wrong size %d\n", st.size);
+    exit();
+  }
+  if (pread(fd, b, sizeof(b), 5000) != sizeof(b))
+  {
+    printf(1, "pread hole failed\n");
+    exit();
+  }
+  for (i = 0; i < sizeof(b); i++)
+  {
+    if (b[i] != 0)
+    {
+      printf(1, "hole is not zero\n");
+      exit();
+    }
+  }
+  if (pwrite(fd, "Q", 1, 1) != 1 || lseek(fd, 0, SEEK_CUR) != 10003)
+  {
+    printf(1, "pwrite moved the offset\n");
+    exit();
+  }
+  if (lseek(fd, -3, SEEK_END) != 10000 || read(fd, b, 3) != 3 || b[0] != 'x')
+  {
+    printf(1, "SEEK_END failed\n");
+    exit();
+  }
+  if (pread(fd, b, 3, 0) != 3 || b[0] != 'a' || b[1] != 'Q' || b[2] != 'c')
+  {
+    printf(1, "pread failed\n");
+    exit();
+  }
+  if (read(fd, b, 1) != 0)
+  {
+    printf(1, "read at end of file failed\n");
+    exit();
+  }
+  if (lseek(fd, -1, SEEK_SET) >= 0)
+  {
+    printf(1, "lseek before start of file succeeded\n");
+    exit();
+  }
+  close(fd);
+  unlink("seekfile");
+
+  if (pipe(p) < 0)
+  {
+    printf(1, "pipe failed\n");
+    exit();
+  }
+  if (lseek(p[0], 0, SEEK_SET) >= 0)
+  {
+    printf(1, "lseek on pipe succeeded\n");
+    exit();
+  }
+  close(p[0]);
+  close(p[1]);
+  printf(1, "seek test ok\n");
+}
+
+// restrict() can only narrow the allowlist, and the allowlist
+// is inherited across fork.
+void restricttest(void)
+{
+  uint allow[NSYSMASK];
+  int pid;
+
+  printf(1, "restrict test\n");
+  pid = fork();
+  if (pid == 0)
+  {
+    memset(allow, 0xff, sizeof(allow));
+    allow[SYS_mkdir / 32] &= ~(1 << (SYS_mkdir % 32));
+    if (sysrestrict(allow, RESTRICT_EPERM) < 0)
+    {
+      printf(1, "restrict failed\n");
+      exit();
+    }
+    if (mkdir("restrictdir") >= 0)
+    {
+      printf(1, "restrict: mkdir allowed; test FAILED\n");
+      exit();
+    }
+
+    // Asking for everything back must not widen the allowlist.
+    memset(allow, 0xff, sizeof(allow));
+    sysrestrict(allow, RESTRICT_EPERM);
+    pid = fork();
+    if (pid == 0)
+    {
+      if (mkdir("restrictdir") >= 0)
+        printf(1, "restrict: mkdir allowed after widening; test FAILED\n");
+      exit();
+    }
+    wait();
+
+    sysrestrict(allow, RESTRICT_KILL);
+    mkdir("restrictdir");
+    printf(1, "restrict: kill mode did not kill; test FAILED\n");
+    exit();
+  }
+  else if (pid < 0)
+  {
+    printf(1, "fork failed\n");
+    exit();
+  }
+  wait();
+  if (unlink("restrictdir") >= 0)
+  {
+    printf(1, "restrict: restrictdir exists; test FAILED\n");
+    exit();
+  }
+  printf(1, "restrict test ok\n");
+}
+
 void argptest()
 {
   int fd;
@@ -2015,7 +3862,26 @@ int main(int argc, char *argv[])
   opentest();
   writetest();
   writetest1();
+  hugefiletest();
+  interleavetest();
   createtest();
+  seektest();
+  openflagstest();
+  duptest();
+  getdentstest();
+  symlinktest();
+  timetest();
+  renametest();
+  truncatetest();
+  synctest();
+  crashattest();
+  disktest();
+  mounttest();
+  fattest();
+  ext2test();
+  tmpfstest();
+  devtest();
+  readaheadtest();
 
   openiputtest();
   exitiputtest();
@@ -2036,8 +3902,11 @@ int main(int argc, char *argv[])
   iref();
   forktest();
   bigdir(); // slow
+  longnametest();
+  hashdirtest(); // slow
 
   uio();
+  restricttest();
 
   exectest();
 